@@ -4,6 +4,7 @@ P4 IP Forwarding Control Plane
 IPアドレス転送表を生成し、P4スイッチに設定するコントロールプレーン
 """
 
+import ipaddress
 import json
 import socket
 import struct
@@ -15,12 +16,15 @@ try:
     from p4runtime_lib import helper
     from p4runtime_lib.switch import ShutdownAllSwitchConnections
     from p4runtime_lib.convert import encodeNum, decodeNum
+    from p4.v1 import p4runtime_pb2
     P4RUNTIME_AVAILABLE = True
 except ImportError:
     print("Warning: p4runtime_lib not available. Using BMv2 CLI instead.")
     P4RUNTIME_AVAILABLE = False
     import subprocess
 
+ECMP_SELECTOR = "MyIngress.ecmp_selector"
+
 class IPForwardingController:
     def __init__(self, switch_address: str = "127.0.0.1", switch_port: int = 50051):
         self.switch_address = switch_address
@@ -65,6 +69,12 @@ class IPForwardingController:
         """IPアドレス文字列を整数に変換"""
         return struct.unpack("!I", socket.inet_aton(ip))[0]
     
+    def lpm_table(self, ip: str) -> Tuple[str, str, str]:
+        """プレフィックスのアドレスファミリーに対応するテーブル・マッチフィールド・アクション名"""
+        if ipaddress.ip_address(ip).version == 6:
+            return "MyIngress.ipv6_lpm", "hdr.ipv6.dstAddr", "MyIngress.ipv6_forward"
+        return "MyIngress.ipv4_lpm", "hdr.ipv4.dstAddr", "MyIngress.ipv4_forward"
+    
     def int_to_ip(self, ip_int: int) -> str:
         """整数をIPアドレス文字列に変換"""
         return socket.inet_ntoa(struct.pack("!I", ip_int))
@@ -73,6 +83,69 @@ class IPForwardingController:
         """MACアドレス文字列をバイト列に変換"""
         return bytes.fromhex(mac.replace(':', ''))
     
+    def next_hops(self, info: Dict) -> List[Dict]:
+        """ルートのネクストホップ一覧（next_hops があればECMP）"""
+        return info.get("next_hops") or [{"port": info["port"], "mac": info["mac"]}]
+    
+    def _write_entity(self, sw, entity):
+        """エンティティを1つINSERTする（メンバー、グループ、エントリの順序を守るため1つずつ書き込む）"""
+        request = p4runtime_pb2.WriteRequest()
+        request.device_id = sw.device_id
+        request.election_id.low = 1
+        update = request.updates.add()
+        update.type = p4runtime_pb2.Update.INSERT
+        update.entity.CopyFrom(entity)
+        sw.client_stub.Write(request)
+    
+    def install_ecmp_route_p4runtime(self, sw, p4info_helper, network: str, group_id: int, member_base: int):
+        """ECMPルートをアクションセレクターのメンバー・グループとして書き込む（作ったメンバー数を返す）"""
+        ip, prefix_len = network.split('/')
+        profile_id = p4info_helper.get_id("action_profiles", ECMP_SELECTOR)
+        hops = self.next_hops(self.routing_table[network])
+        
+        member_ids = []
+        for i, hop in enumerate(hops):
+            # メンバーのアクションはテーブルエントリと同じ形式なので、ipv4_forwardのエントリから取り出す
+            forward = p4info_helper.buildTableEntry(
+                table_name="MyIngress.ipv4_lpm",
+                match_fields={"hdr.ipv4.dstAddr": (self.ip_to_int(ip), int(prefix_len))},
+                action_name="MyIngress.ipv4_forward",
+                action_params={"dstAddr": self.mac_to_bytes(hop["mac"]), "port": hop["port"]}
+            )
+            entity = p4runtime_pb2.Entity()
+            entity.action_profile_member.action_profile_id = profile_id
+            entity.action_profile_member.member_id = member_base + i
+            entity.action_profile_member.action.CopyFrom(forward.action.action)
+            self._write_entity(sw, entity)
+            member_ids.append(member_base + i)
+        
+        entity = p4runtime_pb2.Entity()
+        entity.action_profile_group.action_profile_id = profile_id
+        entity.action_profile_group.group_id = group_id
+        for member_id in member_ids:
+            member = entity.action_profile_group.members.add()
+            member.member_id = member_id
+            member.weight = 1
+        self._write_entity(sw, entity)
+        
+        entity = p4runtime_pb2.Entity()
+        entry = entity.table_entry
+        entry.table_id = p4info_helper.get_tables_id("MyIngress.ecmp_group")
+        entry.match.extend([
+            p4info_helper.get_match_field_pb("MyIngress.ecmp_group", "meta.ecmp_group_id", group_id)
+        ])
+        entry.action.action_profile_group_id = group_id
+        self._write_entity(sw, entity)
+        
+        sw.WriteTableEntry(p4info_helper.buildTableEntry(
+            table_name="MyIngress.ipv4_lpm",
+            match_fields={"hdr.ipv4.dstAddr": (self.ip_to_int(ip), int(prefix_len))},
+            action_name="MyIngress.set_ecmp_group",
+            action_params={"group_id": group_id}
+        ))
+        print(f"Installed ECMP route: {network} -> group {group_id} ({len(hops)} next hops)")
+        return len(hops)
+    
     def install_routes_p4runtime(self):
         """P4Runtimeを使用してルートをインストール"""
         if not P4RUNTIME_AVAILABLE:
@@ -90,17 +163,25 @@ class IPForwardingController:
             )
             
             # ルーティングテーブルエントリを追加
+            group_id = 1
+            member_id = 1
             for network, info in self.routing_table.items():
+                if "next_hops" in info:
+                    member_id += self.install_ecmp_route_p4runtime(sw, p4info_helper, network, group_id, member_id)
+                    group_id += 1
+                    continue
+                
                 ip, prefix_len = network.split('/')
                 prefix_len = int(prefix_len)
+                table_name, field_name, action_name = self.lpm_table(ip)
                 
                 # LPMテーブルエントリを作成
                 table_entry = p4info_helper.buildTableEntry(
-                    table_name="MyIngress.ipv4_lpm",
+                    table_name=table_name,
                     match_fields={
-                        "hdr.ipv4.dstAddr": (self.ip_to_int(ip), prefix_len)
+                        field_name: (int(ipaddress.ip_address(ip)), prefix_len)
                     },
-                    action_name="MyIngress.ipv4_forward",
+                    action_name=action_name,
                     action_params={
                         "dstAddr": self.mac_to_bytes(info["mac"]),
                         "port": info["port"]
@@ -120,13 +201,32 @@ class IPForwardingController:
     def install_routes_bmv2_cli(self):
         """BMv2 CLIを使用してルートをインストール"""
         cli_commands = []
+        # simple_switch_CLIはメンバーとグループのハンドルを作成順に0から振る
+        member_handle = 0
+        group_handle = 0
         
         for network, info in self.routing_table.items():
             ip, prefix_len = network.split('/')
             prefix_len = int(prefix_len)
             
+            if "next_hops" in info:
+                # ECMP: ネクストホップごとのメンバーを1つのグループにまとめ、ipv4_lpmでグループIDを設定
+                group_id = group_handle + 1
+                cli_commands.append(f"act_prof_create_group {ECMP_SELECTOR}")
+                for hop in info["next_hops"]:
+                    cli_commands.append(
+                        f"act_prof_create_member {ECMP_SELECTOR} MyIngress.ipv4_forward {hop['mac']} {hop['port']}"
+                    )
+                    cli_commands.append(f"act_prof_add_member_to_group {ECMP_SELECTOR} {member_handle} {group_handle}")
+                    member_handle += 1
+                cli_commands.append(f"table_indirect_add_with_group MyIngress.ecmp_group {group_id} => {group_handle}")
+                cli_commands.append(f"table_add MyIngress.ipv4_lpm MyIngress.set_ecmp_group {ip}/{prefix_len} => {group_id}")
+                group_handle += 1
+                continue
+            
             # BMv2 CLIコマンドを生成
-            cmd = f"table_add MyIngress.ipv4_lpm MyIngress.ipv4_forward {ip}/{prefix_len} => {info['mac']} {info['port']}"
+            table_name, _, action_name = self.lpm_table(ip)
+            cmd = f"table_add {table_name} {action_name} {ip}/{prefix_len} => {info['mac']} {info['port']}"
             cli_commands.append(cmd)
         
         # CLIコマンドをファイルに書き込み
@@ -158,6 +258,13 @@ class IPForwardingController:
         }
         print(f"Added route: {network} -> port {port}, MAC {mac}")
     
+    def add_ecmp_route(self, network: str, next_hops: List[Tuple[int, str]]):
+        """複数のネクストホップ (port, mac) に負荷分散するルートを追加"""
+        self.routing_table[network] = {
+            "next_hops": [{"port": port, "mac": mac} for port, mac in next_hops]
+        }
+        print(f"Added ECMP route: {network} -> {len(next_hops)} next hops")
+    
     def remove_route(self, network: str):
         """ルートを削除"""
         if network in self.routing_table:
@@ -170,7 +277,8 @@ class IPForwardingController:
         """ルーティングテーブルを表示"""
         print("\n=== Routing Table ===")
         for network, info in self.routing_table.items():
-            print(f"{network:15} -> port {info['port']}, MAC {info['mac']}")
+            hops = ", ".join(f"port {h['port']}, MAC {h['mac']}" for h in self.next_hops(info))
+            print(f"{network:15} -> {hops}")
         print("====================\n")
 
 def main():
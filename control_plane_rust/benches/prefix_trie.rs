@@ -0,0 +1,80 @@
+//! 線形探索とプレフィックストライの最長プレフィックスマッチの比較
+//!
+//! `cargo bench --features bench --bench prefix_trie` で実行する。
+
+use ipnet::Ipv4Net;
+use p4_controller::prefix_trie::PrefixTrie;
+use p4_controller::test_support::Rng;
+use std::hint::black_box;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+const LOOKUPS: usize = 100_000;
+
+/// /8〜/32のランダムなプレフィックス
+fn random_prefixes(rng: &mut Rng, count: usize) -> Vec<Ipv4Net> {
+    (0..count)
+        .map(|_| {
+            let prefix_len = 8 + (rng.next_u32() % 25) as u8;
+            Ipv4Net::new_assert(Ipv4Addr::from(rng.next_u32()), prefix_len).trunc()
+        })
+        .collect()
+}
+
+/// これまでの実装と同じ、マスクを作って全エントリを比較する検索
+fn linear_lookup(prefixes: &[(Ipv4Net, usize)], dst: u32) -> Option<usize> {
+    let mut best: Option<(u8, usize)> = None;
+    for (prefix, value) in prefixes {
+        let prefix_len = prefix.prefix_len();
+        let mask = if prefix_len == 0 { 0 } else { !((1u32 << (32 - prefix_len)) - 1) };
+        if u32::from(prefix.network()) & mask == dst & mask && best.is_none_or(|(len, _)| prefix_len >= len) {
+            best = Some((prefix_len, *value));
+        }
+    }
+    best.map(|(_, value)| value)
+}
+
+fn time(lookups: &[u32], mut lookup: impl FnMut(u32) -> Option<usize>) -> Duration {
+    let start = Instant::now();
+    for dst in lookups {
+        black_box(lookup(black_box(*dst)));
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let mut rng = Rng::default();
+    let lookups: Vec<u32> = (0..LOOKUPS).map(|_| rng.next_u32()).collect();
+    
+    println!("{:>8} {:>14} {:>14} {:>14} {:>10}", "Prefixes", "Insert", "Linear/lookup", "Trie/lookup", "Speedup");
+    for count in [100, 1_000, 10_000, 50_000] {
+        let prefixes: Vec<(Ipv4Net, usize)> = random_prefixes(&mut rng, count).into_iter().zip(0..).collect();
+        
+        let start = Instant::now();
+        let trie: PrefixTrie<Ipv4Net, usize> = prefixes.iter().copied().collect();
+        let insert = start.elapsed();
+        
+        // 結果が一致することを確かめてから計測する
+        for dst in lookups.iter().take(1_000) {
+            let expected = linear_lookup(&prefixes, *dst).map(|value| prefixes[value].0);
+            let actual = trie.longest_match(&Ipv4Net::from(Ipv4Addr::from(*dst))).map(|(prefix, _)| prefix);
+            assert_eq!(expected, actual, "lookup mismatch for {}", Ipv4Addr::from(*dst));
+        }
+        
+        // 線形探索は遅いので、エントリ数に応じて検索回数を減らす
+        let linear_lookups = &lookups[..(LOOKUPS * 100 / count).min(LOOKUPS)];
+        let linear = time(linear_lookups, |dst| linear_lookup(&prefixes, dst)) / linear_lookups.len() as u32;
+        let trie_time = time(&lookups, |dst| {
+            trie.longest_match(&Ipv4Net::from(Ipv4Addr::from(dst))).map(|(_, value)| *value)
+        }) / LOOKUPS as u32;
+        
+        println!(
+            "{:>8} {:>14?} {:>14?} {:>14?} {:>9.1}x",
+            count,
+            insert,
+            linear,
+            trie_time,
+            linear.as_secs_f64() / trie_time.as_secs_f64()
+        );
+    }
+}
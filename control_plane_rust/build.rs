@@ -0,0 +1,25 @@
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // システムにprotocが無くてもビルドできるよう、ベンダリング版を使用
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    
+    // google/protobuf/any.proto などの標準protoはprotoc付属のものを使う
+    let includes = [
+        PathBuf::from("proto"),
+        protoc_bin_vendored::include_path()?,
+    ];
+    
+    // テキスト形式のP4Infoを読み込むためにディスクリプタも出力する
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    
+    tonic_build::configure()
+        .build_server(false)
+        .file_descriptor_set_path(out_dir.join("p4runtime_descriptor.bin"))
+        .compile(&["proto/p4/v1/p4runtime.proto"], &includes)?;
+    
+    println!("cargo:rerun-if-changed=proto");
+    Ok(())
+}
@@ -0,0 +1,489 @@
+use crate::table_manager::canonicalize_action;
+use crate::types::*;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// デバイスごとのアクションプロファイルのメンバーとグループ
+#[derive(Debug, Clone, Default)]
+struct DeviceProfiles {
+    members: Vec<ActionProfileMember>,
+    groups: Vec<ActionProfileGroup>,
+}
+
+impl DeviceProfiles {
+    /// どのグループからも参照されていないメンバーかどうか
+    fn is_unreferenced(&self, profile: &str, member_id: u32) -> bool {
+        !self
+            .groups
+            .iter()
+            .any(|g| g.profile == profile && g.members.iter().any(|m| m.member_id == member_id))
+    }
+}
+
+/// グループの変更に伴ってデバイスに送る更新
+///
+/// 1つのWriteRequest内の更新の順序は保証されないため、メンバーのINSERT、グループ、
+/// グループを参照するテーブルエントリ、メンバーのDELETEの順に別々に送る必要がある。
+/// マネージャーの状態はまだ変えていないので、書き込めた更新を [`ActionProfileManager::commit`] で反映する。
+#[derive(Debug, Clone, Default)]
+pub struct ActionProfileChanges {
+    /// グループより先に送るメンバーのINSERT
+    pub member_inserts: Vec<EntityUpdate>,
+    /// グループのINSERT / MODIFY / DELETE（変更がなければNone）
+    pub group_update: Option<EntityUpdate>,
+    /// グループの後に送る、どのグループからも参照されなくなったメンバーのDELETE
+    pub member_deletes: Vec<EntityUpdate>,
+}
+
+impl ActionProfileChanges {
+    /// 送る更新がないかどうか
+    pub fn is_empty(&self) -> bool {
+        self.member_inserts.is_empty() && self.group_update.is_none() && self.member_deletes.is_empty()
+    }
+}
+
+/// アクションプロファイルのメンバー・グループマネージャー
+///
+/// デバイスに書き込んだメンバーとグループを保持し、同じアクションのメンバーはグループ間で共有する。
+/// 変更は更新として返すだけで、デバイスへの書き込みが成功した更新だけを `commit` で状態に反映する。
+#[derive(Debug)]
+pub struct ActionProfileManager {
+    /// デバイスごとのメンバーとグループ
+    device_profiles: Arc<RwLock<HashMap<DeviceId, DeviceProfiles>>>,
+    /// デバイスごとのP4Info
+    p4infos: Arc<RwLock<HashMap<DeviceId, P4Info>>>,
+}
+
+impl ActionProfileManager {
+    pub fn new() -> Self {
+        Self {
+            device_profiles: Arc::new(RwLock::new(HashMap::new())),
+            p4infos: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+    
+    /// デバイスのP4Infoを設定（プロファイルとアクションの検証に使う）
+    pub async fn set_device_p4info(&self, device_id: DeviceId, p4info: P4Info) {
+        let mut p4infos = self.p4infos.write().await;
+        p4infos.insert(device_id, p4info);
+    }
+    
+    /// デバイスを削除
+    pub async fn remove_device(&self, device_id: DeviceId) {
+        self.device_profiles.write().await.remove(&device_id);
+        self.p4infos.write().await.remove(&device_id);
+    }
+    
+    /// デバイスから読み戻したメンバーとグループで、デバイスの状態を置き換える
+    ///
+    /// 再接続したデバイスに残っているIDと衝突しないように、書き込みを始める前に呼ぶ。
+    pub async fn load_device_profiles(
+        &self,
+        device_id: DeviceId,
+        members: Vec<ActionProfileMember>,
+        groups: Vec<ActionProfileGroup>,
+    ) {
+        tracing::info!(
+            "Loaded {} action profile members and {} groups from device {}",
+            members.len(),
+            groups.len(),
+            device_id
+        );
+        let mut device_profiles = self.device_profiles.write().await;
+        device_profiles.insert(device_id, DeviceProfiles { members, groups });
+    }
+    
+    /// P4Infoが読み込まれていれば、プロファイル名を完全修飾名に揃える（読み戻した名前と比べられるように）
+    async fn profile_name(&self, device_id: DeviceId, profile: &str) -> String {
+        let p4infos = self.p4infos.read().await;
+        p4infos
+            .get(&device_id)
+            .and_then(|p4info| p4info.find_action_profile(profile))
+            .map_or_else(|| profile.to_string(), |info| info.name.clone())
+    }
+    
+    /// プロファイルのグループID
+    pub async fn group_ids(&self, device_id: DeviceId, profile: &str) -> Vec<u32> {
+        let profile = self.profile_name(device_id, profile).await;
+        let device_profiles = self.device_profiles.read().await;
+        device_profiles
+            .get(&device_id)
+            .map(|p| p.groups.iter().filter(|g| g.profile == profile).map(|g| g.group_id).collect())
+            .unwrap_or_default()
+    }
+    
+    /// プロファイルで使われていない最小のグループID（1から）
+    pub async fn next_group_id(&self, device_id: DeviceId, profile: &str) -> u32 {
+        let used = self.group_ids(device_id, profile).await;
+        (1..).find(|id| !used.contains(id)).unwrap_or(1)
+    }
+    
+    /// グループのメンバーをactionsに合わせ、デバイスに送るべき更新を返す
+    ///
+    /// 新しいグループならINSERT、メンバーが変われば MODIFY、同じなら更新なし。
+    /// 同じアクションのメンバーがあれば再利用し、なければ新しいメンバーを作る。
+    /// 状態は変えないので、書き込めた更新を `commit` で反映する。
+    pub async fn set_group(
+        &self,
+        device_id: DeviceId,
+        profile: &str,
+        group_id: u32,
+        actions: &[ActionCall],
+    ) -> Result<ActionProfileChanges> {
+        if actions.is_empty() {
+            return Err(P4RuntimeError::InvalidTableEntry(format!("Group {} in {} has no members", group_id, profile)).into());
+        }
+        
+        // P4Infoが読み込まれていればプロファイルを検証し、パラメータを正規形に揃える
+        let mut actions = actions.to_vec();
+        if let Some(p4info) = self.p4infos.read().await.get(&device_id) {
+            let info = p4info
+                .find_action_profile(profile)
+                .ok_or_else(|| P4RuntimeError::InvalidTableEntry(format!("Unknown action profile {}", profile)))?;
+            if !info.with_selector {
+                return Err(P4RuntimeError::InvalidTableEntry(format!("Action profile {} has no selector", profile)).into());
+            }
+            if info.max_group_size > 0 && actions.len() > info.max_group_size as usize {
+                return Err(P4RuntimeError::InvalidTableEntry(format!(
+                    "Group {} has {} members but {} allows {}",
+                    group_id,
+                    actions.len(),
+                    profile,
+                    info.max_group_size
+                ))
+                .into());
+            }
+            for action in &mut actions {
+                canonicalize_action(p4info, action)?;
+            }
+        }
+        let profile = self.profile_name(device_id, profile).await;
+        let profile = profile.as_str();
+        
+        let mut profiles = self.device_profiles.read().await.get(&device_id).cloned().unwrap_or_default();
+        let mut changes = ActionProfileChanges::default();
+        
+        // アクションごとにメンバーを探し、なければ作る
+        let mut members: Vec<GroupMember> = Vec::new();
+        for action in &actions {
+            let existing = profiles
+                .members
+                .iter()
+                .find(|m| m.profile == profile && m.action.is_equivalent(action))
+                .map(|m| m.member_id);
+            let member_id = match existing {
+                Some(member_id) => member_id,
+                None => {
+                    let member_id = profiles
+                        .members
+                        .iter()
+                        .filter(|m| m.profile == profile)
+                        .map(|m| m.member_id)
+                        .max()
+                        .unwrap_or(0)
+                        + 1;
+                    let member = ActionProfileMember {
+                        profile: profile.to_string(),
+                        member_id,
+                        action: action.clone(),
+                    };
+                    profiles.members.push(member.clone());
+                    changes.member_inserts.push(EntityUpdate::insert(Entity::ActionProfileMember(member)));
+                    member_id
+                }
+            };
+            if !members.iter().any(|m| m.member_id == member_id) {
+                members.push(GroupMember { member_id, weight: 1 });
+            }
+        }
+        
+        let group = ActionProfileGroup {
+            profile: profile.to_string(),
+            group_id,
+            members,
+            max_size: 0,
+        };
+        
+        let previous = match profiles.groups.iter().position(|g| g.profile == profile && g.group_id == group_id) {
+            Some(index) if profiles.groups[index] == group => {
+                tracing::debug!("Group {} in {} on device {} is unchanged", group_id, profile, device_id);
+                return Ok(changes);
+            }
+            Some(index) => {
+                changes.group_update = Some(EntityUpdate::modify(Entity::ActionProfileGroup(group.clone())));
+                Some(std::mem::replace(&mut profiles.groups[index], group))
+            }
+            None => {
+                changes.group_update = Some(EntityUpdate::insert(Entity::ActionProfileGroup(group.clone())));
+                profiles.groups.push(group);
+                None
+            }
+        };
+        
+        // グループから外れて使われなくなったメンバーを削除
+        if let Some(previous) = previous {
+            changes.member_deletes = remove_unreferenced_members(&mut profiles, profile, &previous.members);
+        }
+        
+        Ok(changes)
+    }
+    
+    /// グループを削除するためにデバイスに送るべき更新を返す（どのグループからも参照されなくなるメンバーも削除）
+    ///
+    /// 状態は変えないので、書き込めた更新を `commit` で反映する。
+    pub async fn remove_group(&self, device_id: DeviceId, profile: &str, group_id: u32) -> ActionProfileChanges {
+        let profile = self.profile_name(device_id, profile).await;
+        let profile = profile.as_str();
+        let mut changes = ActionProfileChanges::default();
+        let Some(mut profiles) = self.device_profiles.read().await.get(&device_id).cloned() else {
+            return changes;
+        };
+        let Some(index) = profiles.groups.iter().position(|g| g.profile == profile && g.group_id == group_id) else {
+            tracing::warn!("Group {} not found in {} on device {}", group_id, profile, device_id);
+            return changes;
+        };
+        
+        let group = profiles.groups.remove(index);
+        changes.member_deletes = remove_unreferenced_members(&mut profiles, profile, &group.members);
+        changes.group_update = Some(EntityUpdate::delete(Entity::ActionProfileGroup(group)));
+        changes
+    }
+    
+    /// どのグループからも参照されていないメンバーを削除するためにデバイスに送るべき更新を返す
+    ///
+    /// 状態は変えないので、書き込めた更新を `commit` で反映する。
+    pub async fn remove_unreferenced_members(&self, device_id: DeviceId, profile: &str) -> ActionProfileChanges {
+        let profile = self.profile_name(device_id, profile).await;
+        let mut changes = ActionProfileChanges::default();
+        let Some(mut profiles) = self.device_profiles.read().await.get(&device_id).cloned() else {
+            return changes;
+        };
+        
+        let candidates: Vec<GroupMember> = profiles
+            .members
+            .iter()
+            .filter(|m| m.profile == profile)
+            .map(|m| GroupMember { member_id: m.member_id, weight: 1 })
+            .collect();
+        changes.member_deletes = remove_unreferenced_members(&mut profiles, &profile, &candidates);
+        changes
+    }
+    
+    /// デバイスに書き込めたメンバー・グループの更新を状態に反映する
+    ///
+    /// 書き込めなかった更新は渡さないことで、状態をデバイスと揃えたままにする。
+    pub async fn commit(&self, device_id: DeviceId, updates: &[EntityUpdate]) {
+        let mut device_profiles = self.device_profiles.write().await;
+        let profiles = device_profiles.entry(device_id).or_default();
+        for update in updates {
+            match (&update.update_type, &update.entity) {
+                (UpdateType::Delete, Entity::ActionProfileMember(member)) => {
+                    profiles
+                        .members
+                        .retain(|m| !(m.profile == member.profile && m.member_id == member.member_id));
+                }
+                (_, Entity::ActionProfileMember(member)) => {
+                    profiles
+                        .members
+                        .retain(|m| !(m.profile == member.profile && m.member_id == member.member_id));
+                    profiles.members.push(member.clone());
+                }
+                (UpdateType::Delete, Entity::ActionProfileGroup(group)) => {
+                    profiles
+                        .groups
+                        .retain(|g| !(g.profile == group.profile && g.group_id == group.group_id));
+                    tracing::info!("Removed group {} from {} on device {}", group.group_id, group.profile, device_id);
+                }
+                (_, Entity::ActionProfileGroup(group)) => {
+                    match profiles
+                        .groups
+                        .iter_mut()
+                        .find(|g| g.profile == group.profile && g.group_id == group.group_id)
+                    {
+                        Some(existing) => {
+                            *existing = group.clone();
+                            tracing::info!("Updated group {} in {} on device {}", group.group_id, group.profile, device_id);
+                        }
+                        None => {
+                            profiles.groups.push(group.clone());
+                            tracing::info!("Added group {} to {} on device {}", group.group_id, group.profile, device_id);
+                        }
+                    }
+                }
+                (_, Entity::TableEntry(_)) => {}
+            }
+        }
+    }
+    
+    /// グループを取得
+    pub async fn get_group(&self, device_id: DeviceId, profile: &str, group_id: u32) -> Option<ActionProfileGroup> {
+        let profile = self.profile_name(device_id, profile).await;
+        let device_profiles = self.device_profiles.read().await;
+        device_profiles
+            .get(&device_id)?
+            .groups
+            .iter()
+            .find(|g| g.profile == profile && g.group_id == group_id)
+            .cloned()
+    }
+    
+    /// デバイスの全グループを取得
+    pub async fn get_groups(&self, device_id: DeviceId) -> Vec<ActionProfileGroup> {
+        let device_profiles = self.device_profiles.read().await;
+        device_profiles.get(&device_id).map(|p| p.groups.clone()).unwrap_or_default()
+    }
+    
+    /// デバイスの全メンバーを取得
+    pub async fn get_members(&self, device_id: DeviceId) -> Vec<ActionProfileMember> {
+        let device_profiles = self.device_profiles.read().await;
+        device_profiles.get(&device_id).map(|p| p.members.clone()).unwrap_or_default()
+    }
+}
+
+impl Default for ActionProfileManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// candidatesのうち、どのグループからも参照されなくなったメンバーを削除してDELETEを返す
+fn remove_unreferenced_members(
+    profiles: &mut DeviceProfiles,
+    profile: &str,
+    candidates: &[GroupMember],
+) -> Vec<EntityUpdate> {
+    let mut deletes = Vec::new();
+    for candidate in candidates {
+        if !profiles.is_unreferenced(profile, candidate.member_id) {
+            continue;
+        }
+        if let Some(index) = profiles
+            .members
+            .iter()
+            .position(|m| m.profile == profile && m.member_id == candidate.member_id)
+        {
+            let member = profiles.members.remove(index);
+            deletes.push(EntityUpdate::delete(Entity::ActionProfileMember(member)));
+        }
+    }
+    deletes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const PROFILE: &str = "MyIngress.ecmp_selector";
+    
+    fn forward(port: u8) -> ActionCall {
+        ActionCall::new("MyIngress.ipv4_forward").with_param("port", vec![0, port])
+    }
+    
+    /// 変更をすべてデバイスに書き込めたものとして反映する
+    async fn commit_all(manager: &ActionProfileManager, device_id: DeviceId, changes: &ActionProfileChanges) {
+        manager.commit(device_id, &changes.member_inserts).await;
+        manager.commit(device_id, changes.group_update.as_slice()).await;
+        manager.commit(device_id, &changes.member_deletes).await;
+    }
+    
+    fn member_ids(updates: &[EntityUpdate]) -> Vec<u32> {
+        updates
+            .iter()
+            .filter_map(|u| match &u.entity {
+                Entity::ActionProfileMember(member) => Some(member.member_id),
+                _ => None,
+            })
+            .collect()
+    }
+    
+    fn group_member_ids(group: &ActionProfileGroup) -> Vec<u32> {
+        group.members.iter().map(|m| m.member_id).collect()
+    }
+    
+    #[tokio::test]
+    async fn groups_share_members_with_the_same_action() {
+        let manager = ActionProfileManager::new();
+        
+        let changes = manager.set_group(1, PROFILE, 1, &[forward(1), forward(2)]).await.unwrap();
+        assert_eq!(member_ids(&changes.member_inserts), vec![1, 2]);
+        commit_all(&manager, 1, &changes).await;
+        
+        // port 2のメンバーは再利用し、port 3のメンバーだけを作る
+        let changes = manager.set_group(1, PROFILE, 2, &[forward(2), forward(3)]).await.unwrap();
+        assert_eq!(member_ids(&changes.member_inserts), vec![3]);
+        assert_eq!(changes.group_update.as_ref().unwrap().update_type, UpdateType::Insert);
+        commit_all(&manager, 1, &changes).await;
+        
+        let group = manager.get_group(1, PROFILE, 2).await.unwrap();
+        assert_eq!(group_member_ids(&group), vec![2, 3]);
+        assert_eq!(manager.get_members(1).await.len(), 3);
+        
+        // 同じメンバーならデバイスに送る更新はない
+        let changes = manager.set_group(1, PROFILE, 2, &[forward(2), forward(3)]).await.unwrap();
+        assert!(changes.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn only_unreferenced_members_are_deleted() {
+        let manager = ActionProfileManager::new();
+        let changes = manager.set_group(1, PROFILE, 1, &[forward(1), forward(2)]).await.unwrap();
+        commit_all(&manager, 1, &changes).await;
+        let changes = manager.set_group(1, PROFILE, 2, &[forward(2), forward(3)]).await.unwrap();
+        commit_all(&manager, 1, &changes).await;
+        
+        // グループ1を削除しても、グループ2が使うport 2のメンバーは残す
+        let changes = manager.remove_group(1, PROFILE, 1).await;
+        assert_eq!(changes.group_update.as_ref().unwrap().update_type, UpdateType::Delete);
+        assert_eq!(member_ids(&changes.member_deletes), vec![1]);
+        commit_all(&manager, 1, &changes).await;
+        assert_eq!(manager.group_ids(1, PROFILE).await, vec![2]);
+        
+        // グループ2から外れたport 2のメンバーは使われなくなる
+        let changes = manager.set_group(1, PROFILE, 2, &[forward(3)]).await.unwrap();
+        assert_eq!(changes.group_update.as_ref().unwrap().update_type, UpdateType::Modify);
+        assert_eq!(member_ids(&changes.member_deletes), vec![2]);
+        commit_all(&manager, 1, &changes).await;
+        
+        let members: Vec<u32> = manager.get_members(1).await.iter().map(|m| m.member_id).collect();
+        assert_eq!(members, vec![3]);
+        assert!(manager.remove_unreferenced_members(1, PROFILE).await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn changes_are_not_applied_until_committed() {
+        let manager = ActionProfileManager::new();
+        
+        // 書き込みに失敗して反映しなければ、次の変更も同じIDで作り直す
+        let changes = manager.set_group(1, PROFILE, 1, &[forward(1)]).await.unwrap();
+        assert!(manager.get_group(1, PROFILE, 1).await.is_none());
+        assert!(manager.get_members(1).await.is_empty());
+        let retried = manager.set_group(1, PROFILE, 1, &[forward(1)]).await.unwrap();
+        assert_eq!(member_ids(&retried.member_inserts), member_ids(&changes.member_inserts));
+        commit_all(&manager, 1, &retried).await;
+        
+        // グループのMODIFYに失敗した場合は、書き込めたメンバーのINSERTだけを反映する
+        let changes = manager.set_group(1, PROFILE, 1, &[forward(2)]).await.unwrap();
+        manager.commit(1, &changes.member_inserts).await;
+        let group = manager.get_group(1, PROFILE, 1).await.unwrap();
+        assert_eq!(group_member_ids(&group), vec![1]);
+        assert_eq!(manager.get_members(1).await.len(), 2);
+        
+        // どのグループからも使われていない新しいメンバーは後で削除できる
+        let changes = manager.remove_unreferenced_members(1, PROFILE).await;
+        assert_eq!(member_ids(&changes.member_deletes), vec![2]);
+        
+        // グループのDELETEに失敗した場合は、グループとメンバーを残す
+        let changes = manager.remove_group(1, PROFILE, 1).await;
+        assert_eq!(member_ids(&changes.member_deletes), vec![1]);
+        assert_eq!(manager.group_ids(1, PROFILE).await, vec![1]);
+        assert_eq!(manager.get_members(1).await.len(), 2);
+    }
+    
+    #[tokio::test]
+    async fn empty_group_is_rejected() {
+        let manager = ActionProfileManager::new();
+        assert!(manager.set_group(1, PROFILE, 1, &[]).await.is_err());
+    }
+}
@@ -0,0 +1,455 @@
+use crate::types::*;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// ARPのEtherType
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+/// IPv4のEtherType
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+
+const ARP_HTYPE_ETHERNET: u16 = 1;
+const ARP_OP_REQUEST: u16 = 1;
+const ARP_OP_REPLY: u16 = 2;
+/// イーサネットヘッダー（14バイト）+ ARP（28バイト）
+const ARP_FRAME_LEN: usize = 42;
+
+/// ARPの操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOperation {
+    Request,
+    Reply,
+}
+
+/// イーサネットフレームから取り出したARPパケット
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: ArpOperation,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Address,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Address,
+}
+
+impl ArpPacket {
+    /// ブロードキャストのARPリクエストを作成
+    pub fn request(sender_mac: MacAddress, sender_ip: Ipv4Address, target_ip: Ipv4Address) -> Self {
+        Self {
+            operation: ArpOperation::Request,
+            sender_mac,
+            sender_ip,
+            target_mac: MacAddress::new([0; 6]),
+            target_ip,
+        }
+    }
+    
+    /// イーサネットフレームに変換（リクエストはブロードキャスト宛て）
+    pub fn to_frame(&self) -> Vec<u8> {
+        let (dst_mac, op) = match self.operation {
+            ArpOperation::Request => ([0xff; 6], ARP_OP_REQUEST),
+            ArpOperation::Reply => (*self.target_mac.as_bytes(), ARP_OP_REPLY),
+        };
+        
+        let mut frame = Vec::with_capacity(ARP_FRAME_LEN);
+        frame.extend_from_slice(&dst_mac);
+        frame.extend_from_slice(self.sender_mac.as_bytes());
+        frame.extend_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+        frame.extend_from_slice(&ARP_HTYPE_ETHERNET.to_be_bytes());
+        frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        frame.push(6);
+        frame.push(4);
+        frame.extend_from_slice(&op.to_be_bytes());
+        frame.extend_from_slice(self.sender_mac.as_bytes());
+        frame.extend_from_slice(&self.sender_ip.as_u32().to_be_bytes());
+        frame.extend_from_slice(self.target_mac.as_bytes());
+        frame.extend_from_slice(&self.target_ip.as_u32().to_be_bytes());
+        frame
+    }
+    
+    /// イーサネットフレームをパース（IPv4 over EthernetのARPでなければNone）
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.len() < ARP_FRAME_LEN {
+            return None;
+        }
+        
+        let u16_at = |offset: usize| u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+        let mac_at = |offset: usize| {
+            let mut mac = [0u8; 6];
+            mac.copy_from_slice(&frame[offset..offset + 6]);
+            MacAddress::new(mac)
+        };
+        let ip_at = |offset: usize| {
+            Ipv4Address::from_u32(u32::from_be_bytes([
+                frame[offset],
+                frame[offset + 1],
+                frame[offset + 2],
+                frame[offset + 3],
+            ]))
+        };
+        
+        if u16_at(12) != ETHERTYPE_ARP
+            || u16_at(14) != ARP_HTYPE_ETHERNET
+            || u16_at(16) != ETHERTYPE_IPV4
+            || frame[18] != 6
+            || frame[19] != 4
+        {
+            return None;
+        }
+        
+        let operation = match u16_at(20) {
+            ARP_OP_REQUEST => ArpOperation::Request,
+            ARP_OP_REPLY => ArpOperation::Reply,
+            _ => return None,
+        };
+        
+        Some(Self {
+            operation,
+            sender_mac: mac_at(22),
+            sender_ip: ip_at(28),
+            target_mac: mac_at(32),
+            target_ip: ip_at(38),
+        })
+    }
+}
+
+/// ARP / NDPによるネクストホップ解決のリトライ設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpResolverConfig {
+    /// 応答がない場合に送るリクエストの最大数
+    pub max_attempts: u32,
+    /// 最初のリトライまでの待ち時間（以降は倍々に延ばす）
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// 応答しなかったネクストホップへのリクエストを止めておく時間
+    pub negative_cache_ttl: Duration,
+}
+
+impl Default for ArpResolverConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(8),
+            negative_cache_ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+/// 動的ARPエントリのエージング設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpAgingConfig {
+    /// この時間確認できなかったエントリを再確認（ARPリクエストを送信）する
+    pub reachable_time: Duration,
+    /// 再確認を始めてから応答がなければ削除するまでの時間
+    pub probe_time: Duration,
+    /// エージングを行う間隔
+    pub interval: Duration,
+}
+
+impl Default for ArpAgingConfig {
+    fn default() -> Self {
+        Self {
+            reachable_time: Duration::from_secs(300),
+            probe_time: Duration::from_secs(15),
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// 解決待ちのネクストホップ
+#[derive(Debug, Clone)]
+struct PendingResolution {
+    interface: String,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+/// `NeighborResolver::poll` の結果
+#[derive(Debug, Clone)]
+pub struct NeighborPoll<A> {
+    /// リクエストを送るネクストホップとインターフェース
+    pub requests: Vec<(A, String)>,
+    /// リトライを使い切ってネガティブキャッシュに入れたネクストホップ
+    pub failed: Vec<A>,
+}
+
+impl<A> Default for NeighborPoll<A> {
+    fn default() -> Self {
+        Self {
+            requests: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+}
+
+/// `ArpResolver::poll` の結果
+pub type ArpPoll = NeighborPoll<Ipv4Address>;
+
+/// 未解決のネクストホップの解決（IPv4はARP、IPv6はNDP）を管理
+///
+/// パケットの送受信は行わず、いつ誰にリクエストを送るかだけを決める。
+#[derive(Debug)]
+pub struct NeighborResolver<A> {
+    config: ArpResolverConfig,
+    pending: Arc<RwLock<HashMap<A, PendingResolution>>>,
+    /// 応答しなかったネクストホップと、再び解決を試みてよい時刻
+    negative_cache: Arc<RwLock<HashMap<A, Instant>>>,
+}
+
+/// IPv4のネクストホップをARPで解決する
+pub type ArpResolver = NeighborResolver<Ipv4Address>;
+/// IPv6のネクストホップをNDP（近隣要請）で解決する
+pub type NdpResolver = NeighborResolver<Ipv6Address>;
+
+impl<A: Copy + Eq + Hash + Display> NeighborResolver<A> {
+    pub fn new(config: ArpResolverConfig) -> Self {
+        Self {
+            config,
+            pending: Arc::new(RwLock::new(HashMap::new())),
+            negative_cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+    
+    /// ネクストホップの解決を要求（すでに解決待ち、またはネガティブキャッシュ中ならfalse）
+    pub async fn request(&self, ip: A, interface: &str) -> bool {
+        let now = Instant::now();
+        {
+            let mut negative_cache = self.negative_cache.write().await;
+            match negative_cache.get(&ip) {
+                Some(expires) if *expires > now => return false,
+                Some(_) => {
+                    negative_cache.remove(&ip);
+                }
+                None => {}
+            }
+        }
+        
+        let mut pending = self.pending.write().await;
+        if pending.contains_key(&ip) {
+            return false;
+        }
+        
+        tracing::info!("Resolving next hop {} on {}", ip, interface);
+        pending.insert(
+            ip,
+            PendingResolution {
+                interface: interface.to_string(),
+                attempts: 0,
+                next_attempt: now,
+            },
+        );
+        true
+    }
+    
+    /// 送信時刻になったリクエストを取り出し、次の送信時刻をバックオフで延ばす
+    pub async fn poll(&self) -> NeighborPoll<A> {
+        let now = Instant::now();
+        let mut result = NeighborPoll::default();
+        let mut pending = self.pending.write().await;
+        
+        for (ip, resolution) in pending.iter_mut() {
+            if resolution.next_attempt > now {
+                continue;
+            }
+            if resolution.attempts >= self.config.max_attempts {
+                result.failed.push(*ip);
+                continue;
+            }
+            
+            resolution.attempts += 1;
+            resolution.next_attempt = now + self.backoff(resolution.attempts);
+            result.requests.push((*ip, resolution.interface.clone()));
+        }
+        
+        if !result.failed.is_empty() {
+            let mut negative_cache = self.negative_cache.write().await;
+            for ip in &result.failed {
+                pending.remove(ip);
+                negative_cache.insert(*ip, now + self.config.negative_cache_ttl);
+                tracing::warn!(
+                    "Next hop {} did not answer {} resolution requests; suppressing for {:?}",
+                    ip,
+                    self.config.max_attempts,
+                    self.config.negative_cache_ttl
+                );
+            }
+        }
+        
+        result
+    }
+    
+    /// 応答を受け取ったネクストホップを解決済みにする（解決待ちだったらtrue）
+    pub async fn resolved(&self, ip: A) -> bool {
+        self.negative_cache.write().await.remove(&ip);
+        self.pending.write().await.remove(&ip).is_some()
+    }
+    
+    /// 不要になったネクストホップの解決をやめる
+    pub async fn cancel(&self, ip: A) {
+        self.pending.write().await.remove(&ip);
+    }
+    
+    /// 解決待ちかどうか
+    pub async fn is_pending(&self, ip: A) -> bool {
+        self.pending.read().await.contains_key(&ip)
+    }
+    
+    /// ネガティブキャッシュに入っているかどうか
+    pub async fn is_negative(&self, ip: A) -> bool {
+        let negative_cache = self.negative_cache.read().await;
+        matches!(negative_cache.get(&ip), Some(expires) if *expires > Instant::now())
+    }
+    
+    /// 解決待ちのネクストホップ一覧
+    pub async fn pending_next_hops(&self) -> Vec<A> {
+        self.pending.read().await.keys().copied().collect()
+    }
+    
+    /// n回目のリクエストの後、次のリクエストまでの待ち時間
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u32 << attempts.saturating_sub(1).min(16);
+        self.config.initial_backoff.saturating_mul(factor).min(self.config.max_backoff)
+    }
+}
+
+impl<A: Copy + Eq + Hash + Display> Default for NeighborResolver<A> {
+    fn default() -> Self {
+        Self::new(ArpResolverConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn ip(addr: [u8; 4]) -> Ipv4Address {
+        Ipv4Address::new(addr.into())
+    }
+    
+    const SENDER_MAC: [u8; 6] = [0x08, 0x00, 0x00, 0x00, 0x00, 0x01];
+    const TARGET_MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    
+    fn resolver(max_attempts: u32, backoff: Duration, negative_cache_ttl: Duration) -> ArpResolver {
+        ArpResolver::new(ArpResolverConfig {
+            max_attempts,
+            initial_backoff: backoff,
+            max_backoff: backoff,
+            negative_cache_ttl,
+        })
+    }
+    
+    #[test]
+    fn request_frame_round_trips() {
+        let request = ArpPacket::request(MacAddress::new(SENDER_MAC), ip([192, 168, 1, 10]), ip([192, 168, 1, 1]));
+        let frame = request.to_frame();
+        
+        assert_eq!(frame.len(), ARP_FRAME_LEN);
+        assert_eq!(&frame[..6], &[0xff; 6]);
+        assert_eq!(&frame[6..12], &SENDER_MAC);
+        assert_eq!(ArpPacket::parse(&frame), Some(request));
+    }
+    
+    #[test]
+    fn reply_frame_is_sent_to_the_target_and_round_trips() {
+        let reply = ArpPacket {
+            operation: ArpOperation::Reply,
+            sender_mac: MacAddress::new(TARGET_MAC),
+            sender_ip: ip([192, 168, 1, 1]),
+            target_mac: MacAddress::new(SENDER_MAC),
+            target_ip: ip([192, 168, 1, 10]),
+        };
+        let mut frame = reply.to_frame();
+        assert_eq!(&frame[..6], &SENDER_MAC);
+        
+        // 最小フレーム長までのパディングは無視する
+        frame.resize(60, 0);
+        assert_eq!(ArpPacket::parse(&frame), Some(reply));
+    }
+    
+    #[test]
+    fn short_or_malformed_frames_are_rejected() {
+        let frame = ArpPacket::request(MacAddress::new(SENDER_MAC), ip([192, 168, 1, 10]), ip([192, 168, 1, 1])).to_frame();
+        assert_eq!(ArpPacket::parse(&frame[..ARP_FRAME_LEN - 1]), None);
+        assert_eq!(ArpPacket::parse(&[]), None);
+        
+        // EtherType・ハードウェアタイプ・プロトコルタイプ・アドレス長・オペコードのいずれかが違えばARPとして扱わない
+        for (offset, value) in [(13, 0x00), (15, 6), (16, 0x86), (18, 8), (19, 16), (21, 3)] {
+            let mut malformed = frame.clone();
+            malformed[offset] = value;
+            assert_eq!(ArpPacket::parse(&malformed), None, "byte {} = {:#x}", offset, value);
+        }
+    }
+    
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let resolver = ArpResolver::default();
+        let backoffs: Vec<u64> = (1..=6).map(|attempts| resolver.backoff(attempts).as_secs()).collect();
+        assert_eq!(backoffs, [1, 2, 4, 8, 8, 8]);
+        assert_eq!(resolver.backoff(u32::MAX), Duration::from_secs(8));
+    }
+    
+    #[tokio::test]
+    async fn retry_waits_for_the_backoff() {
+        let resolver = resolver(4, Duration::from_secs(60), Duration::from_secs(60));
+        assert!(resolver.request(ip([192, 168, 1, 1]), "eth1").await);
+        assert!(!resolver.request(ip([192, 168, 1, 1]), "eth1").await);
+        
+        let poll = resolver.poll().await;
+        assert_eq!(poll.requests, [(ip([192, 168, 1, 1]), "eth1".to_string())]);
+        
+        // 次の送信はバックオフの後なので、すぐにpollしても送らない
+        let poll = resolver.poll().await;
+        assert!(poll.requests.is_empty() && poll.failed.is_empty());
+        assert!(resolver.is_pending(ip([192, 168, 1, 1])).await);
+    }
+    
+    #[tokio::test]
+    async fn unanswered_next_hop_goes_to_the_negative_cache() {
+        let resolver = resolver(3, Duration::ZERO, Duration::from_secs(60));
+        resolver.request(ip([192, 168, 1, 1]), "eth1").await;
+        
+        for _ in 0..3 {
+            assert_eq!(resolver.poll().await.requests.len(), 1);
+        }
+        let poll = resolver.poll().await;
+        assert!(poll.requests.is_empty());
+        assert_eq!(poll.failed, [ip([192, 168, 1, 1])]);
+        
+        assert!(!resolver.is_pending(ip([192, 168, 1, 1])).await);
+        assert!(resolver.is_negative(ip([192, 168, 1, 1])).await);
+        assert!(!resolver.request(ip([192, 168, 1, 1]), "eth1").await);
+        
+        // 応答を受け取ればネガティブキャッシュから外れ、再び解決できる
+        assert!(!resolver.resolved(ip([192, 168, 1, 1])).await);
+        assert!(!resolver.is_negative(ip([192, 168, 1, 1])).await);
+        assert!(resolver.request(ip([192, 168, 1, 1]), "eth1").await);
+    }
+    
+    #[tokio::test]
+    async fn expired_negative_cache_entry_allows_a_new_request() {
+        let resolver = resolver(1, Duration::ZERO, Duration::ZERO);
+        resolver.request(ip([192, 168, 1, 1]), "eth1").await;
+        resolver.poll().await;
+        assert_eq!(resolver.poll().await.failed, [ip([192, 168, 1, 1])]);
+        
+        assert!(!resolver.is_negative(ip([192, 168, 1, 1])).await);
+        assert!(resolver.request(ip([192, 168, 1, 1]), "eth1").await);
+    }
+    
+    #[tokio::test]
+    async fn resolved_and_cancelled_next_hops_stop_polling() {
+        let resolver = resolver(4, Duration::ZERO, Duration::from_secs(60));
+        resolver.request(ip([192, 168, 1, 1]), "eth1").await;
+        resolver.request(ip([192, 168, 1, 2]), "eth1").await;
+        
+        assert!(resolver.resolved(ip([192, 168, 1, 1])).await);
+        resolver.cancel(ip([192, 168, 1, 2])).await;
+        
+        let poll = resolver.poll().await;
+        assert!(poll.requests.is_empty() && poll.failed.is_empty());
+        assert!(resolver.pending_next_hops().await.is_empty());
+    }
+}
@@ -0,0 +1,548 @@
+use crate::policy::{AttributeCategory, AttributeSchema, AttributeValues, PolicyRule, SERVICE_ATTRIBUTE};
+use crate::types::*;
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// 値のコードの最大のビット幅（0は属性が設定されていないことを表すので、値は255個まで）
+const MAX_CODE_WIDTH: u32 = 8;
+/// エントリを減らせる場合に、最小のビット幅より広げてみるコードのビット幅
+const EXTRA_CODE_WIDTH: u32 = 1;
+
+/// 値の集合
+type ValueSet = BTreeSet<String>;
+
+/// メタデータのビット列のうち、属性1つ分のフィールド
+///
+/// 下位 `code_width` ビットが値のコードで、その上に値の集合ごとの所属ビットが並ぶ。
+/// 所属ビットは、コードでは1つのternaryにまとめられない値の集合を条件に使うときに割り当てる。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeField {
+    /// 最下位ビットからの位置
+    pub offset: u32,
+    /// コードと所属ビットを合わせたビット幅
+    pub width: u32,
+    /// 値のコードのビット幅
+    pub code_width: u32,
+    /// 値 -> フィールド内の値（コードと所属ビット、0は属性が設定されていないことを表す）
+    pub codes: BTreeMap<String, u32>,
+    /// 所属ビットを割り当てた値の集合と、フィールド内のビット位置
+    pub value_sets: Vec<(ValueSet, u32)>,
+}
+
+impl AttributeField {
+    /// メタデータ内でこのフィールドを選ぶマスク
+    pub fn mask(&self) -> u32 {
+        (((1u64 << self.width) - 1) << self.offset) as u32
+    }
+    
+    /// 値をメタデータ内の位置にずらしたフィールドの値（未定義の値ならNone）
+    pub fn encode(&self, value: &str) -> Option<u32> {
+        self.codes.get(value).map(|code| code << self.offset)
+    }
+    
+    /// 値のいずれかに一致するternaryマッチ（未定義の値を含めばNone）
+    ///
+    /// 所属ビットがあればそのビットだけを、なければコードの最小に近い被覆をマッチにする。
+    pub fn ternary(&self, values: &[String]) -> Option<Vec<AttributeMatch>> {
+        let set: ValueSet = values.iter().cloned().collect();
+        if let Some((_, bit)) = self.value_sets.iter().find(|(value_set, _)| *value_set == set) {
+            let bit = 1 << (bit + self.offset);
+            return Some(vec![AttributeMatch::new(bit, bit)]);
+        }
+        
+        let code_mask = (1 << self.code_width) - 1;
+        let codes = set
+            .iter()
+            .map(|value| self.codes.get(value).map(|code| code & code_mask))
+            .collect::<Option<BTreeSet<u32>>>()?;
+        let used = self.codes.values().map(|code| code & code_mask).collect();
+        Some(
+            cover_codes(&codes, &used, self.code_width)
+                .into_iter()
+                .map(|(value, mask)| AttributeMatch::new(value << self.offset, mask << self.offset))
+                .collect(),
+        )
+    }
+}
+
+/// 主体・対象の属性をメタデータのビット列に割り当てた配置
+///
+/// 値のコードは、ルールの条件に使う値の集合がなるべく1つのternary（2の累乗の整列したブロック）に
+/// 収まるように振り、属性ごとに最小のビット幅とそれより少し広い幅のうちエントリが少なくなる方を選ぶ。
+/// それでも複数のエントリが必要な集合には、メタデータのビットが余る限り、エントリを多く減らせる順に所属ビットを割り当てる。
+/// フィールドは属性の名前順に下位ビットから並べる。
+/// 版のビット（`ABAC_VERSION_BIT`）はフィールドに使わず、分類エントリとポリシーのエントリの両方に入れる。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttributeLayout {
+    subject: BTreeMap<String, AttributeField>,
+    object: BTreeMap<String, AttributeField>,
+    /// エンコードの版（trueなら版のビットを立てる）
+    version: bool,
+}
+
+impl AttributeLayout {
+    /// スキーマとルールの条件から配置を作る（ビット幅に収まらなければエラー）
+    pub fn new(schema: &AttributeSchema, rules: &[PolicyRule]) -> Result<Self> {
+        Ok(Self {
+            subject: Self::assign(AttributeCategory::Subject, &schema.subject, rules)?,
+            object: Self::assign(AttributeCategory::Object, &schema.object, rules)?,
+            version: false,
+        })
+    }
+    
+    /// エンコードの版を指定した配置
+    pub fn with_version(mut self, version: bool) -> Self {
+        self.version = version;
+        self
+    }
+    
+    /// エンコードの版
+    pub fn version(&self) -> bool {
+        self.version
+    }
+    
+    /// この版のエンコードで立てる版のビット
+    pub fn version_bits(&self) -> u32 {
+        if self.version { ABAC_VERSION_BIT } else { 0 }
+    }
+    
+    /// ternaryマッチに版のビットの一致を加える（別の版でエンコードしたビット列には一致しない）
+    pub fn versioned(&self, pattern: AttributeMatch) -> AttributeMatch {
+        AttributeMatch::new(pattern.value | self.version_bits(), pattern.mask | ABAC_VERSION_BIT)
+    }
+    
+    fn assign(
+        category: AttributeCategory,
+        attributes: &AttributeValues,
+        rules: &[PolicyRule],
+    ) -> Result<BTreeMap<String, AttributeField>> {
+        let invalid = |msg: String| -> anyhow::Error { P4RuntimeError::InvalidPolicy(msg).into() };
+        
+        let mut encodings = BTreeMap::new();
+        for (attribute, values) in attributes {
+            let min_width = u32::BITS - (values.len() as u32).leading_zeros();
+            if min_width > MAX_CODE_WIDTH {
+                return Err(invalid(format!(
+                    "Too many values for {} attribute {} ({} > {})",
+                    category,
+                    attribute,
+                    values.len(),
+                    (1 << MAX_CODE_WIDTH) - 1
+                )));
+            }
+            
+            let conditions = condition_sets(category, attribute, values, rules);
+            let encode = |width| {
+                let codes = assign_codes(values, &conditions, width);
+                let cost: usize = conditions
+                    .iter()
+                    .map(|(set, count)| cover_size(&codes, set, width) * count)
+                    .sum();
+                (cost, width, codes)
+            };
+            let minimal = encode(min_width);
+            let best = (min_width + 1..=(min_width + EXTRA_CODE_WIDTH).min(MAX_CODE_WIDTH))
+                .map(encode)
+                .fold(minimal.clone(), |best, candidate| {
+                    if (candidate.0, candidate.1) < (best.0, best.1) {
+                        candidate
+                    } else {
+                        best
+                    }
+                });
+            encodings.insert(attribute.clone(), (conditions, minimal, best));
+        }
+        
+        // 広げたコードで収まらなければ、全属性を最小のビット幅に戻す
+        let total = |minimal: bool| -> u32 {
+            encodings
+                .values()
+                .map(|(_, min, best)| if minimal { min.1 } else { best.1 })
+                .sum()
+        };
+        let use_minimal = total(false) > ABAC_ENCODING_BITS;
+        if total(true) > ABAC_ENCODING_BITS {
+            return Err(invalid(format!(
+                "Too many {} attributes for {} metadata bits ({} bits needed)",
+                category,
+                ABAC_ENCODING_BITS,
+                total(true)
+            )));
+        }
+        let mut fields: BTreeMap<String, AttributeField> = encodings
+            .iter()
+            .map(|(attribute, (_, minimal, best))| {
+                let (_, code_width, codes) = if use_minimal { minimal } else { best };
+                let field = AttributeField {
+                    offset: 0,
+                    width: *code_width,
+                    code_width: *code_width,
+                    codes: codes.clone(),
+                    value_sets: Vec::new(),
+                };
+                (attribute.clone(), field)
+            })
+            .collect();
+        
+        // 余ったビットを、所属ビットで減らせるエントリが多い値の集合から割り当てる
+        let mut candidates = Vec::new();
+        for (attribute, (conditions, _, _)) in &encodings {
+            let field = &fields[attribute];
+            for (set, count) in conditions {
+                let entries = cover_size(&field.codes, set, field.code_width);
+                if entries > 1 {
+                    candidates.push((Reverse((entries - 1) * count), attribute.clone(), set.clone()));
+                }
+            }
+        }
+        candidates.sort();
+        
+        let remaining = ABAC_ENCODING_BITS - fields.values().map(|field| field.width).sum::<u32>();
+        for (_, attribute, set) in candidates.into_iter().take(remaining as usize) {
+            let field = fields.get_mut(&attribute).expect("candidate attribute has a field");
+            let bit = field.width;
+            for value in &set {
+                if let Some(code) = field.codes.get_mut(value) {
+                    *code |= 1 << bit;
+                }
+            }
+            field.value_sets.push((set, bit));
+            field.width += 1;
+        }
+        
+        let mut offset = 0;
+        for field in fields.values_mut() {
+            field.offset = offset;
+            offset += field.width;
+        }
+        Ok(fields)
+    }
+    
+    /// 種類ごとの属性のフィールド（環境属性はデータプレーンに置かないのでNone）
+    pub fn fields(&self, category: AttributeCategory) -> Option<&BTreeMap<String, AttributeField>> {
+        match category {
+            AttributeCategory::Subject => Some(&self.subject),
+            AttributeCategory::Object => Some(&self.object),
+            AttributeCategory::Environment => None,
+        }
+    }
+    
+    /// 属性のフィールドを取得
+    pub fn field(&self, category: AttributeCategory, attribute: &str) -> Option<&AttributeField> {
+        self.fields(category)?.get(attribute)
+    }
+    
+    /// 種類ごとに使うメタデータのビット数
+    pub fn bits(&self, category: AttributeCategory) -> u32 {
+        self.fields(category).map_or(0, |fields| fields.values().map(|field| field.width).sum())
+    }
+    
+    /// 属性の集合をメタデータのビット列にエンコード（配置にない属性・値はエラー）
+    pub fn encode(&self, category: AttributeCategory, attributes: &BTreeMap<String, String>) -> Result<u32> {
+        let mut bits = 0;
+        for (attribute, value) in attributes {
+            let code = self
+                .field(category, attribute)
+                .and_then(|field| field.encode(value))
+                .ok_or_else(|| {
+                    P4RuntimeError::InvalidPolicy(format!("Unknown {} attribute {}={}", category, attribute, value))
+                })?;
+            bits |= code;
+        }
+        Ok(bits)
+    }
+    
+    /// 配置にある属性・値だけをエンコード（スキーマにない属性・値は無視する）
+    pub fn encode_known(&self, category: AttributeCategory, attributes: &BTreeMap<String, String>) -> u32 {
+        attributes
+            .iter()
+            .filter_map(|(attribute, value)| self.field(category, attribute)?.encode(value))
+            .fold(0, |bits, code| bits | code)
+    }
+    
+    /// ホストやプレフィックスの分類エントリのビット列（版のビットを含む）
+    ///
+    /// 対象の `service` 属性は宛先ポートで決めるので含めない。
+    pub fn encode_prefix(&self, category: AttributeCategory, attributes: &BTreeMap<String, String>) -> u32 {
+        let mut attributes = attributes.clone();
+        if category == AttributeCategory::Object {
+            attributes.remove(SERVICE_ATTRIBUTE);
+        }
+        self.encode_known(category, &attributes) | self.version_bits()
+    }
+    
+    /// 条件を満たすビット列をternaryマッチで列挙（条件のない属性はマスク0）
+    pub fn ternary_patterns(&self, category: AttributeCategory, conditions: &AttributeValues) -> Result<Vec<AttributeMatch>> {
+        let mut patterns = vec![AttributeMatch::ANY];
+        
+        for (attribute, values) in conditions {
+            let field = self.field(category, attribute).ok_or_else(|| {
+                P4RuntimeError::InvalidPolicy(format!("Unknown {} attribute {}", category, attribute))
+            })?;
+            let matches = field.ternary(values).ok_or_else(|| {
+                P4RuntimeError::InvalidPolicy(format!("Unknown value in {} attribute {} condition", category, attribute))
+            })?;
+            
+            patterns = patterns
+                .iter()
+                .flat_map(|pattern| {
+                    matches
+                        .iter()
+                        .map(move |m| AttributeMatch::new(pattern.value | m.value, pattern.mask | m.mask))
+                })
+                .collect();
+        }
+        
+        Ok(patterns)
+    }
+}
+
+/// ルールの条件に使われる、2つ以上の値を持つ値の集合とその出現回数（大きい集合、よく使われる集合の順）
+fn condition_sets(
+    category: AttributeCategory,
+    attribute: &str,
+    values: &[String],
+    rules: &[PolicyRule],
+) -> Vec<(ValueSet, usize)> {
+    let mut counts: BTreeMap<ValueSet, usize> = BTreeMap::new();
+    for rule in rules {
+        if let Some(condition) = rule.conditions(category).get(attribute) {
+            let set: ValueSet = condition.iter().filter(|value| values.contains(value)).cloned().collect();
+            if set.len() > 1 {
+                *counts.entry(set).or_default() += 1;
+            }
+        }
+    }
+    
+    let mut sets: Vec<(ValueSet, usize)> = counts.into_iter().collect();
+    sets.sort_by_key(|(set, count)| (Reverse(set.len()), Reverse(*count)));
+    sets
+}
+
+/// 値にコードを振る
+///
+/// 条件の値の集合を順に、まだコードのない値だけからなる集合なら0を含まない整列したブロックにまとめて置く。
+/// ブロックの余りは他の値に使わないでおき（被覆の don't care になる）、足りなくなったときだけ使う。
+fn assign_codes(values: &[String], conditions: &[(ValueSet, usize)], width: u32) -> BTreeMap<String, u32> {
+    let size = 1u32 << width;
+    let mut codes = BTreeMap::new();
+    let mut taken = HashSet::new();
+    let mut reserved = BTreeSet::new();
+    
+    for (set, _) in conditions {
+        if set.iter().any(|value| codes.contains_key(value)) {
+            continue;
+        }
+        let block = (set.len() as u32).next_power_of_two();
+        let start = (block..size)
+            .step_by(block as usize)
+            .find(|start| (*start..start + block).all(|code| !taken.contains(&code) && !reserved.contains(&code)));
+        let Some(start) = start else {
+            continue;
+        };
+        
+        for (value, code) in values.iter().filter(|value| set.contains(*value)).zip(start..) {
+            codes.insert(value.clone(), code);
+            taken.insert(code);
+        }
+        reserved.extend(start..start + block);
+    }
+    
+    let free: Vec<u32> = (1..size)
+        .filter(|code| !reserved.contains(code))
+        .chain(reserved.iter().copied())
+        .filter(|code| !taken.contains(code))
+        .collect();
+    let unassigned: Vec<&String> = values.iter().filter(|value| !codes.contains_key(*value)).collect();
+    for (value, code) in unassigned.into_iter().zip(free) {
+        codes.insert(value.clone(), code);
+    }
+    codes
+}
+
+/// 値の集合を被覆するのに必要なternaryの数
+fn cover_size(codes: &BTreeMap<String, u32>, set: &ValueSet, width: u32) -> usize {
+    let set_codes: BTreeSet<u32> = set.iter().filter_map(|value| codes.get(value).copied()).collect();
+    let used: BTreeSet<u32> = codes.values().copied().collect();
+    cover_codes(&set_codes, &used, width).len()
+}
+
+/// `codes` のコードだけに一致するternary（値, マスク）の組で、`codes` をすべて被覆する
+///
+/// 使われていないコードは don't care として含めてよいが、属性なしを表す0と他の値のコードは含めない。
+/// 使えるキューブを列挙し、未被覆のコードを最も多く含むものから貪欲に選ぶ。
+fn cover_codes(codes: &BTreeSet<u32>, used: &BTreeSet<u32>, width: u32) -> Vec<(u32, u32)> {
+    let full = (1u32 << width) - 1;
+    let allowed = |code: u32| code != 0 && (codes.contains(&code) || !used.contains(&code));
+    
+    let mut checked = HashSet::new();
+    let mut cubes = Vec::new();
+    for mask in 0..=full {
+        for code in codes {
+            let value = code & mask;
+            if !checked.insert((value, mask)) {
+                continue;
+            }
+            // マスクの外のビットのすべての組み合わせが使えるコードならキューブにできる
+            let free = full & !mask;
+            let mut bits = free;
+            let valid = loop {
+                if !allowed(value | bits) {
+                    break false;
+                }
+                if bits == 0 {
+                    break true;
+                }
+                bits = (bits - 1) & free;
+            };
+            if valid {
+                cubes.push((value, mask));
+            }
+        }
+    }
+    
+    let mut uncovered = codes.clone();
+    let mut cover = Vec::new();
+    while !uncovered.is_empty() {
+        let &(value, mask) = cubes
+            .iter()
+            .max_by_key(|(value, mask)| {
+                let count = uncovered.iter().filter(|code| *code & mask == *value).count();
+                (count, Reverse(mask.count_ones()), Reverse(*mask), Reverse(*value))
+            })
+            .expect("every code has its own cube");
+        uncovered.retain(|code| code & mask != value);
+        cover.push((value, mask));
+    }
+    cover
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Effect;
+    
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+    
+    /// 主体の属性 `role` だけを持つスキーマと、`role` の条件だけを持つルールから配置を作る
+    fn role_layout(values: &[&str], conditions: &[&[&str]]) -> Result<AttributeLayout> {
+        let schema = AttributeSchema {
+            subject: AttributeValues::from([("role".to_string(), strings(values))]),
+            ..Default::default()
+        };
+        let rules: Vec<PolicyRule> = conditions
+            .iter()
+            .enumerate()
+            .map(|(i, condition)| PolicyRule {
+                id: format!("rule-{}", i),
+                priority: 1,
+                effect: Effect::Permit,
+                subject: AttributeValues::from([("role".to_string(), strings(condition))]),
+                object: AttributeValues::new(),
+                environment: AttributeValues::new(),
+                description: None,
+            })
+            .collect();
+        AttributeLayout::new(&schema, &rules)
+    }
+    
+    /// ternaryが集合の値のコードにだけ一致し、属性なしの0や他の値には一致しないことを確かめ、エントリ数を返す
+    fn assert_exact_cover(field: &AttributeField, set: &[&str]) -> usize {
+        let matches = field.ternary(&strings(set)).unwrap();
+        assert!(!matches.iter().any(|m| m.matches(0)));
+        for value in field.codes.keys() {
+            let bits = field.encode(value).unwrap();
+            assert_eq!(matches.iter().any(|m| m.matches(bits)), set.contains(&value.as_str()), "value {}", value);
+        }
+        matches.len()
+    }
+    
+    #[test]
+    fn two_value_set_is_one_entry() {
+        let layout = role_layout(&["admin", "engineer", "guest"], &[&["admin", "guest"]]).unwrap();
+        let field = layout.field(AttributeCategory::Subject, "role").unwrap();
+        assert_eq!(assert_exact_cover(field, &["admin", "guest"]), 1);
+        assert_eq!(assert_exact_cover(field, &["engineer"]), 1);
+        assert!(field.value_sets.is_empty());
+    }
+    
+    #[test]
+    fn three_value_set_fills_an_aligned_block_of_four() {
+        let values = strings(&["a", "b", "c", "d", "e"]);
+        let set: ValueSet = ["a", "b", "c"].iter().map(|value| value.to_string()).collect();
+        let codes = assign_codes(&values, &[(set.clone(), 1)], 3);
+        assert_eq!(codes["a"], 4);
+        assert_eq!(codes["b"], 5);
+        assert_eq!(codes["c"], 6);
+        // 7は使われないので don't care として被覆に含められる
+        assert!(!codes.values().any(|code| *code == 7));
+        assert_eq!(cover_size(&codes, &set, 3), 1);
+        
+        let layout = role_layout(&["a", "b", "c", "d", "e"], &[&["a", "b", "c"]]).unwrap();
+        let field = layout.field(AttributeCategory::Subject, "role").unwrap();
+        assert_eq!(assert_exact_cover(field, &["a", "b", "c"]), 1);
+        assert_eq!(assert_exact_cover(field, &["d", "e"]), 2);
+    }
+    
+    #[test]
+    fn sets_without_a_shared_block_get_membership_bits() {
+        // {a, b} がブロックを取ると、{a, c} はコードでは2エントリになるので所属ビットを割り当てる
+        let layout = role_layout(&["a", "b", "c"], &[&["a", "b"], &["a", "c"]]).unwrap();
+        let field = layout.field(AttributeCategory::Subject, "role").unwrap();
+        assert_eq!(field.value_sets.len(), 1);
+        assert_eq!(field.value_sets[0].0, ["a", "c"].iter().map(|value| value.to_string()).collect());
+        assert_eq!(assert_exact_cover(field, &["a", "c"]), 1);
+        assert_eq!(assert_exact_cover(field, &["a", "b"]), 1);
+        assert_exact_cover(field, &["b", "c"]);
+        assert_eq!(layout.bits(AttributeCategory::Subject), field.width);
+    }
+    
+    #[test]
+    fn layouts_past_the_metadata_width_are_rejected() {
+        // 8値の属性はコードに4ビット要るので、8属性で32ビットになり、版のビットを除いた31ビットに収まらない
+        let values = strings(&["v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7"]);
+        let schema = AttributeSchema {
+            subject: (0..8).map(|i| (format!("attr{}", i), values.clone())).collect(),
+            ..Default::default()
+        };
+        assert!(AttributeLayout::new(&schema, &[]).is_err());
+        
+        // 7属性の28ビットと、4値の属性の3ビットでちょうど31ビット
+        let mut subject: AttributeValues = (0..7).map(|i| (format!("attr{}", i), values.clone())).collect();
+        subject.insert("small".to_string(), strings(&["v0", "v1", "v2", "v3"]));
+        let schema = AttributeSchema { subject, ..Default::default() };
+        let layout = AttributeLayout::new(&schema, &[]).unwrap();
+        assert_eq!(layout.bits(AttributeCategory::Subject), ABAC_ENCODING_BITS);
+        
+        // コードは8ビットまで（0を除く255値）
+        let values: Vec<String> = (0..256).map(|i| format!("v{}", i)).collect();
+        let values: Vec<&str> = values.iter().map(|value| value.as_str()).collect();
+        assert!(role_layout(&values, &[]).is_err());
+    }
+    
+    #[test]
+    fn prefix_encoding_leaves_out_the_service_attribute() {
+        let schema = AttributeSchema {
+            object: AttributeValues::from([
+                ("classification".to_string(), strings(&["internal", "secret"])),
+                (SERVICE_ATTRIBUTE.to_string(), strings(&["ssh", "http"])),
+            ]),
+            ..Default::default()
+        };
+        let layout = AttributeLayout::new(&schema, &[]).unwrap();
+        let attributes = BTreeMap::from([
+            ("classification".to_string(), "secret".to_string()),
+            (SERVICE_ATTRIBUTE.to_string(), "ssh".to_string()),
+        ]);
+        
+        let classification = layout.field(AttributeCategory::Object, "classification").unwrap();
+        assert_eq!(
+            layout.encode_prefix(AttributeCategory::Object, &attributes),
+            classification.encode("secret").unwrap()
+        );
+        assert_ne!(layout.encode_known(AttributeCategory::Object, &attributes), layout.encode_prefix(AttributeCategory::Object, &attributes));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::prefix_trie::PrefixTrie;
+use crate::types::*;
+use anyhow::Result;
+use ipnet::Ipv4Net;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+
+/// 属性の値
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+}
+
+impl AttributeValue {
+    /// CLIなどの文字列から型を推定して変換（true/false、整数、それ以外は文字列）
+    pub fn parse(text: &str) -> Self {
+        match text {
+            "true" => AttributeValue::Boolean(true),
+            "false" => AttributeValue::Boolean(false),
+            _ => text
+                .parse()
+                .map(AttributeValue::Integer)
+                .unwrap_or_else(|_| AttributeValue::String(text.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttributeValue::Boolean(value) => write!(f, "{}", value),
+            AttributeValue::Integer(value) => write!(f, "{}", value),
+            AttributeValue::String(value) => f.write_str(value),
+        }
+    }
+}
+
+/// 属性名 -> 値
+pub type AttributeSet = BTreeMap<String, AttributeValue>;
+
+/// 属性の値を、ポリシーのスキーマと比べるための文字列にする
+pub fn attribute_strings(attributes: &AttributeSet) -> BTreeMap<String, String> {
+    attributes
+        .iter()
+        .map(|(name, value)| (name.clone(), value.to_string()))
+        .collect()
+}
+
+/// JSONでやり取りする、プレフィックス1つ分の属性
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributeRecord {
+    /// ホストアドレスまたはプレフィックス（例: "192.168.1.10" / "10.0.0.0/8"）
+    pub prefix: String,
+    pub attributes: AttributeSet,
+}
+
+/// "192.168.1.10" や "10.0.0.0/8" をプレフィックスに変換（アドレスだけなら/32）
+pub fn parse_prefix(text: &str) -> Result<Ipv4Net> {
+    let prefix = match text.parse::<Ipv4Net>() {
+        Ok(prefix) => prefix,
+        Err(_) => Ipv4Net::from(
+            text.parse::<std::net::Ipv4Addr>()
+                .map_err(|_| anyhow::anyhow!("Invalid IPv4 address or prefix {}", text))?,
+        ),
+    };
+    Ok(prefix.trunc())
+}
+
+/// ホストとプレフィックスの属性のストア
+///
+/// プレフィックスの属性は、それに含まれるプレフィックスとホストに引き継がれる（長いプレフィックスの値が優先）。
+/// 更新の結果が変わりうるのは、更新したプレフィックスとそれに含まれる登録済みのプレフィックスだけなので、
+/// 変更系のメソッドはそれらを影響を受けるプレフィックスとして返す。
+#[derive(Debug)]
+pub struct AttributeStore {
+    entries: Arc<RwLock<PrefixTrie<Ipv4Net, AttributeSet>>>,
+}
+
+impl AttributeStore {
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(PrefixTrie::new())),
+        }
+    }
+    
+    /// 属性を設定（既存の属性に上書きでマージ）し、影響を受けるプレフィックスを返す
+    pub async fn set_attributes(&self, prefix: Ipv4Net, attributes: AttributeSet) -> Vec<Ipv4Net> {
+        let prefix = prefix.trunc();
+        let mut entries = self.entries.write().await;
+        entries.get_or_insert_with(prefix, AttributeSet::new).extend(attributes);
+        info!("Updated attributes of {}", prefix);
+        affected_prefixes(&entries, prefix)
+    }
+    
+    /// 属性を1つ削除し、影響を受けるプレフィックスを返す（属性が残らなければプレフィックスごと削除）
+    pub async fn remove_attribute(&self, prefix: Ipv4Net, name: &str) -> Result<Vec<Ipv4Net>> {
+        let prefix = prefix.trunc();
+        let mut entries = self.entries.write().await;
+        let attributes = entries
+            .get_mut(&prefix)
+            .ok_or_else(|| anyhow::anyhow!("No attributes for {}", prefix))?;
+        if attributes.remove(name).is_none() {
+            return Err(anyhow::anyhow!("Attribute {} is not set for {}", name, prefix));
+        }
+        if attributes.is_empty() {
+            entries.remove(&prefix);
+        }
+        
+        info!("Removed attribute {} of {}", name, prefix);
+        Ok(affected_prefixes(&entries, prefix))
+    }
+    
+    /// プレフィックスの属性をすべて削除し、影響を受けるプレフィックスを返す
+    pub async fn remove_prefix(&self, prefix: Ipv4Net) -> Result<Vec<Ipv4Net>> {
+        let prefix = prefix.trunc();
+        let mut entries = self.entries.write().await;
+        if entries.remove(&prefix).is_none() {
+            return Err(anyhow::anyhow!("No attributes for {}", prefix));
+        }
+        
+        info!("Removed all attributes of {}", prefix);
+        Ok(affected_prefixes(&entries, prefix))
+    }
+    
+    /// プレフィックスに設定された属性を取得（引き継いだ属性は含まない）
+    pub async fn get_attributes(&self, prefix: Ipv4Net) -> Option<AttributeSet> {
+        self.entries.read().await.get(&prefix.trunc()).cloned()
+    }
+    
+    /// 上位のプレフィックスから引き継いだ属性を含む、プレフィックス（ホストなら/32）の属性
+    pub async fn effective_attributes(&self, prefix: Ipv4Net) -> AttributeSet {
+        let entries = self.entries.read().await;
+        let mut attributes = AttributeSet::new();
+        for (_, inherited) in entries.covering(&prefix.trunc()) {
+            attributes.extend(inherited.clone());
+        }
+        attributes
+    }
+    
+    /// プレフィックスが登録されているか
+    pub async fn contains(&self, prefix: Ipv4Net) -> bool {
+        self.entries.read().await.get(&prefix.trunc()).is_some()
+    }
+    
+    /// 登録されている全プレフィックスの属性をアドレス順に取得
+    pub async fn list(&self) -> Vec<(Ipv4Net, AttributeSet)> {
+        self.entries
+            .read()
+            .await
+            .iter()
+            .map(|(prefix, attributes)| (prefix, attributes.clone()))
+            .collect()
+    }
+    
+    /// 全プレフィックスの属性をJSONに書き出す
+    pub async fn export_json(&self) -> Result<String> {
+        let records: Vec<AttributeRecord> = self
+            .list()
+            .await
+            .into_iter()
+            .map(|(prefix, attributes)| AttributeRecord {
+                prefix: prefix.to_string(),
+                attributes,
+            })
+            .collect();
+        Ok(serde_json::to_string_pretty(&records).map_err(P4RuntimeError::from)?)
+    }
+    
+    /// JSONから属性を読み込み、影響を受けるプレフィックスを返す
+    ///
+    /// `replace` なら既存の属性をすべて置き換え、そうでなければプレフィックスごとにマージする。
+    /// JSONが不正な場合はストアを変更しない。
+    pub async fn import_json(&self, text: &str, replace: bool) -> Result<Vec<Ipv4Net>> {
+        let records: Vec<AttributeRecord> = serde_json::from_str(text).map_err(P4RuntimeError::from)?;
+        let records = records
+            .into_iter()
+            .map(|record| Ok((parse_prefix(&record.prefix)?, record.attributes)))
+            .collect::<Result<Vec<_>>>()?;
+        
+        let mut entries = self.entries.write().await;
+        let mut changed: BTreeSet<Ipv4Net> = BTreeSet::new();
+        if replace {
+            changed.extend(entries.iter().map(|(prefix, _)| prefix));
+            entries.clear();
+        }
+        for (prefix, attributes) in records {
+            entries.get_or_insert_with(prefix, AttributeSet::new).extend(attributes);
+            changed.insert(prefix);
+        }
+        
+        let mut affected = BTreeSet::new();
+        for prefix in changed {
+            affected.extend(affected_prefixes(&entries, prefix));
+        }
+        info!("Imported attributes ({} prefixes affected)", affected.len());
+        Ok(affected.into_iter().collect())
+    }
+}
+
+impl Default for AttributeStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 更新したプレフィックスと、それに含まれる登録済みのプレフィックス
+fn affected_prefixes(entries: &PrefixTrie<Ipv4Net, AttributeSet>, prefix: Ipv4Net) -> Vec<Ipv4Net> {
+    let mut affected: Vec<Ipv4Net> = entries.covered(&prefix).map(|(covered, _)| covered).collect();
+    if affected.first() != Some(&prefix) {
+        affected.insert(0, prefix);
+    }
+    affected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn net(text: &str) -> Ipv4Net {
+        parse_prefix(text).unwrap()
+    }
+    
+    fn attributes(pairs: &[(&str, &str)]) -> AttributeSet {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), AttributeValue::parse(value)))
+            .collect()
+    }
+    
+    #[test]
+    fn values_are_parsed_by_type() {
+        assert_eq!(AttributeValue::parse("true"), AttributeValue::Boolean(true));
+        assert_eq!(AttributeValue::parse("42"), AttributeValue::Integer(42));
+        assert_eq!(AttributeValue::parse("rnd"), AttributeValue::String("rnd".to_string()));
+        assert_eq!(net("192.168.1.10"), net("192.168.1.10/32"));
+        assert_eq!(net("10.1.2.3/8"), net("10.0.0.0/8"));
+    }
+    
+    #[tokio::test]
+    async fn longer_prefixes_override_inherited_attributes() {
+        let store = AttributeStore::new();
+        store.set_attributes(net("10.0.0.0/8"), attributes(&[("role", "engineer"), ("dept", "rnd")])).await;
+        store.set_attributes(net("10.1.0.0/16"), attributes(&[("role", "admin")])).await;
+        
+        assert_eq!(
+            store.effective_attributes(net("10.1.0.5")).await,
+            attributes(&[("role", "admin"), ("dept", "rnd")])
+        );
+        assert_eq!(
+            store.effective_attributes(net("10.2.0.5")).await,
+            attributes(&[("role", "engineer"), ("dept", "rnd")])
+        );
+        assert!(store.effective_attributes(net("192.168.0.1")).await.is_empty());
+        // 設定した属性だけを取得する場合は引き継いだ属性を含まない
+        assert_eq!(store.get_attributes(net("10.1.0.0/16")).await, Some(attributes(&[("role", "admin")])));
+        assert!(!store.contains(net("10.1.0.5")).await);
+    }
+    
+    #[tokio::test]
+    async fn updates_affect_the_prefix_and_the_prefixes_it_covers() {
+        let store = AttributeStore::new();
+        store.set_attributes(net("10.1.0.0/16"), attributes(&[("role", "engineer")])).await;
+        store.set_attributes(net("10.1.0.10"), attributes(&[("role", "admin")])).await;
+        store.set_attributes(net("192.168.0.0/24"), attributes(&[("classification", "internal")])).await;
+        
+        let affected = store.set_attributes(net("10.0.0.0/8"), attributes(&[("dept", "rnd")])).await;
+        assert_eq!(affected, [net("10.0.0.0/8"), net("10.1.0.0/16"), net("10.1.0.10")]);
+        
+        let affected = store.remove_attribute(net("10.1.0.0/16"), "role").await.unwrap();
+        assert_eq!(affected, [net("10.1.0.0/16"), net("10.1.0.10")]);
+        // 属性が残らなければプレフィックスごと削除する
+        assert!(!store.contains(net("10.1.0.0/16")).await);
+        
+        let affected = store.remove_prefix(net("10.0.0.0/8")).await.unwrap();
+        assert_eq!(affected, [net("10.0.0.0/8"), net("10.1.0.10")]);
+        
+        assert!(store.remove_prefix(net("10.0.0.0/8")).await.is_err());
+        assert!(store.remove_attribute(net("10.1.0.10"), "dept").await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn import_merges_or_replaces_existing_attributes() {
+        let store = AttributeStore::new();
+        store.set_attributes(net("10.1.0.0/16"), attributes(&[("role", "engineer"), ("dept", "rnd")])).await;
+        store.set_attributes(net("192.168.0.0/24"), attributes(&[("classification", "internal")])).await;
+        let json = r#"[
+            { "prefix": "10.1.0.0/16", "attributes": { "role": "admin" } },
+            { "prefix": "10.1.0.10", "attributes": { "on_call": true } }
+        ]"#;
+        
+        let affected = store.import_json(json, false).await.unwrap();
+        assert_eq!(affected, [net("10.1.0.0/16"), net("10.1.0.10")]);
+        assert_eq!(
+            store.get_attributes(net("10.1.0.0/16")).await,
+            Some(attributes(&[("role", "admin"), ("dept", "rnd")]))
+        );
+        assert!(store.contains(net("192.168.0.0/24")).await);
+        
+        // 置き換えでは、なくなったプレフィックスも影響を受ける
+        let affected = store.import_json(json, true).await.unwrap();
+        assert_eq!(affected, [net("10.1.0.0/16"), net("10.1.0.10"), net("192.168.0.0/24")]);
+        assert_eq!(store.get_attributes(net("10.1.0.0/16")).await, Some(attributes(&[("role", "admin")])));
+        assert!(!store.contains(net("192.168.0.0/24")).await);
+        
+        // 書き出したJSONを読み込めば同じ内容になる
+        let exported = store.export_json().await.unwrap();
+        let copy = AttributeStore::new();
+        copy.import_json(&exported, true).await.unwrap();
+        assert_eq!(copy.list().await, store.list().await);
+    }
+    
+    #[tokio::test]
+    async fn invalid_import_leaves_the_store_unchanged() {
+        let store = AttributeStore::new();
+        store.set_attributes(net("10.1.0.0/16"), attributes(&[("role", "engineer")])).await;
+        
+        let json = r#"[
+            { "prefix": "10.2.0.0/16", "attributes": { "role": "admin" } },
+            { "prefix": "not-a-prefix", "attributes": {} }
+        ]"#;
+        assert!(store.import_json(json, true).await.is_err());
+        assert!(store.import_json("{", true).await.is_err());
+        assert_eq!(store.list().await, vec![(net("10.1.0.0/16"), attributes(&[("role", "engineer")]))]);
+    }
+}
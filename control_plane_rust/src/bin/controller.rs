@@ -2,7 +2,6 @@ use p4_controller::cli::{Cli, CliHandler};
 use anyhow::Result;
 use clap::Parser;
 use tracing::{info, Level};
-use tracing_subscriber;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -1,8 +1,14 @@
+use crate::attribute_store::{attribute_strings, parse_prefix, AttributeSet, AttributeValue};
 use crate::controller::P4Controller;
+use crate::attribute_encoding::AttributeLayout;
+use crate::policy::{compile_policy, load_policy, parse_attribute_assignments, AttributeCategory, CompiledPolicy};
+use crate::policy_check::{check_policy, Severity};
+use crate::routing_manager::RouteBuilder;
 use crate::types::*;
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
 use std::str::FromStr;
 use tracing::{info, error};
 
@@ -33,11 +39,29 @@ pub enum Commands {
         #[command(subcommand)]
         action: ArpCommands,
     },
+    /// IPv6近隣キャッシュ（NDP）管理コマンド
+    Ndp {
+        #[command(subcommand)]
+        action: NdpCommands,
+    },
     /// ポート管理コマンド
     Port {
         #[command(subcommand)]
         action: PortCommands,
     },
+    /// ABACポリシー管理コマンド
+    Policy {
+        #[command(subcommand)]
+        action: PolicyCommands,
+    },
+    /// ホスト・プレフィックスの属性管理コマンド
+    Attribute {
+        /// 属性ストアのファイル (JSON、起動時に読み込み、変更後に書き戻す)
+        #[arg(short, long)]
+        store: Option<PathBuf>,
+        #[command(subcommand)]
+        action: AttributeCommands,
+    },
     /// 統計情報表示コマンド
     Stats,
     /// コントローラー状態表示コマンド
@@ -57,6 +81,27 @@ pub enum DeviceCommands {
         /// gRPCエンドポイント
         #[arg(short, long)]
         endpoint: String,
+        /// マスターアービトレーションのelection ID
+        #[arg(long, default_value = "1")]
+        election_id: u128,
+        /// P4Runtimeのロール名
+        #[arg(long)]
+        role: Option<String>,
+        /// プッシュするBMv2 JSON (例: build/ip_forwarding.json)
+        #[arg(long, requires = "p4info")]
+        bmv2_json: Option<PathBuf>,
+        /// プッシュするP4Info (例: build/ip_forwarding.p4info.bin)
+        #[arg(long, requires = "bmv2_json")]
+        p4info: Option<PathBuf>,
+        /// RECONCILE_AND_COMMITで既存のテーブル状態を保ったままプッシュ
+        #[arg(long)]
+        reconcile: bool,
+        /// 1つのWriteRequestに含める更新の最大数
+        #[arg(long, default_value = "128")]
+        max_batch_size: usize,
+        /// WriteRequestのアトミック性 (continue-on-error, rollback-on-error, dataplane-atomic)
+        #[arg(long, default_value = "continue-on-error")]
+        atomicity: WriteAtomicity,
     },
     /// デバイスを削除
     Remove {
@@ -66,36 +111,57 @@ pub enum DeviceCommands {
     },
     /// デバイス一覧を表示
     List,
+    /// コントローラーのテーブル状態とデバイスを比較
+    Reconcile {
+        /// デバイスID
+        #[arg(short, long)]
+        device_id: u64,
+        /// 差分をINSERT/MODIFY/DELETEで修正する
+        #[arg(long)]
+        repair: bool,
+    },
+    /// テーブルごとの使用量と容量（P4Infoの `size`）を表示
+    Tables {
+        /// デバイスID（省略時は全デバイス）
+        #[arg(short, long)]
+        device_id: Option<u64>,
+        /// 高水位とみなす使用率（%）
+        #[arg(long, default_value = "90")]
+        high_watermark: u8,
+    },
 }
 
 #[derive(Subcommand)]
 pub enum RouteCommands {
     /// ルートを追加
     Add {
-        /// プレフィックス (例: 192.168.1.0)
+        /// プレフィックス (例: 192.168.1.0 / 2001:db8::)
         #[arg(short, long)]
         prefix: String,
         /// プレフィックス長
         #[arg(short, long)]
         prefix_len: u8,
-        /// ネクストホップ (例: 192.168.1.1)
-        #[arg(short, long)]
-        next_hop: Option<String>,
-        /// インターフェース名
+        /// ネクストホップ (例: 192.168.1.1 / fe80::1、IPv4は複数指定するとECMP)
         #[arg(short, long)]
-        interface: String,
+        next_hop: Vec<String>,
+        /// インターフェース名（ネクストホップごとに指定するか、1つを全ネクストホップに使う）
+        #[arg(short, long, required = true)]
+        interface: Vec<String>,
         /// メトリック
         #[arg(short, long, default_value = "1")]
         metric: u32,
     },
     /// ルートを削除
     Remove {
-        /// プレフィックス (例: 192.168.1.0)
+        /// プレフィックス (例: 192.168.1.0 / 2001:db8::)
         #[arg(short, long)]
         prefix: String,
         /// プレフィックス長
         #[arg(short, long)]
         prefix_len: u8,
+        /// このネクストホップの候補だけを削除（省略時はプレフィックスの全候補）
+        #[arg(short, long)]
+        next_hop: Option<String>,
     },
     /// ルート一覧を表示
     List,
@@ -105,6 +171,8 @@ pub enum RouteCommands {
         #[arg(short, long)]
         ip: String,
     },
+    /// FIB圧縮でipv4_lpmのエントリがどれだけ減るかを表示
+    Compress,
 }
 
 #[derive(Subcommand)]
@@ -137,6 +205,30 @@ pub enum ArpCommands {
     },
 }
 
+#[derive(Subcommand)]
+pub enum NdpCommands {
+    /// 近隣エントリを追加
+    Add {
+        /// IPv6アドレス
+        #[arg(short, long)]
+        ip: String,
+        /// MACアドレス (例: 00:11:22:33:44:55)
+        #[arg(short, long)]
+        mac: String,
+        /// インターフェース名
+        #[arg(short = 'n', long)]
+        interface: String,
+    },
+    /// 近隣エントリを削除
+    Remove {
+        /// IPv6アドレス
+        #[arg(short, long)]
+        ip: String,
+    },
+    /// 近隣キャッシュを表示
+    List,
+}
+
 #[derive(Subcommand)]
 pub enum PortCommands {
     /// ポートを追加
@@ -173,6 +265,94 @@ pub enum PortCommands {
     },
 }
 
+#[derive(Subcommand)]
+pub enum PolicyCommands {
+    /// ポリシーをコンパイルしてABACポリシーテーブルのエントリを表示
+    Compile {
+        /// ポリシーファイル (JSON)
+        #[arg(short, long)]
+        file: PathBuf,
+        /// 環境属性 (例: --env mode=normal)
+        #[arg(short, long)]
+        env: Vec<String>,
+    },
+    /// ポリシーをコンパイルして全デバイスに適用
+    Apply {
+        /// ポリシーファイル (JSON)
+        #[arg(short, long)]
+        file: PathBuf,
+        /// 環境属性 (例: --env mode=normal)
+        #[arg(short, long)]
+        env: Vec<String>,
+    },
+    /// 影になるルール・効果の異なるルールの重なり・未定義の属性を検査
+    Check {
+        /// ポリシーファイル (JSON)
+        #[arg(short, long)]
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AttributeCommands {
+    /// 属性を設定（既存の属性にマージ）
+    Set {
+        /// ホストアドレスまたはプレフィックス (例: 192.168.1.10, 10.0.0.0/8)
+        #[arg(short, long)]
+        prefix: String,
+        /// 属性 (例: --attr role=admin --attr clearance=3)
+        #[arg(short, long, required = true)]
+        attr: Vec<String>,
+    },
+    /// 属性を1つ削除
+    Unset {
+        /// ホストアドレスまたはプレフィックス
+        #[arg(short, long)]
+        prefix: String,
+        /// 属性名
+        #[arg(short, long)]
+        name: String,
+    },
+    /// プレフィックスの属性をすべて削除
+    Remove {
+        /// ホストアドレスまたはプレフィックス
+        #[arg(short, long)]
+        prefix: String,
+    },
+    /// 設定された属性と、上位のプレフィックスから引き継いだ属性を表示
+    Show {
+        /// ホストアドレスまたはプレフィックス
+        #[arg(short, long)]
+        prefix: String,
+    },
+    /// 属性一覧を表示
+    List,
+    /// 属性をJSONファイルに書き出す
+    Export {
+        /// 出力ファイル (省略時は標準出力)
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+    /// JSONファイルから属性を読み込む
+    Import {
+        /// 入力ファイル (JSON)
+        #[arg(short, long)]
+        file: PathBuf,
+        /// 既存の属性をすべて置き換える
+        #[arg(long)]
+        replace: bool,
+    },
+    /// ポリシーの配置で属性をエンコードした分類エントリを表示（デバイスには書き込まない）
+    Classify {
+        /// ポリシーファイル (JSON)
+        #[arg(short, long)]
+        policy: PathBuf,
+        /// 環境属性 (例: --env mode=normal)
+        #[arg(short, long)]
+        env: Vec<String>,
+    },
+}
+
 /// CLIハンドラー
 pub struct CliHandler {
     controller: P4Controller,
@@ -200,9 +380,18 @@ impl CliHandler {
             Commands::Arp { action } => {
                 self.handle_arp_command(action).await?;
             }
+            Commands::Ndp { action } => {
+                self.handle_ndp_command(action).await?;
+            }
             Commands::Port { action } => {
                 self.handle_port_command(action).await?;
             }
+            Commands::Policy { action } => {
+                self.handle_policy_command(action).await?;
+            }
+            Commands::Attribute { store, action } => {
+                self.handle_attribute_command(store, action).await?;
+            }
             Commands::Stats => {
                 self.show_statistics().await?;
             }
@@ -217,12 +406,32 @@ impl CliHandler {
     /// デバイスコマンドを処理
     async fn handle_device_command(&self, action: DeviceCommands) -> Result<()> {
         match action {
-            DeviceCommands::Add { device_id, name, endpoint } => {
+            DeviceCommands::Add { device_id, name, endpoint, election_id, role, bmv2_json, p4info, reconcile, max_batch_size, atomicity } => {
+                let pipeline = match (bmv2_json, p4info) {
+                    (Some(bmv2_json), Some(p4info)) => Some(PipelineSpec {
+                        bmv2_json,
+                        p4info,
+                        action: if reconcile {
+                            PipelineAction::ReconcileAndCommit
+                        } else {
+                            PipelineAction::VerifyAndCommit
+                        },
+                    }),
+                    _ => None,
+                };
+                
                 let device_info = DeviceInfo {
                     device_id,
                     name,
                     grpc_endpoint: endpoint,
                     p4info: None,
+                    election_id,
+                    role,
+                    pipeline,
+                    write_options: WriteOptions {
+                        max_batch_size,
+                        atomicity,
+                    },
                 };
                 
                 self.controller.add_device(device_info).await?;
@@ -246,6 +455,66 @@ impl CliHandler {
                     );
                 }
             }
+            DeviceCommands::Reconcile { device_id, repair } => {
+                let mode = if repair { ReconcileMode::Repair } else { ReconcileMode::Report };
+                let drift = self.controller.reconcile_device(device_id, mode).await?;
+                
+                println!("Table Drift (device {}):", device_id);
+                println!("{:<10} {:<30} {:<40}", "Kind", "Table", "Entry");
+                println!("{}", "-".repeat(80));
+                
+                for entry in &drift.missing {
+                    println!("{:<10} {:<30} {:?} -> {:?}", "missing", entry.table, entry.matches, entry.action);
+                }
+                for entry in &drift.extra {
+                    println!("{:<10} {:<30} {:?} -> {:?}", "extra", entry.table, entry.matches, entry.action);
+                }
+                for entry in &drift.modified {
+                    println!("{:<10} {:<30} {:?} -> {:?} (device: {:?})",
+                        "modified",
+                        entry.expected.table,
+                        entry.expected.matches,
+                        entry.expected.action,
+                        entry.actual.action
+                    );
+                }
+                
+                if drift.is_empty() {
+                    println!("Device is in sync");
+                } else if repair {
+                    info!("Device repaired successfully");
+                }
+            }
+            DeviceCommands::Tables { device_id, high_watermark } => {
+                self.controller.set_table_high_watermark(high_watermark).await;
+                let device_ids: Vec<u64> = match device_id {
+                    Some(device_id) => vec![device_id],
+                    None => self.controller.list_devices().await.into_iter().map(|device| device.device_id).collect(),
+                };
+                if device_ids.is_empty() {
+                    println!("No devices connected");
+                }
+                
+                for device_id in device_ids {
+                    let usage = self.controller.get_table_usage(device_id).await?;
+                    println!("Table Usage (device {}):", device_id);
+                    println!("{:<40} {:>15} {:>8}", "Table", "Used/Total", "Usage");
+                    println!("{}", "-".repeat(65));
+                    
+                    for table in usage {
+                        let total = if table.capacity > 0 { table.capacity.to_string() } else { "-".to_string() };
+                        let percent = table.percent().map_or("-".to_string(), |percent| format!("{:.1}%", percent));
+                        let marker = if table.is_full() {
+                            " FULL"
+                        } else if table.percent().is_some_and(|percent| percent >= high_watermark as f64) {
+                            " HIGH"
+                        } else {
+                            ""
+                        };
+                        println!("{:<40} {:>15} {:>8}{}", table.table, format!("{}/{}", table.used, total), percent, marker);
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -254,72 +523,95 @@ impl CliHandler {
     async fn handle_route_command(&self, action: RouteCommands) -> Result<()> {
         match action {
             RouteCommands::Add { prefix, prefix_len, next_hop, interface, metric } => {
-                let prefix_ip = Ipv4Addr::from_str(&prefix)?;
-                let next_hop_ip = if let Some(nh) = next_hop {
-                    Some(Ipv4Addr::from_str(&nh)?)
-                } else {
-                    None
+                let prefix_ip = match IpAddr::from_str(&prefix)? {
+                    IpAddr::V4(prefix_ip) => prefix_ip,
+                    IpAddr::V6(prefix_ip) => {
+                        return self.add_ipv6_route(prefix_ip, prefix_len, &next_hop, &interface, metric).await;
+                    }
                 };
+                if interface.len() > 1 && interface.len() != next_hop.len() {
+                    return Err(anyhow::anyhow!(
+                        "Give one interface or one per next hop ({} next hops, {} interfaces)",
+                        next_hop.len(),
+                        interface.len()
+                    ));
+                }
                 
-                let route = RouteEntry {
-                    prefix: Ipv4Address::new(prefix_ip),
-                    prefix_len,
-                    next_hop: next_hop_ip.map(Ipv4Address::new),
-                    interface,
-                    metric,
-                };
+                let mut builder = RouteBuilder::new()
+                    .prefix(Ipv4Address::new(prefix_ip))
+                    .prefix_len(prefix_len)
+                    .interface(interface[0].clone())
+                    .metric(metric);
+                for (i, nh) in next_hop.iter().enumerate() {
+                    let ip = Ipv4Address::new(Ipv4Addr::from_str(nh)?);
+                    let nh_interface = interface.get(i).unwrap_or(&interface[0]).clone();
+                    builder = if i == 0 {
+                        builder.next_hop(ip)
+                    } else {
+                        builder.ecmp_next_hop(ip, nh_interface)
+                    };
+                }
+                let route = builder.build()?;
                 
                 self.controller.add_route(route).await?;
                 info!("Route added successfully");
             }
-            RouteCommands::Remove { prefix, prefix_len } => {
-                let prefix_ip = Ipv4Addr::from_str(&prefix)?;
-                self.controller.remove_route(Ipv4Address::new(prefix_ip), prefix_len).await?;
+            RouteCommands::Remove { prefix, prefix_len, next_hop } => {
+                let prefix_ip = match IpAddr::from_str(&prefix)? {
+                    IpAddr::V4(prefix_ip) => prefix_ip,
+                    IpAddr::V6(prefix_ip) => {
+                        let next_hop_ip = match next_hop {
+                            Some(nh) => Some(Ipv6Address::new(Ipv6Addr::from_str(&nh)?)),
+                            None => None,
+                        };
+                        self.controller.remove_ipv6_route(Ipv6Address::new(prefix_ip), prefix_len, next_hop_ip).await?;
+                        info!("Route removed successfully");
+                        return Ok(());
+                    }
+                };
+                let next_hop_ip = if let Some(nh) = next_hop {
+                    Some(Ipv4Address::new(Ipv4Addr::from_str(&nh)?))
+                } else {
+                    None
+                };
+                self.controller.remove_route(Ipv4Address::new(prefix_ip), prefix_len, next_hop_ip).await?;
                 info!("Route removed successfully");
             }
             RouteCommands::List => {
                 let routes = self.controller.list_routes().await;
-                println!("Routing Table:");
-                println!("{:<18} {:<4} {:<15} {:<10} {:<8}", "Prefix", "Len", "Next Hop", "Interface", "Metric");
-                println!("{}", "-".repeat(65));
+                let best_routes = self.controller.list_best_routes().await;
+                println!("Routing Table (* = installed):");
+                println!("  {:<18} {:<4} {:<15} {:<10} {:<8}", "Prefix", "Len", "Next Hop", "Interface", "Metric");
+                println!("{}", "-".repeat(67));
                 
                 for route in routes {
-                    let next_hop_str = route.next_hop.map(|nh| nh.to_string()).unwrap_or_else(|| "direct".to_string());
-                    println!("{:<18} {:<4} {:<15} {:<10} {:<8}", 
+                    let marker = if best_routes.contains(&route) { "*" } else { " " };
+                    let (next_hop_str, interface_str) = format_next_hops(&route);
+                    println!("{} {:<18} {:<4} {:<15} {:<10} {:<8}", 
+                        marker,
                         route.prefix, 
                         route.prefix_len, 
                         next_hop_str,
-                        route.interface,
+                        interface_str,
                         route.metric
                     );
                 }
-            }
-            RouteCommands::Lookup { ip } => {
-                let lookup_ip = Ipv4Addr::from_str(&ip)?;
-                let routes = self.controller.list_routes().await;
                 
-                println!("Route lookup for {}:", ip);
-                println!("{:<18} {:<4} {:<15} {:<10} {:<8}", "Prefix", "Len", "Next Hop", "Interface", "Metric");
-                println!("{}", "-".repeat(65));
-                
-                for route in routes {
-                    let prefix_ip = route.prefix.as_ipv4();
-                    let prefix_len = route.prefix_len;
-                    let lookup_ip_u32: u32 = lookup_ip.into();
-                    let prefix_u32: u32 = prefix_ip.into();
+                let ipv6_routes = self.controller.list_ipv6_routes().await;
+                if !ipv6_routes.is_empty() {
+                    let best_routes = self.controller.list_best_ipv6_routes().await;
+                    println!();
+                    println!("IPv6 Routing Table (* = installed):");
+                    println!("  {:<40} {:<4} {:<40} {:<10} {:<8}", "Prefix", "Len", "Next Hop", "Interface", "Metric");
+                    println!("{}", "-".repeat(108));
                     
-                    // プレフィックスマッチをチェック
-                    let mask = if prefix_len == 0 {
-                        0
-                    } else {
-                        !((1u32 << (32 - prefix_len)) - 1)
-                    };
-                    
-                    if (prefix_u32 & mask) == (lookup_ip_u32 & mask) {
+                    for route in ipv6_routes {
+                        let marker = if best_routes.contains(&route) { "*" } else { " " };
                         let next_hop_str = route.next_hop.map(|nh| nh.to_string()).unwrap_or_else(|| "direct".to_string());
-                        println!("{:<18} {:<4} {:<15} {:<10} {:<8}", 
-                            route.prefix, 
-                            route.prefix_len, 
+                        println!("{} {:<40} {:<4} {:<40} {:<10} {:<8}",
+                            marker,
+                            route.prefix,
+                            route.prefix_len,
                             next_hop_str,
                             route.interface,
                             route.metric
@@ -327,6 +619,150 @@ impl CliHandler {
                     }
                 }
             }
+            RouteCommands::Lookup { ip } => {
+                let lookup_ip = match IpAddr::from_str(&ip)? {
+                    IpAddr::V4(lookup_ip) => lookup_ip,
+                    IpAddr::V6(lookup_ip) => return self.lookup_ipv6_route(lookup_ip).await,
+                };
+                let lookup_ip = Ipv4Address::new(lookup_ip);
+                let routes = self.controller.lookup_routes(lookup_ip).await;
+                
+                println!("Route lookup for {}:", ip);
+                println!("{:<18} {:<4} {:<15} {:<10} {:<8}", "Prefix", "Len", "Next Hop", "Interface", "Metric");
+                println!("{}", "-".repeat(65));
+                
+                for route in routes {
+                    let (next_hop_str, interface_str) = format_next_hops(&route);
+                    println!("{:<18} {:<4} {:<15} {:<10} {:<8}", 
+                        route.prefix, 
+                        route.prefix_len, 
+                        next_hop_str,
+                        interface_str,
+                        route.metric
+                    );
+                }
+                
+                match self.controller.find_route(lookup_ip).await {
+                    Some(route) => println!("Selected: {}/{} via {}", route.prefix, route.prefix_len, route.describe_next_hop()),
+                    None => println!("Selected: none (no usable route)"),
+                }
+            }
+            RouteCommands::Compress => {
+                let devices = self.controller.list_devices().await;
+                if devices.is_empty() {
+                    println!("No devices connected");
+                }
+                
+                for device in devices {
+                    let report = self.controller.fib_compression_report(device.device_id).await?;
+                    println!("Device {} ({}):", device.device_id, device.name);
+                    println!("  Entries before compression: {}", report.original_count);
+                    println!("  Entries after compression:  {}", report.entries.len());
+                    println!("  Saved: {} ({} redundant, {} sibling merges)", report.saved(), report.redundant_removed, report.siblings_merged);
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// IPv6ルートを追加（IPv6ではECMPは未対応）
+    async fn add_ipv6_route(
+        &self,
+        prefix: Ipv6Addr,
+        prefix_len: u8,
+        next_hop: &[String],
+        interface: &[String],
+        metric: u32,
+    ) -> Result<()> {
+        if prefix_len > 128 {
+            return Err(anyhow::anyhow!("Invalid IPv6 prefix length {}", prefix_len));
+        }
+        if next_hop.len() > 1 || interface.len() > 1 {
+            return Err(anyhow::anyhow!("ECMP is not supported for IPv6 routes"));
+        }
+        let next_hop = match next_hop.first() {
+            Some(nh) => Some(Ipv6Address::new(Ipv6Addr::from_str(nh)?)),
+            None => None,
+        };
+        
+        let route = Ipv6RouteEntry {
+            prefix: Ipv6Address::new(prefix),
+            prefix_len,
+            next_hop,
+            interface: interface[0].clone(),
+            metric,
+        };
+        
+        self.controller.add_ipv6_route(route).await?;
+        info!("Route added successfully");
+        Ok(())
+    }
+    
+    /// IPv6ルートを検索
+    async fn lookup_ipv6_route(&self, lookup_ip: Ipv6Addr) -> Result<()> {
+        let lookup_ip = Ipv6Address::new(lookup_ip);
+        let routes = self.controller.lookup_ipv6_routes(lookup_ip).await;
+        
+        println!("Route lookup for {}:", lookup_ip);
+        println!("{:<40} {:<4} {:<40} {:<10} {:<8}", "Prefix", "Len", "Next Hop", "Interface", "Metric");
+        println!("{}", "-".repeat(106));
+        
+        for route in routes {
+            let next_hop_str = route.next_hop.map(|nh| nh.to_string()).unwrap_or_else(|| "direct".to_string());
+            println!("{:<40} {:<4} {:<40} {:<10} {:<8}",
+                route.prefix,
+                route.prefix_len,
+                next_hop_str,
+                route.interface,
+                route.metric
+            );
+        }
+        
+        match self.controller.find_ipv6_route(lookup_ip).await {
+            Some(route) => println!("Selected: {}/{} via {}", route.prefix, route.prefix_len, route.describe_next_hop()),
+            None => println!("Selected: none (no usable route)"),
+        }
+        
+        Ok(())
+    }
+    
+    /// NDPコマンドを処理
+    async fn handle_ndp_command(&self, action: NdpCommands) -> Result<()> {
+        match action {
+            NdpCommands::Add { ip, mac, interface } => {
+                let ip_addr = Ipv6Addr::from_str(&ip)?;
+                let mac_bytes = parse_mac_address(&mac)?;
+                
+                let entry = NeighborEntry::new_static(
+                    Ipv6Address::new(ip_addr),
+                    MacAddress::new(mac_bytes),
+                    &interface,
+                );
+                
+                self.controller.add_neighbor_entry(entry).await?;
+                info!("Neighbor entry added successfully");
+            }
+            NdpCommands::Remove { ip } => {
+                let ip_addr = Ipv6Addr::from_str(&ip)?;
+                self.controller.remove_neighbor_entry(Ipv6Address::new(ip_addr)).await?;
+                info!("Neighbor entry removed successfully");
+            }
+            NdpCommands::List => {
+                let entries = self.controller.list_neighbor_entries().await;
+                println!("Neighbor Cache:");
+                println!("{:<40} {:<17} {:<10} {:<8} {:<8}", "IPv6 Address", "MAC Address", "Interface", "Type", "Age (s)");
+                println!("{}", "-".repeat(87));
+                
+                for entry in entries {
+                    println!("{:<40} {:<17} {:<10} {:<8} {:<8}",
+                        entry.ip,
+                        entry.mac,
+                        entry.interface,
+                        entry.entry_type,
+                        entry.age().as_secs()
+                    );
+                }
+            }
         }
         Ok(())
     }
@@ -338,32 +774,33 @@ impl CliHandler {
                 let ip_addr = Ipv4Addr::from_str(&ip)?;
                 let mac_bytes = parse_mac_address(&mac)?;
                 
-                let arp_entry = ArpEntry {
-                    ip: Ipv4Address::new(ip_addr),
-                    mac: MacAddress::new(mac_bytes),
-                    interface,
-                };
+                let arp_entry = ArpEntry::new_static(
+                    Ipv4Address::new(ip_addr),
+                    MacAddress::new(mac_bytes),
+                    &interface,
+                );
                 
                 self.controller.add_arp_entry(arp_entry).await?;
                 info!("ARP entry added successfully");
             }
             ArpCommands::Remove { ip } => {
-                let _ip_addr = Ipv4Addr::from_str(&ip)?;
-                // ARPエントリの削除は直接ルーティングマネージャーから行う
-                // self.controller.remove_arp_entry(Ipv4Address::new(ip_addr)).await?;
-                info!("ARP entry removal not implemented yet");
+                let ip_addr = Ipv4Addr::from_str(&ip)?;
+                self.controller.remove_arp_entry(Ipv4Address::new(ip_addr)).await?;
+                info!("ARP entry removed successfully");
             }
             ArpCommands::List => {
                 let arp_entries = self.controller.list_arp_entries().await;
                 println!("ARP Table:");
-                println!("{:<15} {:<17} {:<10}", "IP Address", "MAC Address", "Interface");
-                println!("{}", "-".repeat(42));
+                println!("{:<15} {:<17} {:<10} {:<8} {:<8}", "IP Address", "MAC Address", "Interface", "Type", "Age (s)");
+                println!("{}", "-".repeat(62));
                 
                 for entry in arp_entries {
-                    println!("{:<15} {:<17} {:<10}", 
+                    println!("{:<15} {:<17} {:<10} {:<8} {:<8}", 
                         entry.ip, 
                         entry.mac, 
-                        entry.interface
+                        entry.interface,
+                        entry.entry_type,
+                        entry.age().as_secs()
                     );
                 }
             }
@@ -455,6 +892,145 @@ impl CliHandler {
         Ok(())
     }
     
+    /// ポリシーコマンドを処理
+    async fn handle_policy_command(&self, action: PolicyCommands) -> Result<()> {
+        match action {
+            PolicyCommands::Compile { file, env } => {
+                let policy = load_policy(&file)?;
+                let environment = parse_attribute_assignments(&env)?;
+                let compiled = compile_policy(&policy, &environment)?;
+                print_compiled_policy(&compiled);
+            }
+            PolicyCommands::Apply { file, env } => {
+                let policy = load_policy(&file)?;
+                let environment = parse_attribute_assignments(&env)?;
+                let compiled = self.controller.apply_policy(&policy, &environment).await?;
+                print_compiled_policy(&compiled);
+                info!("Policy {} applied successfully", policy.name);
+            }
+            PolicyCommands::Check { file } => {
+                let policy = load_policy(&file)?;
+                let findings = check_policy(&policy);
+                if findings.is_empty() {
+                    println!("Policy {}: no issues found", policy.name);
+                    return Ok(());
+                }
+                
+                println!("Policy {}:", policy.name);
+                println!("{:<8} {:<30} Finding", "Severity", "Rules");
+                println!("{}", "-".repeat(80));
+                for finding in &findings {
+                    println!("{:<8} {:<30} {}", finding.severity, finding.rules.join(","), finding.message);
+                }
+                
+                let count = |severity| findings.iter().filter(|finding| finding.severity == severity).count();
+                let errors = count(Severity::Error);
+                println!();
+                println!("{} errors, {} warnings, {} info", errors, count(Severity::Warning), count(Severity::Info));
+                if errors > 0 {
+                    return Err(P4RuntimeError::InvalidPolicy(format!("Policy {} has {} errors", policy.name, errors)).into());
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// 属性コマンドを処理
+    async fn handle_attribute_command(&self, store: Option<PathBuf>, action: AttributeCommands) -> Result<()> {
+        if let Some(path) = store.as_ref().filter(|path| path.exists()) {
+            self.controller.import_attributes(&std::fs::read_to_string(path)?, true).await?;
+        }
+        
+        let modified = match action {
+            AttributeCommands::Set { prefix, attr } => {
+                let prefix = parse_prefix(&prefix)?;
+                let attributes: AttributeSet = parse_attribute_assignments(&attr)?
+                    .into_iter()
+                    .map(|(name, value)| (name, AttributeValue::parse(&value)))
+                    .collect();
+                self.controller.set_attributes(prefix, attributes).await?;
+                info!("Attributes of {} updated successfully", prefix);
+                true
+            }
+            AttributeCommands::Unset { prefix, name } => {
+                let prefix = parse_prefix(&prefix)?;
+                self.controller.remove_attribute(prefix, &name).await?;
+                info!("Attribute {} of {} removed successfully", name, prefix);
+                true
+            }
+            AttributeCommands::Remove { prefix } => {
+                let prefix = parse_prefix(&prefix)?;
+                self.controller.remove_attributes(prefix).await?;
+                info!("Attributes of {} removed successfully", prefix);
+                true
+            }
+            AttributeCommands::Show { prefix } => {
+                let prefix = parse_prefix(&prefix)?;
+                let (own, effective) = self.controller.get_attributes(prefix).await;
+                let own = own.unwrap_or_default();
+                
+                println!("Attributes of {}:", prefix);
+                println!("{:<20} {:<20} {:<10}", "Name", "Value", "Source");
+                println!("{}", "-".repeat(50));
+                for (name, value) in &effective {
+                    let source = if own.get(name) == Some(value) { "set" } else { "inherited" };
+                    println!("{:<20} {:<20} {:<10}", name, value, source);
+                }
+                false
+            }
+            AttributeCommands::List => {
+                println!("Attributes:");
+                println!("{:<20} {:<50}", "Prefix", "Attributes");
+                println!("{}", "-".repeat(70));
+                for (prefix, attributes) in self.controller.list_attributes().await {
+                    println!("{:<20} {:<50}", prefix.to_string(), format_attributes(&attributes));
+                }
+                false
+            }
+            AttributeCommands::Export { file } => {
+                let json = self.controller.export_attributes().await?;
+                match file {
+                    Some(path) => {
+                        std::fs::write(&path, json)?;
+                        info!("Attributes exported to {}", path.display());
+                    }
+                    None => println!("{}", json),
+                }
+                false
+            }
+            AttributeCommands::Import { file, replace } => {
+                let count = self.controller.import_attributes(&std::fs::read_to_string(&file)?, replace).await?;
+                info!("Attributes imported from {} ({} prefixes affected)", file.display(), count);
+                true
+            }
+            AttributeCommands::Classify { policy, env } => {
+                let policy = load_policy(&policy)?;
+                let environment = parse_attribute_assignments(&env)?;
+                // ポリシーはローカルでコンパイルするだけで、デバイスへの適用は `policy apply` に任せる
+                let compiled = compile_policy(&policy, &environment)?;
+                
+                println!("ABAC Classification Entries:");
+                println!("{:<8} {:<20} {:<12}", "Table", "Prefix", "Attributes");
+                println!("{}", "-".repeat(40));
+                for (prefix, _) in self.controller.list_attributes().await {
+                    let (_, effective) = self.controller.get_attributes(prefix).await;
+                    let attributes = attribute_strings(&effective);
+                    for (table, category) in [("subject", AttributeCategory::Subject), ("object", AttributeCategory::Object)] {
+                        let bits = compiled.layout.encode_prefix(category, &attributes);
+                        println!("{:<8} {:<20} 0x{:08x}", table, prefix.to_string(), bits);
+                    }
+                }
+                false
+            }
+        };
+        
+        if let Some(path) = store.filter(|_| modified) {
+            std::fs::write(&path, self.controller.export_attributes().await?)?;
+            info!("Attribute store saved to {}", path.display());
+        }
+        Ok(())
+    }
+    
     /// 統計情報を表示
     async fn show_statistics(&self) -> Result<()> {
         let stats = self.controller.get_statistics().await?;
@@ -514,3 +1090,115 @@ fn parse_mac_address(mac_str: &str) -> Result<[u8; 6]> {
     
     Ok(bytes)
 }
+
+/// ルートのネクストホップとインターフェースの表示用文字列（ECMPはカンマ区切り）
+fn format_next_hops(route: &RouteEntry) -> (String, String) {
+    let next_hops = route.next_hops();
+    if next_hops.is_empty() {
+        return ("direct".to_string(), route.interface.clone());
+    }
+    
+    let mut interfaces: Vec<String> = Vec::new();
+    for next_hop in &next_hops {
+        if !interfaces.contains(&next_hop.interface) {
+            interfaces.push(next_hop.interface.clone());
+        }
+    }
+    let ips: Vec<String> = next_hops.iter().map(|n| n.ip.to_string()).collect();
+    (ips.join(","), interfaces.join(","))
+}
+
+/// 属性を "名前=値" のカンマ区切りで表示
+fn format_attributes(attributes: &AttributeSet) -> String {
+    attributes
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// コンパイルしたポリシーのルールとエントリを表示
+fn print_compiled_policy(compiled: &CompiledPolicy) {
+    println!("Compiled Rules:");
+    println!("{:<20} {:<8} {:>8} {:>8}", "Rule", "Effect", "Priority", "Entries");
+    println!("{}", "-".repeat(47));
+    for rule in &compiled.rules {
+        println!("{:<20} {:<8} {:>8} {:>8}", rule.rule_id, rule.effect, rule.priority, rule.entries);
+    }
+    for rule_id in &compiled.inactive_rules {
+        println!("{:<20} (inactive in this environment)", rule_id);
+    }
+    
+    println!();
+    print_attribute_encoding(&compiled.layout);
+    
+    if !compiled.services.is_empty() {
+        println!();
+        println!("ABAC Service Entries:");
+        println!("{:<8} {:<12} {:<12} {:<12}", "Protocol", "Ports", "Attributes", "Mask");
+        println!("{}", "-".repeat(44));
+        for service in &compiled.services {
+            if let AbacClassifier::Service { protocol, low_port, high_port, attributes, mask, .. } = service {
+                let protocol = protocol.map_or("*".to_string(), |protocol| protocol.to_string());
+                println!("{:<8} {:<12} 0x{:08x}   0x{:08x}", protocol, format!("{}-{}", low_port, high_port), attributes, mask);
+            }
+        }
+    }
+    
+    println!();
+    println!("ABAC Policy Entries:");
+    println!("{:>8} {:<28} {:<28} {:<12}", "Priority", "Subject", "Object", "Action");
+    println!("{}", "-".repeat(79));
+    for entry in &compiled.entries {
+        let action = entry.action.as_direct().map_or("-", |action| action.name.as_str());
+        println!("{:>8} {:<28} {:<28} {:<12}",
+            entry.priority,
+            format_ternary(entry, ABAC_SUBJECT_FIELD),
+            format_ternary(entry, ABAC_OBJECT_FIELD),
+            action
+        );
+    }
+}
+
+/// 属性ごとのメタデータのビットの割り当てと、種類ごとの合計を表示
+fn print_attribute_encoding(layout: &AttributeLayout) {
+    println!("Attribute Encoding:");
+    println!("{:<8} {:<16} {:>6} {:>6} {:>6} {:>6} {:>6}", "Category", "Attribute", "Values", "Offset", "Code", "Sets", "Bits");
+    println!("{}", "-".repeat(62));
+    for category in [AttributeCategory::Subject, AttributeCategory::Object] {
+        for (attribute, field) in layout.fields(category).into_iter().flatten() {
+            println!("{:<8} {:<16} {:>6} {:>6} {:>6} {:>6} {:>6}",
+                category,
+                attribute,
+                field.codes.len(),
+                field.offset,
+                field.code_width,
+                field.value_sets.len(),
+                field.width
+            );
+        }
+    }
+    for category in [AttributeCategory::Subject, AttributeCategory::Object] {
+        println!("Total {} bits: {}/{}", category, layout.bits(category), ABAC_ENCODING_BITS);
+    }
+    
+    for category in [AttributeCategory::Subject, AttributeCategory::Object] {
+        for (attribute, field) in layout.fields(category).into_iter().flatten() {
+            let codes: Vec<String> = field
+                .codes
+                .iter()
+                .map(|(value, code)| format!("{}=0x{:x}", value, code))
+                .collect();
+            println!("  {}.{}: {}", category, attribute, codes.join(" "));
+        }
+    }
+}
+
+/// ternaryマッチを "値 &&& マスク" の形式で表示（マッチがなければワイルドカード）
+fn format_ternary(entry: &TableEntry, field: &str) -> String {
+    let to_hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    match entry.matches.iter().find(|m| m.field == field).map(|m| &m.value) {
+        Some(MatchValue::Ternary { value, mask }) => format!("0x{} &&& 0x{}", to_hex(value), to_hex(mask)),
+        _ => "*".to_string(),
+    }
+}
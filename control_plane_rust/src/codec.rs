@@ -0,0 +1,269 @@
+//! P4Runtimeのバイト列（bytestring）のエンコード・デコード
+//!
+//! P4Runtime仕様では、ビット列の値は先頭の0バイトを除いた最短のビッグエンディアン表現
+//! （canonical binary string）で送る。値0は `\x00` の1バイトで表す。
+
+use crate::types::*;
+use anyhow::Result;
+
+/// ビット幅を表すのに必要なバイト数
+pub fn byte_width(bitwidth: u32) -> usize {
+    bitwidth.div_ceil(8).max(1) as usize
+}
+
+/// バイト列が表す値の有効ビット数
+pub fn significant_bits(value: &[u8]) -> u32 {
+    match value.iter().position(|b| *b != 0) {
+        Some(first) => (value.len() - first - 1) as u32 * 8 + (8 - value[first].leading_zeros()),
+        None => 0,
+    }
+}
+
+/// 値がビット幅に収まるか
+pub fn fits(value: &[u8], bitwidth: u32) -> bool {
+    significant_bits(value) <= bitwidth
+}
+
+/// ビット幅を検証して正規形（先頭の0を除いた最短表現）に変換
+pub fn encode(value: &[u8], bitwidth: u32) -> Result<Vec<u8>> {
+    if !fits(value, bitwidth) {
+        return Err(out_of_range(value, bitwidth));
+    }
+    
+    let canonical: Vec<u8> = value.iter().copied().skip_while(|b| *b == 0).collect();
+    if canonical.is_empty() {
+        Ok(vec![0])
+    } else {
+        Ok(canonical)
+    }
+}
+
+/// 整数値をビット幅を検証して正規形に変換
+pub fn encode_u64(value: u64, bitwidth: u32) -> Result<Vec<u8>> {
+    encode(&value.to_be_bytes(), bitwidth)
+}
+
+/// 受信したバイト列を検証して正規形に変換（ゼロ埋めされた値も受け付ける）
+pub fn decode(value: &[u8], bitwidth: u32) -> Result<Vec<u8>> {
+    encode(value, bitwidth)
+}
+
+/// バイト列を検証して整数値に変換
+pub fn decode_u64(value: &[u8], bitwidth: u32) -> Result<u64> {
+    if !fits(value, bitwidth.min(64)) {
+        return Err(out_of_range(value, bitwidth.min(64)));
+    }
+    Ok(value.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+}
+
+/// ビット幅ちょうどのバイト数にゼロ埋めする（BMv2のCLIなど固定長が必要な場合）
+pub fn to_width(value: &[u8], bitwidth: u32) -> Result<Vec<u8>> {
+    let canonical = encode(value, bitwidth)?;
+    let mut bytes = vec![0u8; byte_width(bitwidth) - canonical.len()];
+    bytes.extend_from_slice(&canonical);
+    Ok(bytes)
+}
+
+/// LPMの値からプレフィックス長より後ろのホスト部のビットを落とす
+pub fn mask_prefix(value: &[u8], prefix_len: u32, bitwidth: u32) -> Result<Vec<u8>> {
+    if prefix_len > bitwidth {
+        return Err(P4RuntimeError::InvalidTableEntry(format!(
+            "Prefix length {} exceeds bitwidth {}",
+            prefix_len, bitwidth
+        ))
+        .into());
+    }
+    
+    let mut bytes = to_width(value, bitwidth)?;
+    let host_bits = bitwidth - prefix_len;
+    let len = bytes.len();
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        // 最下位バイトから数えたビット位置
+        let low_bit = (len - 1 - i) as u32 * 8;
+        if low_bit + 8 <= host_bits {
+            *byte = 0;
+        } else if low_bit < host_bits {
+            *byte &= 0xffu8 << (host_bits - low_bit);
+        }
+    }
+    
+    encode(&bytes, bitwidth)
+}
+
+/// フィールドマッチの値を検証して正規形に変換
+///
+/// LPMはホスト部をマスクし、ternaryはマスク外のビットを0にする。
+/// 仕様ではワイルドカード（プレフィックス長0のLPM、マスクが0のternary）はマッチを省略するので、
+/// それらはNoneを返す。
+pub fn encode_match(value: &MatchValue, bitwidth: u32) -> Result<Option<MatchValue>> {
+    Ok(Some(match value {
+        MatchValue::Exact(value) => MatchValue::Exact(encode(value, bitwidth)?),
+        MatchValue::Lpm { value, prefix_len } => {
+            let value = mask_prefix(value, *prefix_len, bitwidth)?;
+            if *prefix_len == 0 {
+                return Ok(None);
+            }
+            MatchValue::Lpm {
+                value,
+                prefix_len: *prefix_len,
+            }
+        }
+        MatchValue::Ternary { value, mask } => {
+            let value = to_width(value, bitwidth)?;
+            let mask = to_width(mask, bitwidth)?;
+            if mask.iter().all(|m| *m == 0) {
+                return Ok(None);
+            }
+            let masked: Vec<u8> = value.iter().zip(&mask).map(|(v, m)| v & m).collect();
+            MatchValue::Ternary {
+                value: encode(&masked, bitwidth)?,
+                mask: encode(&mask, bitwidth)?,
+            }
+        }
+        MatchValue::Range { low, high } => {
+            if to_width(low, bitwidth)? > to_width(high, bitwidth)? {
+                return Err(P4RuntimeError::InvalidTableEntry(format!(
+                    "Range low {} is greater than high {}",
+                    hex(low),
+                    hex(high)
+                ))
+                .into());
+            }
+            MatchValue::Range {
+                low: encode(low, bitwidth)?,
+                high: encode(high, bitwidth)?,
+            }
+        }
+        MatchValue::Optional(value) => MatchValue::Optional(encode(value, bitwidth)?),
+    }))
+}
+
+/// MACアドレスを正規形に変換
+pub fn encode_mac(mac: &MacAddress, bitwidth: u32) -> Result<Vec<u8>> {
+    encode(mac.as_bytes(), bitwidth)
+}
+
+/// バイト列をMACアドレスに変換
+pub fn decode_mac(value: &[u8]) -> Result<MacAddress> {
+    let bytes = to_width(value, 48)?;
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&bytes);
+    Ok(MacAddress::new(mac))
+}
+
+/// IPv4アドレスを正規形に変換
+pub fn encode_ipv4(addr: &Ipv4Address, bitwidth: u32) -> Result<Vec<u8>> {
+    encode_u64(addr.as_u32() as u64, bitwidth)
+}
+
+/// バイト列をIPv4アドレスに変換
+pub fn decode_ipv4(value: &[u8]) -> Result<Ipv4Address> {
+    Ok(Ipv4Address::from_u32(decode_u64(value, 32)? as u32))
+}
+
+/// ポート番号を正規形に変換（v1modelの `egressSpec_t` は9ビット）
+pub fn encode_port(port: PortId, bitwidth: u32) -> Result<Vec<u8>> {
+    encode_u64(port as u64, bitwidth)
+}
+
+/// バイト列をポート番号に変換
+pub fn decode_port(value: &[u8], bitwidth: u32) -> Result<PortId> {
+    Ok(decode_u64(value, bitwidth.min(32))? as PortId)
+}
+
+fn out_of_range(value: &[u8], bitwidth: u32) -> anyhow::Error {
+    P4RuntimeError::ValueOutOfRange {
+        value: hex(value),
+        bitwidth,
+    }
+    .into()
+}
+
+/// エラーメッセージ用の16進表記
+fn hex(value: &[u8]) -> String {
+    let digits: String = value.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("0x{}", digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn zero_encodes_to_single_zero_byte() {
+        assert_eq!(encode(&[0, 0, 0], 24).unwrap(), vec![0]);
+        assert_eq!(encode(&[], 8).unwrap(), vec![0]);
+        assert_eq!(encode_u64(0, 9).unwrap(), vec![0]);
+    }
+    
+    #[test]
+    fn leading_zeros_are_stripped() {
+        // 仕様の例: 9ビットのポート1は \x01、\x00\x01 も同じ値として受け付ける
+        assert_eq!(encode(&[0x00, 0x01], 9).unwrap(), vec![0x01]);
+        assert_eq!(encode(&[0x00, 0x00, 0x0a, 0x00], 32).unwrap(), vec![0x0a, 0x00]);
+        assert_eq!(decode(&[0x00, 0x00, 0x01, 0xff], 9).unwrap(), vec![0x01, 0xff]);
+    }
+    
+    #[test]
+    fn u64_round_trip() {
+        for (value, bitwidth) in [(0u64, 1), (1, 1), (511, 9), (0x1234, 16), (u64::MAX, 64), (1 << 47, 48)] {
+            let encoded = encode_u64(value, bitwidth).unwrap();
+            assert_eq!(decode_u64(&encoded, bitwidth).unwrap(), value);
+        }
+    }
+    
+    #[test]
+    fn mac_and_ipv4_round_trip() {
+        for mac in [[0u8; 6], [0x00, 0x11, 0x22, 0x33, 0x44, 0x55], [0xff; 6]] {
+            let mac = MacAddress::new(mac);
+            let encoded = encode_mac(&mac, 48).unwrap();
+            assert_eq!(decode_mac(&encoded).unwrap(), mac);
+        }
+        
+        for addr in [0u32, 0x0a000001, 0xc0a80101, u32::MAX] {
+            let addr = Ipv4Address::from_u32(addr);
+            let encoded = encode_ipv4(&addr, 32).unwrap();
+            assert_eq!(decode_ipv4(&encoded).unwrap(), addr);
+        }
+        // 10.0.0.1 は先頭の0を除いた4バイト、0.0.0.1 は1バイト
+        assert_eq!(encode_ipv4(&Ipv4Address::from_u32(0x0a000001), 32).unwrap(), vec![0x0a, 0x00, 0x00, 0x01]);
+        assert_eq!(encode_ipv4(&Ipv4Address::from_u32(1), 32).unwrap(), vec![0x01]);
+    }
+    
+    #[test]
+    fn port_out_of_range_is_rejected() {
+        assert_eq!(encode_port(511, 9).unwrap(), vec![0x01, 0xff]);
+        assert!(encode_port(512, 9).is_err());
+        assert!(decode_port(&[0x02, 0x00], 9).is_err());
+    }
+    
+    #[test]
+    fn lpm_host_bits_are_masked() {
+        assert_eq!(mask_prefix(&[10, 1, 2, 3], 8, 32).unwrap(), vec![10, 0, 0, 0]);
+        assert_eq!(mask_prefix(&[192, 168, 1, 255], 25, 32).unwrap(), vec![192, 168, 1, 128]);
+        assert_eq!(mask_prefix(&[192, 168, 1, 255], 32, 32).unwrap(), vec![192, 168, 1, 255]);
+        assert_eq!(mask_prefix(&[192, 168, 1, 255], 0, 32).unwrap(), vec![0]);
+        assert!(mask_prefix(&[10, 0, 0, 0], 33, 32).is_err());
+    }
+    
+    #[test]
+    fn wildcard_matches_are_omitted() {
+        let lpm = MatchValue::Lpm { value: vec![0], prefix_len: 0 };
+        assert_eq!(encode_match(&lpm, 32).unwrap(), None);
+        
+        let ternary = MatchValue::Ternary { value: vec![0x12], mask: vec![0, 0] };
+        assert_eq!(encode_match(&ternary, 16).unwrap(), None);
+        
+        let ternary = MatchValue::Ternary { value: vec![0x12, 0x34], mask: vec![0x00, 0xf0] };
+        assert_eq!(
+            encode_match(&ternary, 16).unwrap(),
+            Some(MatchValue::Ternary { value: vec![0x30], mask: vec![0xf0] })
+        );
+        
+        let lpm = MatchValue::Lpm { value: vec![10, 1, 2, 3], prefix_len: 16 };
+        assert_eq!(
+            encode_match(&lpm, 32).unwrap(),
+            Some(MatchValue::Lpm { value: vec![10, 1, 0, 0], prefix_len: 16 })
+        );
+    }
+}
@@ -1,20 +1,46 @@
 use crate::types::*;
+use crate::action_profile_manager::{ActionProfileChanges, ActionProfileManager};
+use crate::attribute_encoding::AttributeLayout;
+use crate::attribute_store::{attribute_strings, AttributeSet, AttributeStore};
+use crate::arp_resolver::{ArpAgingConfig, ArpPacket, ArpResolver, NdpResolver};
+use crate::fib_compression::{compress_ipv4_fib, FibCompression};
+use crate::ndp::{NdpOperation, NdpPacket};
 use crate::p4runtime_client::DeviceManager;
+use crate::policy::{compile_policy_with_version, AttributeCategory, CompiledPolicy, Environment, Policy};
 use crate::table_manager::TableManager;
 use crate::routing_manager::RoutingManager;
 use anyhow::Result;
-use std::collections::HashMap;
+use ipnet::Ipv4Net;
+use std::collections::{HashMap, HashSet};
 use std::net::Ipv4Addr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{info, error};
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use tracing::{info, warn, error};
+
+/// ARPリクエストの再送を確認する間隔
+const ARP_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 /// P4コントローラーのメインアプリケーション
-#[derive(Debug)]
+///
+/// 内部状態はすべて共有されるので、クローンしてバックグラウンドタスクに渡せる。
+#[derive(Debug, Clone)]
 pub struct P4Controller {
     device_manager: Arc<DeviceManager>,
     table_manager: Arc<TableManager>,
+    action_profile_manager: Arc<ActionProfileManager>,
     routing_manager: Arc<RoutingManager>,
+    arp_resolver: Arc<ArpResolver>,
+    ndp_resolver: Arc<NdpResolver>,
+    arp_aging: ArpAgingConfig,
+    /// ipv4_lpmに書き込む前にFIBを圧縮するか
+    fib_compression: bool,
+    /// 適用中のABACポリシーをコンパイルした結果
+    abac_policy: Arc<RwLock<Option<CompiledPolicy>>>,
+    /// ABACの分類テーブルのエントリ（全デバイスに同じものを書き込む）
+    abac_classifiers: Arc<RwLock<Vec<AbacClassifier>>>,
+    /// ホストとプレフィックスの属性
+    attribute_store: Arc<AttributeStore>,
     state: Arc<RwLock<ControllerState>>,
 }
 
@@ -23,11 +49,31 @@ impl P4Controller {
         Self {
             device_manager: Arc::new(DeviceManager::new()),
             table_manager: Arc::new(TableManager::new()),
+            action_profile_manager: Arc::new(ActionProfileManager::new()),
             routing_manager: Arc::new(RoutingManager::new()),
+            arp_resolver: Arc::new(ArpResolver::default()),
+            ndp_resolver: Arc::new(NdpResolver::default()),
+            arp_aging: ArpAgingConfig::default(),
+            fib_compression: false,
+            abac_policy: Arc::new(RwLock::new(None)),
+            abac_classifiers: Arc::new(RwLock::new(Vec::new())),
+            attribute_store: Arc::new(AttributeStore::new()),
             state: Arc::new(RwLock::new(ControllerState::default())),
         }
     }
     
+    /// 動的ARPエントリのエージング設定を変更（initializeの前に呼ぶ）
+    pub fn set_arp_aging_config(&mut self, config: ArpAgingConfig) {
+        self.arp_aging = config;
+    }
+    
+    /// ipv4_lpmに書き込む前のFIB圧縮を有効にする（initializeの前に呼ぶ）
+    ///
+    /// 有効にすると、ルートの変更のたびにFIB全体を圧縮し直し、デバイスとの差分だけを書き込む。
+    pub fn set_fib_compression(&mut self, enabled: bool) {
+        self.fib_compression = enabled;
+    }
+    
     /// コントローラーを初期化
     pub async fn initialize(&self) -> Result<()> {
         info!("Initializing P4 Controller...");
@@ -41,6 +87,10 @@ impl P4Controller {
         // デフォルトARPエントリを追加
         self.setup_default_arp_entries().await?;
         
+        // 未解決のネクストホップへのARPリクエストを送るタスクと、動的ARPエントリのエージングタスクを開始
+        self.spawn_arp_resolver_task();
+        self.spawn_arp_aging_task();
+        
         info!("P4 Controller initialized successfully");
         Ok(())
     }
@@ -52,8 +102,18 @@ impl P4Controller {
         // デバイスマネージャーに追加
         self.device_manager.add_device(device_info.clone()).await?;
         
+        // P4Infoが読み込まれていれば反映されたデバイス情報を使う
+        let device_info = self.device_manager
+            .get_device(device_info.device_id)
+            .await
+            .unwrap_or(device_info);
+        
         // テーブルマネージャーでデバイスを初期化
         self.table_manager.initialize_device_tables(device_info.device_id).await;
+        if let Some(p4info) = &device_info.p4info {
+            self.table_manager.set_device_p4info(device_info.device_id, p4info.clone()).await;
+            self.action_profile_manager.set_device_p4info(device_info.device_id, p4info.clone()).await;
+        }
         
         // 状態を更新
         let device_id = device_info.device_id;
@@ -62,8 +122,40 @@ impl P4Controller {
             state.connected_devices.insert(device_id, device_info);
         }
         
-        // ルーティングテーブルをデバイスに適用
-        self.apply_routing_table_to_device(device_id).await?;
+        // ARP応答などコントローラー宛てのパケットの処理を開始
+        self.spawn_packet_in_handler(device_id).await?;
+        
+        // プライマリかつパイプラインが動作している場合のみルーティングテーブルをデバイスに適用
+        match self.device_manager.get_mastership(device_id).await? {
+            Some(MastershipStatus::Primary) => {
+                match self.device_manager.get_pipeline_cookie(device_id).await? {
+                    Some(cookie) => {
+                        info!("Device {} is running pipeline {:#x}", device_id, cookie);
+                        // 再起動前のエントリが残っている可能性があるため、シャドウを作ってから差分だけ書き込む
+                        self.load_routing_table_into_shadow(device_id).await?;
+                        self.load_abac_into_shadow(device_id).await?;
+                        self.load_ecmp_state_from_device(device_id).await?;
+                        self.reconcile_device(device_id, ReconcileMode::Repair).await?;
+                        // ECMPルートはメンバーとグループを先に書き込む必要があるため、差分の修正の後に適用
+                        self.apply_ecmp_routes_to_device(device_id).await?;
+                        let unreleased = self.release_unused_ecmp_groups(device_id).await?;
+                        if !unreleased.is_empty() {
+                            let groups: Vec<String> = unreleased.iter().map(|(group_id, _)| group_id.to_string()).collect();
+                            warn!("Left unused ECMP groups {} on device {}", groups.join(", "), device_id);
+                        }
+                        if self.fib_compression {
+                            self.sync_compressed_fib(device_id).await?;
+                        }
+                    }
+                    None => {
+                        warn!("Skipping table programming for device {}: no pipeline installed", device_id);
+                    }
+                }
+            }
+            status => {
+                warn!("Skipping table programming for device {}: mastership {:?}", device_id, status);
+            }
+        }
         
         info!("Device added successfully");
         Ok(())
@@ -76,6 +168,7 @@ impl P4Controller {
         // 各マネージャーからデバイスを削除
         self.device_manager.remove_device(device_id).await?;
         self.table_manager.remove_device(device_id).await;
+        self.action_profile_manager.remove_device(device_id).await;
         
         // 状態を更新
         {
@@ -87,6 +180,85 @@ impl P4Controller {
         Ok(())
     }
     
+    /// シャドウのテーブル状態とデバイスのテーブルを比較し、必要なら修正
+    pub async fn reconcile_device(&self, device_id: DeviceId, mode: ReconcileMode) -> Result<TableDrift> {
+        let device_entries = self.device_manager.read_table_entries_from_device(device_id).await?;
+        let drift = self.table_manager.diff_device_entries(device_id, &device_entries).await?;
+        
+        if drift.is_empty() {
+            info!("Device {} is in sync ({} entries)", device_id, device_entries.len());
+            return Ok(drift);
+        }
+        
+        warn!(
+            "Device {} drift: {} missing, {} extra, {} modified",
+            device_id,
+            drift.missing.len(),
+            drift.extra.len(),
+            drift.modified.len()
+        );
+        
+        if mode == ReconcileMode::Repair {
+            self.repair_drift(device_id, &drift).await?;
+        }
+        
+        Ok(drift)
+    }
+    
+    /// 差分をDELETE → MODIFY → INSERTの段階ごとに別々のWriteで修正する
+    ///
+    /// 失敗した更新があっても残りの段階は送り、Write自体が失敗したらそこで止める。
+    /// 失敗があればデバイスを読み直し、反映できた更新の数と残った差分をエラーで返す。
+    async fn repair_drift(&self, device_id: DeviceId, drift: &TableDrift) -> Result<()> {
+        let mut applied = 0;
+        let mut errors = Vec::new();
+        for updates in drift.to_update_phases() {
+            if updates.is_empty() {
+                continue;
+            }
+            match self.device_manager.write_to_device(device_id, &updates).await {
+                Ok(result) => {
+                    applied += result.succeeded();
+                    if let Err(e) = result.into_result() {
+                        errors.push(e.to_string());
+                    }
+                }
+                Err(e) => {
+                    errors.push(e.to_string());
+                    break;
+                }
+            }
+        }
+        
+        if errors.is_empty() {
+            info!("Repaired device {} with {} updates", device_id, applied);
+            return Ok(());
+        }
+        
+        let device_entries = self.device_manager.read_table_entries_from_device(device_id).await?;
+        let remaining = self.table_manager.diff_device_entries(device_id, &device_entries).await?;
+        Err(anyhow::anyhow!(
+            "Repair of device {} applied {} of {} updates; {} missing, {} extra, {} modified remain: {}",
+            device_id,
+            applied,
+            drift.len(),
+            remaining.missing.len(),
+            remaining.extra.len(),
+            remaining.modified.len(),
+            errors.join("; ")
+        ))
+    }
+    
+    /// デバイスからコントローラーに送られたパケットを購読
+    pub async fn subscribe_packet_in(&self, device_id: DeviceId) -> Result<tokio::sync::broadcast::Receiver<PacketIn>> {
+        self.device_manager.subscribe_packet_in(device_id).await
+    }
+    
+    /// デバイスからパケットを送信
+    pub async fn send_packet_out(&self, device_id: DeviceId, packet: &PacketOut) -> Result<()> {
+        self.device_manager.send_packet_out(device_id, packet).await
+    }
+    
     /// ルートを追加
     pub async fn add_route(&self, route: RouteEntry) -> Result<()> {
         info!("Adding route: {}/{}", route.prefix, route.prefix_len);
@@ -94,22 +266,62 @@ impl P4Controller {
         // ルーティングマネージャーに追加
         self.routing_manager.add_route(route.clone()).await?;
         
-        // 全接続デバイスにルートを適用
-        self.apply_route_to_all_devices(&route).await?;
+        // 最良ルートが変わった可能性があるため、全接続デバイスのプレフィックスを再設定
+        let failures = self.reprogram_prefix_on_all_devices(route.prefix, route.prefix_len).await;
+        route_failures_to_result(route.prefix, route.prefix_len, "added", failures)?;
         
         info!("Route added successfully");
         Ok(())
     }
     
-    /// ルートを削除
-    pub async fn remove_route(&self, prefix: Ipv4Address, prefix_len: u8) -> Result<()> {
+    /// ルートを削除（ネクストホップを指定しなければプレフィックスの全候補を削除）
+    pub async fn remove_route(&self, prefix: Ipv4Address, prefix_len: u8, next_hop: Option<Ipv4Address>) -> Result<()> {
         info!("Removing route: {}/{}", prefix, prefix_len);
         
         // ルーティングマネージャーから削除
-        self.routing_manager.remove_route(prefix, prefix_len).await?;
+        let removed = self.routing_manager.remove_route(prefix, prefix_len, next_hop).await?;
         
-        // 全接続デバイスからルートを削除
-        self.remove_route_from_all_devices(prefix, prefix_len).await?;
+        // 残った候補があればそれに切り替え、なければ全接続デバイスからルートを削除
+        let failures = self.reprogram_prefix_on_all_devices(prefix, prefix_len).await;
+        if !failures.is_empty() {
+            // デバイスに残ったエントリとRIBが食い違わないよう、削除した候補を戻して元の最良ルートを再設定する
+            for route in removed {
+                self.routing_manager.add_route(route).await?;
+            }
+            self.reprogram_prefix_on_all_devices(prefix, prefix_len).await;
+            return Err(route_removal_failed(prefix, prefix_len, failures));
+        }
+        
+        info!("Route removed successfully");
+        Ok(())
+    }
+    
+    /// IPv6ルートを追加
+    pub async fn add_ipv6_route(&self, route: Ipv6RouteEntry) -> Result<()> {
+        info!("Adding route: {}/{}", route.prefix, route.prefix_len);
+        
+        self.routing_manager.add_ipv6_route(route.clone()).await?;
+        let failures = self.reprogram_ipv6_prefix_on_all_devices(route.prefix, route.prefix_len).await;
+        route_failures_to_result(route.prefix, route.prefix_len, "added", failures)?;
+        
+        info!("Route added successfully");
+        Ok(())
+    }
+    
+    /// IPv6ルートを削除（ネクストホップを指定しなければプレフィックスの全候補を削除）
+    pub async fn remove_ipv6_route(&self, prefix: Ipv6Address, prefix_len: u8, next_hop: Option<Ipv6Address>) -> Result<()> {
+        info!("Removing route: {}/{}", prefix, prefix_len);
+        
+        let removed = self.routing_manager.remove_ipv6_route(prefix, prefix_len, next_hop).await?;
+        
+        let failures = self.reprogram_ipv6_prefix_on_all_devices(prefix, prefix_len).await;
+        if !failures.is_empty() {
+            for route in removed {
+                self.routing_manager.add_ipv6_route(route).await?;
+            }
+            self.reprogram_ipv6_prefix_on_all_devices(prefix, prefix_len).await;
+            return Err(route_removal_failed(prefix, prefix_len, failures));
+        }
         
         info!("Route removed successfully");
         Ok(())
@@ -129,6 +341,39 @@ impl P4Controller {
         Ok(())
     }
     
+    /// ARPエントリを削除し、そのMACアドレスを使うエントリを再設定
+    pub async fn remove_arp_entry(&self, ip: Ipv4Address) -> Result<()> {
+        info!("Removing ARP entry: {}", ip);
+        
+        if let Some(entry) = self.routing_manager.remove_arp_entry(ip).await {
+            self.reprogram_entries_using_mac(entry.mac).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// 近隣キャッシュにエントリを追加し、そのネクストホップを使うIPv6ルートをインストール
+    pub async fn add_neighbor_entry(&self, entry: NeighborEntry) -> Result<()> {
+        info!("Adding neighbor entry: {} -> {}", entry.ip, entry.mac);
+        
+        let ip = entry.ip;
+        self.routing_manager.add_neighbor_entry(entry).await;
+        self.reprogram_ipv6_routes_via(ip).await;
+        
+        Ok(())
+    }
+    
+    /// 近隣キャッシュからエントリを削除し、そのネクストホップを使うIPv6ルートを取り下げる
+    pub async fn remove_neighbor_entry(&self, ip: Ipv6Address) -> Result<()> {
+        info!("Removing neighbor entry: {}", ip);
+        
+        if self.routing_manager.remove_neighbor_entry(ip).await.is_some() {
+            self.reprogram_ipv6_routes_via(ip).await;
+        }
+        
+        Ok(())
+    }
+    
     /// ポートを追加
     pub async fn add_port(&self, port: PortInfo) -> Result<()> {
         info!("Adding port: {} ({})", port.port_id, port.name);
@@ -145,7 +390,18 @@ impl P4Controller {
         info!("Updating port {} status: {}", port_id, if is_up { "UP" } else { "DOWN" });
         
         // ルーティングマネージャーで状態を更新
-        self.routing_manager.update_port_status(port_id, is_up).await?;
+        if !self.routing_manager.update_port_status(port_id, is_up).await? {
+            return Ok(());
+        }
+        
+        if is_up {
+            // ダウン中に取り下げたエントリを再インストール
+            self.apply_routing_table_to_all_devices().await?;
+        } else {
+            // このポートに転送するエントリを取り下げるか、別のルートに切り替える
+            self.withdraw_entries_via_port(port_id).await;
+            self.withdraw_ipv6_entries_via_port(port_id).await;
+        }
         
         info!("Port status updated successfully");
         Ok(())
@@ -153,88 +409,1244 @@ impl P4Controller {
     
     /// ルートを特定のデバイスに適用
     async fn apply_route_to_device(&self, device_id: DeviceId, route: &RouteEntry) -> Result<()> {
-        if let Some(table_entry) = self.routing_manager.convert_route_to_table_entry(route, device_id).await? {
-            self.table_manager.add_ipv4_lpm_entry(
-                device_id,
-                table_entry.key.ipv4_dst,
-                table_entry.key.prefix_len,
-                table_entry.action.clone(),
-                table_entry.priority,
-            ).await?;
-            
-            // デバイスにテーブルエントリを書き込み
-            self.device_manager.write_table_entries_to_device(
-                device_id,
-                &[table_entry],
-            ).await?;
+        if route.is_ecmp() {
+            return self.apply_ecmp_route_to_device(device_id, route).await;
+        }
+        
+        let Some(table_entry) = self.routing_manager.convert_route_to_table_entry(route, device_id).await? else {
+            // ネクストホップのARPが未解決なら解決を要求し、応答を受けてからインストールする
+            if let Some(next_hop) = route.next_hop {
+                if self.routing_manager.is_interface_up(&route.interface).await {
+                    self.arp_resolver.request(next_hop, &route.interface).await;
+                }
+            }
+            // インストールできるまでは以前のエントリを取り下げ、より短いプレフィックスのルートで転送させる
+            return self.remove_route_from_device(device_id, route.prefix, route.prefix_len).await;
+        };
+        
+        // ECMPルートから切り替わる場合は、書き込んだ後に使われなくなったグループを削除
+        let previous_group = self.ecmp_group_of(device_id, &table_entry).await?;
+        
+        // 変更がなければ書き込まない（既存のキーはMODIFYで更新）
+        if let Some(staged) = self.table_manager.stage_entry(device_id, table_entry).await? {
+            self.write_staged_updates(device_id, &[staged]).await?;
+        }
+        
+        if let Some(group_id) = previous_group {
+            self.release_ecmp_group(device_id, group_id).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// ECMPルートをアクションセレクターのグループとして特定のデバイスに適用
+    ///
+    /// ARPが解決済みでインターフェースがアップしているネクストホップだけをメンバーにし、
+    /// 使えるネクストホップがなければ取り下げる。
+    async fn apply_ecmp_route_to_device(&self, device_id: DeviceId, route: &RouteEntry) -> Result<()> {
+        let (actions, unresolved) = self.routing_manager.resolve_ecmp_next_hops(route).await;
+        for (next_hop, interface) in unresolved {
+            self.arp_resolver.request(next_hop, &interface).await;
+        }
+        if actions.is_empty() {
+            return self.remove_route_from_device(device_id, route.prefix, route.prefix_len).await;
+        }
+        
+        let key = TableKey {
+            ipv4_dst: route.prefix,
+            prefix_len: route.prefix_len,
+        };
+        let group_id = match self.ecmp_group_of(device_id, &TableEntry::ipv4_lpm(key.clone(), TableAction::Drop, 0)).await? {
+            Some(group_id) => group_id,
+            None => self.action_profile_manager.next_group_id(device_id, ECMP_SELECTOR).await,
+        };
+        
+        let calls: Vec<ActionCall> = actions.iter().map(TableAction::to_action_call).collect();
+        let changes = self
+            .action_profile_manager
+            .set_group(device_id, ECMP_SELECTOR, group_id, &calls)
+            .await?;
+        
+        let mut table_updates = Vec::new();
+        let entries = [
+            TableEntry::ecmp_group(group_id),
+            TableEntry::ipv4_lpm(key, TableAction::SetEcmpGroup { group_id }, 0),
+        ];
+        for entry in entries {
+            if let Some(staged) = self.table_manager.stage_entry(device_id, entry).await? {
+                table_updates.push(staged);
+            }
+        }
+        
+        self.write_action_profile_changes(device_id, changes, table_updates).await
+    }
+    
+    /// デバイスのipv4_lpmエントリが参照しているECMPグループ
+    async fn ecmp_group_of(&self, device_id: DeviceId, key: &TableEntry) -> Result<Option<u32>> {
+        let entry = self.table_manager.get_entry(device_id, key).await?;
+        Ok(match entry.and_then(|e| e.forwarding_action()) {
+            Some(TableAction::SetEcmpGroup { group_id }) => Some(group_id),
+            _ => None,
+        })
+    }
+    
+    /// 使われなくなったECMPグループと、そのグループのecmp_groupエントリをデバイスから削除
+    async fn release_ecmp_group(&self, device_id: DeviceId, group_id: u32) -> Result<()> {
+        let mut table_updates = Vec::new();
+        if let Some(entry) = self.table_manager.remove_entry(device_id, &TableEntry::ecmp_group(group_id)).await? {
+            table_updates.push(StagedUpdate::delete(entry));
+        }
+        let changes = self
+            .action_profile_manager
+            .remove_group(device_id, ECMP_SELECTOR, group_id)
+            .await;
+        
+        self.write_action_profile_changes(device_id, changes, table_updates).await?;
+        info!("Released ECMP group {} on device {}", group_id, device_id);
+        Ok(())
+    }
+    
+    /// アクションプロファイルの変更とテーブルの更新を、参照される側が先になる順に別々のWriteで送る
+    ///
+    /// 1つのWriteRequest内の更新は順不同で処理されるため、メンバー → グループ → テーブルエントリ →
+    /// （削除する）グループ → メンバーの順に書き込む。テーブルの更新を書き込めなかった場合は
+    /// （それより前の段階で失敗して送らなかった場合も）シャドウを元に戻す。
+    /// メンバーとグループは書き込めた更新だけをアクションプロファイルマネージャーに反映する。
+    async fn write_action_profile_changes(
+        &self,
+        device_id: DeviceId,
+        changes: ActionProfileChanges,
+        table_updates: Vec<StagedUpdate>,
+    ) -> Result<()> {
+        let (group_deletes, group_writes): (Vec<EntityUpdate>, Vec<EntityUpdate>) = changes
+            .group_update
+            .into_iter()
+            .partition(|u| u.update_type == UpdateType::Delete);
+        
+        for updates in [changes.member_inserts, group_writes] {
+            if let Err(e) = self.write_action_profile_updates(device_id, updates).await {
+                self.revert_staged_updates(device_id, &table_updates).await;
+                return Err(e);
+            }
+        }
+        
+        self.write_staged_updates(device_id, &table_updates).await?;
+        
+        for updates in [group_deletes, changes.member_deletes] {
+            self.write_action_profile_updates(device_id, updates).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// メンバー・グループの更新をデバイスに書き込み、書き込めた更新だけをアクションプロファイルマネージャーに反映
+    ///
+    /// NOT_FOUNDになったDELETEはデバイスにもないので、成功として扱う。
+    /// Write自体が失敗した場合はどれも反映しない（デバイスに残った分は再接続時の読み戻しで引き継ぐ）。
+    async fn write_action_profile_updates(&self, device_id: DeviceId, updates: Vec<EntityUpdate>) -> Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+        
+        let mut result = self.device_manager.write_entities_to_device(device_id, &updates).await?;
+        result.failures.retain(|failure| !failure.is_already_deleted());
+        let written: Vec<EntityUpdate> = updates
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| !result.failures.iter().any(|failure| failure.index == *index))
+            .map(|(_, update)| update)
+            .collect();
+        self.action_profile_manager.commit(device_id, &written).await;
+        result.into_result()?;
+        Ok(())
+    }
+    
+    /// ECMPの最良ルートをすべて特定のデバイスに適用
+    async fn apply_ecmp_routes_to_device(&self, device_id: DeviceId) -> Result<()> {
+        for route in self.routing_manager.best_routes().await {
+            if route.is_ecmp() {
+                self.apply_ecmp_route_to_device(device_id, &route).await?;
+            }
         }
         
         Ok(())
     }
     
-    /// ルートを全デバイスに適用
-    async fn apply_route_to_all_devices(&self, route: &RouteEntry) -> Result<()> {
-        let devices = self.device_manager.list_devices().await;
+    /// シャドウに反映済みの更新をデバイスに書き込み、書き込めなかった更新はシャドウを元に戻す
+    ///
+    /// NOT_FOUNDになったDELETEはデバイスにもエントリがないので、成功として扱う。
+    /// Write自体が失敗した場合はどの更新が反映されたか分からないため、すべて戻す
+    /// （デバイスと食い違った分は `reconcile_device` で検出できる）。
+    async fn write_staged_updates(&self, device_id: DeviceId, staged: &[StagedUpdate]) -> Result<()> {
+        if staged.is_empty() {
+            return Ok(());
+        }
+        
+        let updates: Vec<TableUpdate> = staged.iter().map(|s| s.update.clone()).collect();
+        match self.device_manager.write_to_device(device_id, &updates).await {
+            Ok(mut result) => {
+                result.failures.retain(|failure| {
+                    if failure.is_already_deleted() {
+                        warn!("{} was already deleted from device {}", failure.update.entity, device_id);
+                    }
+                    !failure.is_already_deleted()
+                });
+                let failed: Vec<StagedUpdate> = result
+                    .failures
+                    .iter()
+                    .filter_map(|failure| staged.get(failure.index).cloned())
+                    .collect();
+                self.revert_staged_updates(device_id, &failed).await;
+                result.into_result()?;
+                Ok(())
+            }
+            Err(e) => {
+                self.revert_staged_updates(device_id, staged).await;
+                Err(e)
+            }
+        }
+    }
+    
+    /// 書き込めなかった更新をシャドウから取り消す（取り消しの失敗はログに残して続ける）
+    async fn revert_staged_updates(&self, device_id: DeviceId, staged: &[StagedUpdate]) {
+        for staged in staged {
+            if let Err(e) = self.table_manager.revert_update(device_id, staged).await {
+                error!("Failed to revert {} entry in shadow of device {}: {}", staged.update.entry.table, device_id, e);
+            }
+        }
+    }
+    
+    /// プレフィックスの最良ルートをデバイスに適用（インストールできる候補がなければ取り下げる）
+    async fn reprogram_prefix(&self, device_id: DeviceId, prefix: Ipv4Address, prefix_len: u8) -> Result<()> {
+        // 圧縮したFIBではエントリとプレフィックスが対応しないため、FIB全体を圧縮し直す
+        if self.fib_compression {
+            return self.sync_compressed_fib(device_id).await.map(|_| ());
+        }
+        
+        // ARPが未解決で最良ルートに選ばれなかった候補も、解決を要求して応答を受けたら切り替える
+        for (next_hop, interface) in self.routing_manager.unresolved_next_hops_of(prefix, prefix_len).await {
+            self.arp_resolver.request(next_hop, &interface).await;
+        }
+        match self.routing_manager.best_route(prefix, prefix_len).await {
+            Some(route) => self.apply_route_to_device(device_id, &route).await,
+            None => self.remove_route_from_device(device_id, prefix, prefix_len).await,
+        }
+    }
+    
+    /// プレフィックスの最良ルートをプライマリの全デバイスに適用（失敗したデバイスとエラーを返す）
+    async fn reprogram_prefix_on_all_devices(&self, prefix: Ipv4Address, prefix_len: u8) -> Vec<(DeviceId, anyhow::Error)> {
+        let devices = self.device_manager.list_primary_devices().await;
+        let mut failures = Vec::new();
         
         for device in devices {
-            if let Err(e) = self.apply_route_to_device(device.device_id, route).await {
-                error!("Failed to apply route to device {}: {}", device.device_id, e);
+            if let Err(e) = self.reprogram_prefix(device.device_id, prefix, prefix_len).await {
+                error!("Failed to reprogram {}/{} on device {}: {}", prefix, prefix_len, device.device_id, e);
+                failures.push((device.device_id, e));
             }
         }
         
+        failures
+    }
+    
+    /// IPv6ルートを特定のデバイスに適用（ネクストホップが未解決ならNDPで解決を要求し、取り下げる）
+    async fn apply_ipv6_route_to_device(&self, device_id: DeviceId, route: &Ipv6RouteEntry) -> Result<()> {
+        let Some(table_entry) = self.routing_manager.convert_ipv6_route_to_table_entry(route).await else {
+            if let Some(next_hop) = route.next_hop {
+                if self.routing_manager.is_interface_up(&route.interface).await {
+                    self.ndp_resolver.request(next_hop, &route.interface).await;
+                }
+            }
+            return self.remove_ipv6_route_from_device(device_id, route.prefix, route.prefix_len).await;
+        };
+        
+        if let Some(staged) = self.table_manager.stage_entry(device_id, table_entry).await? {
+            self.write_staged_updates(device_id, &[staged]).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// IPv6プレフィックスの最良ルートをデバイスに適用（候補がなければ取り下げる）
+    async fn reprogram_ipv6_prefix(&self, device_id: DeviceId, prefix: Ipv6Address, prefix_len: u8) -> Result<()> {
+        match self.routing_manager.best_ipv6_route(prefix, prefix_len).await {
+            Some(route) => self.apply_ipv6_route_to_device(device_id, &route).await,
+            None => self.remove_ipv6_route_from_device(device_id, prefix, prefix_len).await,
+        }
+    }
+    
+    /// IPv6プレフィックスの最良ルートをプライマリの全デバイスに適用（失敗したデバイスとエラーを返す）
+    async fn reprogram_ipv6_prefix_on_all_devices(&self, prefix: Ipv6Address, prefix_len: u8) -> Vec<(DeviceId, anyhow::Error)> {
+        let devices = self.device_manager.list_primary_devices().await;
+        let mut failures = Vec::new();
+        
+        for device in devices {
+            if let Err(e) = self.reprogram_ipv6_prefix(device.device_id, prefix, prefix_len).await {
+                error!("Failed to reprogram {}/{} on device {}: {}", prefix, prefix_len, device.device_id, e);
+                failures.push((device.device_id, e));
+            }
+        }
+        
+        failures
+    }
+    
+    /// IPv6ネクストホップを経由するルートのプレフィックスを全デバイスで再設定
+    async fn reprogram_ipv6_routes_via(&self, next_hop: Ipv6Address) {
+        for route in self.routing_manager.ipv6_routes_via_next_hop(next_hop).await {
+            self.reprogram_ipv6_prefix_on_all_devices(route.prefix, route.prefix_len).await;
+        }
+    }
+    
+    /// デバイスからIPv6ルートを削除
+    async fn remove_ipv6_route_from_device(&self, device_id: DeviceId, prefix: Ipv6Address, prefix_len: u8) -> Result<()> {
+        let key = Ipv6TableKey {
+            ipv6_dst: prefix,
+            prefix_len,
+        };
+        let Some(entry) = self
+            .table_manager
+            .remove_entry(device_id, &TableEntry::ipv6_lpm(key, TableAction::Drop, 0))
+            .await?
+        else {
+            return Ok(());
+        };
+        
+        // デバイスにDELETEを送信し、失敗したらシャドウを元に戻す
+        self.write_staged_updates(device_id, &[StagedUpdate::delete(entry.clone())]).await?;
+        
+        info!("Removed route {}/{} from device {}", prefix, prefix_len, device_id);
         Ok(())
     }
     
     /// デバイスからルートを削除
     async fn remove_route_from_device(&self, device_id: DeviceId, prefix: Ipv4Address, prefix_len: u8) -> Result<()> {
-        self.table_manager.remove_ipv4_lpm_entry(device_id, prefix, prefix_len).await?;
+        let key = TableKey {
+            ipv4_dst: prefix,
+            prefix_len,
+        };
+        let Some(entry) = self
+            .table_manager
+            .remove_entry(device_id, &TableEntry::ipv4_lpm(key, TableAction::Drop, 0))
+            .await?
+        else {
+            return Ok(());
+        };
+        
+        // デバイスにDELETEを送信し、失敗したらシャドウを元に戻す
+        self.write_staged_updates(device_id, &[StagedUpdate::delete(entry.clone())]).await?;
         
-        // デバイスからテーブルエントリを削除
-        // 実際のP4Runtimeでは、WriteRequestでDELETE操作を送信
         info!("Removed route {}/{} from device {}", prefix, prefix_len, device_id);
         
+        if let Some(TableAction::SetEcmpGroup { group_id }) = entry.forwarding_action() {
+            self.release_ecmp_group(device_id, group_id).await?;
+        }
         Ok(())
     }
     
-    /// 全デバイスからルートを削除
-    async fn remove_route_from_all_devices(&self, prefix: Ipv4Address, prefix_len: u8) -> Result<()> {
-        let devices = self.device_manager.list_devices().await;
+    /// ルーティングテーブルを特定のデバイスに適用
+    async fn apply_routing_table_to_device(&self, device_id: DeviceId) -> Result<()> {
+        self.request_unresolved_next_hops().await;
+        let mut table_entries = self.routing_manager.convert_all_ipv6_routes_to_table_entries().await;
+        if self.fib_compression {
+            self.sync_compressed_fib(device_id).await?;
+        } else {
+            table_entries.extend(self.routing_manager.convert_all_routes_to_table_entries(device_id).await?);
+        }
         
-        for device in devices {
-            if let Err(e) = self.remove_route_from_device(device.device_id, prefix, prefix_len).await {
-                error!("Failed to remove route from device {}: {}", device.device_id, e);
+        // テーブルマネージャーに追加し、変更のあったエントリだけを集める
+        let (updates, rejected) = self.stage_entries(device_id, table_entries).await?;
+        warn_rejected_entries(device_id, &rejected);
+        
+        // デバイスにテーブルエントリを書き込み
+        self.write_staged_updates(device_id, &updates).await?;
+        
+        self.apply_ecmp_routes_to_device(device_id).await
+    }
+    
+    /// ルーティングテーブルをデバイスのシャドウに読み込む（デバイスには書き込まない）
+    async fn load_routing_table_into_shadow(&self, device_id: DeviceId) -> Result<()> {
+        self.request_unresolved_next_hops().await;
+        let mut table_entries = self.routing_manager.convert_all_routes_to_table_entries(device_id).await?;
+        if self.fib_compression {
+            table_entries = compress_ipv4_fib(table_entries, Vec::new()).entries;
+        }
+        table_entries.extend(self.routing_manager.convert_all_ipv6_routes_to_table_entries().await);
+        
+        let (_, rejected) = self.stage_entries(device_id, table_entries).await?;
+        warn_rejected_entries(device_id, &rejected);
+        Ok(())
+    }
+    
+    /// エントリをシャドウに追加し、デバイスに送るべき更新と、テーブルが満杯で追加できなかったエントリを返す
+    async fn stage_entries(
+        &self,
+        device_id: DeviceId,
+        entries: impl IntoIterator<Item = TableEntry>,
+    ) -> Result<(Vec<StagedUpdate>, Vec<TableEntry>)> {
+        let mut updates = Vec::new();
+        let mut rejected = Vec::new();
+        for entry in entries {
+            match self.table_manager.stage_entry(device_id, entry.clone()).await {
+                Ok(Some(update)) => updates.push(update),
+                Ok(None) => {}
+                Err(e) if is_table_full(&e) => rejected.push(entry),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((updates, rejected))
+    }
+    
+    /// 圧縮したFIBをデバイスのipv4_lpmに適用し、圧縮の結果を返す
+    ///
+    /// ECMPルートは従来どおりプレフィックスごとにグループとして書き込み、圧縮では削除もまとめもしない
+    /// エントリとして扱う。新しいエントリと変更を書き込んでから、不要になったエントリを削除する。
+    /// テーブルが満杯で追加できなかったエントリは、削除で空きができてからもう一度追加する。
+    async fn sync_compressed_fib(&self, device_id: DeviceId) -> Result<FibCompression> {
+        self.request_unresolved_next_hops().await;
+        
+        // 最良ルートがECMPでなくなったプレフィックスは、グループごと取り下げる
+        for entry in self.table_manager.get_ipv4_lpm_entries(device_id).await? {
+            let (Some(key), Some(TableAction::SetEcmpGroup { .. })) = (entry.ipv4_lpm_key(), entry.forwarding_action()) else {
+                continue;
+            };
+            let best = self.routing_manager.best_route(key.ipv4_dst, key.prefix_len).await;
+            if !best.is_some_and(|route| route.is_ecmp()) {
+                self.remove_route_from_device(device_id, key.ipv4_dst, key.prefix_len).await?;
+            }
+        }
+        self.apply_ecmp_routes_to_device(device_id).await?;
+        
+        let current = self.table_manager.get_ipv4_lpm_entries(device_id).await?;
+        let compression = self.fib_compression_report(device_id).await?;
+        
+        let (updates, deferred) = self.stage_entries(device_id, compression.entries.iter().cloned()).await?;
+        self.write_staged_updates(device_id, &updates).await?;
+        
+        // 圧縮後のFIBにないエントリは、新しいエントリで転送できるようになってから削除する
+        let networks: HashSet<Ipv4Net> = compression
+            .entries
+            .iter()
+            .filter_map(|entry| entry.ipv4_lpm_key())
+            .filter_map(|key| key.ipv4_dst.network(key.prefix_len))
+            .collect();
+        let mut deletes = Vec::new();
+        for entry in current {
+            let Some(key) = entry.ipv4_lpm_key() else { continue };
+            if key.ipv4_dst.network(key.prefix_len).is_some_and(|network| networks.contains(&network)) {
+                continue;
+            }
+            if let Some(removed) = self.table_manager.remove_entry(device_id, &entry).await? {
+                deletes.push(StagedUpdate::delete(removed));
+            }
+        }
+        self.write_staged_updates(device_id, &deletes).await?;
+        
+        if !deferred.is_empty() {
+            let (updates, rejected) = self.stage_entries(device_id, deferred).await?;
+            self.write_staged_updates(device_id, &updates).await?;
+            warn_rejected_entries(device_id, &rejected);
+        }
+        
+        info!(
+            "Programmed compressed FIB on device {}: {} entries for {} routes ({} saved)",
+            device_id,
+            compression.entries.len(),
+            compression.original_count,
+            compression.saved()
+        );
+        Ok(compression)
+    }
+    
+    /// デバイスのipv4_lpmに書き込むFIBを圧縮して求める（デバイスには書き込まない）
+    ///
+    /// デバイスに書き込み済みのECMPグループのエントリは、削除もまとめもしないエントリとして扱う。
+    pub async fn fib_compression_report(&self, device_id: DeviceId) -> Result<FibCompression> {
+        let ecmp_entries = self
+            .table_manager
+            .get_ipv4_lpm_entries(device_id)
+            .await?
+            .into_iter()
+            .filter(|entry| matches!(entry.forwarding_action(), Some(TableAction::SetEcmpGroup { .. })))
+            .collect();
+        let entries = self.routing_manager.convert_all_routes_to_table_entries(device_id).await?;
+        Ok(compress_ipv4_fib(entries, ecmp_entries))
+    }
+    
+    /// 圧縮したFIBをプライマリの全デバイスに適用
+    async fn sync_compressed_fib_on_all_devices(&self) {
+        for device in self.device_manager.list_primary_devices().await {
+            if let Err(e) = self.sync_compressed_fib(device.device_id).await {
+                error!("Failed to program compressed FIB on device {}: {}", device.device_id, e);
+            }
+        }
+    }
+    
+    /// ABACポリシーをコンパイルし、全デバイスのABACポリシーテーブルに適用
+    ///
+    /// ルートと同じくTableManagerのシャドウを通して書き込み、前のポリシーにしかないエントリは削除する。
+    /// 配置が変わっても前のポリシーのエントリが新しい配置のビット列に使われないよう、前のポリシーと違う版でエンコードし、
+    /// 新しいポリシーのエントリを追加 → 全プレフィックスとサービスを新しい版で分類し直す → 前のポリシーのエントリを削除
+    /// の順に書き込む（分類し直している間、主体と対象の版が食い違うパケットは破棄される）。
+    /// 途中で失敗した場合は前のポリシーを適用中のままにし、もう一度適用すれば同じ版で続きから書き込む。
+    pub async fn apply_policy(&self, policy: &Policy, environment: &Environment) -> Result<CompiledPolicy> {
+        info!("Applying ABAC policy {}", policy.name);
+        let version = self
+            .abac_policy
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|current| !current.layout.version());
+        let compiled = compile_policy_with_version(policy, environment, version)?;
+        
+        let mut failures = Vec::new();
+        for device in self.device_manager.list_primary_devices().await {
+            if let Err(e) = self.install_policy_entries(device.device_id, &compiled.entries).await {
+                error!("Failed to install ABAC policy on device {}: {}", device.device_id, e);
+                failures.push(format!("device {}: {}", device.device_id, e));
+            }
+        }
+        if failures.is_empty() {
+            // 新しい版のサービスの分類エントリは、新しい版に分類し直した対象だけを書き換える
+            if let Err(e) = self.add_service_classifiers(&compiled.services).await {
+                failures.push(e.to_string());
+            }
+            let mut prefixes: Vec<Ipv4Net> = self.attribute_store.list().await.into_iter().map(|(prefix, _)| prefix).collect();
+            if !prefixes.contains(&Ipv4Net::default()) {
+                prefixes.insert(0, Ipv4Net::default());
+            }
+            if let Err(e) = self.reclassify_prefixes_with(&compiled.layout, prefixes).await {
+                failures.push(e.to_string());
             }
         }
+        if !failures.is_empty() {
+            return Err(anyhow::anyhow!(
+                "ABAC policy {} was compiled but applying it failed on {} (the previous policy stays in effect)",
+                policy.name,
+                failures.join("; ")
+            ));
+        }
         
+        *self.abac_policy.write().await = Some(compiled.clone());
+        let mut failures = Vec::new();
+        if let Err(e) = self.remove_service_classifiers_except(&compiled.services).await {
+            failures.push(e.to_string());
+        }
+        for device in self.device_manager.list_primary_devices().await {
+            if let Err(e) = self.sync_policy_to_device(device.device_id).await {
+                error!("Failed to remove the previous ABAC policy from device {}: {}", device.device_id, e);
+                failures.push(format!("device {}: {}", device.device_id, e));
+            }
+        }
+        if !failures.is_empty() {
+            return Err(anyhow::anyhow!(
+                "ABAC policy {} was applied but removing the previous entries failed on {}",
+                policy.name,
+                failures.join("; ")
+            ));
+        }
+        
+        info!(
+            "Applied ABAC policy {}: {} entries for {} rules ({} inactive)",
+            policy.name,
+            compiled.entries.len(),
+            compiled.rules.len(),
+            compiled.inactive_rules.len()
+        );
+        Ok(compiled)
+    }
+    
+    /// ポリシーのエントリをデバイスに追加（前のポリシーのエントリは残す）
+    ///
+    /// テーブルに入りきらなかったエントリは、前のポリシーのエントリを削除してから `sync_policy_to_device` で入れる。
+    async fn install_policy_entries(&self, device_id: DeviceId, entries: &[TableEntry]) -> Result<()> {
+        let (updates, rejected) = self.stage_entries(device_id, entries.iter().cloned()).await?;
+        self.write_staged_updates(device_id, &updates).await?;
+        if !rejected.is_empty() {
+            warn!(
+                "{} ABAC policy entries do not fit on device {} until the previous policy is removed",
+                rejected.len(),
+                device_id
+            );
+        }
         Ok(())
     }
     
-    /// ルーティングテーブルを特定のデバイスに適用
-    async fn apply_routing_table_to_device(&self, device_id: DeviceId) -> Result<()> {
-        let table_entries = self.routing_manager.convert_all_routes_to_table_entries(device_id).await?;
-        
-        if !table_entries.is_empty() {
-            // テーブルマネージャーに追加
-            for entry in &table_entries {
-                self.table_manager.add_ipv4_lpm_entry(
-                    device_id,
-                    entry.key.ipv4_dst,
-                    entry.key.prefix_len,
-                    entry.action.clone(),
-                    entry.priority,
-                ).await?;
+    /// 適用中のABACポリシーをコンパイルした結果を取得
+    pub async fn get_compiled_policy(&self) -> Option<CompiledPolicy> {
+        self.abac_policy.read().await.clone()
+    }
+    
+    /// 適用中のABACポリシーのエントリ（ポリシーがなければ空）
+    async fn policy_entries(&self) -> Vec<TableEntry> {
+        self.abac_policy
+            .read()
+            .await
+            .as_ref()
+            .map(|compiled| compiled.entries.clone())
+            .unwrap_or_default()
+    }
+    
+    /// ABACの分類エントリと適用中のポリシーをデバイスのシャドウに読み込む（デバイスには書き込まない）
+    async fn load_abac_into_shadow(&self, device_id: DeviceId) -> Result<()> {
+        let mut entries: Vec<TableEntry> = self
+            .abac_classifiers
+            .read()
+            .await
+            .iter()
+            .map(|classifier| classifier.to_table_entry())
+            .collect();
+        entries.extend(self.policy_entries().await);
+        
+        let (_, rejected) = self.stage_entries(device_id, entries).await?;
+        warn_rejected_entries(device_id, &rejected);
+        Ok(())
+    }
+    
+    /// ABACの分類エントリを追加（同じキーのエントリは置き換える）し、全デバイスに書き込む
+    pub async fn add_abac_classifier(&self, classifier: AbacClassifier) -> Result<()> {
+        let entry = classifier.to_table_entry();
+        {
+            let mut classifiers = self.abac_classifiers.write().await;
+            classifiers.retain(|existing| !existing.to_table_entry().has_same_key(&entry));
+            classifiers.push(classifier.clone());
+        }
+        
+        let mut failures = Vec::new();
+        for device in self.device_manager.list_primary_devices().await {
+            let result = async {
+                if let Some(staged) = self.table_manager.add_abac_classifier(device.device_id, &classifier).await? {
+                    self.write_staged_updates(device.device_id, &[staged]).await?;
+                }
+                Ok::<_, anyhow::Error>(())
+            }
+            .await;
+            if let Err(e) = result {
+                error!("Failed to program {} entry on device {}: {}", entry.table, device.device_id, e);
+                failures.push(format!("device {}: {}", device.device_id, e));
             }
+        }
+        abac_failures_to_result(&entry, failures)
+    }
+    
+    /// ABACの分類エントリを削除し、全デバイスから削除
+    pub async fn remove_abac_classifier(&self, classifier: &AbacClassifier) -> Result<()> {
+        let entry = classifier.to_table_entry();
+        self.abac_classifiers
+            .write()
+            .await
+            .retain(|existing| !existing.to_table_entry().has_same_key(&entry));
+        
+        let mut failures = Vec::new();
+        for device in self.device_manager.list_primary_devices().await {
+            let result = async {
+                if let Some(removed) = self.table_manager.remove_abac_classifier(device.device_id, classifier).await? {
+                    self.write_staged_updates(device.device_id, &[StagedUpdate::delete(removed)]).await?;
+                }
+                Ok::<_, anyhow::Error>(())
+            }
+            .await;
+            if let Err(e) = result {
+                error!("Failed to remove {} entry from device {}: {}", entry.table, device.device_id, e);
+                failures.push(format!("device {}: {}", device.device_id, e));
+            }
+        }
+        abac_failures_to_result(&entry, failures)
+    }
+    
+    /// ABACの分類エントリ一覧を取得
+    pub async fn list_abac_classifiers(&self) -> Vec<AbacClassifier> {
+        self.abac_classifiers.read().await.clone()
+    }
+    
+    /// ホストまたはプレフィックスの属性を設定（既存の属性にマージ）し、影響を受ける分類エントリを書き直す
+    pub async fn set_attributes(&self, prefix: Ipv4Net, attributes: AttributeSet) -> Result<()> {
+        let affected = self.attribute_store.set_attributes(prefix, attributes).await;
+        self.reclassify_prefixes(affected).await
+    }
+    
+    /// ホストまたはプレフィックスの属性を1つ削除し、影響を受ける分類エントリを書き直す
+    pub async fn remove_attribute(&self, prefix: Ipv4Net, name: &str) -> Result<()> {
+        let affected = self.attribute_store.remove_attribute(prefix, name).await?;
+        self.reclassify_prefixes(affected).await
+    }
+    
+    /// ホストまたはプレフィックスの属性をすべて削除し、影響を受ける分類エントリを書き直す
+    pub async fn remove_attributes(&self, prefix: Ipv4Net) -> Result<()> {
+        let affected = self.attribute_store.remove_prefix(prefix).await?;
+        self.reclassify_prefixes(affected).await
+    }
+    
+    /// ホストまたはプレフィックスに設定された属性と、上位のプレフィックスから引き継いだ属性を含む属性を取得
+    pub async fn get_attributes(&self, prefix: Ipv4Net) -> (Option<AttributeSet>, AttributeSet) {
+        (
+            self.attribute_store.get_attributes(prefix).await,
+            self.attribute_store.effective_attributes(prefix).await,
+        )
+    }
+    
+    /// 属性を設定した全プレフィックスをアドレス順に取得
+    pub async fn list_attributes(&self) -> Vec<(Ipv4Net, AttributeSet)> {
+        self.attribute_store.list().await
+    }
+    
+    /// 全プレフィックスの属性をJSONに書き出す
+    pub async fn export_attributes(&self) -> Result<String> {
+        self.attribute_store.export_json().await
+    }
+    
+    /// JSONから属性を読み込み（`replace` なら既存の属性を置き換え）、影響を受ける分類エントリを書き直す
+    pub async fn import_attributes(&self, text: &str, replace: bool) -> Result<usize> {
+        let affected = self.attribute_store.import_json(text, replace).await?;
+        let count = affected.len();
+        self.reclassify_prefixes(affected).await?;
+        Ok(count)
+    }
+    
+    /// プレフィックスの主体・対象の分類エントリを、属性ストアと適用中のポリシーの配置から計算し直して書き込む
+    ///
+    /// ポリシーが適用されていなければ配置が決まらないので何もしない（適用時に全プレフィックスを分類する）。
+    async fn reclassify_prefixes(&self, prefixes: Vec<Ipv4Net>) -> Result<()> {
+        let Some(layout) = self.abac_policy.read().await.as_ref().map(|compiled| compiled.layout.clone()) else {
+            return Ok(());
+        };
+        self.reclassify_prefixes_with(&layout, prefixes).await
+    }
+    
+    /// プレフィックスの主体・対象の分類エントリを、属性ストアとlayoutの配置から計算し直して書き込む
+    ///
+    /// 分類エントリは上位のプレフィックスから引き継いだ属性と版のビットを含めてエンコードする。
+    /// 0.0.0.0/0 の分類エントリは、属性のないホストにも版のビットを立てるため常に入れる。
+    /// 書き込みに失敗したプレフィックスがあっても残りを分類し、失敗はまとめてエラーにする。
+    async fn reclassify_prefixes_with(&self, layout: &AttributeLayout, prefixes: Vec<Ipv4Net>) -> Result<()> {
+        let mut changed = 0;
+        let mut failures = Vec::new();
+        for prefix in &prefixes {
+            let is_default = prefix.prefix_len() == 0;
+            let stored = self.attribute_store.contains(*prefix).await;
+            let attributes = attribute_strings(&self.attribute_store.effective_attributes(*prefix).await);
             
-            // デバイスにテーブルエントリを書き込み
-            self.device_manager.write_table_entries_to_device(device_id, &table_entries).await?;
+            for category in [AttributeCategory::Subject, AttributeCategory::Object] {
+                let bits = if stored || is_default { layout.encode_prefix(category, &attributes) } else { 0 };
+                let classifier = match category {
+                    AttributeCategory::Subject => AbacClassifier::Subject { prefix: *prefix, attributes: bits },
+                    _ => AbacClassifier::Object { prefix: *prefix, attributes: bits },
+                };
+                let entry = classifier.to_table_entry();
+                
+                let (current, inherited) = {
+                    let classifiers = self.abac_classifiers.read().await;
+                    let current = classifiers
+                        .iter()
+                        .find(|existing| existing.to_table_entry().has_same_key(&entry))
+                        .cloned();
+                    // 上位のプレフィックスに分類エントリがあると、属性が0でもエントリがないとそちらにマッチしてしまう
+                    let inherited = classifiers.iter().any(|existing| match (existing, &classifier) {
+                        (AbacClassifier::Subject { prefix: outer, .. }, AbacClassifier::Subject { .. })
+                        | (AbacClassifier::Object { prefix: outer, .. }, AbacClassifier::Object { .. }) => {
+                            outer.prefix_len() < prefix.prefix_len() && outer.contains(prefix)
+                        }
+                        _ => false,
+                    });
+                    (current, inherited)
+                };
+                
+                let needed = is_default || (stored && (bits != layout.version_bits() || inherited));
+                let result = match current {
+                    Some(current) if needed && current == classifier => continue,
+                    Some(current) if !needed => self.remove_abac_classifier(&current).await,
+                    None if !needed => continue,
+                    _ => self.add_abac_classifier(classifier).await,
+                };
+                match result {
+                    Ok(()) => changed += 1,
+                    Err(e) => {
+                        error!("Failed to reclassify {} {}: {}", category, prefix, e);
+                        failures.push(format!("{} {}: {}", category, prefix, e));
+                    }
+                }
+            }
         }
         
+        info!("Reclassified {} prefixes ({} classification entries changed)", prefixes.len(), changed);
+        if !failures.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Reclassified {} prefixes but {} classification entries failed: {}",
+                prefixes.len(),
+                failures.len(),
+                failures.join("; ")
+            ));
+        }
         Ok(())
     }
     
-    /// ルーティングテーブルを全デバイスに適用
+    /// サービスの分類エントリを追加（同じキーのエントリは置き換える）
+    async fn add_service_classifiers(&self, services: &[AbacClassifier]) -> Result<()> {
+        let current = self.abac_classifiers.read().await.clone();
+        let mut failures = Vec::new();
+        for service in services {
+            if current.contains(service) {
+                continue;
+            }
+            if let Err(e) = self.add_abac_classifier(service.clone()).await {
+                failures.push(e.to_string());
+            }
+        }
+        service_failures_to_result(failures)
+    }
+    
+    /// `services` と同じキーでないサービスの分類エントリ（前のポリシーの版のものなど）を削除
+    async fn remove_service_classifiers_except(&self, services: &[AbacClassifier]) -> Result<()> {
+        let stale: Vec<AbacClassifier> = self
+            .abac_classifiers
+            .read()
+            .await
+            .iter()
+            .filter(|classifier| matches!(classifier, AbacClassifier::Service { .. }))
+            .filter(|classifier| {
+                let entry = classifier.to_table_entry();
+                !services.iter().any(|service| service.to_table_entry().has_same_key(&entry))
+            })
+            .cloned()
+            .collect();
+        
+        let mut failures = Vec::new();
+        for classifier in &stale {
+            if let Err(e) = self.remove_abac_classifier(classifier).await {
+                failures.push(e.to_string());
+            }
+        }
+        service_failures_to_result(failures)
+    }
+    
+    /// 適用中のABACポリシーをデバイスに書き込み、ポリシーにないエントリを削除
+    async fn sync_policy_to_device(&self, device_id: DeviceId) -> Result<()> {
+        let entries = self.policy_entries().await;
+        let current = self.table_manager.get_abac_policy_entries(device_id).await?;
+        
+        let (updates, rejected) = self.stage_entries(device_id, entries.iter().cloned()).await?;
+        self.write_staged_updates(device_id, &updates).await?;
+        
+        // シャドウの値は正規形なので、シャドウに入ったポリシーのエントリと比べる
+        let mut staged = Vec::new();
+        for entry in &entries {
+            staged.extend(self.table_manager.get_entry(device_id, entry).await?);
+        }
+        let mut deletes = Vec::new();
+        for entry in current {
+            if staged.iter().any(|expected| expected.has_same_key(&entry)) {
+                continue;
+            }
+            if let Some(removed) = self.table_manager.remove_entry(device_id, &entry).await? {
+                deletes.push(StagedUpdate::delete(removed));
+            }
+        }
+        self.write_staged_updates(device_id, &deletes).await?;
+        
+        if !rejected.is_empty() {
+            let (updates, rejected) = self.stage_entries(device_id, rejected).await?;
+            self.write_staged_updates(device_id, &updates).await?;
+            warn_rejected_entries(device_id, &rejected);
+        }
+        
+        Ok(())
+    }
+    
+    /// ARP / NDPが未解決のネクストホップすべてについて解決を要求
+    async fn request_unresolved_next_hops(&self) {
+        for (next_hop, interface) in self.routing_manager.unresolved_next_hops().await {
+            self.arp_resolver.request(next_hop, &interface).await;
+        }
+        for (next_hop, interface) in self.routing_manager.unresolved_ipv6_next_hops().await {
+            self.ndp_resolver.request(next_hop, &interface).await;
+        }
+    }
+    
+    /// ARPリクエストを定期的に（バックオフしながら）送信するタスクを開始
+    fn spawn_arp_resolver_task(&self) {
+        let controller = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ARP_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                controller.poll_arp_resolver().await;
+            }
+        });
+    }
+    
+    /// 送信時刻になったARPリクエストを送る
+    async fn poll_arp_resolver(&self) {
+        // ネガティブキャッシュの期限が切れたネクストホップも再び解決を試みる
+        self.request_unresolved_next_hops().await;
+        let poll = self.arp_resolver.poll().await;
+        
+        for (next_hop, interface) in poll.requests {
+            // 解決済み、またはもう使われていないネクストホップは対象外
+            if self.routing_manager.find_arp_entry(next_hop).await.is_some()
+                || self.routing_manager.routes_via_next_hop(next_hop).await.is_empty()
+            {
+                self.arp_resolver.cancel(next_hop).await;
+                continue;
+            }
+            
+            if let Err(e) = self.send_arp_request(next_hop, &interface).await {
+                warn!("Failed to send ARP request for {}: {}", next_hop, e);
+            }
+        }
+        
+        let poll = self.ndp_resolver.poll().await;
+        for (next_hop, interface) in poll.requests {
+            if self.routing_manager.find_neighbor_entry(next_hop).await.is_some()
+                || self.routing_manager.ipv6_routes_via_next_hop(next_hop).await.is_empty()
+            {
+                self.ndp_resolver.cancel(next_hop).await;
+                continue;
+            }
+            
+            if let Err(e) = self.send_neighbor_solicitation(next_hop, &interface).await {
+                warn!("Failed to send neighbor solicitation for {}: {}", next_hop, e);
+            }
+        }
+    }
+    
+    /// インターフェースのポートから全デバイスに近隣要請を送信（送信元はポートのMACから作るリンクローカルアドレス）
+    async fn send_neighbor_solicitation(&self, target: Ipv6Address, interface: &str) -> Result<()> {
+        let port = self
+            .routing_manager
+            .find_port_by_interface(interface)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Unknown interface {}", interface))?;
+        
+        let sender_ip = Ipv6Address::link_local_from_mac(port.mac_address);
+        let solicitation = NdpPacket::solicitation(port.mac_address, sender_ip, target);
+        let packet = PacketOut::to_port(solicitation.to_frame(None), port.port_id);
+        
+        for device in self.device_manager.list_primary_devices().await {
+            if let Err(e) = self.device_manager.send_packet_out(device.device_id, &packet).await {
+                warn!("Failed to send neighbor solicitation via device {}: {}", device.device_id, e);
+            }
+        }
+        
+        info!("Sent neighbor solicitation for {} on {} (port {})", target, interface, port.port_id);
+        Ok(())
+    }
+    
+    /// インターフェースのポートから全デバイスにARPリクエストを送信
+    async fn send_arp_request(&self, target: Ipv4Address, interface: &str) -> Result<()> {
+        let port = self
+            .routing_manager
+            .find_port_by_interface(interface)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Unknown interface {}", interface))?;
+        
+        let sender_ip = port.ip_address.unwrap_or(Ipv4Address::from_u32(0));
+        let request = ArpPacket::request(port.mac_address, sender_ip, target);
+        let packet = PacketOut::to_port(request.to_frame(), port.port_id);
+        
+        for device in self.device_manager.list_primary_devices().await {
+            if let Err(e) = self.device_manager.send_packet_out(device.device_id, &packet).await {
+                warn!("Failed to send ARP request via device {}: {}", device.device_id, e);
+            }
+        }
+        
+        info!("Sent ARP request for {} on {} (port {})", target, interface, port.port_id);
+        Ok(())
+    }
+    
+    /// 動的ARPエントリを定期的に再確認し、応答がなければ削除するタスクを開始
+    fn spawn_arp_aging_task(&self) {
+        let controller = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(controller.arp_aging.interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = controller.age_arp_entries().await {
+                    warn!("ARP aging failed: {}", e);
+                }
+            }
+        });
+    }
+    
+    /// 古い動的ARPエントリを削除し、確認が必要なエントリにARPリクエストを送る（確認したアドレスを返す）
+    async fn age_arp_entries(&self) -> Result<Vec<Ipv4Address>> {
+        let max_age = self.arp_aging.reachable_time + self.arp_aging.probe_time;
+        for entry in self.routing_manager.expire_arp_entries(max_age).await {
+            warn!("ARP entry {} -> {} expired after {:?}", entry.ip, entry.mac, entry.age());
+            self.reprogram_entries_using_mac(entry.mac).await?;
+        }
+        
+        let mut probed = Vec::new();
+        for entry in self.routing_manager.stale_arp_entries(self.arp_aging.reachable_time).await {
+            match self.send_arp_request(entry.ip, &entry.interface).await {
+                Ok(()) => probed.push(entry.ip),
+                Err(e) => warn!("Failed to probe {}: {}", entry.ip, e),
+            }
+        }
+        
+        Ok(probed)
+    }
+    
+    /// MACアドレスを転送先に使っているipv4_lpmエントリを、現在のARPテーブルに合わせて更新または取り下げ
+    ///
+    /// ECMPグループのエントリは常に再設定する（メンバーが変わらなければ何も書き込まない）。
+    async fn reprogram_entries_using_mac(&self, mac: MacAddress) -> Result<()> {
+        if self.fib_compression {
+            self.sync_compressed_fib_on_all_devices().await;
+            return Ok(());
+        }
+        
+        for (device_id, entries) in self.table_manager.get_all_ipv4_lpm_entries().await {
+            for entry in entries {
+                let Some(key) = entry.ipv4_lpm_key() else { continue };
+                match entry.forwarding_action() {
+                    Some(TableAction::Ipv4Forward { dst_mac, .. }) if dst_mac == mac => {}
+                    Some(TableAction::SetEcmpGroup { .. }) => {}
+                    _ => continue,
+                }
+                
+                // 別のMACで解決できればMODIFY、解決できなければ取り下げてネクストホップを再び解決する
+                if let Err(e) = self.reprogram_prefix(device_id, key.ipv4_dst, key.prefix_len).await {
+                    error!("Failed to reprogram {}/{} on device {}: {}", key.ipv4_dst, key.prefix_len, device_id, e);
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// ポートに転送しているipv4_lpmエントリを、現在のルートで置き換えるか取り下げる
+    ///
+    /// ECMPグループのエントリは常に再設定し、ダウンしたポートのメンバーをグループから外す。
+    async fn withdraw_entries_via_port(&self, port_id: PortId) {
+        if self.fib_compression {
+            self.sync_compressed_fib_on_all_devices().await;
+            return;
+        }
+        
+        for (device_id, entries) in self.table_manager.get_all_ipv4_lpm_entries().await {
+            for entry in entries {
+                let Some(key) = entry.ipv4_lpm_key() else { continue };
+                match entry.forwarding_action() {
+                    Some(TableAction::Ipv4Forward { port, .. }) if port == port_id => {}
+                    Some(TableAction::SetEcmpGroup { .. }) => {}
+                    _ => continue,
+                }
+                
+                // 別の候補があればそれに切り替え（MODIFY）、なければ取り下げる（DELETE）
+                match self.reprogram_prefix(device_id, key.ipv4_dst, key.prefix_len).await {
+                    Ok(()) => info!("Updated {}/{} via port {} on device {}", key.ipv4_dst, key.prefix_len, port_id, device_id),
+                    Err(e) => error!(
+                        "Failed to withdraw {}/{} via port {} on device {}: {}",
+                        key.ipv4_dst, key.prefix_len, port_id, device_id, e
+                    ),
+                }
+            }
+        }
+    }
+    
+    /// ポートに転送しているipv6_lpmエントリを、現在のルートで置き換えるか取り下げる
+    async fn withdraw_ipv6_entries_via_port(&self, port_id: PortId) {
+        for (device_id, entries) in self.table_manager.get_all_ipv6_lpm_entries().await {
+            for entry in entries {
+                let Some(TableAction::Ipv6Forward { port, .. }) = entry.forwarding_action() else { continue };
+                let Some(key) = entry.ipv6_lpm_key() else { continue };
+                if port != port_id {
+                    continue;
+                }
+                
+                match self.reprogram_ipv6_prefix(device_id, key.ipv6_dst, key.prefix_len).await {
+                    Ok(()) => info!("Updated {}/{} via port {} on device {}", key.ipv6_dst, key.prefix_len, port_id, device_id),
+                    Err(e) => error!(
+                        "Failed to withdraw {}/{} via port {} on device {}: {}",
+                        key.ipv6_dst, key.prefix_len, port_id, device_id, e
+                    ),
+                }
+            }
+        }
+    }
+    
+    /// デバイスからのPacketInを処理するタスクを開始
+    async fn spawn_packet_in_handler(&self, device_id: DeviceId) -> Result<()> {
+        let mut packets = self.device_manager.subscribe_packet_in(device_id).await?;
+        let controller = self.clone();
+        
+        tokio::spawn(async move {
+            loop {
+                match packets.recv().await {
+                    Ok(packet) => controller.handle_packet_in(packet).await,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Dropped {} packet-in messages from device {}", skipped, device_id);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        
+        Ok(())
+    }
+    
+    /// コントローラー宛てのパケットを処理
+    async fn handle_packet_in(&self, packet: PacketIn) {
+        if let Some(arp) = ArpPacket::parse(&packet.payload) {
+            if let Err(e) = self.handle_arp_packet(&arp, packet.ingress_port()).await {
+                warn!("Failed to handle ARP from {}: {}", arp.sender_ip, e);
+            }
+        } else if let Some(ndp) = NdpPacket::parse(&packet.payload) {
+            if let Err(e) = self.handle_ndp_packet(&ndp, packet.ingress_port()).await {
+                warn!("Failed to handle NDP from {}: {}", ndp.sender_ip, e);
+            }
+        }
+    }
+    
+    /// ARPパケットの送信元を学習し、そのネクストホップを使うルートをインストール
+    async fn handle_arp_packet(&self, arp: &ArpPacket, ingress_port: Option<PortId>) -> Result<()> {
+        let known = self.routing_manager.find_arp_entry(arp.sender_ip).await;
+        let was_pending = self.arp_resolver.resolved(arp.sender_ip).await;
+        
+        // 解決を待っていたネクストホップと既知のエントリの更新だけを学習する
+        if !was_pending && known.is_none() {
+            return Ok(());
+        }
+        if let Some(known) = &known {
+            if known.entry_type == ArpEntryType::Static {
+                if known.mac != arp.sender_mac {
+                    warn!("Ignoring ARP {} -> {}: static entry is {}", arp.sender_ip, arp.sender_mac, known.mac);
+                }
+                return Ok(());
+            }
+            if known.mac == arp.sender_mac {
+                self.routing_manager.touch_arp_entry(arp.sender_ip).await;
+                return Ok(());
+            }
+        }
+        
+        let port_name = match ingress_port {
+            Some(port_id) => self.routing_manager.get_port(port_id).await.map(|p| p.name),
+            None => None,
+        };
+        let Some(interface) = port_name.or(known.map(|e| e.interface)) else {
+            warn!("Ignoring ARP from {}: unknown ingress port {:?}", arp.sender_ip, ingress_port);
+            return Ok(());
+        };
+        
+        info!("Learned {} -> {} on {}", arp.sender_ip, arp.sender_mac, interface);
+        self.routing_manager
+            .add_arp_entry(ArpEntry::new_dynamic(arp.sender_ip, arp.sender_mac, &interface))
+            .await;
+        
+        if self.fib_compression {
+            self.sync_compressed_fib_on_all_devices().await;
+        } else {
+            for route in self.routing_manager.routes_via_next_hop(arp.sender_ip).await {
+                self.reprogram_prefix_on_all_devices(route.prefix, route.prefix_len).await;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// デバイスに残っているECMPのメンバー・グループを引き継ぎ、まだ使うグループのエントリをシャドウに読み込む
+    ///
+    /// 再起動したコントローラーのIDがデバイスのIDと衝突しないように、メンバーとグループはデバイスの状態をそのまま使う。
+    /// 現在のECMPルートのプレフィックスを指すエントリだけをシャドウに入れ、それ以外は差分の修正で削除させる。
+    async fn load_ecmp_state_from_device(&self, device_id: DeviceId) -> Result<()> {
+        let (members, groups) = self.device_manager.read_action_profiles_from_device(device_id).await?;
+        self.action_profile_manager.load_device_profiles(device_id, members, groups).await;
+        
+        let groups = self.action_profile_manager.group_ids(device_id, ECMP_SELECTOR).await;
+        let mut entries = Vec::new();
+        for entry in self.device_manager.read_table_entries_from_device(device_id).await? {
+            let (Some(key), Some(TableAction::SetEcmpGroup { group_id })) = (entry.ipv4_lpm_key(), entry.forwarding_action()) else {
+                continue;
+            };
+            let is_ecmp_route = self
+                .routing_manager
+                .best_route(key.ipv4_dst, key.prefix_len)
+                .await
+                .is_some_and(|route| route.is_ecmp());
+            if is_ecmp_route && groups.contains(&group_id) {
+                entries.push(TableEntry::ecmp_group(group_id));
+                entries.push(TableEntry::ipv4_lpm(key, TableAction::SetEcmpGroup { group_id }, 0));
+            }
+        }
+        
+        let (_, rejected) = self.stage_entries(device_id, entries).await?;
+        warn_rejected_entries(device_id, &rejected);
+        Ok(())
+    }
+    
+    /// どのecmp_groupエントリからも使われていないECMPグループとメンバーをデバイスから削除
+    ///
+    /// 削除できなかったグループがあっても残りのグループは続け、失敗したグループとエラーを返す
+    /// （使われていないグループが残っても転送には影響しない）。
+    async fn release_unused_ecmp_groups(&self, device_id: DeviceId) -> Result<Vec<(u32, anyhow::Error)>> {
+        let mut failures = Vec::new();
+        for group_id in self.action_profile_manager.group_ids(device_id, ECMP_SELECTOR).await {
+            if self.table_manager.get_entry(device_id, &TableEntry::ecmp_group(group_id)).await?.is_some() {
+                continue;
+            }
+            if let Err(e) = self.release_ecmp_group(device_id, group_id).await {
+                error!("Failed to release ECMP group {} on device {}: {}", group_id, device_id, e);
+                failures.push((group_id, e));
+            }
+        }
+        
+        let changes = self
+            .action_profile_manager
+            .remove_unreferenced_members(device_id, ECMP_SELECTOR)
+            .await;
+        self.write_action_profile_changes(device_id, changes, Vec::new()).await?;
+        Ok(failures)
+    }
+    
+    /// 近隣広告（または送信元リンク層アドレス付きの近隣要請）から近隣を学習し、そのネクストホップを使うIPv6ルートをインストール
+    async fn handle_ndp_packet(&self, ndp: &NdpPacket, ingress_port: Option<PortId>) -> Result<()> {
+        let (ip, mac) = match (ndp.operation, ndp.link_layer_addr) {
+            (NdpOperation::Advertisement, _) => (ndp.target_ip, ndp.advertised_mac()),
+            // 重複アドレス検出（送信元が未指定アドレス）の要請からは学習しない
+            (NdpOperation::Solicitation, Some(mac)) if ndp.sender_ip.as_u128() != 0 => (ndp.sender_ip, mac),
+            (NdpOperation::Solicitation, _) => return Ok(()),
+        };
+        
+        let known = self.routing_manager.find_neighbor_entry(ip).await;
+        let was_pending = self.ndp_resolver.resolved(ip).await;
+        
+        // 解決を待っていたネクストホップと既知のエントリの更新だけを学習する
+        if !was_pending && known.is_none() {
+            return Ok(());
+        }
+        if let Some(known) = &known {
+            if known.entry_type == ArpEntryType::Static {
+                if known.mac != mac {
+                    warn!("Ignoring NDP {} -> {}: static entry is {}", ip, mac, known.mac);
+                }
+                return Ok(());
+            }
+            if known.mac == mac {
+                self.routing_manager.touch_neighbor_entry(ip).await;
+                return Ok(());
+            }
+        }
+        
+        let port_name = match ingress_port {
+            Some(port_id) => self.routing_manager.get_port(port_id).await.map(|p| p.name),
+            None => None,
+        };
+        let Some(interface) = port_name.or(known.map(|e| e.interface)) else {
+            warn!("Ignoring NDP from {}: unknown ingress port {:?}", ip, ingress_port);
+            return Ok(());
+        };
+        
+        info!("Learned {} -> {} on {}", ip, mac, interface);
+        self.routing_manager
+            .add_neighbor_entry(NeighborEntry::new_dynamic(ip, mac, &interface))
+            .await;
+        self.reprogram_ipv6_routes_via(ip).await;
+        
+        Ok(())
+    }
+    
+    /// ルーティングテーブルをプライマリの全デバイスに適用
     async fn apply_routing_table_to_all_devices(&self) -> Result<()> {
-        let devices = self.device_manager.list_devices().await;
+        let devices = self.device_manager.list_primary_devices().await;
         
         for device in devices {
             if let Err(e) = self.apply_routing_table_to_device(device.device_id).await {
@@ -263,6 +1675,7 @@ impl P4Controller {
             next_hop: Some(Ipv4Address::new(Ipv4Addr::new(192, 168, 1, 1))),
             interface: "eth0".to_string(),
             metric: 1,
+            ecmp_next_hops: Vec::new(),
         };
         
         self.routing_manager.add_route(default_route).await?;
@@ -274,6 +1687,7 @@ impl P4Controller {
             next_hop: None, // 直接接続
             interface: "eth0".to_string(),
             metric: 0,
+            ecmp_next_hops: Vec::new(),
         };
         
         self.routing_manager.add_route(local_route).await?;
@@ -286,11 +1700,11 @@ impl P4Controller {
         info!("Setting up default ARP entries");
         
         // デフォルトゲートウェイのARPエントリ
-        let gateway_arp = ArpEntry {
-            ip: Ipv4Address::new(Ipv4Addr::new(192, 168, 1, 1)),
-            mac: MacAddress::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
-            interface: "eth0".to_string(),
-        };
+        let gateway_arp = ArpEntry::new_static(
+            Ipv4Address::new(Ipv4Addr::new(192, 168, 1, 1)),
+            MacAddress::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            "eth0",
+        );
         
         self.routing_manager.add_arp_entry(gateway_arp).await;
         
@@ -313,11 +1727,65 @@ impl P4Controller {
         self.device_manager.list_devices().await
     }
     
-    /// ルート一覧を取得
+    /// デバイスのテーブルごとの使用量と容量を取得
+    pub async fn get_table_usage(&self, device_id: DeviceId) -> Result<Vec<TableUsage>> {
+        self.table_manager.get_table_usage(device_id).await
+    }
+    
+    /// テーブルの高水位警告のしきい値（容量に対する%）を設定
+    pub async fn set_table_high_watermark(&self, percent: u8) {
+        self.table_manager.set_high_watermark(percent).await;
+    }
+    
+    /// ルート一覧（全候補）を取得
     pub async fn list_routes(&self) -> Vec<RouteEntry> {
         self.routing_manager.get_all_routes().await
     }
     
+    /// プレフィックスごとの最良ルート一覧を取得
+    pub async fn list_best_routes(&self) -> Vec<RouteEntry> {
+        self.routing_manager.best_routes().await
+    }
+    
+    /// 宛先アドレスを含むプレフィックスのルート（全候補）を、長いプレフィックスから順に取得
+    pub async fn lookup_routes(&self, dst_ip: Ipv4Address) -> Vec<RouteEntry> {
+        let mut routes = self.routing_manager.covering_routes(dst_ip, 32).await;
+        routes.reverse();
+        routes
+    }
+    
+    /// 宛先アドレスへの転送に使うルート（最良ルートの最長プレフィックスマッチ）を取得
+    pub async fn find_route(&self, dst_ip: Ipv4Address) -> Option<RouteEntry> {
+        self.routing_manager.find_route(dst_ip).await
+    }
+    
+    /// IPv6ルート一覧（全候補）を取得
+    pub async fn list_ipv6_routes(&self) -> Vec<Ipv6RouteEntry> {
+        self.routing_manager.get_all_ipv6_routes().await
+    }
+    
+    /// IPv6プレフィックスごとの最良ルート一覧を取得
+    pub async fn list_best_ipv6_routes(&self) -> Vec<Ipv6RouteEntry> {
+        self.routing_manager.best_ipv6_routes().await
+    }
+    
+    /// 宛先IPv6アドレスを含むプレフィックスのルート（全候補）を、長いプレフィックスから順に取得
+    pub async fn lookup_ipv6_routes(&self, dst_ip: Ipv6Address) -> Vec<Ipv6RouteEntry> {
+        let mut routes = self.routing_manager.covering_ipv6_routes(dst_ip, 128).await;
+        routes.reverse();
+        routes
+    }
+    
+    /// 宛先IPv6アドレスへの転送に使うルート（最良ルートの最長プレフィックスマッチ）を取得
+    pub async fn find_ipv6_route(&self, dst_ip: Ipv6Address) -> Option<Ipv6RouteEntry> {
+        self.routing_manager.find_ipv6_route(dst_ip).await
+    }
+    
+    /// 近隣キャッシュの一覧を取得
+    pub async fn list_neighbor_entries(&self) -> Vec<NeighborEntry> {
+        self.routing_manager.get_all_neighbor_entries().await
+    }
+    
     /// ARPエントリ一覧を取得
     pub async fn list_arp_entries(&self) -> Vec<ArpEntry> {
         self.routing_manager.get_all_arp_entries().await
@@ -334,3 +1802,183 @@ impl Default for P4Controller {
         Self::new()
     }
 }
+
+/// テーブルが満杯でエントリを追加できなかったエラーか
+fn is_table_full(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<P4RuntimeError>(), Some(P4RuntimeError::TableFull { .. }))
+}
+
+/// ルートを再設定できなかったデバイスがあればエラーにする（`change` はルーティングテーブルへの変更）
+fn route_failures_to_result(
+    prefix: impl std::fmt::Display,
+    prefix_len: u8,
+    change: &str,
+    failures: Vec<(DeviceId, anyhow::Error)>,
+) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "Route {}/{} was {} but reprogramming it failed on {}",
+        prefix,
+        prefix_len,
+        change,
+        describe_device_failures(&failures)
+    ))
+}
+
+/// ルートを削除できなかったデバイスのエラー（削除した候補はRIBに戻してある）
+fn route_removal_failed(
+    prefix: impl std::fmt::Display,
+    prefix_len: u8,
+    failures: Vec<(DeviceId, anyhow::Error)>,
+) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Route {}/{} was kept because removing it failed on {}",
+        prefix,
+        prefix_len,
+        describe_device_failures(&failures)
+    )
+}
+
+/// デバイスごとのエラーを1行にまとめる
+fn describe_device_failures(failures: &[(DeviceId, anyhow::Error)]) -> String {
+    failures
+        .iter()
+        .map(|(device_id, e)| format!("device {}: {}", device_id, e))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// サービスの分類エントリの書き込みの失敗をまとめてエラーにする
+fn service_failures_to_result(failures: Vec<String>) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "{} service classification entries failed: {}",
+        failures.len(),
+        failures.join("; ")
+    ))
+}
+
+/// ABACのエントリを書き込めなかったデバイスがあればエラーにする
+fn abac_failures_to_result(entry: &TableEntry, failures: Vec<String>) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!("Updating {} failed on {}", entry.table, failures.join("; ")))
+}
+
+/// テーブルが満杯で書き込めなかったエントリを警告する
+fn warn_rejected_entries(device_id: DeviceId, rejected: &[TableEntry]) {
+    for entry in rejected {
+        warn!("Skipped entry for {} on device {}: table is full", entry.table, device_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    
+    fn ip(addr: [u8; 4]) -> Ipv4Address {
+        Ipv4Address::new(addr.into())
+    }
+    
+    /// `age` 前に最後に確認したARPエントリ
+    fn arp_seen_ago(entry: ArpEntry, age: Duration) -> ArpEntry {
+        ArpEntry { last_seen: SystemTime::now() - age, ..entry }
+    }
+    
+    /// 60秒で再確認、さらに15秒で削除するコントローラー（eth1のみ）
+    async fn controller_with_aging() -> P4Controller {
+        let mut controller = P4Controller::new();
+        controller.set_arp_aging_config(ArpAgingConfig {
+            reachable_time: Duration::from_secs(60),
+            probe_time: Duration::from_secs(15),
+            interval: Duration::from_secs(5),
+        });
+        controller
+            .add_port(PortInfo {
+                port_id: 1,
+                name: "eth1".to_string(),
+                mac_address: MacAddress::new([0x08, 0x00, 0x00, 0x00, 0x00, 0x01]),
+                ip_address: Some(ip([192, 168, 1, 254])),
+                is_up: true,
+            })
+            .await
+            .unwrap();
+        controller
+    }
+    
+    #[tokio::test]
+    async fn backup_device_does_not_block_route_changes() {
+        let controller = controller_with_aging().await;
+        let device = DeviceInfo {
+            device_id: 2,
+            name: "backup".to_string(),
+            grpc_endpoint: "127.0.0.1:9".to_string(),
+            p4info: None,
+            election_id: 1,
+            role: None,
+            pipeline: None,
+            write_options: WriteOptions::default(),
+        };
+        controller.device_manager.add_offline_device(device, MastershipStatus::Backup).await;
+        controller.table_manager.initialize_device_tables(2).await;
+        assert!(controller.device_manager.list_primary_devices().await.is_empty());
+        
+        let mac = MacAddress::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x01]);
+        controller.routing_manager.add_arp_entry(ArpEntry::new_static(ip([192, 168, 1, 1]), mac, "eth1")).await;
+        let route = RouteEntry {
+            prefix: ip([10, 1, 0, 0]),
+            prefix_len: 16,
+            next_hop: Some(ip([192, 168, 1, 1])),
+            interface: "eth1".to_string(),
+            metric: 10,
+            ecmp_next_hops: Vec::new(),
+        };
+        
+        // バックアップには書き込まないので、追加も削除もロールバックされない
+        controller.add_route(route).await.unwrap();
+        controller.remove_route(ip([10, 1, 0, 0]), 16, None).await.unwrap();
+        assert!(controller.lookup_routes(ip([10, 1, 2, 3])).await.iter().all(|route| route.prefix_len != 16));
+    }
+    
+    #[tokio::test]
+    async fn static_arp_entries_never_age() {
+        let controller = controller_with_aging().await;
+        let mac = MacAddress::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x01]);
+        let entry = ArpEntry::new_static(ip([192, 168, 1, 1]), mac, "eth1");
+        controller.routing_manager.add_arp_entry(arp_seen_ago(entry, Duration::from_secs(3600))).await;
+        
+        assert!(controller.age_arp_entries().await.unwrap().is_empty());
+        assert!(controller.routing_manager.find_arp_entry(ip([192, 168, 1, 1])).await.is_some());
+    }
+    
+    #[tokio::test]
+    async fn dynamic_arp_entry_is_probed_then_expired() {
+        let controller = controller_with_aging().await;
+        let mac = MacAddress::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x02]);
+        let entry = ArpEntry::new_dynamic(ip([192, 168, 1, 2]), mac, "eth1");
+        
+        // reachable_time以内なら何もしない
+        controller.routing_manager.add_arp_entry(arp_seen_ago(entry.clone(), Duration::from_secs(30))).await;
+        assert!(controller.age_arp_entries().await.unwrap().is_empty());
+        
+        // reachable_timeを過ぎたらARPリクエストで再確認し、probe_timeの間は残す
+        controller.routing_manager.add_arp_entry(arp_seen_ago(entry.clone(), Duration::from_secs(65))).await;
+        assert_eq!(controller.age_arp_entries().await.unwrap(), [ip([192, 168, 1, 2])]);
+        assert!(controller.routing_manager.find_arp_entry(ip([192, 168, 1, 2])).await.is_some());
+        
+        // 応答があれば確認済みに戻る
+        assert!(controller.routing_manager.touch_arp_entry(ip([192, 168, 1, 2])).await);
+        assert!(controller.age_arp_entries().await.unwrap().is_empty());
+        
+        // probe_timeの間に応答がなければ削除する
+        controller.routing_manager.add_arp_entry(arp_seen_ago(entry, Duration::from_secs(80))).await;
+        assert!(controller.age_arp_entries().await.unwrap().is_empty());
+        assert!(controller.routing_manager.find_arp_entry(ip([192, 168, 1, 2])).await.is_none());
+    }
+}
@@ -0,0 +1,271 @@
+use crate::prefix_trie::PrefixTrie;
+use crate::types::*;
+use ipnet::Ipv4Net;
+use std::cmp::Reverse;
+
+/// FIB圧縮の結果
+#[derive(Debug, Clone, Default)]
+pub struct FibCompression {
+    /// 圧縮後のエントリ（ipv4_lpmのエントリはアドレス順）
+    pub entries: Vec<TableEntry>,
+    /// 圧縮前のエントリ数
+    pub original_count: usize,
+    /// 最も近い上位のプレフィックスと同じアクションのため削除したエントリ数
+    pub redundant_removed: usize,
+    /// 兄弟のプレフィックスを親のプレフィックスにまとめた回数
+    pub siblings_merged: usize,
+}
+
+impl FibCompression {
+    /// 圧縮で減らせたエントリ数
+    pub fn saved(&self) -> usize {
+        self.original_count.saturating_sub(self.entries.len())
+    }
+}
+
+/// 圧縮中のFIBのエントリ
+#[derive(Debug, Clone)]
+struct FibEntry {
+    entry: TableEntry,
+    /// 削除もまとめもしないエントリ
+    fixed: bool,
+}
+
+/// ipv4_lpmのエントリを、転送の結果を変えずに少ないエントリにまとめる
+///
+/// 次の2つを変化がなくなるまで繰り返す。
+/// - 最も近い上位のプレフィックスと同じアクションのエントリを削除する（上位のエントリで同じ転送になる）
+/// - 同じアクションの兄弟プレフィックス（最後の1ビットだけが異なる2つ）を親のプレフィックスにまとめる
+///   （親のエントリがあっても、兄弟が親の範囲をすべて覆っているので親のアクションは使われない）
+///
+/// `fixed` のエントリ（ECMPグループを参照するエントリなど）は上位のプレフィックスとしては比べるが、
+/// 削除もまとめもせずにそのまま残す。アクションの比較は `EntryAction::is_equivalent` で行い、
+/// ipv4_lpm以外のエントリはそのまま返す。
+pub fn compress_ipv4_fib(entries: Vec<TableEntry>, fixed: Vec<TableEntry>) -> FibCompression {
+    let original_count = entries.len() + fixed.len();
+    let mut others = Vec::new();
+    let mut fib: PrefixTrie<Ipv4Net, FibEntry> = PrefixTrie::new();
+    
+    let tagged = entries.into_iter().map(|entry| (entry, false)).chain(fixed.into_iter().map(|entry| (entry, true)));
+    for (entry, fixed) in tagged {
+        match entry.ipv4_lpm_key().and_then(|key| key.ipv4_dst.network(key.prefix_len)) {
+            Some(network) => {
+                fib.insert(network, FibEntry { entry, fixed });
+            }
+            None => others.push(entry),
+        }
+    }
+    
+    let mut result = FibCompression {
+        original_count,
+        ..Default::default()
+    };
+    loop {
+        let removed = remove_redundant(&mut fib);
+        let merged = merge_siblings(&mut fib);
+        result.redundant_removed += removed;
+        result.siblings_merged += merged;
+        if removed == 0 && merged == 0 {
+            break;
+        }
+    }
+    
+    result.entries = fib.values().map(|fib_entry| fib_entry.entry.clone()).chain(others).collect();
+    result
+}
+
+/// 最も近い上位のプレフィックスと同じアクションのエントリを削除し、削除した数を返す
+fn remove_redundant(fib: &mut PrefixTrie<Ipv4Net, FibEntry>) -> usize {
+    // 短いプレフィックスから判定するので、削除したエントリの下位は残ったさらに上位と比べることになる
+    let prefixes: Vec<Ipv4Net> = fib.iter().map(|(prefix, _)| prefix).collect();
+    let mut removed = 0;
+    
+    for prefix in prefixes {
+        let redundant = match fib.covering(&prefix).as_slice() {
+            [.., (_, parent), (_, current)] => !current.fixed && current.entry.action.is_equivalent(&parent.entry.action),
+            _ => false,
+        };
+        if redundant {
+            fib.remove(&prefix);
+            removed += 1;
+        }
+    }
+    
+    removed
+}
+
+/// 同じアクションの兄弟プレフィックスを親のプレフィックスにまとめ、まとめた回数を返す
+fn merge_siblings(fib: &mut PrefixTrie<Ipv4Net, FibEntry>) -> usize {
+    // 長いプレフィックスからまとめる（まとめた親の兄弟は次の繰り返しで扱う）
+    let mut prefixes: Vec<Ipv4Net> = fib.iter().map(|(prefix, _)| prefix).collect();
+    prefixes.sort_by_key(|prefix| Reverse(prefix.prefix_len()));
+    let mut merged = 0;
+    
+    for prefix in prefixes {
+        let Some(parent) = prefix.supernet() else { continue };
+        let Ok(mut halves) = parent.subnets(prefix.prefix_len()) else { continue };
+        let (Some(low), Some(high)) = (halves.next(), halves.next()) else { continue };
+        
+        if fib.get(&parent).is_some_and(|parent_entry| parent_entry.fixed) {
+            continue;
+        }
+        let merged_entry = match (fib.get(&low), fib.get(&high)) {
+            (Some(low_entry), Some(high_entry))
+                if !low_entry.fixed && !high_entry.fixed && low_entry.entry.action.is_equivalent(&high_entry.entry.action) =>
+            {
+                let key = TableKey {
+                    ipv4_dst: Ipv4Address::new(parent.network()),
+                    prefix_len: parent.prefix_len(),
+                };
+                TableEntry {
+                    matches: key.to_field_matches(),
+                    ..low_entry.entry.clone()
+                }
+            }
+            _ => continue,
+        };
+        
+        fib.remove(&low);
+        fib.remove(&high);
+        fib.insert(parent, FibEntry { entry: merged_entry, fixed: false });
+        merged += 1;
+    }
+    
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Rng;
+    use std::net::Ipv4Addr;
+    
+    fn lpm(prefix: &str, action: TableAction) -> TableEntry {
+        let network: Ipv4Net = prefix.parse().unwrap();
+        let key = TableKey {
+            ipv4_dst: Ipv4Address::new(network.network()),
+            prefix_len: network.prefix_len(),
+        };
+        TableEntry::ipv4_lpm(key, action, 0)
+    }
+    
+    fn forward(port: PortId) -> TableAction {
+        TableAction::Ipv4Forward {
+            dst_mac: MacAddress::new([0x00, 0x00, 0x00, 0x00, 0x00, port as u8]),
+            port,
+        }
+    }
+    
+    fn network_of(entry: &TableEntry) -> Ipv4Net {
+        let key = entry.ipv4_lpm_key().unwrap();
+        key.ipv4_dst.network(key.prefix_len).unwrap()
+    }
+    
+    /// 全エントリを比べる最長プレフィックスマッチ（トライを使わない）
+    fn lookup(entries: &[TableEntry], addr: Ipv4Addr) -> Option<TableAction> {
+        entries
+            .iter()
+            .filter(|entry| network_of(entry).contains(&addr))
+            .max_by_key(|entry| network_of(entry).prefix_len())
+            .and_then(|entry| entry.forwarding_action())
+    }
+    
+    /// 各プレフィックスの先頭・末尾とその外側のアドレス、およびランダムなアドレス
+    fn sample_addresses(entries: &[TableEntry], rng: &mut Rng) -> Vec<Ipv4Addr> {
+        let mut addresses: Vec<Ipv4Addr> = (0..1000).map(|_| Ipv4Addr::from(rng.next_u32())).collect();
+        for entry in entries {
+            let network = network_of(entry);
+            let (first, last) = (u32::from(network.network()), u32::from(network.broadcast()));
+            addresses.extend([first, last, first.wrapping_sub(1), last.wrapping_add(1)].map(Ipv4Addr::from));
+        }
+        addresses
+    }
+    
+    fn assert_same_forwarding(original: &[TableEntry], compressed: &[TableEntry], addresses: &[Ipv4Addr]) {
+        for addr in addresses {
+            assert_eq!(lookup(original, *addr), lookup(compressed, *addr), "forwarding for {} changed", addr);
+        }
+    }
+    
+    #[test]
+    fn merges_siblings_and_removes_redundant_entries() {
+        let entries = vec![
+            lpm("0.0.0.0/0", forward(1)),
+            lpm("10.0.0.0/24", forward(2)),
+            lpm("10.0.1.0/24", forward(2)),
+            lpm("10.0.2.0/23", forward(2)),
+            lpm("10.0.0.128/25", forward(2)),
+            lpm("192.168.0.0/16", forward(1)),
+            lpm("172.16.0.0/12", forward(3)),
+        ];
+        let fixed = vec![lpm("10.0.1.0/25", TableAction::SetEcmpGroup { group_id: 1 })];
+        
+        let compression = compress_ipv4_fib(entries.clone(), fixed.clone());
+        let networks: Vec<Ipv4Net> = compression.entries.iter().map(network_of).collect();
+        assert_eq!(
+            networks,
+            ["0.0.0.0/0", "10.0.0.0/22", "10.0.1.0/25", "172.16.0.0/12"].map(|s| s.parse::<Ipv4Net>().unwrap())
+        );
+        assert_eq!(compression.original_count, 8);
+        assert_eq!(compression.saved(), 4);
+        
+        let original: Vec<TableEntry> = entries.into_iter().chain(fixed).collect();
+        let addresses = sample_addresses(&original, &mut Rng::new(0x9e37_79b9_7f4a_7c15));
+        assert_same_forwarding(&original, &compression.entries, &addresses);
+    }
+    
+    #[test]
+    fn fixed_entries_are_kept_even_when_redundant() {
+        let ecmp = TableAction::SetEcmpGroup { group_id: 7 };
+        let entries = vec![lpm("10.0.0.0/24", ecmp.clone()), lpm("10.0.1.0/24", forward(1))];
+        let fixed = vec![lpm("10.0.0.0/25", ecmp.clone()), lpm("10.0.0.128/25", ecmp)];
+        
+        let compression = compress_ipv4_fib(entries, fixed.clone());
+        for entry in &fixed {
+            assert!(compression.entries.contains(entry));
+        }
+        // 固定のエントリは親にまとめられないので、そのままのエントリ数になる
+        assert_eq!(compression.entries.len(), 4);
+    }
+    
+    #[test]
+    fn random_fib_keeps_forwarding() {
+        let mut rng = Rng::default();
+        for _ in 0..10 {
+            // 兄弟や親子が多くできるように、10.0.0.0/16の中の少ないアクションのプレフィックスにする
+            let mut entries = vec![lpm("0.0.0.0/0", forward(1))];
+            for _ in 0..200 {
+                let prefix_len = 16 + (rng.next_u32() % 13) as u8;
+                let addr = Ipv4Addr::from(0x0a00_0000 | (rng.next_u32() & 0xffff));
+                let network = Ipv4Net::new_assert(addr, prefix_len).trunc();
+                entries.push(lpm(&network.to_string(), forward(1 + rng.next_u32() % 3)));
+            }
+            let fixed: Vec<TableEntry> = (0..5)
+                .map(|group_id| {
+                    let network = Ipv4Net::new_assert(Ipv4Addr::new(10, 0, (group_id * 50) as u8, 0), 24);
+                    lpm(&network.to_string(), TableAction::SetEcmpGroup { group_id })
+                })
+                .collect();
+            
+            // 同じプレフィックスは後のエントリが使われる（FIBと同じ）
+            let mut original: PrefixTrie<Ipv4Net, TableEntry> = PrefixTrie::new();
+            for entry in entries.iter().chain(&fixed) {
+                original.insert(network_of(entry), entry.clone());
+            }
+            let fixed_networks: Vec<Ipv4Net> = fixed.iter().map(network_of).collect();
+            let entries: Vec<TableEntry> = entries
+                .into_iter()
+                .filter(|entry| !fixed_networks.contains(&network_of(entry)))
+                .collect();
+            let original: Vec<TableEntry> = original.values().cloned().collect();
+            
+            let compression = compress_ipv4_fib(entries, fixed.clone());
+            assert!(compression.entries.len() < original.len());
+            for entry in &fixed {
+                assert!(compression.entries.contains(entry));
+            }
+            let addresses = sample_addresses(&original, &mut rng);
+            assert_same_forwarding(&original, &compression.entries, &addresses);
+        }
+    }
+}
@@ -1,9 +1,24 @@
 pub mod types;
+pub mod proto;
+pub mod codec;
+pub mod p4info;
+pub mod pipeline;
 pub mod p4runtime_client;
+pub mod prefix_trie;
 pub mod table_manager;
+pub mod action_profile_manager;
 pub mod routing_manager;
+pub mod fib_compression;
+pub mod arp_resolver;
+pub mod ndp;
+pub mod policy;
+pub mod policy_check;
+pub mod attribute_encoding;
+pub mod attribute_store;
 pub mod controller;
 pub mod cli;
+#[cfg(any(test, feature = "bench"))]
+pub mod test_support;
 
 pub use types::*;
 pub use controller::P4Controller;
@@ -0,0 +1,303 @@
+use crate::types::*;
+
+/// IPv6のEtherType
+pub const ETHERTYPE_IPV6: u16 = 0x86DD;
+
+const IPPROTO_ICMPV6: u8 = 58;
+const ICMPV6_NEIGHBOR_SOLICITATION: u8 = 135;
+const ICMPV6_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+/// NDPオプション: 送信元リンク層アドレス / ターゲットリンク層アドレス
+const NDP_OPT_SOURCE_LINK_ADDR: u8 = 1;
+const NDP_OPT_TARGET_LINK_ADDR: u8 = 2;
+/// 近隣広告のフラグ（Solicited + Override）
+const NA_FLAGS_SOLICITED_OVERRIDE: u32 = 0x6000_0000;
+/// NDPのパケットはルーターを越えないことを示すためホップリミットを255にする
+const NDP_HOP_LIMIT: u8 = 255;
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV6_HEADER_LEN: usize = 40;
+/// ICMPv6ヘッダー（8バイト）+ ターゲットアドレス（16バイト）
+const NDP_MESSAGE_LEN: usize = 24;
+/// リンク層アドレスオプション（8バイト）
+const NDP_LINK_ADDR_OPT_LEN: usize = 8;
+
+/// NDPの操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdpOperation {
+    /// 近隣要請（Neighbor Solicitation）
+    Solicitation,
+    /// 近隣広告（Neighbor Advertisement）
+    Advertisement,
+}
+
+/// イーサネットフレームから取り出したNDPの近隣要請・近隣広告
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NdpPacket {
+    pub operation: NdpOperation,
+    /// イーサネットの送信元MACアドレス
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv6Address,
+    pub destination_ip: Ipv6Address,
+    /// 解決対象（要請）または広告されたアドレス
+    pub target_ip: Ipv6Address,
+    /// 送信元 / ターゲットのリンク層アドレスオプション
+    pub link_layer_addr: Option<MacAddress>,
+}
+
+impl NdpPacket {
+    /// 要請ノードマルチキャスト宛ての近隣要請を作成
+    pub fn solicitation(sender_mac: MacAddress, sender_ip: Ipv6Address, target_ip: Ipv6Address) -> Self {
+        Self {
+            operation: NdpOperation::Solicitation,
+            sender_mac,
+            sender_ip,
+            destination_ip: target_ip.solicited_node(),
+            target_ip,
+            link_layer_addr: Some(sender_mac),
+        }
+    }
+    
+    /// 近隣広告でターゲットのアドレスを広告しているMACアドレス
+    ///
+    /// 近隣広告以外、またはリンク層アドレスオプションがなければイーサネットの送信元を使う。
+    pub fn advertised_mac(&self) -> MacAddress {
+        self.link_layer_addr.unwrap_or(self.sender_mac)
+    }
+    
+    /// イーサネットフレームに変換（マルチキャスト宛てならイーサネットもマルチキャスト）
+    pub fn to_frame(&self, dst_mac: Option<MacAddress>) -> Vec<u8> {
+        let dst_mac = dst_mac.unwrap_or_else(|| self.destination_ip.multicast_mac());
+        let (icmp_type, flags, option_type) = match self.operation {
+            NdpOperation::Solicitation => (ICMPV6_NEIGHBOR_SOLICITATION, 0, NDP_OPT_SOURCE_LINK_ADDR),
+            NdpOperation::Advertisement => (ICMPV6_NEIGHBOR_ADVERTISEMENT, NA_FLAGS_SOLICITED_OVERRIDE, NDP_OPT_TARGET_LINK_ADDR),
+        };
+        
+        let mut message = Vec::with_capacity(NDP_MESSAGE_LEN + NDP_LINK_ADDR_OPT_LEN);
+        message.push(icmp_type);
+        message.push(0);
+        message.extend_from_slice(&[0, 0]); // チェックサムは後で埋める
+        message.extend_from_slice(&flags.to_be_bytes());
+        message.extend_from_slice(&self.target_ip.as_u128().to_be_bytes());
+        if let Some(mac) = self.link_layer_addr {
+            message.push(option_type);
+            message.push(1); // 8バイト単位の長さ
+            message.extend_from_slice(mac.as_bytes());
+        }
+        let checksum = icmpv6_checksum(self.sender_ip, self.destination_ip, &message);
+        message[2..4].copy_from_slice(&checksum.to_be_bytes());
+        
+        let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + IPV6_HEADER_LEN + message.len());
+        frame.extend_from_slice(dst_mac.as_bytes());
+        frame.extend_from_slice(self.sender_mac.as_bytes());
+        frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+        frame.extend_from_slice(&0x6000_0000u32.to_be_bytes()); // バージョン6、トラフィッククラスとフローラベルは0
+        frame.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        frame.push(IPPROTO_ICMPV6);
+        frame.push(NDP_HOP_LIMIT);
+        frame.extend_from_slice(&self.sender_ip.as_u128().to_be_bytes());
+        frame.extend_from_slice(&self.destination_ip.as_u128().to_be_bytes());
+        frame.extend_from_slice(&message);
+        frame
+    }
+    
+    /// イーサネットフレームをパース（拡張ヘッダーのない近隣要請・近隣広告でなければNone）
+    ///
+    /// ホップリミットが255でなければルーターを越えてきたパケットなので捨てる（RFC 4861 7.1.1 / 7.1.2）。
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.len() < ETHERNET_HEADER_LEN + IPV6_HEADER_LEN + NDP_MESSAGE_LEN {
+            return None;
+        }
+        
+        let u16_at = |offset: usize| u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+        let ip_at = |offset: usize| {
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(&frame[offset..offset + 16]);
+            Ipv6Address::from_u128(u128::from_be_bytes(bytes))
+        };
+        let mut sender_mac = [0u8; 6];
+        sender_mac.copy_from_slice(&frame[6..12]);
+        
+        let ip = ETHERNET_HEADER_LEN;
+        if u16_at(12) != ETHERTYPE_IPV6 || frame[ip] >> 4 != 6 || frame[ip + 6] != IPPROTO_ICMPV6 {
+            return None;
+        }
+        if frame[ip + 7] != NDP_HOP_LIMIT {
+            return None;
+        }
+        let payload_len = u16_at(ip + 4) as usize;
+        let icmp = ip + IPV6_HEADER_LEN;
+        let message = frame.get(icmp..icmp + payload_len)?;
+        if payload_len < NDP_MESSAGE_LEN || message[1] != 0 {
+            return None;
+        }
+        
+        let (operation, option_type) = match message[0] {
+            ICMPV6_NEIGHBOR_SOLICITATION => (NdpOperation::Solicitation, NDP_OPT_SOURCE_LINK_ADDR),
+            ICMPV6_NEIGHBOR_ADVERTISEMENT => (NdpOperation::Advertisement, NDP_OPT_TARGET_LINK_ADDR),
+            _ => return None,
+        };
+        
+        let sender_ip = ip_at(ip + 8);
+        let destination_ip = ip_at(ip + 24);
+        if icmpv6_checksum(sender_ip, destination_ip, message) != 0 {
+            return None;
+        }
+        
+        Some(Self {
+            operation,
+            sender_mac: MacAddress::new(sender_mac),
+            sender_ip,
+            destination_ip,
+            target_ip: ip_at(icmp + 8),
+            link_layer_addr: find_link_layer_option(&message[NDP_MESSAGE_LEN..], option_type),
+        })
+    }
+}
+
+/// NDPオプションから指定した種類のリンク層アドレスを探す
+fn find_link_layer_option(mut options: &[u8], option_type: u8) -> Option<MacAddress> {
+    while options.len() >= 2 {
+        let len = options[1] as usize * 8;
+        if len == 0 || len > options.len() {
+            return None;
+        }
+        if options[0] == option_type && len >= NDP_LINK_ADDR_OPT_LEN {
+            let mut mac = [0u8; 6];
+            mac.copy_from_slice(&options[2..8]);
+            return Some(MacAddress::new(mac));
+        }
+        options = &options[len..];
+    }
+    None
+}
+
+/// ICMPv6のチェックサム（IPv6疑似ヘッダーを含む1の補数和、正しいメッセージなら0）
+fn icmpv6_checksum(src: Ipv6Address, dst: Ipv6Address, message: &[u8]) -> u16 {
+    let mut pseudo_header = Vec::with_capacity(40);
+    pseudo_header.extend_from_slice(&src.as_u128().to_be_bytes());
+    pseudo_header.extend_from_slice(&dst.as_u128().to_be_bytes());
+    pseudo_header.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    pseudo_header.extend_from_slice(&[0, 0, 0, IPPROTO_ICMPV6]);
+    
+    let mut sum: u32 = 0;
+    for chunk in pseudo_header.chunks(2).chain(message.chunks(2)) {
+        let word = match chunk {
+            [high, low] => u16::from_be_bytes([*high, *low]),
+            [high] => u16::from_be_bytes([*high, 0]),
+            _ => 0,
+        };
+        sum += word as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const SENDER_MAC: [u8; 6] = [0x08, 0x00, 0x00, 0x00, 0x00, 0x01];
+    const TARGET_MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    /// IPv6ヘッダーのホップリミットの位置
+    const HOP_LIMIT_OFFSET: usize = ETHERNET_HEADER_LEN + 7;
+    
+    fn ip(addr: &str) -> Ipv6Address {
+        Ipv6Address::new(addr.parse().unwrap())
+    }
+    
+    fn advertisement() -> NdpPacket {
+        NdpPacket {
+            operation: NdpOperation::Advertisement,
+            sender_mac: MacAddress::new(TARGET_MAC),
+            sender_ip: ip("2001:db8::1"),
+            destination_ip: ip("2001:db8::10"),
+            target_ip: ip("2001:db8::1"),
+            link_layer_addr: Some(MacAddress::new(TARGET_MAC)),
+        }
+    }
+    
+    #[test]
+    fn checksum_of_a_message_with_its_checksum_is_zero() {
+        let (src, dst) = (ip("fe80::1"), ip("ff02::1:ff00:2"));
+        let mut message = vec![ICMPV6_NEIGHBOR_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+        message.extend_from_slice(&ip("2001:db8::2").as_u128().to_be_bytes());
+        message.push(0xab); // 奇数長は最後のバイトを上位8ビットとして足す
+        
+        let checksum = icmpv6_checksum(src, dst, &message);
+        assert_ne!(checksum, 0);
+        message[2..4].copy_from_slice(&checksum.to_be_bytes());
+        assert_eq!(icmpv6_checksum(src, dst, &message), 0);
+        
+        // 疑似ヘッダーのアドレスが違えばチェックサムも合わない
+        assert_ne!(icmpv6_checksum(src, ip("ff02::1"), &message), 0);
+    }
+    
+    #[test]
+    fn solicitation_frame_round_trips() {
+        let solicitation = NdpPacket::solicitation(MacAddress::new(SENDER_MAC), ip("2001:db8::10"), ip("2001:db8::1"));
+        let frame = solicitation.to_frame(None);
+        
+        assert_eq!(&frame[..6], &[0x33, 0x33, 0xff, 0x00, 0x00, 0x01]);
+        assert_eq!(frame[HOP_LIMIT_OFFSET], NDP_HOP_LIMIT);
+        assert_eq!(solicitation.destination_ip, ip("ff02::1:ff00:1"));
+        assert_eq!(NdpPacket::parse(&frame), Some(solicitation));
+    }
+    
+    #[test]
+    fn advertisement_frame_round_trips() {
+        let advertisement = advertisement();
+        let frame = advertisement.to_frame(Some(MacAddress::new(SENDER_MAC)));
+        
+        assert_eq!(&frame[..6], &SENDER_MAC);
+        assert_eq!(NdpPacket::parse(&frame), Some(advertisement));
+    }
+    
+    #[test]
+    fn parse_rejects_frames_that_crossed_a_router() {
+        let mut frame = advertisement().to_frame(None);
+        frame[HOP_LIMIT_OFFSET] = 254;
+        assert_eq!(NdpPacket::parse(&frame), None);
+    }
+    
+    #[test]
+    fn parse_rejects_a_bad_checksum() {
+        let mut frame = advertisement().to_frame(None);
+        let last = frame.len() - 1;
+        frame[last] ^= 0x01;
+        assert_eq!(NdpPacket::parse(&frame), None);
+    }
+    
+    #[test]
+    fn advertisement_without_link_layer_option_uses_the_ethernet_source() {
+        let advertisement = NdpPacket { link_layer_addr: None, ..advertisement() };
+        let parsed = NdpPacket::parse(&advertisement.to_frame(None)).unwrap();
+        
+        assert_eq!(parsed.link_layer_addr, None);
+        assert_eq!(parsed.advertised_mac(), MacAddress::new(TARGET_MAC));
+    }
+    
+    #[test]
+    fn link_layer_option_is_found_after_other_options() {
+        let mut options = vec![5, 1, 0, 0, 0, 0, 0x05, 0xdc]; // MTUオプション
+        options.extend_from_slice(&[NDP_OPT_TARGET_LINK_ADDR, 1]);
+        options.extend_from_slice(&TARGET_MAC);
+        
+        assert_eq!(
+            find_link_layer_option(&options, NDP_OPT_TARGET_LINK_ADDR),
+            Some(MacAddress::new(TARGET_MAC))
+        );
+        assert_eq!(find_link_layer_option(&options, NDP_OPT_SOURCE_LINK_ADDR), None);
+    }
+    
+    #[test]
+    fn malformed_options_are_ignored() {
+        // 長さ0のオプションは不正（RFC 4861 4.6）
+        let zero_length = [NDP_OPT_TARGET_LINK_ADDR, 0, 0, 0x11, 0x22, 0x33, 0x44, 0x55];
+        assert_eq!(find_link_layer_option(&zero_length, NDP_OPT_TARGET_LINK_ADDR), None);
+        
+        // 残りより長いオプション
+        let truncated = [NDP_OPT_TARGET_LINK_ADDR, 2, 0, 0x11, 0x22, 0x33, 0x44, 0x55];
+        assert_eq!(find_link_layer_option(&truncated, NDP_OPT_TARGET_LINK_ADDR), None);
+    }
+}
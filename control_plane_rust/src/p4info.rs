@@ -0,0 +1,372 @@
+use crate::proto::p4::config::v1 as p4config;
+use crate::proto::FILE_DESCRIPTOR_SET;
+use crate::types::*;
+use anyhow::{Context, Result};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// P4Infoのprotobufメッセージ名
+const P4INFO_MESSAGE: &str = "p4.config.v1.P4Info";
+
+/// P4Infoのファイル形式（p4cの `--p4runtime-files` の出力）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum P4InfoFormat {
+    /// テキスト形式（.txt）
+    Text,
+    /// バイナリ形式（.bin）
+    Binary,
+}
+
+impl P4InfoFormat {
+    /// 拡張子から形式を判定（.txt はテキスト、それ以外はバイナリ）
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("txt") => P4InfoFormat::Text,
+            _ => P4InfoFormat::Binary,
+        }
+    }
+}
+
+/// P4Infoファイルを読み込み、コントローラー内部の形式に変換
+pub fn load_p4info(path: &Path) -> Result<P4Info> {
+    Ok(P4Info::from(&load_p4info_proto(path)?))
+}
+
+/// P4Infoファイルをprotobufメッセージとして読み込む
+pub fn load_p4info_proto(path: &Path) -> Result<p4config::P4Info> {
+    let bytes = std::fs::read(path)
+        .with_context(|| format!("Failed to read P4Info {}", path.display()))?;
+    
+    match P4InfoFormat::from_path(path) {
+        P4InfoFormat::Text => {
+            let text = String::from_utf8(bytes)
+                .with_context(|| format!("P4Info {} is not valid UTF-8", path.display()))?;
+            parse_p4info_text(&text)
+        }
+        P4InfoFormat::Binary => parse_p4info_binary(&bytes),
+    }
+    .with_context(|| format!("Failed to parse P4Info {}", path.display()))
+}
+
+/// テキスト形式のP4Infoをパース
+pub fn parse_p4info_text(text: &str) -> Result<p4config::P4Info> {
+    let pool = DescriptorPool::decode(FILE_DESCRIPTOR_SET)?;
+    let descriptor = pool
+        .get_message_by_name(P4INFO_MESSAGE)
+        .ok_or_else(|| anyhow::anyhow!("Descriptor for {} not found", P4INFO_MESSAGE))?;
+    
+    let message = DynamicMessage::parse_text_format(descriptor, text)?;
+    Ok(message.transcode_to::<p4config::P4Info>()?)
+}
+
+/// バイナリ形式のP4Infoをパース
+pub fn parse_p4info_binary(bytes: &[u8]) -> Result<p4config::P4Info> {
+    Ok(p4config::P4Info::decode(bytes)?)
+}
+
+impl From<&p4config::P4Info> for P4Info {
+    fn from(p4info: &p4config::P4Info) -> Self {
+        let action_names: HashMap<u32, String> = p4info
+            .actions
+            .iter()
+            .filter_map(|a| a.preamble.as_ref())
+            .map(|p| (p.id, p.name.clone()))
+            .collect();
+        
+        let mut info = P4Info::default();
+        
+        for table in &p4info.tables {
+            let Some(preamble) = &table.preamble else { continue };
+            
+            // 扱えないマッチタイプのフィールドも残し、キーの構成を実際のテーブルと一致させる
+            let key_fields = table
+                .match_fields
+                .iter()
+                .map(|field| {
+                    let match_type = convert_match_type(field);
+                    if match_type == MatchType::Unsupported {
+                        tracing::warn!(
+                            "Match field {} of table {} has an unsupported match type; entries for the table will be rejected",
+                            field.name,
+                            preamble.name
+                        );
+                    }
+                    KeyField {
+                        id: field.id,
+                        name: field.name.clone(),
+                        bitwidth: field.bitwidth as u32,
+                        match_type,
+                    }
+                })
+                .collect();
+            
+            let action_refs = table
+                .action_refs
+                .iter()
+                .map(|r| ActionRef {
+                    name: action_names.get(&r.id).cloned().unwrap_or_default(),
+                    id: r.id,
+                })
+                .collect();
+            
+            info.tables.insert(
+                preamble.name.clone(),
+                TableInfo {
+                    name: preamble.name.clone(),
+                    id: preamble.id,
+                    key_fields,
+                    action_refs,
+                    size: table.size.max(0) as u64,
+                    implementation_id: table.implementation_id,
+                },
+            );
+        }
+        
+        for action in &p4info.actions {
+            let Some(preamble) = &action.preamble else { continue };
+            
+            let params = action
+                .params
+                .iter()
+                .map(|p| ActionParam {
+                    id: p.id,
+                    name: p.name.clone(),
+                    bitwidth: p.bitwidth as u32,
+                })
+                .collect();
+            
+            info.actions.insert(
+                preamble.name.clone(),
+                ActionInfo {
+                    name: preamble.name.clone(),
+                    id: preamble.id,
+                    params,
+                },
+            );
+        }
+        
+        for counter in &p4info.counters {
+            let Some(preamble) = &counter.preamble else { continue };
+            info.counters.insert(
+                preamble.name.clone(),
+                CounterInfo {
+                    name: preamble.name.clone(),
+                    id: preamble.id,
+                    size: counter.size.max(0) as u64,
+                    direct_table_id: None,
+                },
+            );
+        }
+        
+        for counter in &p4info.direct_counters {
+            let Some(preamble) = &counter.preamble else { continue };
+            info.counters.insert(
+                preamble.name.clone(),
+                CounterInfo {
+                    name: preamble.name.clone(),
+                    id: preamble.id,
+                    size: 0,
+                    direct_table_id: Some(counter.direct_table_id),
+                },
+            );
+        }
+        
+        for meter in &p4info.meters {
+            let Some(preamble) = &meter.preamble else { continue };
+            info.meters.insert(
+                preamble.name.clone(),
+                MeterInfo {
+                    name: preamble.name.clone(),
+                    id: preamble.id,
+                    size: meter.size.max(0) as u64,
+                    direct_table_id: None,
+                },
+            );
+        }
+        
+        for meter in &p4info.direct_meters {
+            let Some(preamble) = &meter.preamble else { continue };
+            info.meters.insert(
+                preamble.name.clone(),
+                MeterInfo {
+                    name: preamble.name.clone(),
+                    id: preamble.id,
+                    size: 0,
+                    direct_table_id: Some(meter.direct_table_id),
+                },
+            );
+        }
+        
+        for digest in &p4info.digests {
+            let Some(preamble) = &digest.preamble else { continue };
+            info.digests.insert(
+                preamble.name.clone(),
+                DigestInfo {
+                    name: preamble.name.clone(),
+                    id: preamble.id,
+                },
+            );
+        }
+        
+        for profile in &p4info.action_profiles {
+            let Some(preamble) = &profile.preamble else { continue };
+            info.action_profiles.insert(
+                preamble.name.clone(),
+                ActionProfileInfo {
+                    name: preamble.name.clone(),
+                    id: preamble.id,
+                    table_ids: profile.table_ids.clone(),
+                    with_selector: profile.with_selector,
+                    size: profile.size.max(0) as u64,
+                    max_group_size: profile.max_group_size.max(0) as u32,
+                },
+            );
+        }
+        
+        for header in &p4info.controller_packet_metadata {
+            let Some(preamble) = &header.preamble else { continue };
+            
+            let metadata = header
+                .metadata
+                .iter()
+                .map(|m| PacketMetadataInfo {
+                    id: m.id,
+                    name: m.name.clone(),
+                    bitwidth: m.bitwidth as u32,
+                })
+                .collect();
+            
+            info.controller_packet_metadata.insert(
+                preamble.name.clone(),
+                ControllerPacketMetadataInfo {
+                    name: preamble.name.clone(),
+                    id: preamble.id,
+                    metadata,
+                },
+            );
+        }
+        
+        info
+    }
+}
+
+/// P4InfoのマッチタイプをMatchTypeに変換（アーキテクチャ固有のものはUnsupported）
+fn convert_match_type(field: &p4config::MatchField) -> MatchType {
+    use p4config::match_field::{Match, MatchType as P4MatchType};
+    
+    let Some(Match::MatchType(value)) = field.r#match else {
+        return MatchType::Unsupported;
+    };
+    
+    match P4MatchType::try_from(value) {
+        Ok(P4MatchType::Exact) => MatchType::Exact,
+        Ok(P4MatchType::Lpm) => MatchType::Lpm,
+        Ok(P4MatchType::Ternary) => MatchType::Ternary,
+        Ok(P4MatchType::Range) => MatchType::Range,
+        Ok(P4MatchType::Optional) => MatchType::Optional,
+        Ok(P4MatchType::Unspecified) | Err(_) => MatchType::Unsupported,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// p4cのテキスト形式出力と同じ形のP4Info（`hash` はアーキテクチャ固有のマッチタイプ）
+    const P4INFO_TEXT: &str = r#"
+pkg_info { arch: "v1model" }
+tables {
+  preamble { id: 37375156 name: "MyIngress.ipv4_lpm" alias: "ipv4_lpm" }
+  match_fields { id: 1 name: "hdr.ipv4.dstAddr" bitwidth: 32 match_type: LPM }
+  action_refs { id: 28792405 }
+  action_refs { id: 25652968 }
+  size: 1024
+}
+tables {
+  preamble { id: 40000001 name: "MyIngress.flow_hash" alias: "flow_hash" }
+  match_fields { id: 1 name: "meta.flow_id" bitwidth: 16 other_match_type: "hash" }
+  action_refs { id: 25652968 }
+  size: 64
+}
+actions {
+  preamble { id: 28792405 name: "MyIngress.ipv4_forward" alias: "ipv4_forward" }
+  params { id: 1 name: "dstAddr" bitwidth: 48 }
+  params { id: 2 name: "port" bitwidth: 9 }
+}
+actions {
+  preamble { id: 25652968 name: "MyIngress.drop" alias: "drop" }
+}
+counters {
+  preamble { id: 302000001 name: "MyIngress.port_counter" alias: "port_counter" }
+  spec { unit: PACKETS }
+  size: 512
+}
+controller_packet_metadata {
+  preamble { id: 67146229 name: "packet_in" alias: "packet_in" }
+  metadata { id: 1 name: "ingress_port" bitwidth: 9 }
+}
+"#;
+    
+    #[test]
+    fn text_format_is_converted_with_names_and_ids() {
+        let info = P4Info::from(&parse_p4info_text(P4INFO_TEXT).unwrap());
+        
+        let table = info.find_table("MyIngress.ipv4_lpm").unwrap();
+        assert_eq!(table.id, 37375156);
+        assert_eq!(table.size, 1024);
+        assert_eq!(table.key_fields.len(), 1);
+        assert_eq!(table.key_fields[0].match_type, MatchType::Lpm);
+        assert_eq!(table.action_refs[0].name, "MyIngress.ipv4_forward");
+        
+        assert_eq!(info.action_id("MyIngress.drop"), Some(25652968));
+        assert_eq!(info.action_param_id("MyIngress.ipv4_forward", "port"), Some(2));
+        assert_eq!(info.counter_id("MyIngress.port_counter"), Some(302000001));
+        assert_eq!(info.controller_packet_metadata["packet_in"].metadata[0].bitwidth, 9);
+    }
+    
+    #[test]
+    fn binary_format_round_trips_through_the_text_format() {
+        let proto = parse_p4info_text(P4INFO_TEXT).unwrap();
+        let decoded = parse_p4info_binary(&proto.encode_to_vec()).unwrap();
+        assert_eq!(decoded, proto);
+        
+        assert!(parse_p4info_binary(&[0xff, 0xff, 0xff]).is_err());
+        assert!(parse_p4info_text("tables { preamble { id: \"x\" } }").is_err());
+    }
+    
+    #[test]
+    fn short_name_shared_by_two_controls_is_ambiguous() {
+        let text = format!(
+            "{}{}",
+            P4INFO_TEXT,
+            r#"actions { preamble { id: 26000001 name: "MyEgress.drop" alias: "MyEgress.drop" } }"#
+        );
+        let info = P4Info::from(&parse_p4info_text(&text).unwrap());
+        
+        assert!(info.find_action("drop").is_none());
+        assert_eq!(info.find_action("MyIngress.drop").map(|action| action.id), Some(25652968));
+        assert_eq!(info.find_action("MyEgress.drop").map(|action| action.id), Some(26000001));
+        // 最後の要素が1つだけのものは短い名前でも引ける
+        assert_eq!(info.find_action("ipv4_forward").map(|action| action.id), Some(28792405));
+        
+        let entry = TableEntry::new("MyIngress.ipv4_lpm", Vec::new(), ActionCall::new("drop"), 0);
+        assert!(crate::table_manager::validate_entry(&info, &entry).is_err());
+    }
+    
+    #[test]
+    fn unsupported_match_kind_keeps_the_field_and_rejects_entries() {
+        let info = P4Info::from(&parse_p4info_text(P4INFO_TEXT).unwrap());
+        
+        // フィールドを落とすとキーが実際のテーブルと食い違うので、残したまま書き込みを拒否する
+        let table = info.find_table("MyIngress.flow_hash").unwrap();
+        assert_eq!(table.key_fields.len(), 1);
+        assert_eq!(table.key_fields[0].match_type, MatchType::Unsupported);
+        assert!(!table.needs_priority());
+        
+        let entry = TableEntry::new("MyIngress.flow_hash", Vec::new(), ActionCall::new("MyIngress.drop"), 0);
+        let error = crate::table_manager::validate_entry(&info, &entry).unwrap_err();
+        assert!(error.to_string().contains("unsupported match type"), "{}", error);
+    }
+}
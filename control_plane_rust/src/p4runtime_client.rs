@@ -1,71 +1,448 @@
+use crate::codec;
+use crate::pipeline::PipelineConfig;
+use crate::proto::google::rpc;
+use crate::proto::p4::v1 as p4v1;
+use crate::proto::p4::v1::p4_runtime_client::P4RuntimeClient as P4RuntimeStub;
 use crate::types::*;
 use anyhow::Result;
+use prost::Message;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::{Channel, Endpoint};
 
+/// アービトレーション応答を待つ時間
+const ARBITRATION_TIMEOUT: Duration = Duration::from_secs(5);
+/// StreamChannel送信キューの長さ
+const STREAM_BUFFER_SIZE: usize = 64;
+/// PacketIn購読者ごとに保持するパケット数
+const PACKET_IN_BUFFER_SIZE: usize = 256;
+
 /// P4Runtime gRPCクライアント
 #[derive(Debug)]
 pub struct P4RuntimeClient {
     device_id: DeviceId,
-    client: tonic::client::Grpc<Channel>,
+    client: P4RuntimeStub<Channel>,
+    election_id: u128,
+    role: Option<String>,
+    p4info: Option<P4Info>,
+    /// 最新のアービトレーション結果（未接続ならNone）
+    mastership: Arc<RwLock<Option<MastershipStatus>>>,
+    /// StreamChannelの送信側（保持している間ストリームが維持される）
+    stream_tx: Option<mpsc::Sender<p4v1::StreamMessageRequest>>,
+    /// StreamChannelの受信タスク（再接続時・破棄時に止める）
+    stream_task: Option<JoinHandle<()>>,
+    write_options: WriteOptions,
+    /// 受信したPacketInの配信先
+    packet_in_tx: broadcast::Sender<PacketIn>,
+    /// packet_inヘッダーの定義（ストリーム受信タスクと共有）
+    packet_in_header: Arc<RwLock<Option<ControllerPacketMetadataInfo>>>,
 }
 
 impl P4RuntimeClient {
     /// 新しいP4Runtimeクライアントを作成
     pub async fn new(device_id: DeviceId, endpoint: &str) -> Result<Self> {
-        let channel = Endpoint::from_shared(endpoint.to_string())?
+        // "127.0.0.1:50051" のようにスキームが省略された場合はhttpを補う
+        let endpoint = if endpoint.contains("://") {
+            endpoint.to_string()
+        } else {
+            format!("http://{}", endpoint)
+        };
+        
+        let channel = Endpoint::from_shared(endpoint)?
             .connect()
             .await?;
         
-        let client = tonic::client::Grpc::new(channel);
-        
-        Ok(Self {
+        Ok(Self::with_channel(device_id, channel))
+    }
+    
+    fn with_channel(device_id: DeviceId, channel: Channel) -> Self {
+        Self {
             device_id,
-            client,
+            client: P4RuntimeStub::new(channel),
+            election_id: 1,
+            role: None,
+            p4info: None,
+            mastership: Arc::new(RwLock::new(None)),
+            stream_tx: None,
+            stream_task: None,
+            write_options: WriteOptions::default(),
+            packet_in_tx: broadcast::channel(PACKET_IN_BUFFER_SIZE).0,
+            packet_in_header: Arc::new(RwLock::new(None)),
+        }
+    }
+    
+    /// アービトレーションに使うelection IDを設定
+    pub fn set_election_id(&mut self, election_id: u128) {
+        self.election_id = election_id;
+    }
+    
+    /// アービトレーションに使うロールを設定
+    pub fn set_role(&mut self, role: Option<String>) {
+        self.role = role;
+    }
+    
+    /// WriteRequestのバッチサイズとアトミック性を設定
+    pub fn set_write_options(&mut self, write_options: WriteOptions) {
+        self.write_options = write_options;
+    }
+    
+    /// StreamChannelを開き、MasterArbitrationUpdateでマスター権を要求
+    ///
+    /// すでに接続していれば、前のストリームと受信タスクを閉じてから開き直す。
+    pub async fn connect(&mut self) -> Result<MastershipStatus> {
+        self.close_stream().await;
+        
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+        tx.send(self.arbitration_request()).await?;
+        
+        let mut inbound = self
+            .client
+            .stream_channel(ReceiverStream::new(rx))
+            .await?
+            .into_inner();
+        
+        // スイッチからのアービトレーション応答を待つ
+        let device_id = self.device_id;
+        let update = tokio::time::timeout(ARBITRATION_TIMEOUT, async {
+            loop {
+                match inbound.message().await? {
+                    Some(p4v1::StreamMessageResponse {
+                        update: Some(p4v1::stream_message_response::Update::Arbitration(update)),
+                    }) => return Ok(update),
+                    Some(_) => continue,
+                    None => {
+                        return Err(anyhow::Error::from(P4RuntimeError::ArbitrationFailed {
+                            device_id,
+                            reason: "stream closed before arbitration reply".to_string(),
+                        }))
+                    }
+                }
+            }
         })
+        .await
+        .map_err(|_| P4RuntimeError::ArbitrationFailed {
+            device_id,
+            reason: "timed out waiting for arbitration reply".to_string(),
+        })??;
+        
+        let status = mastership_from_update(&update);
+        *self.mastership.write().await = Some(status);
+        self.stream_tx = Some(tx);
+        
+        // 以降のアービトレーション更新（他コントローラーの接続など）を監視
+        let mastership = self.mastership.clone();
+        let packet_in_tx = self.packet_in_tx.clone();
+        let packet_in_header = self.packet_in_header.clone();
+        self.stream_task = Some(tokio::spawn(async move {
+            loop {
+                match inbound.message().await {
+                    Ok(Some(message)) => match message.update {
+                        Some(p4v1::stream_message_response::Update::Arbitration(update)) => {
+                            let status = mastership_from_update(&update);
+                            tracing::info!("Device {} mastership changed: {}", device_id, status);
+                            *mastership.write().await = Some(status);
+                        }
+                        Some(p4v1::stream_message_response::Update::Packet(packet)) => {
+                            let header = packet_in_header.read().await;
+                            let packet_in = decode_packet_in(device_id, header.as_ref(), packet);
+                            // 購読者がいなければ捨てる
+                            let _ = packet_in_tx.send(packet_in);
+                        }
+                        Some(p4v1::stream_message_response::Update::Error(error)) => {
+                            tracing::warn!("Stream error from device {}: {}", device_id, error.message);
+                        }
+                        _ => {}
+                    },
+                    Ok(None) => {
+                        tracing::info!("Stream channel to device {} closed", device_id);
+                        *mastership.write().await = None;
+                        break;
+                    }
+                    Err(e) => {
+                        tracing::error!("Stream channel to device {} failed: {}", device_id, e);
+                        *mastership.write().await = None;
+                        break;
+                    }
+                }
+            }
+        }));
+        
+        tracing::info!(
+            "Connected to device {} as {} (election id {})",
+            self.device_id,
+            status,
+            self.election_id
+        );
+        Ok(status)
+    }
+    
+    /// StreamChannelの受信タスクを止め、送信側を破棄してストリームを閉じる
+    async fn close_stream(&mut self) {
+        if let Some(task) = self.stream_task.take() {
+            task.abort();
+        }
+        if self.stream_tx.take().is_some() {
+            *self.mastership.write().await = None;
+            tracing::info!("Closed stream channel to device {}", self.device_id);
+        }
+    }
+    
+    /// 現在のアービトレーション結果を取得
+    pub async fn mastership(&self) -> Option<MastershipStatus> {
+        *self.mastership.read().await
     }
     
-    /// デバイスに接続を確立
-    pub async fn connect(&mut self) -> Result<()> {
-        // 実際のP4Runtimeでは、MasterArbitrationUpdateを送信してマスター権を取得
-        // ここでは簡略化して接続成功とみなす
-        tracing::info!("Connected to device {}", self.device_id);
+    /// SetForwardingPipelineConfigでパイプラインをプッシュ
+    pub async fn set_forwarding_pipeline_config(
+        &mut self,
+        config: &PipelineConfig,
+        action: PipelineAction,
+    ) -> Result<()> {
+        self.ensure_primary().await?;
+        
+        let request = p4v1::SetForwardingPipelineConfigRequest {
+            device_id: self.device_id,
+            role: self.role.clone().unwrap_or_default(),
+            election_id: Some(uint128(self.election_id)),
+            action: action.to_proto() as i32,
+            config: Some(config.to_proto()),
+            ..Default::default()
+        };
+        
+        self.client.set_forwarding_pipeline_config(request).await?;
+        tracing::info!(
+            "Pushed pipeline to device {} ({:?}, cookie {:#x})",
+            self.device_id,
+            action,
+            config.cookie
+        );
         Ok(())
     }
     
-    /// テーブルエントリを書き込み
-    pub async fn write_table_entries(&mut self, entries: &[TableEntry]) -> Result<()> {
-        for entry in entries {
-            self.write_table_entry(entry).await?;
+    /// GetForwardingPipelineConfigで現在のパイプラインを取得（未設定ならNone）
+    pub async fn get_forwarding_pipeline_config(
+        &mut self,
+        response_type: p4v1::get_forwarding_pipeline_config_request::ResponseType,
+    ) -> Result<Option<p4v1::ForwardingPipelineConfig>> {
+        let request = p4v1::GetForwardingPipelineConfigRequest {
+            device_id: self.device_id,
+            response_type: response_type as i32,
+        };
+        
+        match self.client.get_forwarding_pipeline_config(request).await {
+            Ok(response) => Ok(response.into_inner().config),
+            // パイプライン未設定のデバイスはFAILED_PRECONDITIONを返す
+            Err(status) if status.code() == tonic::Code::FailedPrecondition => Ok(None),
+            Err(status) => Err(P4RuntimeError::from(status).into()),
         }
+    }
+    
+    /// 現在動作しているパイプラインのクッキーを取得
+    pub async fn get_pipeline_cookie(&mut self) -> Result<Option<u64>> {
+        let config = self
+            .get_forwarding_pipeline_config(p4v1::get_forwarding_pipeline_config_request::ResponseType::CookieOnly)
+            .await?;
+        Ok(config.and_then(|c| c.cookie).map(|c| c.cookie))
+    }
+    
+    /// テーブルエントリの変換に使うP4Infoを設定
+    pub async fn set_p4info(&mut self, p4info: P4Info) {
+        *self.packet_in_header.write().await = p4info.find_controller_packet_metadata(PACKET_IN_HEADER).cloned();
+        self.p4info = Some(p4info);
+    }
+    
+    /// StreamChannelで受信したPacketInを購読
+    pub fn subscribe_packet_in(&self) -> broadcast::Receiver<PacketIn> {
+        self.packet_in_tx.subscribe()
+    }
+    
+    /// StreamChannelでPacketOutを送信
+    pub async fn send_packet_out(&self, packet: &PacketOut) -> Result<()> {
+        self.ensure_primary().await?;
+        
+        let header = self
+            .p4info()?
+            .find_controller_packet_metadata(PACKET_OUT_HEADER)
+            .ok_or_else(|| P4RuntimeError::InvalidPacket(format!("P4Info has no {} header", PACKET_OUT_HEADER)))?;
+        
+        for metadata in &packet.metadata {
+            if !header.metadata.iter().any(|m| m.name == metadata.name) {
+                return Err(P4RuntimeError::InvalidPacket(format!("Unknown {} metadata {}", PACKET_OUT_HEADER, metadata.name)).into());
+            }
+        }
+        
+        // 指定されていないフィールド（パディングなど）は0で送る
+        let metadata = header
+            .metadata
+            .iter()
+            .map(|info| {
+                let value = packet
+                    .metadata
+                    .iter()
+                    .find(|m| m.name == info.name)
+                    .map(|m| m.value.as_slice())
+                    .unwrap_or(&[0]);
+                Ok(p4v1::PacketMetadata {
+                    metadata_id: info.id,
+                    value: codec::encode(value, info.bitwidth)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        
+        let stream_tx = self.stream_tx.as_ref().ok_or(P4RuntimeError::StreamClosed { device_id: self.device_id })?;
+        stream_tx
+            .send(p4v1::StreamMessageRequest {
+                update: Some(p4v1::stream_message_request::Update::Packet(p4v1::PacketOut {
+                    payload: packet.payload.clone(),
+                    metadata,
+                })),
+            })
+            .await
+            .map_err(|_| P4RuntimeError::StreamClosed { device_id: self.device_id })?;
+        
         Ok(())
     }
     
+    /// テーブルエントリをINSERTでまとめて書き込み
+    pub async fn write_table_entries(&mut self, entries: &[TableEntry]) -> Result<WriteResult> {
+        let updates: Vec<TableUpdate> = entries.iter().cloned().map(TableUpdate::insert).collect();
+        self.write(&updates).await
+    }
+    
     /// 単一のテーブルエントリを書き込み
     pub async fn write_table_entry(&mut self, entry: &TableEntry) -> Result<()> {
-        // 実際のP4Runtimeでは、WriteRequestを送信
-        // ここでは簡略化してログ出力
-        tracing::info!(
-            "Writing table entry: {} -> {:?}",
-            entry.key.ipv4_dst,
-            entry.action
-        );
+        tracing::info!("Writing table entry: {} {:?} -> {:?}", entry.table, entry.matches, entry.action);
+        
+        self.write(&[TableUpdate::insert(entry.clone())]).await?.into_result()?;
         Ok(())
     }
     
     /// テーブルエントリを削除
-    pub async fn delete_table_entry(&mut self, key: &TableKey) -> Result<()> {
-        tracing::info!("Deleting table entry: {}", key.ipv4_dst);
+    pub async fn delete_table_entry(&mut self, entry: &TableEntry) -> Result<()> {
+        tracing::info!("Deleting table entry: {} {:?}", entry.table, entry.matches);
+        
+        self.write(&[TableUpdate::delete(entry.clone())]).await?.into_result()?;
         Ok(())
     }
     
-    /// テーブルエントリを読み取り
+    /// テーブルエントリの更新を送信（`write_entities` を参照）
+    pub async fn write(&mut self, updates: &[TableUpdate]) -> Result<WriteResult> {
+        let updates: Vec<EntityUpdate> = updates.iter().cloned().map(EntityUpdate::from).collect();
+        self.write_entities(&updates).await
+    }
+    
+    /// 更新をmax_batch_sizeごとのWriteRequestにまとめて送信
+    ///
+    /// 失敗した更新は `p4.v1.Error` の内容とともに `WriteResult` に記録する。
+    /// CONTINUE_ON_ERROR以外では、失敗したバッチ以降のバッチは送信しない。
+    /// 1つのWriteRequest内の更新の順序は保証されないため、メンバー・グループとそれを参照するエントリは別々に送ること。
+    pub async fn write_entities(&mut self, updates: &[EntityUpdate]) -> Result<WriteResult> {
+        self.ensure_primary().await?;
+        
+        // 送信前にすべての更新を変換しておき、不正なエントリがあれば何も書き込まない
+        let proto_updates = updates
+            .iter()
+            .map(|update| self.build_update(update))
+            .collect::<Result<Vec<_>>>()?;
+        
+        let options = self.write_options;
+        let mut result = WriteResult::new(self.device_id, updates.len());
+        
+        for batch in write_batches(updates.len(), options.max_batch_size) {
+            if let Err(status) = self.send_write_request(proto_updates[batch.clone()].to_vec(), options.atomicity).await {
+                if !record_batch_failure(&mut result, updates, batch, &status, options.atomicity) {
+                    break;
+                }
+            }
+        }
+        
+        if result.is_success() {
+            tracing::info!("Wrote {} updates to device {}", result.total, self.device_id);
+        } else {
+            tracing::warn!(
+                "Write to device {}: {} of {} updates failed",
+                self.device_id,
+                result.failures.len(),
+                result.total
+            );
+        }
+        Ok(result)
+    }
+    
+    /// 全テーブルのエントリを読み取り
     pub async fn read_table_entries(&mut self) -> Result<Vec<TableEntry>> {
-        // 実際のP4Runtimeでは、ReadRequestを送信
-        // ここでは簡略化して空のベクターを返す
-        Ok(Vec::new())
+        // table_id = 0 はワイルドカードで、全テーブルのエントリが返る
+        let request = p4v1::ReadRequest {
+            device_id: self.device_id,
+            entities: vec![p4v1::Entity {
+                entity: Some(p4v1::entity::Entity::TableEntry(p4v1::TableEntry::default())),
+            }],
+            ..Default::default()
+        };
+        
+        let mut stream = self.client.read(request).await?.into_inner();
+        let mut entries = Vec::new();
+        
+        while let Some(response) = stream.message().await? {
+            for entity in response.entities {
+                if let Some(p4v1::entity::Entity::TableEntry(table_entry)) = entity.entity {
+                    match self.parse_table_entry(&table_entry) {
+                        Ok(entry) => entries.push(entry),
+                        Err(e) => tracing::warn!("Skipping unrecognized table entry: {}", e),
+                    }
+                }
+            }
+        }
+        
+        Ok(entries)
+    }
+    
+    /// 全アクションプロファイルのメンバーとグループを読み取り
+    pub async fn read_action_profiles(&mut self) -> Result<(Vec<ActionProfileMember>, Vec<ActionProfileGroup>)> {
+        // action_profile_id = 0 はワイルドカードで、全プロファイルのメンバー・グループが返る
+        let request = p4v1::ReadRequest {
+            device_id: self.device_id,
+            entities: vec![
+                p4v1::Entity {
+                    entity: Some(p4v1::entity::Entity::ActionProfileMember(p4v1::ActionProfileMember::default())),
+                },
+                p4v1::Entity {
+                    entity: Some(p4v1::entity::Entity::ActionProfileGroup(p4v1::ActionProfileGroup::default())),
+                },
+            ],
+            ..Default::default()
+        };
+        
+        let mut stream = self.client.read(request).await?.into_inner();
+        let mut members = Vec::new();
+        let mut groups = Vec::new();
+        
+        while let Some(response) = stream.message().await? {
+            for entity in response.entities {
+                match entity.entity {
+                    Some(p4v1::entity::Entity::ActionProfileMember(member)) => {
+                        match self.parse_action_profile_member(&member) {
+                            Ok(member) => members.push(member),
+                            Err(e) => tracing::warn!("Skipping unrecognized action profile member: {}", e),
+                        }
+                    }
+                    Some(p4v1::entity::Entity::ActionProfileGroup(group)) => {
+                        match self.parse_action_profile_group(&group) {
+                            Ok(group) => groups.push(group),
+                            Err(e) => tracing::warn!("Skipping unrecognized action profile group: {}", e),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        
+        Ok((members, groups))
     }
     
     /// 統計情報を取得
@@ -74,6 +451,510 @@ impl P4RuntimeClient {
         // ここでは簡略化してデフォルト値を返す
         Ok(Statistics::default())
     }
+    
+    /// WriteRequestを1つ送信
+    async fn send_write_request(
+        &mut self,
+        updates: Vec<p4v1::Update>,
+        atomicity: WriteAtomicity,
+    ) -> std::result::Result<(), tonic::Status> {
+        let request = p4v1::WriteRequest {
+            device_id: self.device_id,
+            role: self.role.clone().unwrap_or_default(),
+            election_id: Some(uint128(self.election_id)),
+            updates,
+            atomicity: atomicity.to_proto() as i32,
+            ..Default::default()
+        };
+        
+        self.client.write(request).await?;
+        Ok(())
+    }
+    
+    /// プライマリでなければ書き込みを拒否
+    async fn ensure_primary(&self) -> Result<()> {
+        match self.mastership().await {
+            Some(MastershipStatus::Primary) => Ok(()),
+            _ => Err(P4RuntimeError::NotPrimary {
+                device_id: self.device_id,
+                election_id: self.election_id,
+            }
+            .into()),
+        }
+    }
+    
+    /// MasterArbitrationUpdateを含むストリームメッセージを作成
+    fn arbitration_request(&self) -> p4v1::StreamMessageRequest {
+        p4v1::StreamMessageRequest {
+            update: Some(p4v1::stream_message_request::Update::Arbitration(
+                p4v1::MasterArbitrationUpdate {
+                    device_id: self.device_id,
+                    role: self.role.as_ref().map(|name| p4v1::Role {
+                        name: name.clone(),
+                        ..Default::default()
+                    }),
+                    election_id: Some(uint128(self.election_id)),
+                    status: None,
+                },
+            )),
+        }
+    }
+    
+    fn p4info(&self) -> Result<&P4Info> {
+        self.p4info
+            .as_ref()
+            .ok_or_else(|| P4RuntimeError::P4InfoNotLoaded { device_id: self.device_id }.into())
+    }
+    
+    /// EntityUpdateをP4RuntimeのUpdateに変換
+    fn build_update(&self, update: &EntityUpdate) -> Result<p4v1::Update> {
+        // DELETEではキー（マッチフィールドやID）のみでエンティティを特定する
+        let with_action = update.update_type != UpdateType::Delete;
+        let entity = match &update.entity {
+            Entity::TableEntry(entry) => {
+                p4v1::entity::Entity::TableEntry(self.build_table_entry(entry, with_action)?)
+            }
+            Entity::ActionProfileMember(member) => {
+                p4v1::entity::Entity::ActionProfileMember(self.build_action_profile_member(member, with_action)?)
+            }
+            Entity::ActionProfileGroup(group) => {
+                p4v1::entity::Entity::ActionProfileGroup(self.build_action_profile_group(group, with_action)?)
+            }
+        };
+        
+        Ok(entity_update(update.update_type, entity))
+    }
+    
+    /// ActionProfileMemberをP4RuntimeのActionProfileMemberに変換
+    fn build_action_profile_member(
+        &self,
+        member: &ActionProfileMember,
+        with_action: bool,
+    ) -> Result<p4v1::ActionProfileMember> {
+        let p4info = self.p4info()?;
+        let profile = p4info
+            .find_action_profile(&member.profile)
+            .ok_or_else(|| P4RuntimeError::InvalidTableEntry(format!("Unknown action profile {}", member.profile)))?;
+        
+        let action = if with_action {
+            Some(self.build_action(p4info, &member.action)?)
+        } else {
+            None
+        };
+        
+        Ok(p4v1::ActionProfileMember {
+            action_profile_id: profile.id,
+            member_id: member.member_id,
+            action,
+        })
+    }
+    
+    /// ActionProfileGroupをP4RuntimeのActionProfileGroupに変換
+    fn build_action_profile_group(
+        &self,
+        group: &ActionProfileGroup,
+        with_members: bool,
+    ) -> Result<p4v1::ActionProfileGroup> {
+        let p4info = self.p4info()?;
+        let profile = p4info
+            .find_action_profile(&group.profile)
+            .ok_or_else(|| P4RuntimeError::InvalidTableEntry(format!("Unknown action profile {}", group.profile)))?;
+        
+        let members = if with_members {
+            group
+                .members
+                .iter()
+                .map(|m| p4v1::action_profile_group::Member {
+                    member_id: m.member_id,
+                    weight: m.weight as i32,
+                    ..Default::default()
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        
+        Ok(p4v1::ActionProfileGroup {
+            action_profile_id: profile.id,
+            group_id: group.group_id,
+            members,
+            max_size: group.max_size as i32,
+        })
+    }
+    
+    /// TableEntryをP4Infoの名前からIDに解決してP4RuntimeのTableEntryに変換
+    fn build_table_entry(&self, entry: &TableEntry, with_action: bool) -> Result<p4v1::TableEntry> {
+        let p4info = self.p4info()?;
+        let table = p4info
+            .find_table(&entry.table)
+            .ok_or_else(|| P4RuntimeError::TableNotFound { table_name: entry.table.clone() })?;
+        
+        let mut matches = Vec::new();
+        for field_match in &entry.matches {
+            let field = table
+                .key_fields
+                .iter()
+                .find(|k| k.name == field_match.field)
+                .ok_or_else(|| {
+                    P4RuntimeError::InvalidTableEntry(format!(
+                        "Unknown match field {} in table {}",
+                        field_match.field, table.name
+                    ))
+                })?;
+            
+            use p4v1::field_match::{self as fm, FieldMatchType};
+            // ワイルドカードのマッチは省略する
+            let Some(value) = codec::encode_match(&field_match.value, field.bitwidth)? else {
+                continue;
+            };
+            let field_match_type = match value {
+                MatchValue::Exact(value) => FieldMatchType::Exact(fm::Exact { value }),
+                MatchValue::Lpm { value, prefix_len } => FieldMatchType::Lpm(fm::Lpm {
+                    value,
+                    prefix_len: prefix_len as i32,
+                }),
+                MatchValue::Ternary { value, mask } => FieldMatchType::Ternary(fm::Ternary { value, mask }),
+                MatchValue::Range { low, high } => FieldMatchType::Range(fm::Range { low, high }),
+                MatchValue::Optional(value) => FieldMatchType::Optional(fm::Optional { value }),
+            };
+            
+            matches.push(p4v1::FieldMatch {
+                field_id: field.id,
+                field_match_type: Some(field_match_type),
+            });
+        }
+        
+        use p4v1::table_action::Type as ActionType;
+        let action = if with_action {
+            Some(match &entry.action {
+                EntryAction::Direct(action) => ActionType::Action(self.build_action(p4info, action)?),
+                EntryAction::Member(member_id) => ActionType::ActionProfileMemberId(*member_id),
+                EntryAction::Group(group_id) => ActionType::ActionProfileGroupId(*group_id),
+            })
+        } else {
+            None
+        };
+        
+        Ok(p4v1::TableEntry {
+            table_id: table.id,
+            r#match: matches,
+            action: action.map(|action| p4v1::TableAction { r#type: Some(action) }),
+            // 優先度はternary / range / optionalを含むテーブルでのみ意味を持つ
+            priority: if table.needs_priority() { entry.priority as i32 } else { 0 },
+            ..Default::default()
+        })
+    }
+    
+    /// ActionCallをP4RuntimeのActionに変換
+    fn build_action(&self, p4info: &P4Info, action: &ActionCall) -> Result<p4v1::Action> {
+        let info = p4info
+            .find_action(&action.name)
+            .ok_or_else(|| P4RuntimeError::ActionNotFound { action_name: action.name.clone() })?;
+        
+        let params = info
+            .params
+            .iter()
+            .map(|param| {
+                let value = action.param(&param.name).ok_or_else(|| {
+                    P4RuntimeError::InvalidTableEntry(format!(
+                        "Missing parameter {} for action {}",
+                        param.name, info.name
+                    ))
+                })?;
+                Ok(p4v1::action::Param {
+                    param_id: param.id,
+                    value: codec::encode(value, param.bitwidth)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        
+        Ok(p4v1::Action {
+            action_id: info.id,
+            params,
+        })
+    }
+    
+    /// P4RuntimeのTableEntryをIDから名前に解決してTableEntryに変換
+    fn parse_table_entry(&self, table_entry: &p4v1::TableEntry) -> Result<TableEntry> {
+        let p4info = self.p4info()?;
+        let table = p4info.find_table_by_id(table_entry.table_id).ok_or_else(|| {
+            P4RuntimeError::TableNotFound { table_name: table_entry.table_id.to_string() }
+        })?;
+        
+        let mut matches = Vec::new();
+        for field_match in &table_entry.r#match {
+            let field = table
+                .key_fields
+                .iter()
+                .find(|k| k.id == field_match.field_id)
+                .ok_or_else(|| {
+                    P4RuntimeError::InvalidTableEntry(format!(
+                        "Unknown match field id {} in table {}",
+                        field_match.field_id, table.name
+                    ))
+                })?;
+            
+            use p4v1::field_match::FieldMatchType;
+            let value = match &field_match.field_match_type {
+                Some(FieldMatchType::Exact(m)) => MatchValue::Exact(m.value.clone()),
+                Some(FieldMatchType::Lpm(m)) => MatchValue::Lpm {
+                    value: m.value.clone(),
+                    prefix_len: m.prefix_len.max(0) as u32,
+                },
+                Some(FieldMatchType::Ternary(m)) => MatchValue::Ternary {
+                    value: m.value.clone(),
+                    mask: m.mask.clone(),
+                },
+                Some(FieldMatchType::Range(m)) => MatchValue::Range {
+                    low: m.low.clone(),
+                    high: m.high.clone(),
+                },
+                Some(FieldMatchType::Optional(m)) => MatchValue::Optional(m.value.clone()),
+                other => {
+                    return Err(P4RuntimeError::InvalidTableEntry(format!("Unexpected match type: {:?}", other)).into());
+                }
+            };
+            if let Some(value) = codec::encode_match(&value, field.bitwidth)? {
+                matches.push(FieldMatch {
+                    field: field.name.clone(),
+                    value,
+                });
+            }
+        }
+        
+        use p4v1::table_action::Type as ActionType;
+        let priority = table_entry.priority.max(0) as u32;
+        let action = match table_entry.action.as_ref().and_then(|a| a.r#type.as_ref()) {
+            Some(ActionType::Action(action)) => action,
+            Some(ActionType::ActionProfileMemberId(member_id)) => {
+                return Ok(TableEntry::new(&table.name, matches, EntryAction::Member(*member_id), priority));
+            }
+            Some(ActionType::ActionProfileGroupId(group_id)) => {
+                return Ok(TableEntry::new(&table.name, matches, EntryAction::Group(*group_id), priority));
+            }
+            _ => return Err(P4RuntimeError::InvalidTableEntry("Missing action".to_string()).into()),
+        };
+        
+        Ok(TableEntry::new(&table.name, matches, parse_action(p4info, action)?, priority))
+    }
+    
+    /// P4RuntimeのActionProfileMemberをIDから名前に解決してActionProfileMemberに変換
+    fn parse_action_profile_member(&self, member: &p4v1::ActionProfileMember) -> Result<ActionProfileMember> {
+        let p4info = self.p4info()?;
+        let profile = p4info.find_action_profile_by_id(member.action_profile_id).ok_or_else(|| {
+            P4RuntimeError::InvalidTableEntry(format!("Unknown action profile id {}", member.action_profile_id))
+        })?;
+        let action = member
+            .action
+            .as_ref()
+            .ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing action".to_string()))?;
+        
+        Ok(ActionProfileMember {
+            profile: profile.name.clone(),
+            member_id: member.member_id,
+            action: parse_action(p4info, action)?,
+        })
+    }
+    
+    /// P4RuntimeのActionProfileGroupをIDから名前に解決してActionProfileGroupに変換
+    fn parse_action_profile_group(&self, group: &p4v1::ActionProfileGroup) -> Result<ActionProfileGroup> {
+        let p4info = self.p4info()?;
+        let profile = p4info.find_action_profile_by_id(group.action_profile_id).ok_or_else(|| {
+            P4RuntimeError::InvalidTableEntry(format!("Unknown action profile id {}", group.action_profile_id))
+        })?;
+        
+        Ok(ActionProfileGroup {
+            profile: profile.name.clone(),
+            group_id: group.group_id,
+            members: group
+                .members
+                .iter()
+                .map(|m| GroupMember {
+                    member_id: m.member_id,
+                    weight: m.weight.max(0) as u32,
+                })
+                .collect(),
+            max_size: group.max_size.max(0) as u32,
+        })
+    }
+}
+
+/// P4RuntimeのActionをIDから名前に解決してActionCallに変換
+fn parse_action(p4info: &P4Info, action: &p4v1::Action) -> Result<ActionCall> {
+    let info = p4info.find_action_by_id(action.action_id).ok_or_else(|| {
+        P4RuntimeError::ActionNotFound { action_name: action.action_id.to_string() }
+    })?;
+    
+    let mut action_call = ActionCall::new(&info.name);
+    for param in &action.params {
+        let spec = info.params.iter().find(|p| p.id == param.param_id).ok_or_else(|| {
+            P4RuntimeError::InvalidTableEntry(format!(
+                "Unknown parameter id {} for action {}",
+                param.param_id, info.name
+            ))
+        })?;
+        action_call = action_call.with_param(&spec.name, codec::decode(&param.value, spec.bitwidth)?);
+    }
+    Ok(action_call)
+}
+
+impl Drop for P4RuntimeClient {
+    fn drop(&mut self) {
+        // 受信タスクがストリームを持ち続けないように止める
+        if let Some(task) = self.stream_task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// u128をP4RuntimeのUint128に変換
+fn uint128(value: u128) -> p4v1::Uint128 {
+    p4v1::Uint128 {
+        high: (value >> 64) as u64,
+        low: value as u64,
+    }
+}
+
+/// アービトレーション応答のステータスからプライマリかどうかを判定
+fn mastership_from_update(update: &p4v1::MasterArbitrationUpdate) -> MastershipStatus {
+    // OK（code = 0）ならプライマリ、それ以外（ALREADY_EXISTS / NOT_FOUND）とステータスのない応答はバックアップ
+    match &update.status {
+        Some(status) if status.code == tonic::Code::Ok as i32 => MastershipStatus::Primary,
+        _ => MastershipStatus::Backup,
+    }
+}
+
+/// PacketInのメタデータをpacket_inヘッダーの定義で名前に解決
+fn decode_packet_in(
+    device_id: DeviceId,
+    header: Option<&ControllerPacketMetadataInfo>,
+    packet: p4v1::PacketIn,
+) -> PacketIn {
+    let metadata = packet
+        .metadata
+        .into_iter()
+        .filter_map(|m| {
+            let Some(info) = header.and_then(|h| h.metadata.iter().find(|i| i.id == m.metadata_id)) else {
+                tracing::warn!("Unknown packet_in metadata id {} from device {}", m.metadata_id, device_id);
+                return None;
+            };
+            match codec::decode(&m.value, info.bitwidth) {
+                Ok(value) => Some(PacketMetadata { name: info.name.clone(), value }),
+                Err(e) => {
+                    tracing::warn!("Invalid packet_in metadata {} from device {}: {}", info.name, device_id, e);
+                    None
+                }
+            }
+        })
+        .collect();
+    
+    PacketIn {
+        device_id,
+        payload: packet.payload,
+        metadata,
+    }
+}
+
+/// エンティティのUpdateを作成
+fn entity_update(update_type: UpdateType, entity: p4v1::entity::Entity) -> p4v1::Update {
+    let update_type = match update_type {
+        UpdateType::Insert => p4v1::update::Type::Insert,
+        UpdateType::Modify => p4v1::update::Type::Modify,
+        UpdateType::Delete => p4v1::update::Type::Delete,
+    };
+    
+    p4v1::Update {
+        r#type: update_type as i32,
+        entity: Some(p4v1::Entity {
+            entity: Some(entity),
+        }),
+    }
+}
+
+/// 更新の範囲をmax_batch_sizeごとのバッチに分ける（0なら1つずつ送る）
+fn write_batches(total: usize, max_batch_size: usize) -> impl Iterator<Item = Range<usize>> {
+    let batch_size = max_batch_size.max(1);
+    (0..total).step_by(batch_size).map(move |start| start..(start + batch_size).min(total))
+}
+
+/// 失敗したバッチの更新ごとの結果をWriteResultに記録し、残りのバッチを送るかを返す
+///
+/// `p4.v1.Error` の詳細はバッチ内の位置で対応する更新に割り当て、詳細がなければバッチ全体が失敗したものとする。
+/// CONTINUE_ON_ERROR以外では、成功した更新も取り消されたものとし、以降のバッチの更新も送らずに失敗とする。
+fn record_batch_failure(
+    result: &mut WriteResult,
+    updates: &[EntityUpdate],
+    batch: Range<usize>,
+    status: &tonic::Status,
+    atomicity: WriteAtomicity,
+) -> bool {
+    let errors = decode_write_errors(status);
+    let batch_len = batch.len();
+    for (i, update) in updates[batch.clone()].iter().enumerate() {
+        let (code, message) = match errors.get(i) {
+            // 詳細がない場合はバッチ全体が失敗したものとみなす
+            None if errors.len() != batch_len => (status.code(), status.message().to_string()),
+            Some(error) if error.canonical_code != tonic::Code::Ok as i32 => {
+                (tonic::Code::from(error.canonical_code), error.message.clone())
+            }
+            // ROLLBACK_ON_ERRORなどでは成功した更新も取り消される
+            _ if atomicity != WriteAtomicity::ContinueOnError => (tonic::Code::Aborted, "rolled back".to_string()),
+            _ => continue,
+        };
+        result.failures.push(UpdateFailure {
+            index: batch.start + i,
+            update: update.clone(),
+            code,
+            message,
+        });
+    }
+    
+    if atomicity == WriteAtomicity::ContinueOnError {
+        return true;
+    }
+    for (i, update) in updates.iter().enumerate().skip(batch.end) {
+        result.failures.push(UpdateFailure {
+            index: i,
+            update: update.clone(),
+            code: tonic::Code::Aborted,
+            message: "not sent: an earlier batch failed".to_string(),
+        });
+    }
+    false
+}
+
+/// Writeのエラーの詳細（google.rpc.Status）から更新ごとの `p4.v1.Error` を取り出す
+///
+/// 詳細は更新と同じ順序で並ぶ。詳細がなければ空を返す。
+fn decode_write_errors(status: &tonic::Status) -> Vec<p4v1::Error> {
+    if status.details().is_empty() {
+        return Vec::new();
+    }
+    
+    let rpc_status = match rpc::Status::decode(status.details()) {
+        Ok(rpc_status) => rpc_status,
+        Err(e) => {
+            tracing::warn!("Failed to decode write error details: {}", e);
+            return Vec::new();
+        }
+    };
+    
+    rpc_status
+        .details
+        .iter()
+        .filter(|any| any.type_url.ends_with("p4.v1.Error"))
+        .filter_map(|any| p4v1::Error::decode(any.value.as_slice()).ok())
+        .collect()
+}
+
+impl WriteAtomicity {
+    fn to_proto(self) -> p4v1::write_request::Atomicity {
+        match self {
+            WriteAtomicity::ContinueOnError => p4v1::write_request::Atomicity::ContinueOnError,
+            WriteAtomicity::RollbackOnError => p4v1::write_request::Atomicity::RollbackOnError,
+            WriteAtomicity::DataplaneAtomic => p4v1::write_request::Atomicity::DataplaneAtomic,
+        }
+    }
 }
 
 /// デバイスマネージャー
@@ -98,7 +979,34 @@ impl DeviceManager {
         
         // クライアントを作成
         let mut client = P4RuntimeClient::new(device_id, &endpoint).await?;
-        client.connect().await?;
+        client.set_election_id(device_info.election_id);
+        client.set_role(device_info.role.clone());
+        client.set_write_options(device_info.write_options);
+        let status = client.connect().await?;
+        if status == MastershipStatus::Backup {
+            tracing::warn!("Device {} connected as backup; writes will be rejected", device_id);
+        }
+        
+        let mut device_info = device_info;
+        if let Some(spec) = &device_info.pipeline {
+            let config = PipelineConfig::load(&spec.bmv2_json, &spec.p4info)?;
+            if status == MastershipStatus::Primary {
+                install_pipeline(&mut client, &config, spec.action).await?;
+            } else {
+                tracing::warn!("Not pushing pipeline to device {}: not primary", device_id);
+            }
+            device_info.p4info = Some(P4Info::from(&config.p4info));
+        } else if device_info.p4info.is_none() {
+            // パイプラインを指定しない場合は、デバイスで動作中のP4Infoを使う
+            let config = client
+                .get_forwarding_pipeline_config(p4v1::get_forwarding_pipeline_config_request::ResponseType::P4infoAndCookie)
+                .await?;
+            device_info.p4info = config.and_then(|c| c.p4info).map(|p4info| P4Info::from(&p4info));
+        }
+        
+        if let Some(p4info) = &device_info.p4info {
+            client.set_p4info(p4info.clone()).await;
+        }
         
         // クライアントとデバイス情報を保存
         {
@@ -131,39 +1039,149 @@ impl DeviceManager {
         Ok(())
     }
     
+    /// デバイスで動作中のパイプラインのクッキーを取得
+    pub async fn get_pipeline_cookie(&self, device_id: DeviceId) -> Result<Option<u64>> {
+        let mut clients = self.clients.write().await;
+        if let Some(client) = clients.get_mut(&device_id) {
+            client.get_pipeline_cookie().await
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
+    /// デバイスのアービトレーション結果を取得
+    pub async fn get_mastership(&self, device_id: DeviceId) -> Result<Option<MastershipStatus>> {
+        let clients = self.clients.read().await;
+        if let Some(client) = clients.get(&device_id) {
+            Ok(client.mastership().await)
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
+    /// デバイス情報を取得
+    pub async fn get_device(&self, device_id: DeviceId) -> Option<DeviceInfo> {
+        let devices = self.devices.read().await;
+        devices.get(&device_id).cloned()
+    }
+    
     /// デバイス一覧を取得
     pub async fn list_devices(&self) -> Vec<DeviceInfo> {
         let devices = self.devices.read().await;
         devices.values().cloned().collect()
     }
     
+    /// プライマリとして接続しているデバイスの一覧を取得（バックアップへの書き込みはすべて拒否される）
+    pub async fn list_primary_devices(&self) -> Vec<DeviceInfo> {
+        let clients = self.clients.read().await;
+        let devices = self.devices.read().await;
+        let mut primary = Vec::new();
+        for (device_id, device) in devices.iter() {
+            let Some(client) = clients.get(device_id) else { continue };
+            if client.mastership().await == Some(MastershipStatus::Primary) {
+                primary.push(device.clone());
+            }
+        }
+        primary
+    }
+    
+    /// 特定のデバイスに更新をまとめて送信
+    pub async fn write_to_device(&self, device_id: DeviceId, updates: &[TableUpdate]) -> Result<WriteResult> {
+        let mut clients = self.clients.write().await;
+        if let Some(client) = clients.get_mut(&device_id) {
+            client.write(updates).await
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
+    /// 特定のデバイスにメンバー・グループを含む更新をまとめて送信
+    pub async fn write_entities_to_device(&self, device_id: DeviceId, updates: &[EntityUpdate]) -> Result<WriteResult> {
+        let mut clients = self.clients.write().await;
+        if let Some(client) = clients.get_mut(&device_id) {
+            client.write_entities(updates).await
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
     /// 特定のデバイスにテーブルエントリを書き込み
     pub async fn write_table_entries_to_device(
         &self,
         device_id: DeviceId,
         entries: &[TableEntry],
-    ) -> Result<()> {
+    ) -> Result<WriteResult> {
         let mut clients = self.clients.write().await;
         if let Some(client) = clients.get_mut(&device_id) {
-            client.write_table_entries(entries).await?;
+            client.write_table_entries(entries).await
         } else {
-            return Err(P4RuntimeError::DeviceNotFound { device_id }.into());
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
         }
-        Ok(())
     }
     
-    /// 全デバイスにテーブルエントリを書き込み
+    /// プライマリの全デバイスにテーブルエントリを書き込み（デバイスごとの結果を返す）
     pub async fn write_table_entries_to_all_devices(
         &self,
         entries: &[TableEntry],
-    ) -> Result<()> {
+    ) -> HashMap<DeviceId, Result<WriteResult>> {
         let mut clients = self.clients.write().await;
+        let mut results = HashMap::new();
+        
         for (device_id, client) in clients.iter_mut() {
-            if let Err(e) = client.write_table_entries(entries).await {
+            if client.mastership().await != Some(MastershipStatus::Primary) {
+                continue;
+            }
+            let result = client.write_table_entries(entries).await;
+            if let Err(e) = &result {
                 tracing::error!("Failed to write entries to device {}: {}", device_id, e);
             }
+            results.insert(*device_id, result);
+        }
+        
+        results
+    }
+    
+    /// デバイスからのPacketInを購読
+    pub async fn subscribe_packet_in(&self, device_id: DeviceId) -> Result<broadcast::Receiver<PacketIn>> {
+        let clients = self.clients.read().await;
+        if let Some(client) = clients.get(&device_id) {
+            Ok(client.subscribe_packet_in())
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
+    /// デバイスにPacketOutを送信
+    pub async fn send_packet_out(&self, device_id: DeviceId, packet: &PacketOut) -> Result<()> {
+        let clients = self.clients.read().await;
+        if let Some(client) = clients.get(&device_id) {
+            client.send_packet_out(packet).await
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
+    /// デバイスの全テーブルのエントリを読み取り
+    pub async fn read_table_entries_from_device(&self, device_id: DeviceId) -> Result<Vec<TableEntry>> {
+        let mut clients = self.clients.write().await;
+        if let Some(client) = clients.get_mut(&device_id) {
+            client.read_table_entries().await
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
+    /// デバイスの全アクションプロファイルのメンバーとグループを読み取り
+    pub async fn read_action_profiles_from_device(
+        &self,
+        device_id: DeviceId,
+    ) -> Result<(Vec<ActionProfileMember>, Vec<ActionProfileGroup>)> {
+        let mut clients = self.clients.write().await;
+        if let Some(client) = clients.get_mut(&device_id) {
+            client.read_action_profiles().await
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
         }
-        Ok(())
     }
     
     /// デバイスから統計情報を取得
@@ -196,8 +1214,394 @@ impl DeviceManager {
     }
 }
 
+#[cfg(test)]
+impl P4RuntimeClient {
+    /// サーバーに接続しないクライアント（アービトレーション結果を指定する）
+    pub(crate) fn offline(device_id: DeviceId, mastership: Option<MastershipStatus>) -> Self {
+        let mut client = Self::with_channel(device_id, Endpoint::from_static("http://127.0.0.1:9").connect_lazy());
+        client.mastership = Arc::new(RwLock::new(mastership));
+        client
+    }
+}
+
+#[cfg(test)]
+impl DeviceManager {
+    /// 接続せずに、指定したアービトレーション結果のデバイスとして追加
+    pub(crate) async fn add_offline_device(&self, device_info: DeviceInfo, mastership: MastershipStatus) {
+        let client = P4RuntimeClient::offline(device_info.device_id, Some(mastership));
+        self.clients.write().await.insert(device_info.device_id, client);
+        self.devices.write().await.insert(device_info.device_id, device_info);
+    }
+}
+
+/// デバイスで動作中のパイプラインと異なればプッシュ
+async fn install_pipeline(
+    client: &mut P4RuntimeClient,
+    config: &PipelineConfig,
+    action: PipelineAction,
+) -> Result<()> {
+    if client.get_pipeline_cookie().await? == Some(config.cookie) {
+        tracing::info!(
+            "Device {} is already running pipeline {:#x}",
+            client.device_id,
+            config.cookie
+        );
+        return Ok(());
+    }
+    
+    client.set_forwarding_pipeline_config(config, action).await
+}
+
 impl Default for DeviceManager {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p4info::parse_p4info_text;
+    
+    const P4INFO_TEXT: &str = r#"
+tables {
+  preamble { id: 37375156 name: "MyIngress.ipv4_lpm" alias: "ipv4_lpm" }
+  match_fields { id: 1 name: "hdr.ipv4.dstAddr" bitwidth: 32 match_type: LPM }
+  action_refs { id: 28792405 }
+  action_refs { id: 25652968 }
+  size: 1024
+}
+tables {
+  preamble { id: 41000001 name: "MyIngress.ecmp_group" alias: "ecmp_group" }
+  match_fields { id: 1 name: "meta.ecmp_group_id" bitwidth: 16 match_type: EXACT }
+  action_refs { id: 28792405 }
+  implementation_id: 291000001
+  size: 64
+}
+tables {
+  preamble { id: 42000001 name: "MyIngress.abac_policy" alias: "abac_policy" }
+  match_fields { id: 1 name: "meta.abac_subject" bitwidth: 32 match_type: TERNARY }
+  match_fields { id: 2 name: "meta.abac_object" bitwidth: 32 match_type: TERNARY }
+  action_refs { id: 23000001 }
+  action_refs { id: 23000002 }
+  size: 256
+}
+actions {
+  preamble { id: 28792405 name: "MyIngress.ipv4_forward" alias: "ipv4_forward" }
+  params { id: 1 name: "dstAddr" bitwidth: 48 }
+  params { id: 2 name: "port" bitwidth: 9 }
+}
+actions {
+  preamble { id: 25652968 name: "MyIngress.drop" alias: "drop" }
+}
+actions {
+  preamble { id: 23000001 name: "MyIngress.abac_allow" alias: "abac_allow" }
+}
+actions {
+  preamble { id: 23000002 name: "MyIngress.abac_drop" alias: "abac_drop" }
+}
+action_profiles {
+  preamble { id: 291000001 name: "MyIngress.ecmp_selector" alias: "ecmp_selector" }
+  table_ids: 41000001
+  with_selector: true
+  size: 128
+}
+controller_packet_metadata {
+  preamble { id: 67146229 name: "packet_in" alias: "packet_in" }
+  metadata { id: 1 name: "ingress_port" bitwidth: 9 }
+  metadata { id: 2 name: "_pad" bitwidth: 7 }
+}
+controller_packet_metadata {
+  preamble { id: 67121543 name: "packet_out" alias: "packet_out" }
+  metadata { id: 1 name: "egress_port" bitwidth: 9 }
+  metadata { id: 2 name: "_pad" bitwidth: 7 }
+}
+"#;
+    
+    /// P4INFO_TEXTを読み込んだプライマリのクライアント（接続はしない）
+    async fn client() -> P4RuntimeClient {
+        let mut client = P4RuntimeClient::offline(1, Some(MastershipStatus::Primary));
+        client.set_p4info(P4Info::from(&parse_p4info_text(P4INFO_TEXT).unwrap())).await;
+        client
+    }
+    
+    /// 変換した結果を読み戻すと正規形の同じキー・同じアクションになり、もう一度変換しても同じになることを確かめる
+    fn assert_round_trip(client: &P4RuntimeClient, entry: &TableEntry) -> p4v1::TableEntry {
+        let proto = client.build_table_entry(entry, true).unwrap();
+        let parsed = client.parse_table_entry(&proto).unwrap();
+        let mut entry = entry.clone();
+        crate::table_manager::canonicalize_entry(client.p4info().unwrap(), &mut entry).unwrap();
+        assert!(parsed.has_same_key(&entry), "{:?} != {:?}", parsed, entry);
+        assert!(parsed.action.is_equivalent(&entry.action), "{:?} != {:?}", parsed.action, entry.action);
+        assert_eq!(client.build_table_entry(&parsed, true).unwrap(), proto);
+        proto
+    }
+    
+    #[tokio::test]
+    async fn lpm_entry_round_trips_with_full_names() {
+        let client = client().await;
+        let key = TableKey {
+            ipv4_dst: Ipv4Address::new(std::net::Ipv4Addr::new(10, 1, 0, 0)),
+            prefix_len: 16,
+        };
+        let action = TableAction::Ipv4Forward {
+            dst_mac: MacAddress::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            port: 3,
+        };
+        let entry = TableEntry::ipv4_lpm(key.clone(), action.clone(), 0);
+        
+        let proto = assert_round_trip(&client, &entry);
+        assert_eq!(proto.table_id, 37375156);
+        let Some(p4v1::field_match::FieldMatchType::Lpm(lpm)) = &proto.r#match[0].field_match_type else {
+            panic!("expected an LPM match: {:?}", proto.r#match);
+        };
+        assert_eq!((lpm.value.as_slice(), lpm.prefix_len), (&[10, 1, 0, 0][..], 16));
+        
+        let parsed = client.parse_table_entry(&proto).unwrap();
+        assert_eq!(parsed.table, "MyIngress.ipv4_lpm");
+        assert_eq!(parsed.forwarding_action(), Some(action.clone()));
+        
+        // LPMだけのテーブルでは優先度を送らない
+        let proto = client.build_table_entry(&TableEntry::ipv4_lpm(key, action, 7), true).unwrap();
+        assert_eq!(proto.priority, 0);
+    }
+    
+    #[tokio::test]
+    async fn ternary_entry_keeps_its_priority_and_omits_wildcards() {
+        let client = client().await;
+        let subject = AttributeMatch { value: 0x1, mask: 0x3 };
+        let entry = TableEntry::abac_policy(subject, AttributeMatch::ANY, AbacAction::Allow, 40);
+        
+        let proto = assert_round_trip(&client, &entry);
+        assert_eq!(proto.priority, 40);
+        assert_eq!(proto.r#match.len(), 1);
+        assert_eq!(client.parse_table_entry(&proto).unwrap().abac_action(), Some(AbacAction::Allow));
+    }
+    
+    #[tokio::test]
+    async fn selector_group_entry_round_trips() {
+        let client = client().await;
+        let entry = TableEntry::new(
+            ECMP_GROUP_TABLE,
+            vec![FieldMatch::exact(ECMP_GROUP_ID_FIELD, vec![0x00, 0x05])],
+            EntryAction::Group(5),
+            0,
+        );
+        
+        let proto = assert_round_trip(&client, &entry);
+        let action = proto.action.as_ref().and_then(|action| action.r#type.as_ref());
+        assert_eq!(action, Some(&p4v1::table_action::Type::ActionProfileGroupId(5)));
+    }
+    
+    #[tokio::test]
+    async fn delete_sends_only_the_key_and_unknown_names_are_rejected() {
+        let client = client().await;
+        let key = TableKey {
+            ipv4_dst: Ipv4Address::new(std::net::Ipv4Addr::new(10, 1, 0, 0)),
+            prefix_len: 16,
+        };
+        let delete = TableEntry::ipv4_lpm(key, TableAction::Drop, 0);
+        let proto = client.build_table_entry(&delete, false).unwrap();
+        assert!(proto.action.is_none());
+        assert!(client.parse_table_entry(&proto).unwrap_err().to_string().contains("Missing action"));
+        
+        let unknown = TableEntry::new("MyIngress.acl", Vec::new(), ActionCall::new(DROP_ACTION), 0);
+        let error = client.build_table_entry(&unknown, true).unwrap_err();
+        assert!(matches!(error.downcast_ref::<P4RuntimeError>(), Some(P4RuntimeError::TableNotFound { .. })));
+        
+        let missing_param = TableEntry { action: ActionCall::new(IPV4_FORWARD_ACTION).into(), ..delete };
+        let error = client.build_table_entry(&missing_param, true).unwrap_err();
+        assert!(error.to_string().contains("Missing parameter dstAddr"), "{}", error);
+    }
+    
+    /// 宛先プレフィックスだけが異なるipv4_lpmのINSERT
+    fn updates(count: u8) -> Vec<EntityUpdate> {
+        (0..count)
+            .map(|i| {
+                let key = TableKey {
+                    ipv4_dst: Ipv4Address::new(std::net::Ipv4Addr::new(10, i, 0, 0)),
+                    prefix_len: 16,
+                };
+                EntityUpdate::from(TableUpdate::insert(TableEntry::ipv4_lpm(key, TableAction::Drop, 0)))
+            })
+            .collect()
+    }
+    
+    /// 更新ごとの `p4.v1.Error` を詳細に持つWriteのエラー
+    fn write_error(codes: &[tonic::Code]) -> tonic::Status {
+        let details = codes
+            .iter()
+            .map(|code| prost_types::Any {
+                type_url: "type.googleapis.com/p4.v1.Error".to_string(),
+                value: p4v1::Error {
+                    canonical_code: *code as i32,
+                    message: format!("{:?}", code),
+                    ..Default::default()
+                }
+                .encode_to_vec(),
+            })
+            .collect();
+        let status = rpc::Status {
+            code: tonic::Code::Unknown as i32,
+            message: "write failed".to_string(),
+            details,
+        };
+        tonic::Status::with_details(tonic::Code::Unknown, "write failed", status.encode_to_vec().into())
+    }
+    
+    fn failures(result: &WriteResult) -> Vec<(usize, tonic::Code)> {
+        result.failures.iter().map(|failure| (failure.index, failure.code)).collect()
+    }
+    
+    fn packet_metadata(metadata_id: u32, value: &[u8]) -> p4v1::PacketMetadata {
+        p4v1::PacketMetadata { metadata_id, value: value.to_vec() }
+    }
+    
+    #[tokio::test]
+    async fn packet_in_metadata_is_resolved_by_name() {
+        let client = client().await;
+        let header = client.p4info().unwrap().find_controller_packet_metadata(PACKET_IN_HEADER).cloned();
+        let packet = p4v1::PacketIn {
+            payload: vec![0xde, 0xad],
+            // 値は正規形（先頭の0を除く）にし、定義にないIDとビット幅を超える値は捨てる
+            metadata: vec![
+                packet_metadata(1, &[0x00, 0x01, 0x02]),
+                packet_metadata(2, &[0x00]),
+                packet_metadata(9, &[0x01]),
+            ],
+        };
+        
+        let packet_in = decode_packet_in(1, header.as_ref(), packet.clone());
+        assert_eq!(packet_in.device_id, 1);
+        assert_eq!(packet_in.payload, [0xde, 0xad]);
+        assert_eq!(packet_in.metadata(INGRESS_PORT_METADATA), Some(&[0x01, 0x02][..]));
+        assert_eq!(packet_in.ingress_port(), Some(258));
+        assert_eq!(packet_in.metadata.len(), 2);
+        
+        let too_wide = p4v1::PacketIn { metadata: vec![packet_metadata(1, &[0x02, 0x00])], ..packet.clone() };
+        assert_eq!(decode_packet_in(1, header.as_ref(), too_wide).ingress_port(), None);
+        
+        // packet_inヘッダーの定義がなければメタデータは解決できない
+        let packet_in = decode_packet_in(1, None, packet);
+        assert!(packet_in.metadata.is_empty());
+        assert_eq!(packet_in.payload, [0xde, 0xad]);
+    }
+    
+    #[tokio::test]
+    async fn packet_out_is_checked_against_the_packet_out_header() {
+        let client = client().await;
+        
+        let unknown = PacketOut::to_port(vec![0xff], 1).with_metadata("queue", vec![1]);
+        let error = client.send_packet_out(&unknown).await.unwrap_err();
+        assert!(error.to_string().contains("Unknown packet_out metadata queue"), "{}", error);
+        
+        let too_wide = PacketOut::new(vec![0xff]).with_metadata(EGRESS_PORT_METADATA, vec![0x02, 0x00]);
+        assert!(client.send_packet_out(&too_wide).await.is_err());
+        
+        // 正しいパケットでもストリームを開いていなければ送れない
+        let error = client.send_packet_out(&PacketOut::to_port(vec![0xff], 1)).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<P4RuntimeError>(), Some(P4RuntimeError::StreamClosed { device_id: 1 })));
+    }
+    
+    fn arbitration_reply(code: Option<tonic::Code>) -> p4v1::MasterArbitrationUpdate {
+        p4v1::MasterArbitrationUpdate {
+            device_id: 1,
+            election_id: Some(uint128(1)),
+            status: code.map(|code| rpc::Status { code: code as i32, ..Default::default() }),
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn only_an_ok_arbitration_reply_makes_the_controller_primary() {
+        assert_eq!(mastership_from_update(&arbitration_reply(Some(tonic::Code::Ok))), MastershipStatus::Primary);
+        assert_eq!(mastership_from_update(&arbitration_reply(Some(tonic::Code::AlreadyExists))), MastershipStatus::Backup);
+        assert_eq!(mastership_from_update(&arbitration_reply(Some(tonic::Code::NotFound))), MastershipStatus::Backup);
+        assert_eq!(mastership_from_update(&arbitration_reply(None)), MastershipStatus::Backup);
+    }
+    
+    #[tokio::test]
+    async fn writes_are_rejected_unless_primary() {
+        for mastership in [Some(MastershipStatus::Backup), None] {
+            let mut client = P4RuntimeClient::offline(1, mastership);
+            let error = client.write_entities(&updates(1)).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<P4RuntimeError>(),
+                Some(P4RuntimeError::NotPrimary { device_id: 1, election_id: 1 })
+            ));
+        }
+        
+        // プライマリなら書き込みに進む（P4Infoがないのでエントリの変換で失敗する）
+        let mut client = P4RuntimeClient::offline(1, Some(MastershipStatus::Primary));
+        let error = client.write_entities(&updates(1)).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<P4RuntimeError>(), Some(P4RuntimeError::P4InfoNotLoaded { .. })));
+    }
+    
+    #[test]
+    fn updates_are_split_into_batches_of_max_batch_size() {
+        let batches = |total, max_batch_size| {
+            write_batches(total, max_batch_size).map(|batch| (batch.start, batch.end)).collect::<Vec<_>>()
+        };
+        assert_eq!(batches(5, 2), [(0, 2), (2, 4), (4, 5)]);
+        assert_eq!(batches(4, 4), [(0, 4)]);
+        assert_eq!(batches(2, 0), [(0, 1), (1, 2)]);
+        assert!(batches(0, 8).is_empty());
+    }
+    
+    #[test]
+    fn write_error_details_are_decoded_in_update_order() {
+        let errors = decode_write_errors(&write_error(&[tonic::Code::Ok, tonic::Code::AlreadyExists]));
+        let codes: Vec<i32> = errors.iter().map(|error| error.canonical_code).collect();
+        assert_eq!(codes, [tonic::Code::Ok as i32, tonic::Code::AlreadyExists as i32]);
+        assert_eq!(errors[1].message, "AlreadyExists");
+        
+        assert!(decode_write_errors(&tonic::Status::unavailable("down")).is_empty());
+        let garbage = tonic::Status::with_details(tonic::Code::Unknown, "bad", vec![0xff, 0xff].into());
+        assert!(decode_write_errors(&garbage).is_empty());
+    }
+    
+    #[test]
+    fn failure_in_a_later_batch_maps_to_the_original_update() {
+        let updates = updates(5);
+        let mut result = WriteResult::new(1, updates.len());
+        let status = write_error(&[tonic::Code::Ok, tonic::Code::NotFound]);
+        
+        assert!(record_batch_failure(&mut result, &updates, 2..4, &status, WriteAtomicity::ContinueOnError));
+        assert_eq!(failures(&result), [(3, tonic::Code::NotFound)]);
+        assert_eq!(result.failures[0].update, updates[3]);
+        assert_eq!(result.failures[0].message, "NotFound");
+    }
+    
+    #[test]
+    fn rollback_marks_the_rest_of_the_batch_and_later_updates_aborted() {
+        let updates = updates(5);
+        let mut result = WriteResult::new(1, updates.len());
+        let status = write_error(&[tonic::Code::InvalidArgument, tonic::Code::Ok]);
+        
+        assert!(!record_batch_failure(&mut result, &updates, 0..2, &status, WriteAtomicity::RollbackOnError));
+        assert_eq!(
+            failures(&result),
+            [
+                (0, tonic::Code::InvalidArgument),
+                (1, tonic::Code::Aborted),
+                (2, tonic::Code::Aborted),
+                (3, tonic::Code::Aborted),
+                (4, tonic::Code::Aborted),
+            ]
+        );
+        assert_eq!(result.failures[1].message, "rolled back");
+        assert_eq!(result.failures[2].message, "not sent: an earlier batch failed");
+    }
+    
+    #[test]
+    fn error_without_details_fails_the_whole_batch() {
+        let updates = updates(3);
+        let mut result = WriteResult::new(1, updates.len());
+        let status = tonic::Status::unavailable("connection reset");
+        
+        assert!(record_batch_failure(&mut result, &updates, 1..3, &status, WriteAtomicity::ContinueOnError));
+        assert_eq!(failures(&result), [(1, tonic::Code::Unavailable), (2, tonic::Code::Unavailable)]);
+        assert_eq!(result.failures[0].message, "connection reset");
+        assert_eq!(result.succeeded(), 1);
+    }
+}
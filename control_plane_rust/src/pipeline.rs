@@ -0,0 +1,112 @@
+use crate::p4info::load_p4info_proto;
+use crate::proto::p4::config::v1 as p4config;
+use crate::proto::p4::v1 as p4v1;
+use crate::types::*;
+use anyhow::{Context, Result};
+use prost::Message;
+use std::path::Path;
+
+/// フォワーディングパイプライン設定（BMv2 JSON + P4Info）
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    /// p4cが出力したP4Info
+    pub p4info: p4config::P4Info,
+    /// ターゲット固有の設定（BMv2ではJSONの中身）
+    pub device_config: Vec<u8>,
+    /// 設定を識別するクッキー（P4InfoとJSONから計算）
+    pub cookie: u64,
+}
+
+impl PipelineConfig {
+    /// BMv2 JSONとP4Info（テキスト形式またはバイナリ形式）をディスクから読み込む
+    pub fn load(bmv2_json: &Path, p4info: &Path) -> Result<Self> {
+        let device_config = std::fs::read(bmv2_json)
+            .with_context(|| format!("Failed to read BMv2 JSON {}", bmv2_json.display()))?;
+        let p4info = load_p4info_proto(p4info)?;
+        
+        Ok(Self::new(p4info, device_config))
+    }
+    
+    /// P4InfoとBMv2 JSONから設定を作成
+    pub fn new(p4info: p4config::P4Info, device_config: Vec<u8>) -> Self {
+        let cookie = pipeline_cookie(&p4info.encode_to_vec(), &device_config);
+        Self {
+            p4info,
+            device_config,
+            cookie,
+        }
+    }
+    
+    /// P4RuntimeのForwardingPipelineConfigに変換
+    pub fn to_proto(&self) -> p4v1::ForwardingPipelineConfig {
+        p4v1::ForwardingPipelineConfig {
+            p4info: Some(self.p4info.clone()),
+            p4_device_config: self.device_config.clone(),
+            cookie: Some(p4v1::forwarding_pipeline_config::Cookie { cookie: self.cookie }),
+        }
+    }
+}
+
+impl PipelineAction {
+    /// SetForwardingPipelineConfigRequestのアクションに変換
+    pub fn to_proto(self) -> p4v1::set_forwarding_pipeline_config_request::Action {
+        use p4v1::set_forwarding_pipeline_config_request::Action;
+        match self {
+            PipelineAction::VerifyAndCommit => Action::VerifyAndCommit,
+            PipelineAction::ReconcileAndCommit => Action::ReconcileAndCommit,
+        }
+    }
+}
+
+/// 設定内容から決まるクッキーを計算（FNV-1a、コントローラー再起動後も同じ値になる）
+fn pipeline_cookie(p4info: &[u8], device_config: &[u8]) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    
+    p4info
+        .iter()
+        .chain(device_config)
+        .fold(FNV_OFFSET, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p4info::parse_p4info_text;
+    
+    fn p4info() -> p4config::P4Info {
+        parse_p4info_text(r#"tables { preamble { id: 37375156 name: "MyIngress.ipv4_lpm" } size: 1024 }"#).unwrap()
+    }
+    
+    #[test]
+    fn proto_carries_p4info_device_config_and_cookie() {
+        let config = PipelineConfig::new(p4info(), br#"{"program": "ip_forwarding.p4"}"#.to_vec());
+        let proto = config.to_proto();
+        
+        assert_eq!(proto.p4info.as_ref(), Some(&config.p4info));
+        assert_eq!(proto.p4_device_config, config.device_config);
+        assert_eq!(proto.cookie.map(|cookie| cookie.cookie), Some(config.cookie));
+    }
+    
+    #[test]
+    fn cookie_depends_only_on_the_configuration() {
+        let json = br#"{"program": "ip_forwarding.p4"}"#.to_vec();
+        let config = PipelineConfig::new(p4info(), json.clone());
+        
+        // 再起動後も同じ値になるよう、内容だけから決まる（FNV-1aの既知の値とも一致する）
+        assert_eq!(PipelineConfig::new(p4info(), json.clone()).cookie, config.cookie);
+        assert_eq!(pipeline_cookie(b"", b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(pipeline_cookie(b"a", b""), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(pipeline_cookie(b"fo", b"o"), pipeline_cookie(b"foo", b""));
+        
+        assert_ne!(PipelineConfig::new(p4info(), b"{}".to_vec()).cookie, config.cookie);
+        assert_ne!(PipelineConfig::new(p4config::P4Info::default(), json).cookie, config.cookie);
+    }
+    
+    #[test]
+    fn pipeline_action_maps_to_the_request_action() {
+        use p4v1::set_forwarding_pipeline_config_request::Action;
+        assert_eq!(PipelineAction::VerifyAndCommit.to_proto(), Action::VerifyAndCommit);
+        assert_eq!(PipelineAction::ReconcileAndCommit.to_proto(), Action::ReconcileAndCommit);
+    }
+}
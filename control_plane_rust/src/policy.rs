@@ -0,0 +1,631 @@
+use crate::attribute_encoding::AttributeLayout;
+use crate::types::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/// 主体と対象の版が食い違うパケット（ポリシーの入れ替え中）を破棄するエントリの優先度
+const VERSION_MISMATCH_PRIORITY: u32 = 1;
+/// どのルールにも一致しないパケットに使うエントリの優先度
+const DEFAULT_ENTRY_PRIORITY: u32 = 2;
+
+/// 宛先のプロトコルとポートから決める対象の属性（ホストやプレフィックスの属性としてはエンコードしない）
+pub const SERVICE_ATTRIBUTE: &str = "service";
+
+/// 属性の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeCategory {
+    /// 通信の主体（送信元のホスト）
+    Subject,
+    /// 通信の対象（宛先のホストとサービス）
+    Object,
+    /// 環境（コントローラーが評価し、データプレーンには書き込まない）
+    Environment,
+}
+
+/// すべての属性の種類
+pub const CATEGORIES: [AttributeCategory; 3] = [
+    AttributeCategory::Subject,
+    AttributeCategory::Object,
+    AttributeCategory::Environment,
+];
+
+impl std::fmt::Display for AttributeCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AttributeCategory::Subject => "subject",
+            AttributeCategory::Object => "object",
+            AttributeCategory::Environment => "environment",
+        };
+        f.pad(name)
+    }
+}
+
+/// 属性名 -> 値（条件では許す値のいずれか、スキーマでは取りうる値）
+pub type AttributeValues = BTreeMap<String, Vec<String>>;
+
+/// 環境属性の現在の値
+pub type Environment = BTreeMap<String, String>;
+
+/// ポリシーで使う属性と、その取りうる値
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributeSchema {
+    #[serde(default)]
+    pub subject: AttributeValues,
+    #[serde(default)]
+    pub object: AttributeValues,
+    #[serde(default)]
+    pub environment: AttributeValues,
+}
+
+impl AttributeSchema {
+    /// 種類ごとの属性
+    pub fn attributes(&self, category: AttributeCategory) -> &AttributeValues {
+        match category {
+            AttributeCategory::Subject => &self.subject,
+            AttributeCategory::Object => &self.object,
+            AttributeCategory::Environment => &self.environment,
+        }
+    }
+    
+    /// 属性の取りうる値（未定義の属性ならNone）
+    pub fn values(&self, category: AttributeCategory, attribute: &str) -> Option<&[String]> {
+        self.attributes(category).get(attribute).map(|values| values.as_slice())
+    }
+}
+
+/// ルールの効果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Permit,
+    Deny,
+    /// 転送せずにコントローラーに送って判断する
+    Punt,
+}
+
+impl Effect {
+    /// ABACポリシーテーブルのアクションに変換
+    pub fn to_abac_action(self) -> AbacAction {
+        match self {
+            Effect::Permit => AbacAction::Allow,
+            Effect::Deny => AbacAction::Drop,
+            Effect::Punt => AbacAction::Punt,
+        }
+    }
+}
+
+impl std::fmt::Display for Effect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Effect::Permit => "permit",
+            Effect::Deny => "deny",
+            Effect::Punt => "punt",
+        })
+    }
+}
+
+fn default_effect() -> Effect {
+    Effect::Deny
+}
+
+/// サービスのL4プロトコル
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceProtocol {
+    Tcp,
+    Udp,
+}
+
+impl ServiceProtocol {
+    /// IPv4ヘッダーのプロトコル番号
+    pub fn number(self) -> u8 {
+        match self {
+            ServiceProtocol::Tcp => 6,
+            ServiceProtocol::Udp => 17,
+        }
+    }
+}
+
+/// サービスの値に対応するプロトコルと宛先ポートの範囲
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServicePorts {
+    /// 省略すればTCPでもUDPでもよい
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<ServiceProtocol>,
+    /// 宛先ポートの範囲（両端を含む）
+    pub ports: (u16, u16),
+}
+
+/// 許可・拒否のルール
+///
+/// 種類ごとの条件は「属性 -> 許す値のいずれか」で、すべての属性の条件を満たすときに一致する。
+/// 条件のない属性はどの値でもよい。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyRule {
+    /// ルールID（ポリシー内で一意）
+    pub id: String,
+    /// 優先度（大きいほど優先、同じ優先度ならdenyを優先）
+    pub priority: u32,
+    pub effect: Effect,
+    #[serde(default)]
+    pub subject: AttributeValues,
+    #[serde(default)]
+    pub object: AttributeValues,
+    #[serde(default)]
+    pub environment: AttributeValues,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl PolicyRule {
+    /// 種類ごとの条件
+    pub fn conditions(&self, category: AttributeCategory) -> &AttributeValues {
+        match category {
+            AttributeCategory::Subject => &self.subject,
+            AttributeCategory::Object => &self.object,
+            AttributeCategory::Environment => &self.environment,
+        }
+    }
+    
+    /// 環境属性の条件を満たすか（条件の属性が環境に設定されていなければ満たさない）
+    pub fn is_active(&self, environment: &Environment) -> bool {
+        self.environment
+            .iter()
+            .all(|(attribute, values)| environment.get(attribute).is_some_and(|value| values.contains(value)))
+    }
+}
+
+/// ABACポリシー
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Policy {
+    pub name: String,
+    #[serde(default)]
+    pub schema: AttributeSchema,
+    /// どのルールにも一致しないときの効果
+    #[serde(rename = "default", default = "default_effect")]
+    pub default_effect: Effect,
+    #[serde(default)]
+    pub rules: Vec<PolicyRule>,
+    /// 対象の `service` 属性の値 -> その値にするプロトコルと宛先ポートの範囲
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub services: BTreeMap<String, Vec<ServicePorts>>,
+}
+
+impl Policy {
+    /// JSON形式のポリシーをパース
+    pub fn from_json(text: &str) -> Result<Self> {
+        Ok(serde_json::from_str(text).map_err(P4RuntimeError::from)?)
+    }
+    
+    /// IDでルールを検索
+    pub fn rule(&self, id: &str) -> Option<&PolicyRule> {
+        self.rules.iter().find(|rule| rule.id == id)
+    }
+    
+    /// ルールIDの重複と、スキーマにない属性・値を参照する条件・サービスを検証
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg: String| -> anyhow::Error { P4RuntimeError::InvalidPolicy(msg).into() };
+        
+        for category in CATEGORIES {
+            for (attribute, values) in self.schema.attributes(category) {
+                let mut seen = HashSet::new();
+                if let Some(duplicate) = values.iter().find(|value| !seen.insert(*value)) {
+                    return Err(invalid(format!("Duplicate value {} for {} attribute {}", duplicate, category, attribute)));
+                }
+            }
+        }
+        
+        let mut ids = HashSet::new();
+        for rule in &self.rules {
+            if rule.id.is_empty() {
+                return Err(invalid("Rule without an id".to_string()));
+            }
+            if !ids.insert(rule.id.as_str()) {
+                return Err(invalid(format!("Duplicate rule id {}", rule.id)));
+            }
+            
+            for category in CATEGORIES {
+                for (attribute, values) in rule.conditions(category) {
+                    let known = self.schema.values(category, attribute).ok_or_else(|| {
+                        invalid(format!("Rule {} references unknown {} attribute {}", rule.id, category, attribute))
+                    })?;
+                    if values.is_empty() {
+                        return Err(invalid(format!("Rule {} has no values for {} attribute {}", rule.id, category, attribute)));
+                    }
+                    if let Some(value) = values.iter().find(|value| !known.contains(value)) {
+                        return Err(invalid(format!(
+                            "Rule {} references unknown value {} of {} attribute {}",
+                            rule.id, value, category, attribute
+                        )));
+                    }
+                }
+            }
+        }
+        
+        for (service, ports) in &self.services {
+            let known = self.schema.values(AttributeCategory::Object, SERVICE_ATTRIBUTE).unwrap_or_default();
+            if !known.contains(service) {
+                return Err(invalid(format!("Service {} is not a value of object attribute {}", service, SERVICE_ATTRIBUTE)));
+            }
+            if let Some(range) = ports.iter().find(|range| range.ports.0 > range.ports.1) {
+                return Err(invalid(format!("Service {} has an empty port range {}-{}", service, range.ports.0, range.ports.1)));
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+/// JSONファイルからポリシーを読み込む
+pub fn load_policy(path: &Path) -> Result<Policy> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read policy {}", path.display()))?;
+    Policy::from_json(&text).with_context(|| format!("Failed to parse policy {}", path.display()))
+}
+
+/// "属性=値" の並びをパース
+pub fn parse_attribute_assignments(assignments: &[String]) -> Result<BTreeMap<String, String>> {
+    assignments
+        .iter()
+        .map(|assignment| match assignment.split_once('=') {
+            Some((attribute, value)) if !attribute.is_empty() && !value.is_empty() => {
+                Ok((attribute.trim().to_string(), value.trim().to_string()))
+            }
+            _ => Err(anyhow::anyhow!("Invalid attribute assignment {} (expected name=value)", assignment)),
+        })
+        .collect()
+}
+
+/// コンパイルしたルール
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledRule {
+    pub rule_id: String,
+    pub effect: Effect,
+    /// テーブルエントリの優先度
+    pub priority: u32,
+    /// 生成したテーブルエントリの数
+    pub entries: usize,
+}
+
+/// ポリシーをコンパイルした結果
+#[derive(Debug, Clone, Default)]
+pub struct CompiledPolicy {
+    /// ABACポリシーテーブルのエントリ
+    pub entries: Vec<TableEntry>,
+    /// 適用したルール（優先される順）
+    pub rules: Vec<CompiledRule>,
+    /// 環境属性の条件を満たさないため除外したルールのID
+    pub inactive_rules: Vec<String>,
+    /// 主体・対象の属性のビット列への配置（分類テーブルのエントリも同じ配置でエンコードする）
+    pub layout: AttributeLayout,
+    /// 宛先のプロトコルとポートを対象の `service` 属性に分類するエントリ
+    pub services: Vec<AbacClassifier>,
+}
+
+/// ポリシーをABACポリシーテーブルのエントリにコンパイル
+///
+/// ルールを優先度の高い順（同じ優先度ならdeny、次にファイル内の順）に並べ、その順にエントリの優先度を振る。
+/// 環境属性の条件は `environment` で評価し、満たさないルールはエントリにしない。
+/// 条件に複数の値を持つルールは、主体と対象それぞれの値の組み合わせごとにエントリを作る。
+/// どのルールにも一致しないパケットには、属性をワイルドカードにしたルールより低い優先度のエントリで
+/// デフォルトの効果を適用する。
+/// `services` のポートの範囲は、対象の `service` 属性のビットだけを書き換える分類エントリにする。
+///
+/// エンコードの版は0（`compile_policy_with_version` を参照）。
+pub fn compile_policy(policy: &Policy, environment: &Environment) -> Result<CompiledPolicy> {
+    compile_policy_with_version(policy, environment, false)
+}
+
+/// エンコードの版を指定してポリシーをコンパイル
+///
+/// ポリシーのエントリは主体・対象とも版のビットが一致するパケットだけに一致するので、版の違う前のポリシーの
+/// エントリと同時に入れておける。どちらの版にも一致しない（主体と対象の版が食い違う）パケットは
+/// 最低優先度のエントリで破棄する。
+pub fn compile_policy_with_version(policy: &Policy, environment: &Environment, version: bool) -> Result<CompiledPolicy> {
+    policy.validate()?;
+    for (attribute, value) in environment {
+        if !policy.schema.values(AttributeCategory::Environment, attribute).is_some_and(|values| values.contains(value)) {
+            return Err(P4RuntimeError::InvalidPolicy(format!("Unknown environment attribute {}={}", attribute, value)).into());
+        }
+    }
+    let layout = AttributeLayout::new(&policy.schema, &policy.rules)?.with_version(version);
+    
+    let mut ordered: Vec<(usize, &PolicyRule)> = policy.rules.iter().enumerate().collect();
+    ordered.sort_by_key(|(index, rule)| (Reverse(rule.priority), rule.effect != Effect::Deny, *index));
+    let (active, inactive): (Vec<_>, Vec<_>) = ordered.into_iter().partition(|(_, rule)| rule.is_active(environment));
+    
+    let mut compiled = CompiledPolicy {
+        inactive_rules: inactive.into_iter().map(|(_, rule)| rule.id.clone()).collect(),
+        ..Default::default()
+    };
+    // 全フィールドがワイルドカードのエントリはすべてのパケットに一致し、それより優先度の低いエントリは
+    // 使われないので、最も優先されるものだけを残す
+    let mut has_catch_all = false;
+    
+    for (rank, (_, rule)) in active.iter().enumerate() {
+        let priority = DEFAULT_ENTRY_PRIORITY + (active.len() - rank) as u32;
+        let subjects = layout.ternary_patterns(AttributeCategory::Subject, &rule.subject)?;
+        let objects = layout.ternary_patterns(AttributeCategory::Object, &rule.object)?;
+        let mut entries = 0;
+        
+        for subject in &subjects {
+            for object in &objects {
+                if subject.is_wildcard() && object.is_wildcard() {
+                    if has_catch_all {
+                        continue;
+                    }
+                    has_catch_all = true;
+                }
+                compiled.entries.push(TableEntry::abac_policy(
+                    layout.versioned(*subject),
+                    layout.versioned(*object),
+                    rule.effect.to_abac_action(),
+                    priority,
+                ));
+                entries += 1;
+            }
+        }
+        
+        compiled.rules.push(CompiledRule {
+            rule_id: rule.id.clone(),
+            effect: rule.effect,
+            priority,
+            entries,
+        });
+    }
+    
+    if !has_catch_all {
+        compiled.entries.push(TableEntry::abac_policy(
+            layout.versioned(AttributeMatch::ANY),
+            layout.versioned(AttributeMatch::ANY),
+            policy.default_effect.to_abac_action(),
+            DEFAULT_ENTRY_PRIORITY,
+        ));
+    }
+    compiled.entries.push(TableEntry::abac_policy(
+        AttributeMatch::ANY,
+        AttributeMatch::ANY,
+        AbacAction::Drop,
+        VERSION_MISMATCH_PRIORITY,
+    ));
+    
+    compiled.services = service_classifiers(policy, &layout);
+    compiled.layout = layout;
+    Ok(compiled)
+}
+
+/// サービスのポートの範囲ごとに、`service` 属性のフィールドをその値にする分類エントリを作る
+fn service_classifiers(policy: &Policy, layout: &AttributeLayout) -> Vec<AbacClassifier> {
+    let Some(field) = layout.field(AttributeCategory::Object, SERVICE_ATTRIBUTE) else {
+        return Vec::new();
+    };
+    policy
+        .services
+        .iter()
+        .filter_map(|(service, ports)| Some((field.encode(service)?, ports)))
+        .flat_map(|(attributes, ports)| {
+            ports.iter().map(move |range| AbacClassifier::Service {
+                protocol: range.protocol.map(ServiceProtocol::number),
+                low_port: range.ports.0,
+                high_port: range.ports.1,
+                attributes,
+                mask: field.mask(),
+                version: layout.version(),
+            })
+        })
+        .collect()
+}
+
+/// テスト用のポリシー（主体の `role`、対象の `service`、環境の `mode` のスキーマに、JSONのルールの配列を入れる）
+#[cfg(test)]
+pub(crate) fn test_policy(rules: &str) -> Policy {
+    let text = format!(
+        r#"{{
+            "name": "test",
+            "schema": {{
+                "subject": {{ "role": ["admin", "guest"] }},
+                "object": {{ "service": ["ssh", "http"] }},
+                "environment": {{ "mode": ["normal", "maintenance"] }}
+            }},
+            "default": "deny",
+            "rules": {}
+        }}"#,
+        rules
+    );
+    Policy::from_json(&text).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_policy as policy;
+    
+    fn environment(mode: &str) -> Environment {
+        Environment::from([("mode".to_string(), mode.to_string())])
+    }
+    
+    /// エントリのternaryマッチ（マッチがなければワイルドカード）
+    fn attribute_match(entry: &TableEntry, field: &str) -> AttributeMatch {
+        match entry.matches.iter().find(|m| m.field == field).map(|m| &m.value) {
+            Some(MatchValue::Ternary { value, mask }) => AttributeMatch::new(
+                u32::from_be_bytes(value.as_slice().try_into().unwrap()),
+                u32::from_be_bytes(mask.as_slice().try_into().unwrap()),
+            ),
+            _ => AttributeMatch::ANY,
+        }
+    }
+    
+    /// 版のビットだけを見るエントリ（版の食い違いを破棄するエントリは除く）
+    fn catch_alls(compiled: &CompiledPolicy) -> Vec<&TableEntry> {
+        let any = compiled.layout.versioned(AttributeMatch::ANY);
+        let matches = TableEntry::abac_policy(any, any, AbacAction::Allow, 0).matches;
+        compiled.entries.iter().filter(|entry| entry.matches == matches).collect()
+    }
+    
+    #[test]
+    fn rules_are_ordered_by_priority_then_deny_then_file_order() {
+        let policy = policy(
+            r#"[
+                { "id": "low", "priority": 10, "effect": "permit", "subject": { "role": ["guest"] } },
+                { "id": "permit-1", "priority": 100, "effect": "permit", "subject": { "role": ["admin"] } },
+                { "id": "deny", "priority": 100, "effect": "deny", "object": { "service": ["ssh"] } },
+                { "id": "permit-2", "priority": 100, "effect": "permit", "object": { "service": ["http"] } }
+            ]"#,
+        );
+        let compiled = compile_policy(&policy, &Environment::new()).unwrap();
+        
+        let order: Vec<&str> = compiled.rules.iter().map(|rule| rule.rule_id.as_str()).collect();
+        assert_eq!(order, ["deny", "permit-1", "permit-2", "low"]);
+        // エントリの優先度は順に下がり、デフォルトのエントリより上にある
+        let priorities: Vec<u32> = compiled.rules.iter().map(|rule| rule.priority).collect();
+        assert!(priorities.windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(priorities.iter().all(|priority| *priority > DEFAULT_ENTRY_PRIORITY));
+        
+        let deny = compiled.entries.iter().find(|entry| entry.priority == compiled.rules[0].priority).unwrap();
+        assert_eq!(deny.abac_action(), Some(AbacAction::Drop));
+    }
+    
+    #[test]
+    fn rules_outside_the_environment_are_inactive() {
+        let policy = policy(
+            r#"[
+                { "id": "maintenance-ssh", "priority": 100, "effect": "permit",
+                  "object": { "service": ["ssh"] }, "environment": { "mode": ["maintenance"] } },
+                { "id": "http", "priority": 10, "effect": "permit", "object": { "service": ["http"] } }
+            ]"#,
+        );
+        
+        let compiled = compile_policy(&policy, &environment("normal")).unwrap();
+        assert_eq!(compiled.inactive_rules, ["maintenance-ssh"]);
+        assert_eq!(compiled.rules.len(), 1);
+        assert_eq!(compiled.entries.len(), 3);
+        
+        // 環境属性が設定されていなければ条件を満たさない
+        let compiled = compile_policy(&policy, &Environment::new()).unwrap();
+        assert_eq!(compiled.inactive_rules, ["maintenance-ssh"]);
+        
+        let compiled = compile_policy(&policy, &environment("maintenance")).unwrap();
+        assert!(compiled.inactive_rules.is_empty());
+        assert_eq!(compiled.rules[0].rule_id, "maintenance-ssh");
+        assert_eq!(compiled.entries.len(), 4);
+        
+        assert!(compile_policy(&policy, &environment("holiday")).is_err());
+    }
+    
+    #[test]
+    fn catch_all_entry_is_emitted_once() {
+        // どのルールも全ワイルドカードでなければ、デフォルトの効果を最低優先度で入れる
+        let compiled = compile_policy(&policy("[]"), &Environment::new()).unwrap();
+        let defaults = catch_alls(&compiled);
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(defaults[0].priority, DEFAULT_ENTRY_PRIORITY);
+        assert_eq!(defaults[0].abac_action(), Some(AbacAction::Drop));
+        
+        // 全ワイルドカードのルールがあれば、最も優先されるものがデフォルトの代わりになる
+        let policy = policy(
+            r#"[
+                { "id": "allow-all", "priority": 50, "effect": "permit" },
+                { "id": "guest", "priority": 100, "effect": "deny", "subject": { "role": ["guest"] } },
+                { "id": "punt-all", "priority": 10, "effect": "punt" }
+            ]"#,
+        );
+        let compiled = compile_policy(&policy, &Environment::new()).unwrap();
+        let defaults = catch_alls(&compiled);
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(defaults[0].abac_action(), Some(AbacAction::Allow));
+        assert_eq!(defaults[0].priority, compiled.rules[1].priority);
+        assert_eq!(compiled.rules[2].rule_id, "punt-all");
+        assert_eq!(compiled.rules[2].entries, 0);
+    }
+    
+    #[test]
+    fn entries_match_only_their_encoding_version() {
+        let policy = policy(
+            r#"[
+                { "id": "guest", "priority": 100, "effect": "deny", "subject": { "role": ["guest"] } },
+                { "id": "http", "priority": 10, "effect": "permit", "object": { "service": ["http"] } }
+            ]"#,
+        );
+        let old = compile_policy_with_version(&policy, &Environment::new(), false).unwrap();
+        let new = compile_policy_with_version(&policy, &Environment::new(), true).unwrap();
+        let guest = old.layout.encode_prefix(AttributeCategory::Subject, &BTreeMap::from([("role".to_string(), "guest".to_string())]));
+        assert_eq!(guest & ABAC_VERSION_BIT, 0);
+        
+        // 主体・対象とも同じ版のビット列にだけ一致する（版の食い違いを破棄するエントリを除く）
+        let matching = |compiled: &CompiledPolicy, subject: u32, object: u32| -> Vec<u32> {
+            compiled
+                .entries
+                .iter()
+                .filter(|entry| entry.priority > VERSION_MISMATCH_PRIORITY)
+                .filter(|entry| {
+                    attribute_match(entry, ABAC_SUBJECT_FIELD).matches(subject)
+                        && attribute_match(entry, ABAC_OBJECT_FIELD).matches(object)
+                })
+                .map(|entry| entry.priority)
+                .collect()
+        };
+        assert_eq!(matching(&old, guest, 0).len(), 2);
+        assert!(matching(&new, guest, 0).is_empty());
+        assert_eq!(matching(&new, guest | ABAC_VERSION_BIT, ABAC_VERSION_BIT).len(), 2);
+        assert!(matching(&new, guest | ABAC_VERSION_BIT, 0).is_empty());
+        
+        // 版が食い違うパケットは最低優先度のエントリで破棄する
+        let fallback = new.entries.iter().min_by_key(|entry| entry.priority).unwrap();
+        assert_eq!(fallback.priority, VERSION_MISMATCH_PRIORITY);
+        assert!(fallback.matches.is_empty());
+        assert_eq!(fallback.abac_action(), Some(AbacAction::Drop));
+        assert!(old.entries.iter().any(|entry| entry.has_same_key(fallback)));
+    }
+    
+    #[test]
+    fn services_become_classifiers_for_the_service_field() {
+        let mut policy = policy("[]");
+        policy.services = BTreeMap::from([
+            ("ssh".to_string(), vec![ServicePorts { protocol: Some(ServiceProtocol::Tcp), ports: (22, 22) }]),
+            ("http".to_string(), vec![ServicePorts { protocol: None, ports: (8000, 8080) }]),
+        ]);
+        let compiled = compile_policy(&policy, &Environment::new()).unwrap();
+        let field = compiled.layout.field(AttributeCategory::Object, SERVICE_ATTRIBUTE).unwrap();
+        
+        assert_eq!(
+            compiled.services,
+            [
+                AbacClassifier::Service {
+                    protocol: None,
+                    low_port: 8000,
+                    high_port: 8080,
+                    attributes: field.encode("http").unwrap(),
+                    mask: field.mask(),
+                    version: false,
+                },
+                AbacClassifier::Service {
+                    protocol: Some(6),
+                    low_port: 22,
+                    high_port: 22,
+                    attributes: field.encode("ssh").unwrap(),
+                    mask: field.mask(),
+                    version: false,
+                },
+            ]
+        );
+    }
+    
+    #[test]
+    fn services_must_be_service_values_with_valid_ranges() {
+        let mut unknown = policy("[]");
+        unknown.services = BTreeMap::from([("smtp".to_string(), vec![ServicePorts { protocol: None, ports: (25, 25) }])]);
+        assert!(unknown.validate().is_err());
+        
+        let mut reversed = policy("[]");
+        reversed.services = BTreeMap::from([("ssh".to_string(), vec![ServicePorts { protocol: None, ports: (23, 22) }])]);
+        assert!(reversed.validate().is_err());
+        
+        let services = r#"{ "ssh": [{ "protocol": "tcp", "ports": [22, 22] }] }"#;
+        let parsed: BTreeMap<String, Vec<ServicePorts>> = serde_json::from_str(services).unwrap();
+        assert_eq!(parsed["ssh"], [ServicePorts { protocol: Some(ServiceProtocol::Tcp), ports: (22, 22) }]);
+    }
+}
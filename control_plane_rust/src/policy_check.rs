@@ -0,0 +1,386 @@
+use crate::policy::{AttributeCategory, Effect, Policy, PolicyRule, CATEGORIES};
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+
+/// 被覆の判定で分割する領域の数の上限（超えたら判定をあきらめる）
+const MAX_REGIONS: usize = 10_000;
+
+/// 指摘の重要度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// ポリシーとしてコンパイルできない
+    Error,
+    /// コンパイルはできるが意図どおりでない可能性がある
+    Warning,
+    /// 意図したものであることが多い（優先度で例外を作るルールなど）
+    Info,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        };
+        f.pad(name)
+    }
+}
+
+/// 指摘の種類
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FindingKind {
+    /// スキーマにない属性を参照している
+    UnknownAttribute { category: AttributeCategory, attribute: String },
+    /// スキーマにない属性値を参照している
+    UnknownValue { category: AttributeCategory, attribute: String, value: String },
+    /// 値のない条件があり、どのパケットにも一致しない
+    EmptyCondition { category: AttributeCategory, attribute: String },
+    /// 優先されるルールに完全に覆われていて、一致することがない
+    Shadowed,
+    /// 効果の異なるルールの条件が同じか、同じ優先度で重なり、結果がdenyの優先やファイル内の順で決まる
+    Conflict { identical: bool },
+    /// 効果の異なるルールの条件が一部重なり、重なった部分は優先度の高いルールが決める
+    Overlap,
+}
+
+/// ポリシーの検査結果の1件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyFinding {
+    pub severity: Severity,
+    pub kind: FindingKind,
+    /// 関係するルールのID（影になる・優先度の低いルールが先頭）
+    pub rules: Vec<String>,
+    pub message: String,
+}
+
+/// ルールが一致する属性の組み合わせ（次元ごとに許す値の番号の集合、最後の番号は属性なし）
+type Region = Vec<BTreeSet<usize>>;
+
+/// 属性の次元（種類・属性名・取りうる値）
+struct Dimension<'a> {
+    category: AttributeCategory,
+    attribute: &'a str,
+    values: &'a [String],
+}
+
+/// ポリシーの矛盾と影になるルールを検査
+///
+/// 検証していないポリシーも検査できる。スキーマにない属性・値を参照するルールは
+/// エラーとして指摘し、被覆と重なりの判定からは除く。
+/// 効果の異なるルールの重なりは、条件が同じか同じ優先度なら警告、優先度の違うルールの一部の重なりは情報として指摘する。
+/// ルールはコンパイルと同じ順（優先度の高い順、同じ優先度ならdeny、次にファイル内の順）で比べ、
+/// 環境属性も条件の一部として、すべての環境での一致を考える。
+pub fn check_policy(policy: &Policy) -> Vec<PolicyFinding> {
+    let mut findings = Vec::new();
+    let dimensions: Vec<Dimension> = CATEGORIES
+        .iter()
+        .flat_map(|category| {
+            policy.schema.attributes(*category).iter().map(|(attribute, values)| Dimension {
+                category: *category,
+                attribute,
+                values,
+            })
+        })
+        .collect();
+    
+    let mut ordered: Vec<(usize, &PolicyRule)> = policy.rules.iter().enumerate().collect();
+    ordered.sort_by_key(|(index, rule)| (Reverse(rule.priority), rule.effect != Effect::Deny, *index));
+    
+    let mut regions: Vec<(&PolicyRule, Region)> = Vec::new();
+    for (_, rule) in ordered {
+        let unknown = check_references(policy, rule, &mut findings);
+        if !unknown {
+            if let Some(region) = rule_region(rule, &dimensions, &mut findings) {
+                regions.push((rule, region));
+            }
+        }
+    }
+    
+    for (index, (rule, region)) in regions.iter().enumerate() {
+        let higher = &regions[..index];
+        
+        for (other, other_region) in higher {
+            if other.effect == rule.effect || !intersects(region, other_region) {
+                continue;
+            }
+            let identical = region == other_region;
+            let same_priority = other.priority == rule.priority;
+            let overlap = if identical { "have identical conditions" } else { "overlap" };
+            let resolution = if same_priority {
+                format!("same priority {}, {} wins", rule.priority, other.effect)
+            } else {
+                format!("priority {} over {}", other.priority, rule.priority)
+            };
+            // 優先度の高いルールで一部を例外にするのはよくある書き方なので、条件が同じか同じ優先度の場合だけ警告する
+            let (severity, kind) = if identical || same_priority {
+                (Severity::Warning, FindingKind::Conflict { identical })
+            } else {
+                (Severity::Info, FindingKind::Overlap)
+            };
+            findings.push(PolicyFinding {
+                severity,
+                kind,
+                rules: vec![rule.id.clone(), other.id.clone()],
+                message: format!(
+                    "Rule {} ({}) and rule {} ({}) {}; {} decides the result ({})",
+                    rule.id, rule.effect, other.id, other.effect, overlap, other.id, resolution
+                ),
+            });
+        }
+        
+        if let Some(covering) = covering_rules(region, higher) {
+            let mut rules = vec![rule.id.clone()];
+            rules.extend(covering.iter().map(|other| other.id.clone()));
+            findings.push(PolicyFinding {
+                severity: Severity::Warning,
+                kind: FindingKind::Shadowed,
+                rules,
+                message: format!(
+                    "Rule {} never matches: it is fully covered by higher-priority rule(s) {}",
+                    rule.id,
+                    covering.iter().map(|other| other.id.as_str()).collect::<Vec<_>>().join(", ")
+                ),
+            });
+        }
+    }
+    
+    findings
+}
+
+/// スキーマにない属性・値の参照を指摘し、見つかればtrue
+fn check_references(policy: &Policy, rule: &PolicyRule, findings: &mut Vec<PolicyFinding>) -> bool {
+    let mut unknown = false;
+    for category in CATEGORIES {
+        for (attribute, values) in rule.conditions(category) {
+            let Some(known) = policy.schema.values(category, attribute) else {
+                findings.push(PolicyFinding {
+                    severity: Severity::Error,
+                    kind: FindingKind::UnknownAttribute { category, attribute: attribute.clone() },
+                    rules: vec![rule.id.clone()],
+                    message: format!("Rule {} references unknown {} attribute {}", rule.id, category, attribute),
+                });
+                unknown = true;
+                continue;
+            };
+            for value in values.iter().filter(|value| !known.contains(value)) {
+                findings.push(PolicyFinding {
+                    severity: Severity::Error,
+                    kind: FindingKind::UnknownValue {
+                        category,
+                        attribute: attribute.clone(),
+                        value: value.clone(),
+                    },
+                    rules: vec![rule.id.clone()],
+                    message: format!(
+                        "Rule {} references unknown value {} of {} attribute {}",
+                        rule.id, value, category, attribute
+                    ),
+                });
+                unknown = true;
+            }
+        }
+    }
+    unknown
+}
+
+/// ルールが一致する領域（値のない条件があればそれを指摘してNone）
+fn rule_region(rule: &PolicyRule, dimensions: &[Dimension], findings: &mut Vec<PolicyFinding>) -> Option<Region> {
+    let mut region = Vec::with_capacity(dimensions.len());
+    for dimension in dimensions {
+        let allowed: BTreeSet<usize> = match rule.conditions(dimension.category).get(dimension.attribute) {
+            // 条件のない属性は、属性がない場合も含めてどの値でもよい
+            None => (0..=dimension.values.len()).collect(),
+            Some(values) => dimension
+                .values
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| values.contains(value))
+                .map(|(index, _)| index)
+                .collect(),
+        };
+        if allowed.is_empty() {
+            findings.push(PolicyFinding {
+                severity: Severity::Error,
+                kind: FindingKind::EmptyCondition {
+                    category: dimension.category,
+                    attribute: dimension.attribute.to_string(),
+                },
+                rules: vec![rule.id.clone()],
+                message: format!(
+                    "Rule {} never matches: it has no values for {} attribute {}",
+                    rule.id, dimension.category, dimension.attribute
+                ),
+            });
+            return None;
+        }
+        region.push(allowed);
+    }
+    Some(region)
+}
+
+fn intersects(a: &Region, b: &Region) -> bool {
+    a.iter().zip(b).all(|(a, b)| !a.is_disjoint(b))
+}
+
+/// 領域 `region` から `other` を除いた残りを、重ならない領域に分けて返す
+fn subtract(region: &Region, other: &Region) -> Vec<Region> {
+    if !intersects(region, other) {
+        return vec![region.clone()];
+    }
+    
+    let mut pieces = Vec::new();
+    let mut rest = region.clone();
+    for dimension in 0..region.len() {
+        let outside: BTreeSet<usize> = rest[dimension].difference(&other[dimension]).copied().collect();
+        if !outside.is_empty() {
+            let mut piece = rest.clone();
+            piece[dimension] = outside;
+            pieces.push(piece);
+        }
+        rest[dimension] = rest[dimension].intersection(&other[dimension]).copied().collect();
+    }
+    pieces
+}
+
+/// 領域が優先されるルールで完全に覆われていれば、覆うのに使ったルールを返す
+///
+/// 1つのルールで覆えればそのルールだけを、そうでなければ重なったルールをすべて返す。
+fn covering_rules<'a>(region: &Region, higher: &[(&'a PolicyRule, Region)]) -> Option<Vec<&'a PolicyRule>> {
+    if let Some((rule, _)) = higher.iter().find(|(_, other)| subtract(region, other).is_empty()) {
+        return Some(vec![*rule]);
+    }
+    
+    let mut remaining = vec![region.clone()];
+    let mut used = Vec::new();
+    for (rule, other) in higher {
+        if !remaining.iter().any(|piece| intersects(piece, other)) {
+            continue;
+        }
+        used.push(*rule);
+        remaining = remaining.iter().flat_map(|piece| subtract(piece, other)).collect();
+        if remaining.is_empty() {
+            return Some(used);
+        }
+        if remaining.len() > MAX_REGIONS {
+            return None;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::test_policy as policy;
+    
+    fn shadowed(findings: &[PolicyFinding]) -> Vec<&[String]> {
+        findings
+            .iter()
+            .filter(|finding| finding.kind == FindingKind::Shadowed)
+            .map(|finding| finding.rules.as_slice())
+            .collect()
+    }
+    
+    #[test]
+    fn rule_covered_by_one_higher_rule_is_shadowed() {
+        let findings = check_policy(&policy(
+            r#"[
+                { "id": "admin-ssh", "priority": 10, "effect": "permit",
+                  "subject": { "role": ["admin"] }, "object": { "service": ["ssh"] } },
+                { "id": "no-ssh", "priority": 100, "effect": "deny", "object": { "service": ["ssh"] } }
+            ]"#,
+        ));
+        assert_eq!(shadowed(&findings), [["admin-ssh", "no-ssh"]]);
+        // 影になったことは警告し、優先度の違うルールとの重なりは情報にとどめる
+        for finding in &findings {
+            let expected = if finding.kind == FindingKind::Overlap { Severity::Info } else { Severity::Warning };
+            assert_eq!(finding.severity, expected);
+        }
+    }
+    
+    #[test]
+    fn rule_covered_only_by_two_rules_together_is_shadowed() {
+        let findings = check_policy(&policy(
+            r#"[
+                { "id": "ssh", "priority": 100, "effect": "permit", "object": { "service": ["ssh"] } },
+                { "id": "http", "priority": 100, "effect": "permit", "object": { "service": ["http"] } },
+                { "id": "any-service", "priority": 10, "effect": "permit", "object": { "service": ["ssh", "http"] } },
+                { "id": "anything", "priority": 1, "effect": "permit" }
+            ]"#,
+        ));
+        // 条件のないルールはサービスの属性がないパケットにも一致するので覆われない
+        assert_eq!(shadowed(&findings), [["any-service", "ssh", "http"]]);
+        assert!(!findings.iter().any(|finding| matches!(finding.kind, FindingKind::Conflict { .. })));
+    }
+    
+    #[test]
+    fn same_priority_permit_and_deny_conflict() {
+        let findings = check_policy(&policy(
+            r#"[
+                { "id": "allow-admin", "priority": 50, "effect": "permit", "subject": { "role": ["admin"] } },
+                { "id": "block-admin", "priority": 50, "effect": "deny", "subject": { "role": ["admin"] } },
+                { "id": "guest-http", "priority": 50, "effect": "permit",
+                  "subject": { "role": ["guest"] }, "object": { "service": ["http"] } }
+            ]"#,
+        ));
+        let conflicts: Vec<&PolicyFinding> = findings
+            .iter()
+            .filter(|finding| matches!(finding.kind, FindingKind::Conflict { .. }))
+            .collect();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, FindingKind::Conflict { identical: true });
+        assert_eq!(conflicts[0].rules, ["allow-admin", "block-admin"]);
+        assert!(conflicts[0].message.contains("same priority 50, deny wins"));
+        assert_eq!(shadowed(&findings), [["allow-admin", "block-admin"]]);
+    }
+    
+    #[test]
+    fn higher_priority_exception_is_only_informational() {
+        let findings = check_policy(&policy(
+            r#"[
+                { "id": "no-guest-ssh", "priority": 100, "effect": "deny",
+                  "subject": { "role": ["guest"] }, "object": { "service": ["ssh"] } },
+                { "id": "ssh", "priority": 10, "effect": "permit", "object": { "service": ["ssh"] } }
+            ]"#,
+        ));
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Info);
+        assert_eq!(findings[0].kind, FindingKind::Overlap);
+        assert_eq!(findings[0].rules, ["ssh", "no-guest-ssh"]);
+        assert!(findings[0].message.contains("priority 100 over 10"));
+        
+        // 条件が同じなら優先度が違っても警告する
+        let findings = check_policy(&policy(
+            r#"[
+                { "id": "deny-ssh", "priority": 100, "effect": "deny", "object": { "service": ["ssh"] } },
+                { "id": "permit-ssh", "priority": 10, "effect": "permit", "object": { "service": ["ssh"] } }
+            ]"#,
+        ));
+        let conflict = findings.iter().find(|finding| finding.kind == FindingKind::Conflict { identical: true }).unwrap();
+        assert_eq!(conflict.severity, Severity::Warning);
+        assert_eq!(shadowed(&findings), [["permit-ssh", "deny-ssh"]]);
+    }
+    
+    #[test]
+    fn unknown_attribute_is_an_error_and_left_out_of_overlaps() {
+        let findings = check_policy(&policy(
+            r#"[
+                { "id": "by-dept", "priority": 100, "effect": "deny", "subject": { "dept": ["rnd"] } },
+                { "id": "anything", "priority": 10, "effect": "permit" }
+            ]"#,
+        ));
+        assert_eq!(
+            findings,
+            [PolicyFinding {
+                severity: Severity::Error,
+                kind: FindingKind::UnknownAttribute {
+                    category: AttributeCategory::Subject,
+                    attribute: "dept".to_string(),
+                },
+                rules: vec!["by-dept".to_string()],
+                message: "Rule by-dept references unknown subject attribute dept".to_string(),
+            }]
+        );
+    }
+}
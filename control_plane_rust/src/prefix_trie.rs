@@ -0,0 +1,525 @@
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// プレフィックストライのキーになるプレフィックス
+///
+/// ビット列はアドレスを左詰めにした `u128` で扱い、IPv4とIPv6で同じトライを使う。
+pub trait Prefix: Copy {
+    /// プレフィックス長の最大値（アドレスのビット幅）
+    const MAX_LEN: u8;
+    
+    /// ネットワークアドレスを左詰めにしたビット列
+    fn bits(&self) -> u128;
+    
+    fn prefix_len(&self) -> u8;
+    
+    /// 左詰めのビット列とプレフィックス長から作る（`prefix_len` は `MAX_LEN` 以下）
+    fn from_bits(bits: u128, prefix_len: u8) -> Self;
+}
+
+impl Prefix for Ipv4Net {
+    const MAX_LEN: u8 = 32;
+    
+    fn bits(&self) -> u128 {
+        (u32::from(self.network()) as u128) << 96
+    }
+    
+    fn prefix_len(&self) -> u8 {
+        Ipv4Net::prefix_len(self)
+    }
+    
+    fn from_bits(bits: u128, prefix_len: u8) -> Self {
+        Ipv4Net::new_assert(Ipv4Addr::from((bits >> 96) as u32), prefix_len)
+    }
+}
+
+impl Prefix for Ipv6Net {
+    const MAX_LEN: u8 = 128;
+    
+    fn bits(&self) -> u128 {
+        u128::from(self.network())
+    }
+    
+    fn prefix_len(&self) -> u8 {
+        Ipv6Net::prefix_len(self)
+    }
+    
+    fn from_bits(bits: u128, prefix_len: u8) -> Self {
+        Ipv6Net::new_assert(Ipv6Addr::from(bits), prefix_len)
+    }
+}
+
+/// トライのノード（値を持たないノードは2つの子の分岐点）
+#[derive(Debug, Clone)]
+struct Node<V> {
+    bits: u128,
+    len: u8,
+    value: Option<V>,
+    children: [Option<Box<Node<V>>>; 2],
+}
+
+impl<V> Node<V> {
+    fn new(bits: u128, len: u8, value: Option<V>) -> Box<Self> {
+        Box::new(Self {
+            bits,
+            len,
+            value,
+            children: [None, None],
+        })
+    }
+    
+    /// このノードのプレフィックスが `bits/len` を含むか
+    fn contains(&self, bits: u128, len: u8) -> bool {
+        self.len <= len && common_len(self.bits, bits, self.len) == self.len
+    }
+    
+    fn is(&self, bits: u128, len: u8) -> bool {
+        self.len == len && self.bits == bits
+    }
+}
+
+/// 長さ `len` のマスク
+fn mask(len: u8) -> u128 {
+    if len == 0 {
+        0
+    } else {
+        !0u128 << (128 - len as u32)
+    }
+}
+
+/// 先頭から `i` ビット目（0始まり）
+fn bit_at(bits: u128, i: u8) -> usize {
+    ((bits >> (127 - i as u32)) & 1) as usize
+}
+
+/// 2つのビット列で先頭から一致するビット数（`max_len` まで）
+fn common_len(a: u128, b: u128, max_len: u8) -> u8 {
+    ((a ^ b).leading_zeros() as u8).min(max_len)
+}
+
+/// 経路圧縮した2分木（ラディックストライ）によるプレフィックスの表
+///
+/// 完全一致・最長プレフィックスマッチ・あるプレフィックスを含む（包含される）プレフィックスの列挙を、
+/// 登録数ではなくプレフィックス長に比例する手間で行う。RIBとFIBのどちらにも使う。
+#[derive(Debug, Clone)]
+pub struct PrefixTrie<P, V> {
+    root: Option<Box<Node<V>>>,
+    len: usize,
+    _prefix: PhantomData<P>,
+}
+
+impl<P: Prefix, V> PrefixTrie<P, V> {
+    pub fn new() -> Self {
+        Self {
+            root: None,
+            len: 0,
+            _prefix: PhantomData,
+        }
+    }
+    
+    /// 登録されているプレフィックスの数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    
+    pub fn clear(&mut self) {
+        self.root = None;
+        self.len = 0;
+    }
+    
+    /// プレフィックスを登録し、すでに登録されていれば以前の値を返す
+    pub fn insert(&mut self, prefix: P, value: V) -> Option<V> {
+        let old = insert_node(&mut self.root, prefix.bits(), prefix.prefix_len(), value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+    
+    /// プレフィックスを削除して値を返す
+    pub fn remove(&mut self, prefix: &P) -> Option<V> {
+        let removed = remove_node(&mut self.root, prefix.bits(), prefix.prefix_len());
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+    
+    /// 完全一致するプレフィックスの値を取得
+    pub fn get(&self, prefix: &P) -> Option<&V> {
+        let (bits, len) = (prefix.bits(), prefix.prefix_len());
+        let mut current = self.root.as_deref();
+        
+        while let Some(node) = current {
+            if node.is(bits, len) {
+                return node.value.as_ref();
+            }
+            if !node.contains(bits, len) {
+                break;
+            }
+            current = node.children[bit_at(bits, node.len)].as_deref();
+        }
+        
+        None
+    }
+    
+    /// 完全一致するプレフィックスの値を変更用に取得
+    pub fn get_mut(&mut self, prefix: &P) -> Option<&mut V> {
+        let (bits, len) = (prefix.bits(), prefix.prefix_len());
+        let mut current = self.root.as_deref_mut();
+        
+        while let Some(node) = current {
+            if node.is(bits, len) {
+                return node.value.as_mut();
+            }
+            if !node.contains(bits, len) {
+                break;
+            }
+            current = node.children[bit_at(bits, node.len)].as_deref_mut();
+        }
+        
+        None
+    }
+    
+    /// プレフィックスの値を取得し、未登録なら `default` で作って登録する
+    pub fn get_or_insert_with(&mut self, prefix: P, default: impl FnOnce() -> V) -> &mut V {
+        if self.get(&prefix).is_none() {
+            self.insert(prefix, default());
+        }
+        self.get_mut(&prefix).expect("prefix was just inserted")
+    }
+    
+    /// プレフィックス（アドレスならホストプレフィックス）を含む最長のプレフィックスを取得
+    pub fn longest_match(&self, prefix: &P) -> Option<(P, &V)> {
+        self.covering(prefix).pop()
+    }
+    
+    /// プレフィックスを含むプレフィックス（自身を含む）を短い順に取得
+    pub fn covering(&self, prefix: &P) -> Vec<(P, &V)> {
+        let (bits, len) = (prefix.bits(), prefix.prefix_len());
+        let mut result = Vec::new();
+        let mut current = self.root.as_deref();
+        
+        while let Some(node) = current {
+            if !node.contains(bits, len) {
+                break;
+            }
+            if let Some(value) = &node.value {
+                result.push((P::from_bits(node.bits, node.len), value));
+            }
+            if node.len == len {
+                break;
+            }
+            current = node.children[bit_at(bits, node.len)].as_deref();
+        }
+        
+        result
+    }
+    
+    /// プレフィックスに含まれるプレフィックス（自身を含む）をアドレス順に列挙
+    pub fn covered(&self, prefix: &P) -> Iter<'_, P, V> {
+        let (bits, len) = (prefix.bits(), prefix.prefix_len());
+        let mut current = self.root.as_deref();
+        
+        while let Some(node) = current {
+            // 部分木の根が `prefix` に含まれれば、その部分木全体が対象
+            if len <= node.len && common_len(node.bits, bits, len) == len {
+                return Iter::new(Some(node));
+            }
+            if !node.contains(bits, len) {
+                break;
+            }
+            current = node.children[bit_at(bits, node.len)].as_deref();
+        }
+        
+        Iter::new(None)
+    }
+    
+    /// 全プレフィックスをアドレス順（同じアドレスなら短い順）に列挙
+    pub fn iter(&self) -> Iter<'_, P, V> {
+        Iter::new(self.root.as_deref())
+    }
+    
+    /// 全プレフィックスの値を列挙
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+impl<P: Prefix, V> Default for PrefixTrie<P, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Prefix, V> FromIterator<(P, V)> for PrefixTrie<P, V> {
+    fn from_iter<I: IntoIterator<Item = (P, V)>>(iter: I) -> Self {
+        let mut trie = Self::new();
+        for (prefix, value) in iter {
+            trie.insert(prefix, value);
+        }
+        trie
+    }
+}
+
+impl<'a, P: Prefix, V> IntoIterator for &'a PrefixTrie<P, V> {
+    type Item = (P, &'a V);
+    type IntoIter = Iter<'a, P, V>;
+    
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// `PrefixTrie` の部分木を行きがけ順にたどるイテレーター
+#[derive(Debug)]
+pub struct Iter<'a, P, V> {
+    stack: Vec<&'a Node<V>>,
+    _prefix: PhantomData<P>,
+}
+
+impl<'a, P, V> Iter<'a, P, V> {
+    fn new(root: Option<&'a Node<V>>) -> Self {
+        Self {
+            stack: root.into_iter().collect(),
+            _prefix: PhantomData,
+        }
+    }
+}
+
+impl<'a, P: Prefix, V> Iterator for Iter<'a, P, V> {
+    type Item = (P, &'a V);
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            // 0側の子を先にたどるため、1側から積む
+            self.stack.extend(node.children.iter().rev().filter_map(|child| child.as_deref()));
+            if let Some(value) = &node.value {
+                return Some((P::from_bits(node.bits, node.len), value));
+            }
+        }
+        None
+    }
+}
+
+fn insert_node<V>(slot: &mut Option<Box<Node<V>>>, bits: u128, len: u8, value: V) -> Option<V> {
+    let bits = bits & mask(len);
+    let Some(node) = slot else {
+        *slot = Some(Node::new(bits, len, Some(value)));
+        return None;
+    };
+    
+    let common = common_len(node.bits, bits, node.len.min(len));
+    if common == node.len && common == len {
+        return node.value.replace(value);
+    }
+    if common == node.len {
+        return insert_node(&mut node.children[bit_at(bits, common)], bits, len, value);
+    }
+    
+    // 新しいプレフィックスがノードを含む場合はその親に、そうでなければ分岐点を作って兄弟にする
+    let parent = if common == len {
+        Node::new(bits, len, Some(value))
+    } else {
+        let mut branch = Node::new(bits & mask(common), common, None);
+        branch.children[bit_at(bits, common)] = Some(Node::new(bits, len, Some(value)));
+        branch
+    };
+    let old = std::mem::replace(node, parent);
+    let side = bit_at(old.bits, common);
+    node.children[side] = Some(old);
+    None
+}
+
+fn remove_node<V>(slot: &mut Option<Box<Node<V>>>, bits: u128, len: u8) -> Option<V> {
+    let bits = bits & mask(len);
+    let node = slot.as_deref_mut()?;
+    
+    let removed = if node.is(bits, len) {
+        node.value.take()
+    } else if node.contains(bits, len) {
+        remove_node(&mut node.children[bit_at(bits, node.len)], bits, len)
+    } else {
+        None
+    };
+    
+    if removed.is_some() {
+        compact(slot);
+    }
+    removed
+}
+
+/// 値を持たないノードのうち、子が1つ以下のものを取り除く
+fn compact<V>(slot: &mut Option<Box<Node<V>>>) {
+    let Some(node) = slot.as_deref_mut() else { return };
+    if node.value.is_some() {
+        return;
+    }
+    
+    let replacement = match (node.children[0].is_some(), node.children[1].is_some()) {
+        (true, true) => return,
+        (true, false) => node.children[0].take(),
+        (false, true) => node.children[1].take(),
+        (false, false) => None,
+    };
+    *slot = replacement;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn v4(s: &str) -> Ipv4Net {
+        s.parse().unwrap()
+    }
+    
+    fn v6(s: &str) -> Ipv6Net {
+        s.parse().unwrap()
+    }
+    
+    fn prefixes<P: Prefix, V>(entries: Vec<(P, &V)>) -> Vec<P> {
+        entries.into_iter().map(|(prefix, _)| prefix).collect()
+    }
+    
+    #[test]
+    fn insert_replaces_existing_value() {
+        let mut trie = PrefixTrie::new();
+        assert_eq!(trie.insert(v4("10.0.0.0/8"), 1), None);
+        assert_eq!(trie.insert(v4("10.0.0.0/16"), 2), None);
+        assert_eq!(trie.insert(v4("10.0.0.0/8"), 3), Some(1));
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.get(&v4("10.0.0.0/8")), Some(&3));
+        assert_eq!(trie.get(&v4("10.0.0.0/16")), Some(&2));
+        assert_eq!(trie.get(&v4("10.0.0.0/12")), None);
+    }
+    
+    #[test]
+    fn host_bits_are_ignored() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(Ipv4Net::new_assert("10.1.2.3".parse().unwrap(), 16), "a");
+        assert_eq!(trie.get(&v4("10.1.0.0/16")), Some(&"a"));
+        assert_eq!(trie.iter().next().map(|(prefix, _)| prefix), Some(v4("10.1.0.0/16")));
+    }
+    
+    #[test]
+    fn remove_merges_branch_nodes() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(v4("10.0.0.0/24"), 1);
+        trie.insert(v4("10.0.1.0/24"), 2);
+        // 兄弟の間に値を持たない分岐点（10.0.0.0/23）ができる
+        let root = trie.root.as_deref().unwrap();
+        assert_eq!((root.len, root.value), (23, None));
+        
+        assert_eq!(trie.remove(&v4("10.0.0.0/24")), Some(1));
+        assert_eq!(trie.remove(&v4("10.0.0.0/24")), None);
+        let root = trie.root.as_deref().unwrap();
+        assert_eq!((root.len, root.value), (24, Some(2)));
+        assert!(root.children.iter().all(Option::is_none));
+        
+        assert_eq!(trie.remove(&v4("10.0.1.0/24")), Some(2));
+        assert!(trie.root.is_none());
+        assert!(trie.is_empty());
+    }
+    
+    #[test]
+    fn remove_keeps_children_of_removed_prefix() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(v4("10.0.0.0/8"), 1);
+        trie.insert(v4("10.1.0.0/16"), 2);
+        trie.insert(v4("10.128.0.0/16"), 3);
+        
+        assert_eq!(trie.remove(&v4("10.0.0.0/8")), Some(1));
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.get(&v4("10.1.0.0/16")), Some(&2));
+        assert_eq!(trie.get(&v4("10.128.0.0/16")), Some(&3));
+        assert_eq!(trie.longest_match(&v4("10.200.0.1/32")), None);
+        
+        // 子が1つだけになった分岐点は取り除かれる
+        trie.remove(&v4("10.1.0.0/16"));
+        let root = trie.root.as_deref().unwrap();
+        assert_eq!((root.len, root.value), (16, Some(3)));
+    }
+    
+    #[test]
+    fn longest_match_with_default_and_host_routes() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(v4("0.0.0.0/0"), "default");
+        trie.insert(v4("10.0.0.0/8"), "ten");
+        trie.insert(v4("10.0.0.1/32"), "host");
+        
+        assert_eq!(trie.longest_match(&v4("10.0.0.1/32")), Some((v4("10.0.0.1/32"), &"host")));
+        assert_eq!(trie.longest_match(&v4("10.0.0.2/32")), Some((v4("10.0.0.0/8"), &"ten")));
+        assert_eq!(trie.longest_match(&v4("192.168.0.1/32")), Some((v4("0.0.0.0/0"), &"default")));
+        assert_eq!(trie.longest_match(&v4("0.0.0.0/0")), Some((v4("0.0.0.0/0"), &"default")));
+        assert_eq!(trie.get(&v4("0.0.0.0/0")), Some(&"default"));
+        assert_eq!(trie.get(&v4("10.0.0.1/32")), Some(&"host"));
+        
+        trie.remove(&v4("0.0.0.0/0"));
+        assert_eq!(trie.longest_match(&v4("192.168.0.1/32")), None);
+    }
+    
+    #[test]
+    fn ipv6_exact_and_longest_match() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(v6("::/0"), 0);
+        trie.insert(v6("2001:db8::/32"), 32);
+        trie.insert(v6("2001:db8::1/128"), 128);
+        trie.insert(v6("2001:db8::2/128"), 129);
+        
+        assert_eq!(trie.get(&v6("2001:db8::1/128")), Some(&128));
+        assert_eq!(trie.get(&v6("2001:db8::2/128")), Some(&129));
+        assert_eq!(trie.get(&v6("2001:db8::3/128")), None);
+        assert_eq!(trie.longest_match(&v6("2001:db8::1/128")).map(|(_, v)| *v), Some(128));
+        assert_eq!(trie.longest_match(&v6("2001:db8::3/128")).map(|(_, v)| *v), Some(32));
+        assert_eq!(trie.longest_match(&v6("fe80::1/128")).map(|(_, v)| *v), Some(0));
+        
+        assert_eq!(trie.remove(&v6("2001:db8::1/128")), Some(128));
+        assert_eq!(trie.longest_match(&v6("2001:db8::1/128")).map(|(_, v)| *v), Some(32));
+    }
+    
+    #[test]
+    fn covering_lists_parents_first() {
+        let mut trie = PrefixTrie::new();
+        // 挿入順によらず短い順になる
+        for prefix in ["10.1.2.0/24", "0.0.0.0/0", "10.1.0.0/16", "10.0.0.0/8", "10.2.0.0/16"] {
+            trie.insert(v4(prefix), ());
+        }
+        
+        assert_eq!(
+            prefixes(trie.covering(&v4("10.1.2.3/32"))),
+            vec![v4("0.0.0.0/0"), v4("10.0.0.0/8"), v4("10.1.0.0/16"), v4("10.1.2.0/24")]
+        );
+        // 自身も含む
+        assert_eq!(
+            prefixes(trie.covering(&v4("10.1.0.0/16"))),
+            vec![v4("0.0.0.0/0"), v4("10.0.0.0/8"), v4("10.1.0.0/16")]
+        );
+        assert_eq!(prefixes(trie.covering(&v4("192.168.0.0/16"))), vec![v4("0.0.0.0/0")]);
+    }
+    
+    #[test]
+    fn covered_lists_parents_before_children_in_address_order() {
+        let mut trie = PrefixTrie::new();
+        for prefix in ["10.2.0.0/16", "10.1.2.0/24", "10.1.0.0/16", "10.0.0.0/8", "192.168.0.0/16", "0.0.0.0/0"] {
+            trie.insert(v4(prefix), ());
+        }
+        
+        assert_eq!(
+            prefixes(trie.covered(&v4("10.0.0.0/8")).collect()),
+            vec![v4("10.0.0.0/8"), v4("10.1.0.0/16"), v4("10.1.2.0/24"), v4("10.2.0.0/16")]
+        );
+        // 登録されていないプレフィックスでも、含まれるものを列挙する
+        assert_eq!(
+            prefixes(trie.covered(&v4("10.0.0.0/15")).collect()),
+            vec![v4("10.1.0.0/16"), v4("10.1.2.0/24")]
+        );
+        assert_eq!(trie.covered(&v4("172.16.0.0/12")).count(), 0);
+        assert_eq!(trie.covered(&v4("0.0.0.0/0")).count(), trie.len());
+        assert_eq!(prefixes(trie.iter().collect()), prefixes(trie.covered(&v4("0.0.0.0/0")).collect()));
+    }
+}
@@ -0,0 +1,22 @@
+//! ベンダリングしたP4Runtime / googleapisの.protoから生成した型
+
+/// 生成元の.protoのファイルディスクリプタ（テキスト形式のパースに使用）
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("p4runtime_descriptor");
+
+pub mod p4 {
+    pub mod v1 {
+        tonic::include_proto!("p4.v1");
+    }
+    
+    pub mod config {
+        pub mod v1 {
+            tonic::include_proto!("p4.config.v1");
+        }
+    }
+}
+
+pub mod google {
+    pub mod rpc {
+        tonic::include_proto!("google.rpc");
+    }
+}
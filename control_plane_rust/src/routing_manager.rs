@@ -1,16 +1,26 @@
+use crate::prefix_trie::PrefixTrie;
 use crate::types::*;
 use anyhow::Result;
+use ipnet::{Ipv4Net, Ipv6Net};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
+/// プレフィックスごとの候補ルート（メトリック順）
+type RouteTable<P, R> = PrefixTrie<P, Vec<R>>;
+
 /// ルーティングテーブルマネージャー
 #[derive(Debug)]
 pub struct RoutingManager {
     /// ルーティングテーブル
-    routes: Arc<RwLock<Vec<RouteEntry>>>,
+    routes: Arc<RwLock<RouteTable<Ipv4Net, RouteEntry>>>,
+    /// IPv6ルーティングテーブル
+    ipv6_routes: Arc<RwLock<RouteTable<Ipv6Net, Ipv6RouteEntry>>>,
     /// ARPテーブル
     arp_table: Arc<RwLock<HashMap<Ipv4Address, ArpEntry>>>,
+    /// IPv6の近隣キャッシュ（NDP）
+    neighbor_table: Arc<RwLock<HashMap<Ipv6Address, NeighborEntry>>>,
     /// ポート情報
     ports: Arc<RwLock<HashMap<PortId, PortInfo>>>,
 }
@@ -18,80 +28,232 @@ pub struct RoutingManager {
 impl RoutingManager {
     pub fn new() -> Self {
         Self {
-            routes: Arc::new(RwLock::new(Vec::new())),
+            routes: Arc::new(RwLock::new(PrefixTrie::new())),
+            ipv6_routes: Arc::new(RwLock::new(PrefixTrie::new())),
             arp_table: Arc::new(RwLock::new(HashMap::new())),
+            neighbor_table: Arc::new(RwLock::new(HashMap::new())),
             ports: Arc::new(RwLock::new(HashMap::new())),
         }
     }
     
     /// ルートを追加
+    ///
+    /// 同じプレフィックスでもネクストホップかインターフェースが異なるルートは別の候補として保持し、
+    /// 同じ経路のルートは置き換える。
     pub async fn add_route(&self, route: RouteEntry) -> Result<()> {
+        let network = route
+            .prefix
+            .network(route.prefix_len)
+            .ok_or_else(|| P4RuntimeError::InvalidTableEntry(format!("Invalid prefix length {}", route.prefix_len)))?;
         let mut routes = self.routes.write().await;
+        let candidates = routes.get_or_insert_with(network, Vec::new);
         
-        // 既存のルートをチェック
-        if let Some(existing_index) = routes.iter().position(|r| 
-            r.prefix == route.prefix && r.prefix_len == route.prefix_len) {
-            routes[existing_index] = route.clone();
-            tracing::info!("Updated route: {}/{}", route.prefix, route.prefix_len);
+        // 既存の候補をチェック
+        if let Some(existing_index) = candidates.iter().position(|r| r.is_same_path(&route)) {
+            candidates[existing_index] = route.clone();
+            tracing::info!("Updated route: {}/{} via {}", route.prefix, route.prefix_len, route.describe_next_hop());
         } else {
-            routes.push(route.clone());
-            tracing::info!("Added route: {}/{}", route.prefix, route.prefix_len);
+            candidates.push(route.clone());
+            tracing::info!("Added route: {}/{} via {}", route.prefix, route.prefix_len, route.describe_next_hop());
         }
         
-        // メトリックでソート（低いメトリックが優先）
-        routes.sort_by(|a, b| a.metric.cmp(&b.metric));
+        // メトリックでソート（低いメトリックが優先、同じメトリックは先に追加した方が優先）
+        candidates.sort_by_key(|r| r.metric);
         
         Ok(())
     }
     
-    /// ルートを削除
-    pub async fn remove_route(&self, prefix: Ipv4Address, prefix_len: u8) -> Result<()> {
+    /// ルートを削除（ネクストホップを指定しなければプレフィックスの全候補を削除）し、削除した候補を返す
+    pub async fn remove_route(
+        &self,
+        prefix: Ipv4Address,
+        prefix_len: u8,
+        next_hop: Option<Ipv4Address>,
+    ) -> Result<Vec<RouteEntry>> {
         let mut routes = self.routes.write().await;
+        let mut removed = Vec::new();
         
-        if let Some(index) = routes.iter().position(|r| 
-            r.prefix == prefix && r.prefix_len == prefix_len) {
-            let removed_route = routes.remove(index);
-            tracing::info!("Removed route: {}/{}", removed_route.prefix, removed_route.prefix_len);
-        } else {
+        if let Some(network) = prefix.network(prefix_len) {
+            if let Some(candidates) = routes.get_mut(&network) {
+                let (matched, kept): (Vec<RouteEntry>, Vec<RouteEntry>) = candidates
+                    .drain(..)
+                    .partition(|r| next_hop.is_none_or(|next_hop| r.uses_next_hop(next_hop)));
+                *candidates = kept;
+                removed = matched;
+                if candidates.is_empty() {
+                    routes.remove(&network);
+                }
+            }
+        }
+        
+        if removed.is_empty() {
             tracing::warn!("Route {}/{} not found", prefix, prefix_len);
         }
+        for route in &removed {
+            tracing::info!("Removed route: {}/{} via {}", route.prefix, route.prefix_len, route.describe_next_hop());
+        }
         
-        Ok(())
+        Ok(removed)
     }
     
-    /// ルートを検索（最長プレフィックスマッチ）
+    /// ルートを検索（FIBに入る最良ルートの中で最長プレフィックスマッチ）
     pub async fn find_route(&self, dst_ip: Ipv4Address) -> Option<RouteEntry> {
         let routes = self.routes.read().await;
+        let ports = self.ports.read().await;
+        let arp_table = self.arp_table.read().await;
+        
+        // 長いプレフィックスから、転送できる候補があるものを探す
+        routes
+            .covering(&Ipv4Net::from(dst_ip.as_ipv4()))
+            .into_iter()
+            .rev()
+            .find_map(|(_, candidates)| select_best_route(candidates, &ports, &arp_table))
+    }
+    
+    /// プレフィックスを含むプレフィックス（自身を含む）の全候補を、短いプレフィックスから順に取得
+    pub async fn covering_routes(&self, prefix: Ipv4Address, prefix_len: u8) -> Vec<RouteEntry> {
+        let Some(network) = prefix.network(prefix_len) else { return Vec::new() };
+        let routes = self.routes.read().await;
+        routes.covering(&network).into_iter().flat_map(|(_, candidates)| candidates.iter().cloned()).collect()
+    }
+    
+    /// プレフィックスに含まれるプレフィックス（自身を含む）の全候補をアドレス順に取得
+    pub async fn covered_routes(&self, prefix: Ipv4Address, prefix_len: u8) -> Vec<RouteEntry> {
+        let Some(network) = prefix.network(prefix_len) else { return Vec::new() };
+        let routes = self.routes.read().await;
+        routes.covered(&network).flat_map(|(_, candidates)| candidates.iter().cloned()).collect()
+    }
+    
+    /// プレフィックスの候補ルートを優先順に取得
+    pub async fn get_route_candidates(&self, prefix: Ipv4Address, prefix_len: u8) -> Vec<RouteEntry> {
+        let Some(network) = prefix.network(prefix_len) else { return Vec::new() };
+        let routes = self.routes.read().await;
+        routes.get(&network).cloned().unwrap_or_default()
+    }
+    
+    /// プレフィックスの最良ルート（インストールできる候補のうちメトリックが最小のもの）を取得
+    pub async fn best_route(&self, prefix: Ipv4Address, prefix_len: u8) -> Option<RouteEntry> {
+        let network = prefix.network(prefix_len)?;
+        let routes = self.routes.read().await;
+        let ports = self.ports.read().await;
+        let arp_table = self.arp_table.read().await;
+        select_best_route(routes.get(&network)?, &ports, &arp_table)
+    }
+    
+    /// プレフィックスごとの最良ルート（FIBに入るルート）をアドレス順に取得
+    pub async fn best_routes(&self) -> Vec<RouteEntry> {
+        let routes = self.routes.read().await;
+        let ports = self.ports.read().await;
+        let arp_table = self.arp_table.read().await;
+        routes.values().filter_map(|candidates| select_best_route(candidates, &ports, &arp_table)).collect()
+    }
+    
+    /// 全ルート（全候補）をアドレス順に取得
+    pub async fn get_all_routes(&self) -> Vec<RouteEntry> {
+        let routes = self.routes.read().await;
+        routes.values().flatten().cloned().collect()
+    }
+    
+    /// IPv6ルートを追加（同じ経路のルートは置き換える）
+    pub async fn add_ipv6_route(&self, route: Ipv6RouteEntry) -> Result<()> {
+        let network = route
+            .prefix
+            .network(route.prefix_len)
+            .ok_or_else(|| P4RuntimeError::InvalidTableEntry(format!("Invalid prefix length {}", route.prefix_len)))?;
+        let mut routes = self.ipv6_routes.write().await;
+        let candidates = routes.get_or_insert_with(network, Vec::new);
         
-        let mut best_match: Option<RouteEntry> = None;
-        let mut best_prefix_len = 0;
+        if let Some(existing_index) = candidates.iter().position(|r| r.is_same_path(&route)) {
+            candidates[existing_index] = route.clone();
+            tracing::info!("Updated route: {}/{} via {}", route.prefix, route.prefix_len, route.describe_next_hop());
+        } else {
+            candidates.push(route.clone());
+            tracing::info!("Added route: {}/{} via {}", route.prefix, route.prefix_len, route.describe_next_hop());
+        }
         
-        for route in routes.iter() {
-            let prefix = route.prefix.as_u32();
-            let prefix_len = route.prefix_len;
-            let dst_ip_u32 = dst_ip.as_u32();
-            
-            // プレフィックスマスクを作成
-            let mask = if prefix_len == 0 {
-                0
-            } else {
-                !((1u32 << (32 - prefix_len)) - 1)
-            };
-            
-            // プレフィックスマッチをチェック
-            if (prefix & mask) == (dst_ip_u32 & mask) && prefix_len >= best_prefix_len {
-                best_match = Some(route.clone());
-                best_prefix_len = prefix_len;
+        candidates.sort_by_key(|r| r.metric);
+        
+        Ok(())
+    }
+    
+    /// IPv6ルートを削除（ネクストホップを指定しなければプレフィックスの全候補を削除）し、削除した候補を返す
+    pub async fn remove_ipv6_route(
+        &self,
+        prefix: Ipv6Address,
+        prefix_len: u8,
+        next_hop: Option<Ipv6Address>,
+    ) -> Result<Vec<Ipv6RouteEntry>> {
+        let mut routes = self.ipv6_routes.write().await;
+        let mut removed = Vec::new();
+        
+        if let Some(network) = prefix.network(prefix_len) {
+            if let Some(candidates) = routes.get_mut(&network) {
+                let (matched, kept): (Vec<Ipv6RouteEntry>, Vec<Ipv6RouteEntry>) = candidates
+                    .drain(..)
+                    .partition(|r| next_hop.is_none_or(|next_hop| r.next_hop == Some(next_hop)));
+                *candidates = kept;
+                removed = matched;
+                if candidates.is_empty() {
+                    routes.remove(&network);
+                }
             }
         }
         
-        best_match
+        if removed.is_empty() {
+            tracing::warn!("Route {}/{} not found", prefix, prefix_len);
+        }
+        for route in &removed {
+            tracing::info!("Removed route: {}/{} via {}", route.prefix, route.prefix_len, route.describe_next_hop());
+        }
+        
+        Ok(removed)
     }
     
-    /// 全ルートを取得
-    pub async fn get_all_routes(&self) -> Vec<RouteEntry> {
-        let routes = self.routes.read().await;
-        routes.clone()
+    /// IPv6ルートを検索（FIBに入る最良ルートの中で最長プレフィックスマッチ）
+    pub async fn find_ipv6_route(&self, dst_ip: Ipv6Address) -> Option<Ipv6RouteEntry> {
+        let routes = self.ipv6_routes.read().await;
+        let ports = self.ports.read().await;
+        
+        routes
+            .covering(&Ipv6Net::from(dst_ip.as_ipv6()))
+            .into_iter()
+            .rev()
+            .find_map(|(_, candidates)| select_best_ipv6_route(candidates, &ports))
+    }
+    
+    /// IPv6プレフィックスを含むプレフィックス（自身を含む）の全候補を、短いプレフィックスから順に取得
+    pub async fn covering_ipv6_routes(&self, prefix: Ipv6Address, prefix_len: u8) -> Vec<Ipv6RouteEntry> {
+        let Some(network) = prefix.network(prefix_len) else { return Vec::new() };
+        let routes = self.ipv6_routes.read().await;
+        routes.covering(&network).into_iter().flat_map(|(_, candidates)| candidates.iter().cloned()).collect()
+    }
+    
+    /// IPv6プレフィックスに含まれるプレフィックス（自身を含む）の全候補をアドレス順に取得
+    pub async fn covered_ipv6_routes(&self, prefix: Ipv6Address, prefix_len: u8) -> Vec<Ipv6RouteEntry> {
+        let Some(network) = prefix.network(prefix_len) else { return Vec::new() };
+        let routes = self.ipv6_routes.read().await;
+        routes.covered(&network).flat_map(|(_, candidates)| candidates.iter().cloned()).collect()
+    }
+    
+    /// IPv6プレフィックスの最良ルート（出力インターフェースがアップしている候補のうちメトリックが最小のもの）を取得
+    pub async fn best_ipv6_route(&self, prefix: Ipv6Address, prefix_len: u8) -> Option<Ipv6RouteEntry> {
+        let network = prefix.network(prefix_len)?;
+        let routes = self.ipv6_routes.read().await;
+        let ports = self.ports.read().await;
+        select_best_ipv6_route(routes.get(&network)?, &ports)
+    }
+    
+    /// IPv6プレフィックスごとの最良ルート（FIBに入るルート）をアドレス順に取得
+    pub async fn best_ipv6_routes(&self) -> Vec<Ipv6RouteEntry> {
+        let routes = self.ipv6_routes.read().await;
+        let ports = self.ports.read().await;
+        routes.values().filter_map(|candidates| select_best_ipv6_route(candidates, &ports)).collect()
+    }
+    
+    /// 全IPv6ルート（全候補）をアドレス順に取得
+    pub async fn get_all_ipv6_routes(&self) -> Vec<Ipv6RouteEntry> {
+        let routes = self.ipv6_routes.read().await;
+        routes.values().flatten().cloned().collect()
     }
     
     /// ARPエントリを追加
@@ -102,11 +264,51 @@ impl RoutingManager {
     }
     
     /// ARPエントリを削除
-    pub async fn remove_arp_entry(&self, ip: Ipv4Address) {
+    pub async fn remove_arp_entry(&self, ip: Ipv4Address) -> Option<ArpEntry> {
         let mut arp_table = self.arp_table.write().await;
-        if let Some(entry) = arp_table.remove(&ip) {
+        let entry = arp_table.remove(&ip);
+        if let Some(entry) = &entry {
             tracing::info!("Removed ARP entry: {} -> {}", entry.ip, entry.mac);
         }
+        entry
+    }
+    
+    /// ARPエントリの最終確認時刻を更新
+    pub async fn touch_arp_entry(&self, ip: Ipv4Address) -> bool {
+        let mut arp_table = self.arp_table.write().await;
+        if let Some(entry) = arp_table.get_mut(&ip) {
+            entry.last_seen = std::time::SystemTime::now();
+            true
+        } else {
+            false
+        }
+    }
+    
+    /// 一定時間確認できていない動的ARPエントリを取得
+    pub async fn stale_arp_entries(&self, reachable_time: Duration) -> Vec<ArpEntry> {
+        let arp_table = self.arp_table.read().await;
+        arp_table
+            .values()
+            .filter(|e| e.entry_type == ArpEntryType::Dynamic && e.age() >= reachable_time)
+            .cloned()
+            .collect()
+    }
+    
+    /// max_age以上確認できていない動的ARPエントリを削除して返す
+    pub async fn expire_arp_entries(&self, max_age: Duration) -> Vec<ArpEntry> {
+        let mut arp_table = self.arp_table.write().await;
+        let expired: Vec<ArpEntry> = arp_table
+            .values()
+            .filter(|e| e.entry_type == ArpEntryType::Dynamic && e.age() >= max_age)
+            .cloned()
+            .collect();
+        
+        for entry in &expired {
+            arp_table.remove(&entry.ip);
+            tracing::info!("Expired ARP entry: {} -> {}", entry.ip, entry.mac);
+        }
+        
+        expired
     }
     
     /// ARPエントリを検索
@@ -121,6 +323,46 @@ impl RoutingManager {
         arp_table.values().cloned().collect()
     }
     
+    /// 近隣キャッシュにエントリを追加
+    pub async fn add_neighbor_entry(&self, entry: NeighborEntry) {
+        let mut neighbor_table = self.neighbor_table.write().await;
+        neighbor_table.insert(entry.ip, entry.clone());
+        tracing::info!("Added neighbor entry: {} -> {}", entry.ip, entry.mac);
+    }
+    
+    /// 近隣キャッシュからエントリを削除
+    pub async fn remove_neighbor_entry(&self, ip: Ipv6Address) -> Option<NeighborEntry> {
+        let mut neighbor_table = self.neighbor_table.write().await;
+        let entry = neighbor_table.remove(&ip);
+        if let Some(entry) = &entry {
+            tracing::info!("Removed neighbor entry: {} -> {}", entry.ip, entry.mac);
+        }
+        entry
+    }
+    
+    /// 近隣キャッシュのエントリの最終確認時刻を更新
+    pub async fn touch_neighbor_entry(&self, ip: Ipv6Address) -> bool {
+        let mut neighbor_table = self.neighbor_table.write().await;
+        if let Some(entry) = neighbor_table.get_mut(&ip) {
+            entry.last_seen = std::time::SystemTime::now();
+            true
+        } else {
+            false
+        }
+    }
+    
+    /// 近隣キャッシュを検索
+    pub async fn find_neighbor_entry(&self, ip: Ipv6Address) -> Option<NeighborEntry> {
+        let neighbor_table = self.neighbor_table.read().await;
+        neighbor_table.get(&ip).cloned()
+    }
+    
+    /// 近隣キャッシュの全エントリを取得
+    pub async fn get_all_neighbor_entries(&self) -> Vec<NeighborEntry> {
+        let neighbor_table = self.neighbor_table.read().await;
+        neighbor_table.values().cloned().collect()
+    }
+    
     /// ポートを追加
     pub async fn add_port(&self, port: PortInfo) {
         let mut ports = self.ports.write().await;
@@ -148,22 +390,30 @@ impl RoutingManager {
         ports.values().cloned().collect()
     }
     
-    /// ポートの状態を更新
-    pub async fn update_port_status(&self, port_id: PortId, is_up: bool) -> Result<()> {
+    /// ポートの状態を更新（状態が変わった場合はtrue）
+    pub async fn update_port_status(&self, port_id: PortId, is_up: bool) -> Result<bool> {
         let mut ports = self.ports.write().await;
         if let Some(port) = ports.get_mut(&port_id) {
+            let changed = port.is_up != is_up;
             port.is_up = is_up;
             tracing::info!("Updated port {} status: {}", port_id, if is_up { "UP" } else { "DOWN" });
+            Ok(changed)
         } else {
             tracing::warn!("Port {} not found", port_id);
+            Ok(false)
         }
-        Ok(())
+    }
+    
+    /// インターフェースがリンクアップしているか（ポートとして登録されていなければアップとみなす）
+    pub async fn is_interface_up(&self, interface: &str) -> bool {
+        let ports = self.ports.read().await;
+        is_interface_up(&ports, interface)
     }
     
     /// ルーティングテーブルをクリア
     pub async fn clear_routes(&self) {
-        let mut routes = self.routes.write().await;
-        routes.clear();
+        self.routes.write().await.clear();
+        self.ipv6_routes.write().await.clear();
         tracing::info!("Cleared all routes");
     }
     
@@ -182,11 +432,23 @@ impl RoutingManager {
     }
     
     /// ルートをP4テーブルエントリに変換
+    ///
+    /// ECMPルートはアクションセレクターのグループとして書き込むため対象外（`resolve_ecmp_next_hops` を使う）。
     pub async fn convert_route_to_table_entry(
         &self,
         route: &RouteEntry,
         _device_id: DeviceId,
     ) -> Result<Option<TableEntry>> {
+        if route.is_ecmp() {
+            return Ok(None);
+        }
+        
+        // 出力ポートがダウンしているルートはインストールしない（より短いプレフィックスのルートに任せる）
+        if !self.is_interface_up(&route.interface).await {
+            tracing::debug!("Skipping route {}/{}: {} is down", route.prefix, route.prefix_len, route.interface);
+            return Ok(None);
+        }
+        
         // ネクストホップのMACアドレスを取得
         let next_hop_mac = if let Some(next_hop) = route.next_hop {
             if let Some(arp_entry) = self.find_arp_entry(next_hop).await {
@@ -210,14 +472,144 @@ impl RoutingManager {
             port: port_id,
         };
         
-        Ok(Some(TableEntry {
-            key: TableKey {
-                ipv4_dst: route.prefix,
-                prefix_len: route.prefix_len,
-            },
-            action,
-            priority: route.metric,
-        }))
+        let key = TableKey {
+            ipv4_dst: route.prefix,
+            prefix_len: route.prefix_len,
+        };
+        
+        // LPMテーブルは優先度を使わない（メトリックはRIBでの最良ルートの選択にだけ使う）
+        Ok(Some(TableEntry::ipv4_lpm(key, action, 0)))
+    }
+    
+    /// ECMPルートのネクストホップを、グループのメンバーにするアクションとARPが未解決のネクストホップに分ける
+    ///
+    /// ダウンしているインターフェースのネクストホップはどちらにも含めない。
+    pub async fn resolve_ecmp_next_hops(&self, route: &RouteEntry) -> (Vec<TableAction>, Vec<(Ipv4Address, String)>) {
+        let mut actions = Vec::new();
+        let mut unresolved = Vec::new();
+        
+        for next_hop in route.next_hops() {
+            if !self.is_interface_up(&next_hop.interface).await {
+                continue;
+            }
+            let Some(arp_entry) = self.find_arp_entry(next_hop.ip).await else {
+                unresolved.push((next_hop.ip, next_hop.interface));
+                continue;
+            };
+            
+            let port_id = self.get_port_id_by_interface(&next_hop.interface).await
+                .unwrap_or(1); // デフォルトポート
+            let action = TableAction::Ipv4Forward {
+                dst_mac: arp_entry.mac,
+                port: port_id,
+            };
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+        }
+        
+        (actions, unresolved)
+    }
+    
+    /// IPv6ルートをP4テーブルエントリに変換（インターフェースがダウンしているか、ネクストホップが未解決ならNone）
+    pub async fn convert_ipv6_route_to_table_entry(&self, route: &Ipv6RouteEntry) -> Option<TableEntry> {
+        if !self.is_interface_up(&route.interface).await {
+            tracing::debug!("Skipping route {}/{}: {} is down", route.prefix, route.prefix_len, route.interface);
+            return None;
+        }
+        
+        let next_hop_mac = if let Some(next_hop) = route.next_hop {
+            let Some(entry) = self.find_neighbor_entry(next_hop).await else {
+                tracing::warn!("No neighbor entry found for next hop {}", next_hop);
+                return None;
+            };
+            entry.mac
+        } else {
+            // 直接接続されたネットワーク（IPv4と同じくデフォルトゲートウェイのMACを使用）
+            MacAddress::new([0x08, 0x00, 0x00, 0x00, 0x00, 0x01])
+        };
+        
+        let port_id = self.get_port_id_by_interface(&route.interface).await
+            .unwrap_or(1); // デフォルトポート
+        
+        let key = Ipv6TableKey {
+            ipv6_dst: route.prefix,
+            prefix_len: route.prefix_len,
+        };
+        let action = TableAction::Ipv6Forward {
+            dst_mac: next_hop_mac,
+            port: port_id,
+        };
+        Some(TableEntry::ipv6_lpm(key, action, 0))
+    }
+    
+    /// IPv6プレフィックスごとの最良ルートをP4テーブルエントリに一括変換
+    pub async fn convert_all_ipv6_routes_to_table_entries(&self) -> Vec<TableEntry> {
+        let mut table_entries = Vec::new();
+        for route in self.best_ipv6_routes().await {
+            if let Some(entry) = self.convert_ipv6_route_to_table_entry(&route).await {
+                table_entries.push(entry);
+            }
+        }
+        table_entries
+    }
+    
+    /// IPv6ネクストホップを経由するルートを取得
+    pub async fn ipv6_routes_via_next_hop(&self, next_hop: Ipv6Address) -> Vec<Ipv6RouteEntry> {
+        let routes = self.ipv6_routes.read().await;
+        routes.values().flatten().filter(|r| r.next_hop == Some(next_hop)).cloned().collect()
+    }
+    
+    /// 近隣キャッシュにないIPv6ネクストホップとそのインターフェースを取得（ダウンしているインターフェースは除く）
+    pub async fn unresolved_ipv6_next_hops(&self) -> Vec<(Ipv6Address, String)> {
+        let routes = self.ipv6_routes.read().await;
+        let neighbor_table = self.neighbor_table.read().await;
+        let ports = self.ports.read().await;
+        let mut next_hops: Vec<(Ipv6Address, String)> = Vec::new();
+        
+        for route in routes.values().flatten() {
+            if !is_interface_up(&ports, &route.interface) {
+                continue;
+            }
+            if let Some(next_hop) = route.next_hop {
+                if !neighbor_table.contains_key(&next_hop) && !next_hops.iter().any(|(ip, _)| *ip == next_hop) {
+                    next_hops.push((next_hop, route.interface.clone()));
+                }
+            }
+        }
+        
+        next_hops
+    }
+    
+    /// インターフェース名からポートを取得
+    pub async fn find_port_by_interface(&self, interface: &str) -> Option<PortInfo> {
+        let ports = self.ports.read().await;
+        ports.values().find(|port| port.name == interface).cloned()
+    }
+    
+    /// ネクストホップを経由するルートを取得
+    pub async fn routes_via_next_hop(&self, next_hop: Ipv4Address) -> Vec<RouteEntry> {
+        let routes = self.routes.read().await;
+        routes.values().flatten().filter(|r| r.uses_next_hop(next_hop)).cloned().collect()
+    }
+    
+    /// ARPエントリがないネクストホップとそのインターフェースを取得（ダウンしているインターフェースは除く）
+    pub async fn unresolved_next_hops(&self) -> Vec<(Ipv4Address, String)> {
+        let routes = self.routes.read().await;
+        let arp_table = self.arp_table.read().await;
+        let ports = self.ports.read().await;
+        unresolved_next_hops(routes.values().flatten(), &ports, &arp_table)
+    }
+    
+    /// プレフィックスの候補のうち、ARPエントリがないネクストホップとそのインターフェースを取得
+    ///
+    /// 最良ルートに選ばれない候補も含めるので、解決すればより優先される候補に切り替えられる。
+    pub async fn unresolved_next_hops_of(&self, prefix: Ipv4Address, prefix_len: u8) -> Vec<(Ipv4Address, String)> {
+        let Some(network) = prefix.network(prefix_len) else { return Vec::new() };
+        let routes = self.routes.read().await;
+        let arp_table = self.arp_table.read().await;
+        let ports = self.ports.read().await;
+        unresolved_next_hops(routes.get(&network).into_iter().flatten(), &ports, &arp_table)
     }
     
     /// インターフェース名からポートIDを取得
@@ -231,12 +623,12 @@ impl RoutingManager {
         None
     }
     
-    /// ルーティングテーブルをP4テーブルエントリに一括変換
+    /// プレフィックスごとの最良ルートをP4テーブルエントリに一括変換（ECMPルートは含まない）
     pub async fn convert_all_routes_to_table_entries(
         &self,
         device_id: DeviceId,
     ) -> Result<Vec<TableEntry>> {
-        let routes = self.routes.read().await;
+        let routes = self.best_routes().await;
         let mut table_entries = Vec::new();
         
         for route in routes.iter() {
@@ -255,6 +647,63 @@ impl Default for RoutingManager {
     }
 }
 
+/// インターフェースがリンクアップしているか（ポートとして登録されていなければアップとみなす）
+fn is_interface_up(ports: &HashMap<PortId, PortInfo>, interface: &str) -> bool {
+    ports.values().find(|port| port.name == interface).is_none_or(|port| port.is_up)
+}
+
+/// ルートをインストールできるか（ECMPルートはいずれかのネクストホップが使えればよい）
+///
+/// ネクストホップはインターフェースがアップしていて、ARPが解決済みのときに使える。
+fn is_route_installable(
+    ports: &HashMap<PortId, PortInfo>,
+    arp_table: &HashMap<Ipv4Address, ArpEntry>,
+    route: &RouteEntry,
+) -> bool {
+    if route.next_hop.is_none() {
+        return is_interface_up(ports, &route.interface);
+    }
+    route
+        .next_hops()
+        .iter()
+        .any(|next_hop| is_interface_up(ports, &next_hop.interface) && arp_table.contains_key(&next_hop.ip))
+}
+
+/// メトリック順に並んだプレフィックスの候補から、最良ルートを選ぶ
+///
+/// ネクストホップのARPが未解決の候補は飛ばし、メトリックが大きくてもインストールできる候補を使う。
+fn select_best_route(
+    candidates: &[RouteEntry],
+    ports: &HashMap<PortId, PortInfo>,
+    arp_table: &HashMap<Ipv4Address, ArpEntry>,
+) -> Option<RouteEntry> {
+    candidates.iter().find(|r| is_route_installable(ports, arp_table, r)).cloned()
+}
+
+/// ルートのネクストホップのうちARPエントリがないものとそのインターフェース（ダウンしているインターフェースは除く）
+fn unresolved_next_hops<'a>(
+    routes: impl Iterator<Item = &'a RouteEntry>,
+    ports: &HashMap<PortId, PortInfo>,
+    arp_table: &HashMap<Ipv4Address, ArpEntry>,
+) -> Vec<(Ipv4Address, String)> {
+    let mut next_hops: Vec<(Ipv4Address, String)> = Vec::new();
+    for next_hop in routes.flat_map(|route| route.next_hops()) {
+        // ダウンしているインターフェースでは解決しない
+        if !is_interface_up(ports, &next_hop.interface) {
+            continue;
+        }
+        if !arp_table.contains_key(&next_hop.ip) && !next_hops.iter().any(|(ip, _)| *ip == next_hop.ip) {
+            next_hops.push((next_hop.ip, next_hop.interface));
+        }
+    }
+    next_hops
+}
+
+/// メトリック順に並んだIPv6プレフィックスの候補から、最良ルートを選ぶ
+fn select_best_ipv6_route(candidates: &[Ipv6RouteEntry], ports: &HashMap<PortId, PortInfo>) -> Option<Ipv6RouteEntry> {
+    candidates.iter().find(|r| is_interface_up(ports, &r.interface)).cloned()
+}
+
 /// ルーティングテーブルビルダー
 #[derive(Debug)]
 pub struct RouteBuilder {
@@ -263,6 +712,7 @@ pub struct RouteBuilder {
     next_hop: Option<Ipv4Address>,
     interface: Option<String>,
     metric: u32,
+    ecmp_next_hops: Vec<NextHop>,
 }
 
 impl RouteBuilder {
@@ -273,6 +723,7 @@ impl RouteBuilder {
             next_hop: None,
             interface: None,
             metric: 1,
+            ecmp_next_hops: Vec::new(),
         }
     }
     
@@ -301,10 +752,19 @@ impl RouteBuilder {
         self
     }
     
+    /// 等コストのネクストホップを追加（next_hop と合わせてECMPになる）
+    pub fn ecmp_next_hop(mut self, ip: Ipv4Address, interface: String) -> Self {
+        self.ecmp_next_hops.push(NextHop { ip, interface });
+        self
+    }
+    
     pub fn build(self) -> Result<RouteEntry> {
         let prefix = self.prefix.ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing prefix".to_string()))?;
         let prefix_len = self.prefix_len.ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing prefix length".to_string()))?;
         let interface = self.interface.ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing interface".to_string()))?;
+        if !self.ecmp_next_hops.is_empty() && self.next_hop.is_none() {
+            return Err(P4RuntimeError::InvalidTableEntry("ECMP route requires a next hop".to_string()).into());
+        }
         
         Ok(RouteEntry {
             prefix,
@@ -312,6 +772,7 @@ impl RouteBuilder {
             next_hop: self.next_hop,
             interface,
             metric: self.metric,
+            ecmp_next_hops: self.ecmp_next_hops,
         })
     }
 }
@@ -321,3 +782,114 @@ impl Default for RouteBuilder {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn ip(addr: [u8; 4]) -> Ipv4Address {
+        Ipv4Address::new(addr.into())
+    }
+    
+    fn route(prefix: [u8; 4], prefix_len: u8, next_hop: [u8; 4], interface: &str, metric: u32) -> RouteEntry {
+        RouteBuilder::new()
+            .prefix(ip(prefix))
+            .prefix_len(prefix_len)
+            .next_hop(ip(next_hop))
+            .interface(interface.to_string())
+            .metric(metric)
+            .build()
+            .unwrap()
+    }
+    
+    fn arp(next_hop: [u8; 4], interface: &str) -> ArpEntry {
+        ArpEntry::new_static(ip(next_hop), MacAddress::new([0x00, 0x11, 0x22, 0x33, 0x44, next_hop[3]]), interface)
+    }
+    
+    /// eth1・eth2がアップしているルーティングマネージャー
+    async fn manager() -> RoutingManager {
+        let manager = RoutingManager::new();
+        for (port_id, name) in [(1, "eth1"), (2, "eth2")] {
+            manager
+                .add_port(PortInfo {
+                    port_id,
+                    name: name.to_string(),
+                    mac_address: MacAddress::new([0x08, 0x00, 0x00, 0x00, 0x00, port_id as u8]),
+                    ip_address: None,
+                    is_up: true,
+                })
+                .await;
+        }
+        manager
+    }
+    
+    fn metrics(routes: &[RouteEntry]) -> Vec<u32> {
+        routes.iter().map(|route| route.metric).collect()
+    }
+    
+    #[tokio::test]
+    async fn candidates_are_ordered_by_metric() {
+        let manager = manager().await;
+        for (next_hop, metric) in [([192, 168, 1, 3], 30), ([192, 168, 1, 1], 10), ([192, 168, 1, 2], 20)] {
+            manager.add_arp_entry(arp(next_hop, "eth1")).await;
+            manager.add_route(route([10, 0, 0, 0], 8, next_hop, "eth1", metric)).await.unwrap();
+        }
+        
+        let candidates = manager.get_route_candidates(ip([10, 0, 0, 0]), 8).await;
+        assert_eq!(metrics(&candidates), [10, 20, 30]);
+        let best = manager.best_route(ip([10, 0, 0, 0]), 8).await.unwrap();
+        assert_eq!(best.next_hop, Some(ip([192, 168, 1, 1])));
+        assert_eq!(manager.find_route(ip([10, 1, 2, 3])).await, Some(best));
+    }
+    
+    #[tokio::test]
+    async fn route_with_the_same_next_hop_replaces_the_candidate() {
+        let manager = manager().await;
+        manager.add_route(route([10, 0, 0, 0], 8, [192, 168, 1, 1], "eth1", 10)).await.unwrap();
+        manager.add_route(route([10, 0, 0, 0], 8, [192, 168, 1, 2], "eth1", 20)).await.unwrap();
+        manager.add_route(route([10, 0, 0, 0], 8, [192, 168, 1, 1], "eth1", 30)).await.unwrap();
+        
+        let candidates = manager.get_route_candidates(ip([10, 0, 0, 0]), 8).await;
+        assert_eq!(metrics(&candidates), [20, 30]);
+        assert_eq!(candidates[1].next_hop, Some(ip([192, 168, 1, 1])));
+    }
+    
+    #[tokio::test]
+    async fn removing_the_winner_promotes_the_runner_up() {
+        let manager = manager().await;
+        for (next_hop, interface, metric) in [([192, 168, 1, 1], "eth1", 10), ([192, 168, 2, 1], "eth2", 20)] {
+            manager.add_arp_entry(arp(next_hop, interface)).await;
+            manager.add_route(route([10, 0, 0, 0], 8, next_hop, interface, metric)).await.unwrap();
+        }
+        
+        let removed = manager.remove_route(ip([10, 0, 0, 0]), 8, Some(ip([192, 168, 1, 1]))).await.unwrap();
+        assert_eq!(metrics(&removed), [10]);
+        let best = manager.best_route(ip([10, 0, 0, 0]), 8).await.unwrap();
+        assert_eq!(best.next_hop, Some(ip([192, 168, 2, 1])));
+        
+        manager.remove_route(ip([10, 0, 0, 0]), 8, None).await.unwrap();
+        assert_eq!(manager.best_route(ip([10, 0, 0, 0]), 8).await, None);
+        assert!(manager.get_route_candidates(ip([10, 0, 0, 0]), 8).await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn unresolved_candidate_is_skipped_until_its_arp_resolves() {
+        let manager = manager().await;
+        manager.add_route(route([10, 0, 0, 0], 8, [192, 168, 1, 1], "eth1", 10)).await.unwrap();
+        manager.add_route(route([10, 0, 0, 0], 8, [192, 168, 2, 1], "eth2", 20)).await.unwrap();
+        manager.add_arp_entry(arp([192, 168, 2, 1], "eth2")).await;
+        
+        // メトリックの小さい候補はARPが未解決なのでインストールできず、解決を要求する
+        let best = manager.best_route(ip([10, 0, 0, 0]), 8).await.unwrap();
+        assert_eq!(best.next_hop, Some(ip([192, 168, 2, 1])));
+        assert_eq!(
+            manager.unresolved_next_hops_of(ip([10, 0, 0, 0]), 8).await,
+            [(ip([192, 168, 1, 1]), "eth1".to_string())]
+        );
+        
+        manager.add_arp_entry(arp([192, 168, 1, 1], "eth1")).await;
+        let best = manager.best_route(ip([10, 0, 0, 0]), 8).await.unwrap();
+        assert_eq!(best.next_hop, Some(ip([192, 168, 1, 1])));
+        assert!(manager.unresolved_next_hops_of(ip([10, 0, 0, 0]), 8).await.is_empty());
+    }
+}
@@ -1,30 +1,98 @@
+use crate::codec;
+use crate::prefix_trie::PrefixTrie;
 use crate::types::*;
 use anyhow::Result;
-use std::collections::HashMap;
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+/// デバイスごとのテーブル名 -> エントリ一覧
+type DeviceTables = HashMap<DeviceId, HashMap<String, Vec<TableEntry>>>;
+
+/// 高水位警告のデフォルトのしきい値（%）
+pub const DEFAULT_HIGH_WATERMARK_PERCENT: u8 = 90;
+
+/// LPMテーブルのエントリをプレフィックスで引くための索引
+#[derive(Debug, Default)]
+struct LpmIndex {
+    ipv4: PrefixTrie<Ipv4Net, TableEntry>,
+    ipv6: PrefixTrie<Ipv6Net, TableEntry>,
+}
+
+impl LpmIndex {
+    /// IPv4 / IPv6 LPMテーブルのエントリであれば索引に登録（同じプレフィックスは置き換える）
+    fn insert(&mut self, entry: &TableEntry) {
+        if let Some(key) = entry.ipv4_lpm_key() {
+            if let Some(network) = key.ipv4_dst.network(key.prefix_len) {
+                self.ipv4.insert(network, entry.clone());
+            }
+        } else if let Some(key) = entry.ipv6_lpm_key() {
+            if let Some(network) = key.ipv6_dst.network(key.prefix_len) {
+                self.ipv6.insert(network, entry.clone());
+            }
+        }
+    }
+    
+    /// IPv4 / IPv6 LPMテーブルのエントリであれば索引から削除
+    fn remove(&mut self, entry: &TableEntry) {
+        if let Some(key) = entry.ipv4_lpm_key() {
+            if let Some(network) = key.ipv4_dst.network(key.prefix_len) {
+                self.ipv4.remove(&network);
+            }
+        } else if let Some(key) = entry.ipv6_lpm_key() {
+            if let Some(network) = key.ipv6_dst.network(key.prefix_len) {
+                self.ipv6.remove(&network);
+            }
+        }
+    }
+}
+
 /// テーブルエントリマネージャー
 #[derive(Debug)]
 pub struct TableManager {
     /// デバイスごとのテーブルエントリ
-    device_tables: Arc<RwLock<HashMap<DeviceId, HashMap<String, Vec<TableEntry>>>>>,
+    device_tables: Arc<RwLock<DeviceTables>>,
+    /// デバイスごとのLPMテーブルの索引（`device_tables` と同時に更新する）
+    lpm_indexes: Arc<RwLock<HashMap<DeviceId, LpmIndex>>>,
     /// テーブル名のマッピング
     table_names: Arc<RwLock<HashMap<String, String>>>,
+    /// デバイスごとのP4Info
+    p4infos: Arc<RwLock<HashMap<DeviceId, P4Info>>>,
+    /// 高水位警告のしきい値（容量に対する%）
+    high_watermark: Arc<RwLock<u8>>,
+    /// 高水位を超えて警告済みの（デバイス, テーブル）
+    watermark_alerts: Arc<RwLock<HashSet<(DeviceId, String)>>>,
 }
 
 impl TableManager {
     pub fn new() -> Self {
         Self {
             device_tables: Arc::new(RwLock::new(HashMap::new())),
+            lpm_indexes: Arc::new(RwLock::new(HashMap::new())),
             table_names: Arc::new(RwLock::new(HashMap::new())),
+            p4infos: Arc::new(RwLock::new(HashMap::new())),
+            high_watermark: Arc::new(RwLock::new(DEFAULT_HIGH_WATERMARK_PERCENT)),
+            watermark_alerts: Arc::new(RwLock::new(HashSet::new())),
         }
     }
     
+    /// 高水位警告のしきい値を設定（容量に対する%、100を超える値は100として扱う）
+    pub async fn set_high_watermark(&self, percent: u8) {
+        *self.high_watermark.write().await = percent.min(100);
+        self.watermark_alerts.write().await.clear();
+    }
+    
+    /// 高水位警告のしきい値を取得
+    pub async fn high_watermark(&self) -> u8 {
+        *self.high_watermark.read().await
+    }
+    
     /// デバイスのテーブルを初期化
     pub async fn initialize_device_tables(&self, device_id: DeviceId) {
         let mut tables = self.device_tables.write().await;
         tables.insert(device_id, HashMap::new());
+        self.lpm_indexes.write().await.insert(device_id, LpmIndex::default());
         tracing::info!("Initialized tables for device {}", device_id);
     }
     
@@ -34,46 +102,216 @@ impl TableManager {
         names.insert(table_name.to_string(), p4_table_name.to_string());
     }
     
-    /// IPv4 LPMテーブルにエントリを追加
-    pub async fn add_ipv4_lpm_entry(
-        &self,
-        device_id: DeviceId,
-        prefix: Ipv4Address,
-        prefix_len: u8,
-        action: TableAction,
-        priority: u32,
-    ) -> Result<()> {
-        let key = TableKey {
-            ipv4_dst: prefix,
-            prefix_len,
-        };
+    /// デバイスのP4Infoを設定し、テーブルの短縮名を登録
+    pub async fn set_device_p4info(&self, device_id: DeviceId, p4info: P4Info) {
+        for table_name in p4info.tables.keys() {
+            let short_name = table_name.rsplit('.').next().unwrap_or(table_name);
+            self.register_table_name(short_name, table_name).await;
+        }
         
-        let entry = TableEntry {
-            key,
-            action,
-            priority,
-        };
+        let mut p4infos = self.p4infos.write().await;
+        p4infos.insert(device_id, p4info);
+    }
+    
+    /// 登録済みの短縮名をP4のテーブル名に変換（未登録ならそのまま）
+    async fn canonical_table_name(&self, table_name: &str) -> String {
+        let names = self.table_names.read().await;
+        names.get(table_name).cloned().unwrap_or_else(|| table_name.to_string())
+    }
+    
+    /// テーブル名（"ipv4_lpm" や "MyIngress.ipv4_lpm"）をP4RuntimeのテーブルIDに解決
+    pub async fn resolve_table_id(&self, device_id: DeviceId, table_name: &str) -> Result<u32> {
+        let p4_table_name = self.canonical_table_name(table_name).await;
+        
+        let p4infos = self.p4infos.read().await;
+        let p4info = p4infos
+            .get(&device_id)
+            .ok_or(P4RuntimeError::P4InfoNotLoaded { device_id })?;
+        
+        p4info
+            .table_id(&p4_table_name)
+            .ok_or_else(|| P4RuntimeError::TableNotFound { table_name: table_name.to_string() }.into())
+    }
+    
+    /// テーブルにエントリを追加し、デバイスに送るべき更新を返す
+    ///
+    /// 新しいキーならINSERT、同じキーでアクションが異なればMODIFY、同一ならNone（書き込み不要）。
+    /// 新しいキーでテーブルがP4Infoの `size` に達していれば、デバイスに送る前に `TableFull` エラーにする。
+    /// 拒否したエントリは保留せず、空きができても自動では追加しない。
+    pub async fn add_entry(&self, device_id: DeviceId, entry: TableEntry) -> Result<Option<TableUpdate>> {
+        Ok(self.stage_entry(device_id, entry).await?.map(|staged| staged.update))
+    }
+    
+    /// `add_entry` と同じくエントリを追加し、書き込みに失敗したときに `revert_update` で戻せる形で返す
+    pub async fn stage_entry(&self, device_id: DeviceId, mut entry: TableEntry) -> Result<Option<StagedUpdate>> {
+        entry.table = self.canonical_table_name(&entry.table).await;
+        
+        // P4Infoが読み込まれていればスキーマに照らして検証し、値を正規形に揃える
+        let mut capacity = 0;
+        if let Some(p4info) = self.p4infos.read().await.get(&device_id) {
+            validate_entry(p4info, &entry)?;
+            canonicalize_entry(p4info, &mut entry)?;
+            capacity = p4info.find_table(&entry.table).map_or(0, |table| table.size);
+        }
         
         let mut tables = self.device_tables.write().await;
         if let Some(device_tables) = tables.get_mut(&device_id) {
-            let table_name = "ipv4_lpm".to_string();
-            let table_entries = device_tables.entry(table_name).or_insert_with(Vec::new);
+            let table_entries = device_tables.entry(entry.table.clone()).or_default();
             
             // 既存のエントリをチェックして重複を避ける
-            if let Some(existing_index) = table_entries.iter().position(|e| e.key == entry.key) {
-                table_entries[existing_index] = entry.clone();
-                tracing::info!("Updated existing LPM entry for {} on device {}", prefix, device_id);
+            if let Some(existing_index) = table_entries.iter().position(|e| e.has_same_key(&entry)) {
+                if table_entries[existing_index].action.is_equivalent(&entry.action) {
+                    tracing::debug!("Entry in {} on device {} is unchanged", entry.table, device_id);
+                    return Ok(None);
+                }
+                tracing::info!("Updated existing entry in {} on device {}", entry.table, device_id);
+                let previous = std::mem::replace(&mut table_entries[existing_index], entry.clone());
+                self.index_entry(device_id, &entry).await;
+                Ok(Some(StagedUpdate {
+                    update: TableUpdate::modify(entry),
+                    previous: Some(previous),
+                }))
             } else {
+                if capacity > 0 && table_entries.len() as u64 >= capacity {
+                    tracing::warn!("Rejected new entry for {} on device {}: table is full", entry.table, device_id);
+                    return Err(P4RuntimeError::TableFull {
+                        device_id,
+                        table_name: entry.table.clone(),
+                        capacity,
+                    }
+                    .into());
+                }
+                tracing::info!("Added new entry to {} on device {}", entry.table, device_id);
                 table_entries.push(entry.clone());
-                tracing::info!("Added new LPM entry for {} on device {}", prefix, device_id);
+                let used = table_entries.len();
+                self.index_entry(device_id, &entry).await;
+                self.update_watermark(device_id, &entry.table, used, capacity).await;
+                Ok(Some(StagedUpdate {
+                    update: TableUpdate::insert(entry),
+                    previous: None,
+                }))
             }
         } else {
-            return Err(P4RuntimeError::DeviceNotFound { device_id }.into());
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
         }
-        
+    }
+    
+    /// デバイスに書き込めなかった更新をシャドウから取り消す
+    ///
+    /// INSERTしたエントリは削除し、MODIFY・DELETEしたエントリは更新前のエントリに戻す。
+    pub async fn revert_update(&self, device_id: DeviceId, staged: &StagedUpdate) -> Result<()> {
+        match (&staged.update.update_type, &staged.previous) {
+            (UpdateType::Insert, _) => {
+                self.remove_entry(device_id, &staged.update.entry).await?;
+            }
+            (_, Some(previous)) => {
+                self.restore_entry(device_id, previous.clone()).await?;
+            }
+            (_, None) => {
+                self.restore_entry(device_id, staged.update.entry.clone()).await?;
+            }
+        }
+        tracing::warn!(
+            "Reverted {} of {} entry on device {} after a failed write",
+            staged.update.update_type,
+            staged.update.entry.table,
+            device_id
+        );
+        Ok(())
+    }
+    
+    /// 書き込めなかったMODIFY・DELETEの前のエントリをシャドウに戻す
+    ///
+    /// デバイスには残っているエントリなので、その間に他の書き込みで満杯になっていても戻し、高水位の判定もしない。
+    async fn restore_entry(&self, device_id: DeviceId, entry: TableEntry) -> Result<()> {
+        let mut tables = self.device_tables.write().await;
+        let device_tables = tables.get_mut(&device_id).ok_or(P4RuntimeError::DeviceNotFound { device_id })?;
+        let table_entries = device_tables.entry(entry.table.clone()).or_default();
+        match table_entries.iter().position(|e| e.has_same_key(&entry)) {
+            Some(index) => table_entries[index] = entry.clone(),
+            None => table_entries.push(entry.clone()),
+        }
+        self.index_entry(device_id, &entry).await;
         Ok(())
     }
     
+    /// キーが一致するエントリを削除して返す
+    pub async fn remove_entry(&self, device_id: DeviceId, key: &TableEntry) -> Result<Option<TableEntry>> {
+        let table_name = self.canonical_table_name(&key.table).await;
+        let mut key = key.clone();
+        if let Some(p4info) = self.p4infos.read().await.get(&device_id) {
+            canonicalize_entry(p4info, &mut key)?;
+        }
+        
+        let mut tables = self.device_tables.write().await;
+        if let Some(device_tables) = tables.get_mut(&device_id) {
+            if let Some(table_entries) = device_tables.get_mut(&table_name) {
+                if let Some(index) = table_entries.iter().position(|e| e.has_same_key(&key)) {
+                    tracing::info!("Removed entry from {} on device {}", table_name, device_id);
+                    let removed = table_entries.remove(index);
+                    let used = table_entries.len();
+                    if let Some(index) = self.lpm_indexes.write().await.get_mut(&device_id) {
+                        index.remove(&removed);
+                    }
+                    let capacity = self.table_capacity(device_id, &table_name).await;
+                    self.update_watermark(device_id, &table_name, used, capacity).await;
+                    return Ok(Some(removed));
+                }
+            }
+            tracing::warn!("Entry not found in {} on device {}", table_name, device_id);
+            Ok(None)
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
+    /// キーが一致するエントリを取得
+    pub async fn get_entry(&self, device_id: DeviceId, key: &TableEntry) -> Result<Option<TableEntry>> {
+        let table_name = self.canonical_table_name(&key.table).await;
+        let mut key = key.clone();
+        if let Some(p4info) = self.p4infos.read().await.get(&device_id) {
+            canonicalize_entry(p4info, &mut key)?;
+        }
+        
+        let tables = self.device_tables.read().await;
+        if let Some(device_tables) = tables.get(&device_id) {
+            Ok(device_tables
+                .get(&table_name)
+                .and_then(|entries| entries.iter().find(|e| e.has_same_key(&key)))
+                .cloned())
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
+    /// デバイスの特定テーブルのエントリを取得
+    pub async fn get_entries(&self, device_id: DeviceId, table_name: &str) -> Result<Vec<TableEntry>> {
+        let table_name = self.canonical_table_name(table_name).await;
+        
+        let tables = self.device_tables.read().await;
+        if let Some(device_tables) = tables.get(&device_id) {
+            Ok(device_tables.get(&table_name).cloned().unwrap_or_default())
+        } else {
+            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
+        }
+    }
+    
+    /// IPv4 LPMテーブルにエントリを追加
+    pub async fn add_ipv4_lpm_entry(
+        &self,
+        device_id: DeviceId,
+        prefix: Ipv4Address,
+        prefix_len: u8,
+        action: TableAction,
+    ) -> Result<Option<TableUpdate>> {
+        let key = TableKey {
+            ipv4_dst: prefix,
+            prefix_len,
+        };
+        
+        self.add_entry(device_id, TableEntry::ipv4_lpm(key, action, 0)).await
+    }
+    
     /// IPv4 LPMテーブルからエントリを削除
     pub async fn remove_ipv4_lpm_entry(
         &self,
@@ -86,40 +324,60 @@ impl TableManager {
             prefix_len,
         };
         
-        let mut tables = self.device_tables.write().await;
-        if let Some(device_tables) = tables.get_mut(&device_id) {
-            if let Some(table_entries) = device_tables.get_mut("ipv4_lpm") {
-                if let Some(index) = table_entries.iter().position(|e| e.key == key) {
-                    table_entries.remove(index);
-                    tracing::info!("Removed LPM entry for {} from device {}", prefix, device_id);
-                } else {
-                    tracing::warn!("LPM entry for {} not found on device {}", prefix, device_id);
-                }
-            }
-        } else {
-            return Err(P4RuntimeError::DeviceNotFound { device_id }.into());
-        }
-        
+        self.remove_entry(device_id, &TableEntry::ipv4_lpm(key, TableAction::Drop, 0)).await?;
         Ok(())
     }
     
+    /// ABACの分類テーブル（abac_subject / abac_object / abac_service）にエントリを追加
+    pub async fn add_abac_classifier(&self, device_id: DeviceId, classifier: &AbacClassifier) -> Result<Option<StagedUpdate>> {
+        self.stage_entry(device_id, classifier.to_table_entry()).await
+    }
+    
+    /// ABACの分類テーブルからエントリを削除
+    pub async fn remove_abac_classifier(&self, device_id: DeviceId, classifier: &AbacClassifier) -> Result<Option<TableEntry>> {
+        self.remove_entry(device_id, &classifier.to_table_entry()).await
+    }
+    
+    /// ABACポリシーテーブルにエントリを追加
+    pub async fn add_abac_policy_entry(
+        &self,
+        device_id: DeviceId,
+        subject: AttributeMatch,
+        object: AttributeMatch,
+        action: AbacAction,
+        priority: u32,
+    ) -> Result<Option<TableUpdate>> {
+        self.add_entry(device_id, TableEntry::abac_policy(subject, object, action, priority)).await
+    }
+    
+    /// デバイスのABACポリシーテーブルのエントリを取得
+    pub async fn get_abac_policy_entries(&self, device_id: DeviceId) -> Result<Vec<TableEntry>> {
+        self.get_entries(device_id, ABAC_POLICY_TABLE).await
+    }
+    
     /// デバイスのIPv4 LPMテーブルエントリを取得
     pub async fn get_ipv4_lpm_entries(&self, device_id: DeviceId) -> Result<Vec<TableEntry>> {
-        let tables = self.device_tables.read().await;
-        if let Some(device_tables) = tables.get(&device_id) {
-            Ok(device_tables.get("ipv4_lpm").cloned().unwrap_or_default())
-        } else {
-            Err(P4RuntimeError::DeviceNotFound { device_id }.into())
-        }
+        self.get_entries(device_id, IPV4_LPM_TABLE).await
     }
     
     /// 全デバイスのIPv4 LPMテーブルエントリを取得
     pub async fn get_all_ipv4_lpm_entries(&self) -> HashMap<DeviceId, Vec<TableEntry>> {
+        self.get_all_devices_entries(IPV4_LPM_TABLE).await
+    }
+    
+    /// 全デバイスのIPv6 LPMテーブルエントリを取得
+    pub async fn get_all_ipv6_lpm_entries(&self) -> HashMap<DeviceId, Vec<TableEntry>> {
+        self.get_all_devices_entries(IPV6_LPM_TABLE).await
+    }
+    
+    /// 全デバイスの特定テーブルのエントリを取得
+    async fn get_all_devices_entries(&self, table_name: &str) -> HashMap<DeviceId, Vec<TableEntry>> {
+        let table_name = self.canonical_table_name(table_name).await;
         let tables = self.device_tables.read().await;
         let mut result = HashMap::new();
         
         for (device_id, device_tables) in tables.iter() {
-            if let Some(entries) = device_tables.get("ipv4_lpm") {
+            if let Some(entries) = device_tables.get(&table_name) {
                 result.insert(*device_id, entries.clone());
             }
         }
@@ -142,6 +400,8 @@ impl TableManager {
         let mut tables = self.device_tables.write().await;
         if let Some(device_tables) = tables.get_mut(&device_id) {
             device_tables.clear();
+            self.lpm_indexes.write().await.insert(device_id, LpmIndex::default());
+            self.watermark_alerts.write().await.retain(|(id, _)| *id != device_id);
             tracing::info!("Cleared all tables for device {}", device_id);
         } else {
             return Err(P4RuntimeError::DeviceNotFound { device_id }.into());
@@ -153,40 +413,124 @@ impl TableManager {
     pub async fn remove_device(&self, device_id: DeviceId) {
         let mut tables = self.device_tables.write().await;
         tables.remove(&device_id);
+        self.lpm_indexes.write().await.remove(&device_id);
+        self.p4infos.write().await.remove(&device_id);
+        self.watermark_alerts.write().await.retain(|(id, _)| *id != device_id);
         tracing::info!("Removed device {} from table manager", device_id);
     }
     
-    /// テーブルエントリの検索（最長プレフィックスマッチ）
+    /// IPv4 LPMテーブルのエントリを最長プレフィックスマッチで検索
     pub async fn find_lpm_entry(
         &self,
         device_id: DeviceId,
         dst_ip: Ipv4Address,
     ) -> Result<Option<TableEntry>> {
-        let entries = self.get_ipv4_lpm_entries(device_id).await?;
+        let indexes = self.lpm_indexes.read().await;
+        let index = indexes.get(&device_id).ok_or(P4RuntimeError::DeviceNotFound { device_id })?;
+        Ok(index.ipv4.longest_match(&Ipv4Net::from(dst_ip.as_ipv4())).map(|(_, entry)| entry.clone()))
+    }
+    
+    /// IPv6 LPMテーブルのエントリを最長プレフィックスマッチで検索
+    pub async fn find_ipv6_lpm_entry(
+        &self,
+        device_id: DeviceId,
+        dst_ip: Ipv6Address,
+    ) -> Result<Option<TableEntry>> {
+        let indexes = self.lpm_indexes.read().await;
+        let index = indexes.get(&device_id).ok_or(P4RuntimeError::DeviceNotFound { device_id })?;
+        Ok(index.ipv6.longest_match(&Ipv6Net::from(dst_ip.as_ipv6())).map(|(_, entry)| entry.clone()))
+    }
+    
+    /// LPMテーブルのエントリを索引に登録
+    async fn index_entry(&self, device_id: DeviceId, entry: &TableEntry) {
+        if let Some(index) = self.lpm_indexes.write().await.get_mut(&device_id) {
+            index.insert(entry);
+        }
+    }
+    
+    /// P4Infoのテーブルサイズ（P4Infoがない、またはサイズが不明なら0）
+    async fn table_capacity(&self, device_id: DeviceId, table_name: &str) -> u64 {
+        let p4infos = self.p4infos.read().await;
+        p4infos
+            .get(&device_id)
+            .and_then(|p4info| p4info.find_table(table_name))
+            .map_or(0, |table| table.size)
+    }
+    
+    /// 使用量が高水位を超えたら一度だけ警告し、下回ったら再び警告できるようにする
+    async fn update_watermark(&self, device_id: DeviceId, table_name: &str, used: usize, capacity: u64) {
+        if capacity == 0 {
+            return;
+        }
+        let percent = *self.high_watermark.read().await as u64;
+        let key = (device_id, table_name.to_string());
+        let mut alerts = self.watermark_alerts.write().await;
         
-        let mut best_match: Option<TableEntry> = None;
-        let mut best_prefix_len = 0;
+        if used as u64 * 100 >= capacity * percent {
+            if alerts.insert(key) {
+                tracing::warn!(
+                    "Table {} on device {} is above the high watermark: {}/{} entries ({}% threshold)",
+                    table_name,
+                    device_id,
+                    used,
+                    capacity,
+                    percent
+                );
+            }
+        } else if alerts.remove(&key) {
+            tracing::info!(
+                "Table {} on device {} is back below the high watermark: {}/{} entries",
+                table_name,
+                device_id,
+                used,
+                capacity
+            );
+        }
+    }
+    
+    /// シャドウのエントリとデバイスから読み出したエントリを比較
+    pub async fn diff_device_entries(&self, device_id: DeviceId, device_entries: &[TableEntry]) -> Result<TableDrift> {
+        let shadow: Vec<TableEntry> = self
+            .get_all_device_entries(device_id)
+            .await?
+            .into_values()
+            .flatten()
+            .collect();
         
-        for entry in entries {
-            let prefix = entry.key.ipv4_dst.as_u32();
-            let prefix_len = entry.key.prefix_len;
-            let dst_ip_u32 = dst_ip.as_u32();
-            
-            // プレフィックスマスクを作成
-            let mask = if prefix_len == 0 {
-                0
-            } else {
-                !((1u32 << (32 - prefix_len)) - 1)
-            };
+        let mut drift = TableDrift {
+            device_id,
+            ..Default::default()
+        };
+        let mut matched = vec![false; device_entries.len()];
+        
+        for expected in shadow {
+            let found = device_entries
+                .iter()
+                .enumerate()
+                .find(|(i, actual)| !matched[*i] && actual.has_same_key(&expected));
             
-            // プレフィックスマッチをチェック
-            if (prefix & mask) == (dst_ip_u32 & mask) && prefix_len >= best_prefix_len {
-                best_match = Some(entry);
-                best_prefix_len = prefix_len;
+            match found {
+                Some((i, actual)) => {
+                    matched[i] = true;
+                    if !actual.action.is_equivalent(&expected.action) {
+                        drift.modified.push(ModifiedEntry {
+                            expected,
+                            actual: actual.clone(),
+                        });
+                    }
+                }
+                None => drift.missing.push(expected),
             }
         }
         
-        Ok(best_match)
+        drift.extra = device_entries
+            .iter()
+            .zip(&matched)
+            .filter(|(_, matched)| !**matched)
+            .map(|(entry, _)| entry.clone())
+            .collect();
+        
+        Ok(drift)
     }
     
     /// テーブル統計情報を取得
@@ -202,6 +546,38 @@ impl TableManager {
             Err(P4RuntimeError::DeviceNotFound { device_id }.into())
         }
     }
+    
+    /// テーブルごとの使用量と容量を取得（P4Infoの全テーブルとシャドウにあるテーブル、名前順）
+    pub async fn get_table_usage(&self, device_id: DeviceId) -> Result<Vec<TableUsage>> {
+        let mut usage: HashMap<String, TableUsage> = HashMap::new();
+        
+        if let Some(p4info) = self.p4infos.read().await.get(&device_id) {
+            for table in p4info.tables.values() {
+                usage.insert(
+                    table.name.clone(),
+                    TableUsage {
+                        table: table.name.clone(),
+                        used: 0,
+                        capacity: table.size,
+                    },
+                );
+            }
+        }
+        for (table_name, used) in self.get_table_statistics(device_id).await? {
+            usage
+                .entry(table_name.clone())
+                .or_insert_with(|| TableUsage {
+                    table: table_name,
+                    used: 0,
+                    capacity: 0,
+                })
+                .used = used;
+        }
+        
+        let mut usage: Vec<TableUsage> = usage.into_values().collect();
+        usage.sort_by(|a, b| a.table.cmp(&b.table));
+        Ok(usage)
+    }
 }
 
 impl Default for TableManager {
@@ -210,13 +586,162 @@ impl Default for TableManager {
     }
 }
 
+/// テーブルエントリをP4Infoのスキーマに照らして検証
+pub fn validate_entry(p4info: &P4Info, entry: &TableEntry) -> Result<()> {
+    let invalid = |msg: String| -> anyhow::Error { P4RuntimeError::InvalidTableEntry(msg).into() };
+    
+    let table = p4info
+        .find_table(&entry.table)
+        .ok_or_else(|| P4RuntimeError::TableNotFound { table_name: entry.table.clone() })?;
+    
+    // 扱えないマッチタイプのフィールドがあると、キーを正しく組み立てられない
+    if let Some(key_field) = table.key_fields.iter().find(|k| k.match_type == MatchType::Unsupported) {
+        return Err(invalid(format!(
+            "Table {} has match field {} with an unsupported match type",
+            table.name, key_field.name
+        )));
+    }
+    
+    // マッチフィールドの検証
+    for (i, field_match) in entry.matches.iter().enumerate() {
+        if entry.matches[..i].iter().any(|m| m.field == field_match.field) {
+            return Err(invalid(format!("Duplicate match field {}", field_match.field)));
+        }
+        
+        let key_field = table
+            .key_fields
+            .iter()
+            .find(|k| k.name == field_match.field)
+            .ok_or_else(|| invalid(format!("Unknown match field {} in table {}", field_match.field, table.name)))?;
+        
+        if key_field.match_type != field_match.value.match_type() {
+            return Err(invalid(format!(
+                "Field {} expects {:?} match, got {:?}",
+                key_field.name,
+                key_field.match_type,
+                field_match.value.match_type()
+            )));
+        }
+        
+        codec::encode_match(&field_match.value, key_field.bitwidth)
+            .map_err(|e| invalid(format!("Field {}: {}", key_field.name, e)))?;
+    }
+    
+    // exactフィールドは省略できない
+    for key_field in &table.key_fields {
+        if key_field.match_type == MatchType::Exact && !entry.matches.iter().any(|m| m.field == key_field.name) {
+            return Err(invalid(format!("Missing exact match field {}", key_field.name)));
+        }
+    }
+    
+    // ternary / range / optionalを含むテーブルは優先度が必須
+    if table.needs_priority() && entry.priority == 0 {
+        return Err(invalid(format!("Table {} requires a non-zero priority", table.name)));
+    }
+    
+    // アクションプロファイルを使うテーブルはメンバーかグループ、それ以外は直接のアクションのみ
+    let entry_action = match (&entry.action, table.implementation_id) {
+        (EntryAction::Direct(action), 0) => action,
+        (EntryAction::Direct(_), _) => {
+            return Err(invalid(format!("Table {} requires an action profile member or group", table.name)));
+        }
+        (_, 0) => {
+            return Err(invalid(format!("Table {} does not use an action profile", table.name)));
+        }
+        (EntryAction::Member(_), _) => return Ok(()),
+        (EntryAction::Group(_), profile_id) => {
+            let with_selector = p4info.find_action_profile_by_id(profile_id).is_some_and(|p| p.with_selector);
+            if !with_selector {
+                return Err(invalid(format!("Table {} has no action selector for groups", table.name)));
+            }
+            return Ok(());
+        }
+    };
+    
+    validate_action(p4info, table, entry_action)
+}
+
+/// アクションがテーブルで使えるか、パラメータがそろっていて幅に収まるかを検証
+pub fn validate_action(p4info: &P4Info, table: &TableInfo, entry_action: &ActionCall) -> Result<()> {
+    let invalid = |msg: String| -> anyhow::Error { P4RuntimeError::InvalidTableEntry(msg).into() };
+    
+    let action = p4info
+        .find_action(&entry_action.name)
+        .ok_or_else(|| P4RuntimeError::ActionNotFound { action_name: entry_action.name.clone() })?;
+    if !table.action_refs.iter().any(|r| r.id == action.id) {
+        return Err(invalid(format!("Action {} is not allowed in table {}", action.name, table.name)));
+    }
+    
+    for param in &entry_action.params {
+        let info = action
+            .params
+            .iter()
+            .find(|p| p.name == param.name)
+            .ok_or_else(|| invalid(format!("Unknown parameter {} for action {}", param.name, action.name)))?;
+        codec::encode(&param.value, info.bitwidth)
+            .map_err(|e| invalid(format!("Parameter {}: {}", info.name, e)))?;
+    }
+    for info in &action.params {
+        if entry_action.param(&info.name).is_none() {
+            return Err(invalid(format!("Missing parameter {} for action {}", info.name, action.name)));
+        }
+    }
+    
+    Ok(())
+}
+
+/// マッチとパラメータの値をP4Runtimeの正規形に揃える（検証済みのエントリが前提）
+pub(crate) fn canonicalize_entry(p4info: &P4Info, entry: &mut TableEntry) -> Result<()> {
+    let Some(table) = p4info.find_table(&entry.table) else {
+        return Ok(());
+    };
+    
+    // ワイルドカードのマッチ（プレフィックス長0のLPM、マスクが0のternary）は省略する
+    let mut matches = Vec::with_capacity(entry.matches.len());
+    for mut field_match in std::mem::take(&mut entry.matches) {
+        if let Some(key_field) = table.key_fields.iter().find(|k| k.name == field_match.field) {
+            match codec::encode_match(&field_match.value, key_field.bitwidth)? {
+                Some(value) => field_match.value = value,
+                None => continue,
+            }
+        }
+        matches.push(field_match);
+    }
+    entry.matches = matches;
+    // 優先度を使わないテーブルでは優先度をキーに含めないので0にそろえる
+    if !table.needs_priority() {
+        entry.priority = 0;
+    }
+    
+    if let EntryAction::Direct(action) = &mut entry.action {
+        canonicalize_action(p4info, action)?;
+    }
+    
+    Ok(())
+}
+
+/// アクションパラメータの値をP4Runtimeの正規形に揃える
+pub fn canonicalize_action(p4info: &P4Info, action: &mut ActionCall) -> Result<()> {
+    if let Some(info) = p4info.find_action(&action.name) {
+        for param in &mut action.params {
+            if let Some(param_info) = info.params.iter().find(|p| p.name == param.name) {
+                param.value = codec::encode(&param.value, param_info.bitwidth)?;
+            }
+        }
+    }
+    
+    Ok(())
+}
+
 /// テーブルエントリビルダー
 #[derive(Debug)]
 pub struct TableEntryBuilder {
     device_id: Option<DeviceId>,
+    table: Option<String>,
+    matches: Vec<FieldMatch>,
+    action: Option<EntryAction>,
     prefix: Option<Ipv4Address>,
     prefix_len: Option<u8>,
-    action: Option<TableAction>,
     priority: u32,
 }
 
@@ -224,9 +749,11 @@ impl TableEntryBuilder {
     pub fn new() -> Self {
         Self {
             device_id: None,
+            table: None,
+            matches: Vec::new(),
+            action: None,
             prefix: None,
             prefix_len: None,
-            action: None,
             priority: 0,
         }
     }
@@ -236,6 +763,37 @@ impl TableEntryBuilder {
         self
     }
     
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = Some(table.to_string());
+        self
+    }
+    
+    pub fn field_match(mut self, field_match: FieldMatch) -> Self {
+        self.matches.push(field_match);
+        self
+    }
+    
+    pub fn exact(self, field: &str, value: Vec<u8>) -> Self {
+        self.field_match(FieldMatch::exact(field, value))
+    }
+    
+    pub fn lpm(self, field: &str, value: Vec<u8>, prefix_len: u32) -> Self {
+        self.field_match(FieldMatch::lpm(field, value, prefix_len))
+    }
+    
+    pub fn ternary(self, field: &str, value: Vec<u8>, mask: Vec<u8>) -> Self {
+        self.field_match(FieldMatch::ternary(field, value, mask))
+    }
+    
+    pub fn range(self, field: &str, low: Vec<u8>, high: Vec<u8>) -> Self {
+        self.field_match(FieldMatch::range(field, low, high))
+    }
+    
+    pub fn optional(self, field: &str, value: Vec<u8>) -> Self {
+        self.field_match(FieldMatch::optional(field, value))
+    }
+    
+    /// IPv4 LPMのプレフィックス（テーブル未指定ならipv4_lpmになる）
     pub fn prefix(mut self, prefix: Ipv4Address) -> Self {
         self.prefix = Some(prefix);
         self
@@ -246,8 +804,27 @@ impl TableEntryBuilder {
         self
     }
     
+    /// IPv4 LPMのアクション
     pub fn action(mut self, action: TableAction) -> Self {
-        self.action = Some(action);
+        self.action = Some(action.to_action_call().into());
+        self
+    }
+    
+    /// 名前付きのアクション
+    pub fn action_call(mut self, action: ActionCall) -> Self {
+        self.action = Some(action.into());
+        self
+    }
+    
+    /// アクションプロファイルのメンバー
+    pub fn member(mut self, member_id: u32) -> Self {
+        self.action = Some(EntryAction::Member(member_id));
+        self
+    }
+    
+    /// アクションセレクターのグループ
+    pub fn group(mut self, group_id: u32) -> Self {
+        self.action = Some(EntryAction::Group(group_id));
         self
     }
     
@@ -256,16 +833,20 @@ impl TableEntryBuilder {
         self
     }
     
-    pub fn build(self) -> Result<TableEntry> {
-        let prefix = self.prefix.ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing prefix".to_string()))?;
-        let prefix_len = self.prefix_len.ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing prefix length".to_string()))?;
+    pub fn build(mut self) -> Result<TableEntry> {
+        if self.prefix.is_some() || self.prefix_len.is_some() {
+            let prefix = self.prefix.ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing prefix".to_string()))?;
+            let prefix_len = self.prefix_len.ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing prefix length".to_string()))?;
+            self.matches.extend(TableKey { ipv4_dst: prefix, prefix_len }.to_field_matches());
+            self.table.get_or_insert_with(|| IPV4_LPM_TABLE.to_string());
+        }
+        
+        let table = self.table.ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing table".to_string()))?;
         let action = self.action.ok_or_else(|| P4RuntimeError::InvalidTableEntry("Missing action".to_string()))?;
         
         Ok(TableEntry {
-            key: TableKey {
-                ipv4_dst: prefix,
-                prefix_len,
-            },
+            table,
+            matches: self.matches,
             action,
             priority: self.priority,
         })
@@ -277,3 +858,266 @@ impl Default for TableEntryBuilder {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn route(prefix: [u8; 4], prefix_len: u8, port: PortId) -> TableEntry {
+        let key = TableKey {
+            ipv4_dst: Ipv4Address::new(prefix.into()),
+            prefix_len,
+        };
+        let action = TableAction::Ipv4Forward {
+            dst_mac: MacAddress::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            port,
+        };
+        TableEntry::ipv4_lpm(key, action, 0)
+    }
+    
+    /// `size` 付きのIPv4 LPMテーブルだけを持つP4Info
+    fn ipv4_lpm_p4info(size: u64) -> P4Info {
+        let table = TableInfo {
+            name: format!("MyIngress.{}", IPV4_LPM_TABLE),
+            id: 1,
+            key_fields: vec![KeyField {
+                id: 1,
+                name: IPV4_DST_FIELD.to_string(),
+                bitwidth: 32,
+                match_type: MatchType::Lpm,
+            }],
+            action_refs: vec![ActionRef { name: format!("MyIngress.{}", IPV4_FORWARD_ACTION), id: 2 }],
+            size,
+            implementation_id: 0,
+        };
+        let action = ActionInfo {
+            name: format!("MyIngress.{}", IPV4_FORWARD_ACTION),
+            id: 2,
+            params: vec![
+                ActionParam { id: 1, name: "dstAddr".to_string(), bitwidth: 48 },
+                ActionParam { id: 2, name: "port".to_string(), bitwidth: 9 },
+            ],
+        };
+        P4Info {
+            tables: HashMap::from([(table.name.clone(), table)]),
+            actions: HashMap::from([(action.name.clone(), action)]),
+            ..Default::default()
+        }
+    }
+    
+    /// 送信元アドレスのternaryでdropするだけのテーブルを持つP4Info
+    fn acl_p4info() -> P4Info {
+        let table = TableInfo {
+            name: "MyIngress.acl".to_string(),
+            id: 3,
+            key_fields: vec![KeyField {
+                id: 1,
+                name: IPV4_SRC_FIELD.to_string(),
+                bitwidth: 32,
+                match_type: MatchType::Ternary,
+            }],
+            action_refs: vec![ActionRef { name: format!("MyIngress.{}", DROP_ACTION), id: 4 }],
+            size: 0,
+            implementation_id: 0,
+        };
+        let action = ActionInfo { name: format!("MyIngress.{}", DROP_ACTION), id: 4, params: Vec::new() };
+        P4Info {
+            tables: HashMap::from([(table.name.clone(), table)]),
+            actions: HashMap::from([(action.name.clone(), action)]),
+            ..Default::default()
+        }
+    }
+    
+    async fn watermark_alerted(manager: &TableManager, device_id: DeviceId) -> bool {
+        let table_name = format!("MyIngress.{}", IPV4_LPM_TABLE);
+        manager.watermark_alerts.read().await.contains(&(device_id, table_name))
+    }
+    
+    async fn manager_with(device_id: DeviceId, entries: &[TableEntry]) -> TableManager {
+        let manager = TableManager::new();
+        manager.initialize_device_tables(device_id).await;
+        for entry in entries {
+            manager.add_entry(device_id, entry.clone()).await.unwrap();
+        }
+        manager
+    }
+    
+    #[tokio::test]
+    async fn diff_reports_missing_extra_and_modified_entries() {
+        let in_sync = route([10, 0, 0, 0], 8, 1);
+        let missing = route([10, 1, 0, 0], 16, 1);
+        let modified = route([10, 2, 0, 0], 16, 2);
+        let manager = manager_with(1, &[in_sync.clone(), missing.clone(), modified.clone()]).await;
+        
+        let device_modified = route([10, 2, 0, 0], 16, 3);
+        let extra = route([192, 168, 0, 0], 16, 1);
+        let device_entries = [extra.clone(), device_modified.clone(), in_sync];
+        
+        let drift = manager.diff_device_entries(1, &device_entries).await.unwrap();
+        assert_eq!(drift.missing, vec![missing]);
+        assert_eq!(drift.extra, vec![extra]);
+        assert_eq!(drift.modified, vec![ModifiedEntry { expected: modified, actual: device_modified }]);
+        assert_eq!(drift.len(), 3);
+    }
+    
+    #[tokio::test]
+    async fn diff_is_empty_when_device_matches_shadow() {
+        let entries = [route([10, 0, 0, 0], 8, 1), route([0, 0, 0, 0], 0, 2)];
+        let manager = manager_with(1, &entries).await;
+        
+        // デバイスから読み出した順序はシャドウと異なってもよい
+        let device_entries = [entries[1].clone(), entries[0].clone()];
+        assert!(manager.diff_device_entries(1, &device_entries).await.unwrap().is_empty());
+        
+        let drift = manager.diff_device_entries(1, &[]).await.unwrap();
+        assert_eq!(drift.missing.len(), 2);
+        assert!(drift.extra.is_empty() && drift.modified.is_empty());
+    }
+    
+    #[test]
+    fn repair_phases_delete_before_modify_before_insert() {
+        let drift = TableDrift {
+            device_id: 1,
+            missing: vec![route([10, 1, 0, 0], 16, 1)],
+            extra: vec![route([192, 168, 0, 0], 16, 1), route([172, 16, 0, 0], 12, 1)],
+            modified: vec![ModifiedEntry {
+                expected: route([10, 2, 0, 0], 16, 2),
+                actual: route([10, 2, 0, 0], 16, 3),
+            }],
+        };
+        
+        let [deletes, modifies, inserts] = drift.to_update_phases();
+        assert_eq!(deletes, drift.extra.iter().cloned().map(TableUpdate::delete).collect::<Vec<_>>());
+        assert_eq!(modifies, vec![TableUpdate::modify(route([10, 2, 0, 0], 16, 2))]);
+        assert_eq!(inserts, vec![TableUpdate::insert(route([10, 1, 0, 0], 16, 1))]);
+    }
+    
+    #[tokio::test]
+    async fn new_keys_are_rejected_when_table_is_full() {
+        let manager = manager_with(1, &[]).await;
+        manager.set_device_p4info(1, ipv4_lpm_p4info(2)).await;
+        manager.add_entry(1, route([10, 0, 0, 0], 8, 1)).await.unwrap();
+        manager.add_entry(1, route([10, 1, 0, 0], 16, 1)).await.unwrap();
+        
+        let err = manager.add_entry(1, route([10, 2, 0, 0], 16, 1)).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<P4RuntimeError>(),
+            Some(P4RuntimeError::TableFull { device_id: 1, capacity: 2, .. })
+        ));
+        // 拒否したエントリはシャドウに残さない
+        assert_eq!(manager.get_entries(1, IPV4_LPM_TABLE).await.unwrap().len(), 2);
+        
+        // 既存のキーの変更は満杯でもできる
+        let update = manager.add_entry(1, route([10, 1, 0, 0], 16, 2)).await.unwrap().unwrap();
+        assert_eq!(update.update_type, UpdateType::Modify);
+        
+        // 空きができても拒否したエントリは自動では追加されない
+        manager.remove_entry(1, &route([10, 0, 0, 0], 8, 1)).await.unwrap();
+        let entries = manager.get_entries(1, IPV4_LPM_TABLE).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].has_same_key(&route([10, 1, 0, 0], 16, 2)));
+    }
+    
+    #[tokio::test]
+    async fn failed_delete_is_reverted_even_if_the_table_filled_up() {
+        let manager = manager_with(1, &[]).await;
+        manager.set_device_p4info(1, ipv4_lpm_p4info(1)).await;
+        manager.set_high_watermark(100).await;
+        let kept = route([10, 0, 0, 0], 8, 1);
+        manager.add_entry(1, kept.clone()).await.unwrap();
+        assert!(watermark_alerted(&manager, 1).await);
+        
+        // DELETEを書き込んでいる間に、空いた枠を別のエントリが使う
+        let removed = manager.remove_entry(1, &kept).await.unwrap().unwrap();
+        assert!(!watermark_alerted(&manager, 1).await);
+        let other = route([10, 1, 0, 0], 16, 1);
+        manager.add_entry(1, other.clone()).await.unwrap();
+        
+        // DELETEに失敗したエントリはデバイスに残っているので、容量を超えてもシャドウに戻す
+        manager.revert_update(1, &StagedUpdate::delete(removed)).await.unwrap();
+        let entries = manager.get_entries(1, IPV4_LPM_TABLE).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(manager.get_entry(1, &kept).await.unwrap().is_some());
+        assert!(manager.get_entry(1, &other).await.unwrap().is_some());
+        
+        // 失敗したMODIFYは前のアクションに戻す
+        let staged = manager.stage_entry(1, route([10, 0, 0, 0], 8, 2)).await.unwrap().unwrap();
+        manager.revert_update(1, &staged).await.unwrap();
+        let restored = manager.get_entry(1, &kept).await.unwrap().unwrap();
+        assert_eq!(restored.forwarding_action(), kept.forwarding_action());
+    }
+    
+    #[tokio::test]
+    async fn high_watermark_alerts_once_until_usage_drops() {
+        let manager = manager_with(1, &[]).await;
+        manager.set_device_p4info(1, ipv4_lpm_p4info(4)).await;
+        manager.set_high_watermark(50).await;
+        
+        manager.add_entry(1, route([10, 0, 0, 0], 8, 1)).await.unwrap();
+        assert!(!watermark_alerted(&manager, 1).await);
+        manager.add_entry(1, route([10, 1, 0, 0], 16, 1)).await.unwrap();
+        assert!(watermark_alerted(&manager, 1).await);
+        
+        // しきい値を超えたまま追加しても警告済みの状態は変わらない
+        manager.add_entry(1, route([10, 2, 0, 0], 16, 1)).await.unwrap();
+        assert_eq!(manager.watermark_alerts.read().await.len(), 1);
+        
+        // しきい値を下回ると解除され、再び超えたときに警告できる
+        manager.remove_entry(1, &route([10, 1, 0, 0], 16, 1)).await.unwrap();
+        manager.remove_entry(1, &route([10, 2, 0, 0], 16, 1)).await.unwrap();
+        assert!(!watermark_alerted(&manager, 1).await);
+        manager.add_entry(1, route([10, 3, 0, 0], 16, 1)).await.unwrap();
+        assert!(watermark_alerted(&manager, 1).await);
+    }
+    
+    #[tokio::test]
+    async fn abac_catch_all_with_new_priority_is_a_new_key() {
+        let manager = manager_with(1, &[]).await;
+        let low = TableEntry::abac_policy(AttributeMatch::ANY, AttributeMatch::ANY, AbacAction::Drop, 1);
+        let high = TableEntry::abac_policy(AttributeMatch::ANY, AttributeMatch::ANY, AbacAction::Drop, 5);
+        manager.add_entry(1, low.clone()).await.unwrap();
+        
+        // マッチのない全ワイルドカードのエントリでも優先度はキーの一部
+        let update = manager.add_entry(1, high).await.unwrap().unwrap();
+        assert_eq!(update.update_type, UpdateType::Insert);
+        assert_eq!(manager.get_entries(1, ABAC_POLICY_TABLE).await.unwrap().len(), 2);
+        assert_eq!(manager.add_entry(1, low).await.unwrap(), None);
+    }
+    
+    #[tokio::test]
+    async fn priority_is_part_of_the_key_only_in_priority_tables() {
+        let manager = manager_with(1, &[]).await;
+        manager.set_device_p4info(1, acl_p4info()).await;
+        
+        // マスクが0のマッチは省略されるが、ternaryのテーブルなので優先度で別のエントリになる
+        let acl = |priority| {
+            let src = FieldMatch::ternary(IPV4_SRC_FIELD, vec![10, 0, 0, 1], vec![0; 4]);
+            TableEntry::new("acl", vec![src], ActionCall::new(DROP_ACTION), priority)
+        };
+        manager.add_entry(1, acl(10)).await.unwrap();
+        let update = manager.add_entry(1, acl(20)).await.unwrap().unwrap();
+        assert_eq!(update.update_type, UpdateType::Insert);
+        assert_eq!(manager.get_entries(1, "acl").await.unwrap().len(), 2);
+        assert_eq!(manager.add_entry(1, acl(10)).await.unwrap(), None);
+        
+        // LPMのテーブルの優先度は0にそろえるので、優先度だけが違うエントリは同じキーになる
+        let manager = manager_with(1, &[]).await;
+        manager.set_device_p4info(1, ipv4_lpm_p4info(0)).await;
+        let mut with_priority = route([10, 0, 0, 0], 8, 1);
+        with_priority.priority = 7;
+        manager.add_entry(1, with_priority).await.unwrap();
+        assert_eq!(manager.add_entry(1, route([10, 0, 0, 0], 8, 1)).await.unwrap(), None);
+        assert_eq!(manager.get_entries(1, IPV4_LPM_TABLE).await.unwrap()[0].priority, 0);
+    }
+    
+    #[tokio::test]
+    async fn ipv4_lpm_entry_can_be_removed_without_p4info() {
+        let manager = manager_with(1, &[]).await;
+        let action = TableAction::Ipv4Forward { dst_mac: MacAddress::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]), port: 1 };
+        let prefix = Ipv4Address::new([10, 0, 0, 0].into());
+        manager.add_ipv4_lpm_entry(1, prefix, 8, action).await.unwrap();
+        
+        manager.remove_ipv4_lpm_entry(1, prefix, 8).await.unwrap();
+        assert!(manager.get_entries(1, IPV4_LPM_TABLE).await.unwrap().is_empty());
+    }
+}
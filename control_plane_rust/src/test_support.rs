@@ -0,0 +1,26 @@
+//! テストとベンチマークで共有する補助
+
+/// 再現性のある疑似乱数（xorshift）
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// シードを指定して作成（0以外）
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+    
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u32
+    }
+}
+
+impl Default for Rng {
+    /// テストとベンチマークで共通のシード
+    fn default() -> Self {
+        Self::new(0x2545_f491_4f6c_dd1d)
+    }
+}
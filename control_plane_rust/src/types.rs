@@ -1,6 +1,9 @@
+use ipnet::{Ipv4Net, Ipv6Net};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
 /// P4Runtime関連のエラー型
@@ -20,6 +23,36 @@ pub enum P4RuntimeError {
     
     #[error("Table not found: {table_name}")]
     TableNotFound { table_name: String },
+    
+    #[error("Action not found: {action_name}")]
+    ActionNotFound { action_name: String },
+    
+    #[error("P4Info not loaded for device {device_id}")]
+    P4InfoNotLoaded { device_id: u64 },
+    
+    #[error("Master arbitration failed for device {device_id}: {reason}")]
+    ArbitrationFailed { device_id: u64, reason: String },
+    
+    #[error("Controller is not primary for device {device_id} (election id {election_id})")]
+    NotPrimary { device_id: u64, election_id: u128 },
+    
+    #[error("Value {value} does not fit in {bitwidth} bits")]
+    ValueOutOfRange { value: String, bitwidth: u32 },
+    
+    #[error("Stream channel to device {device_id} is closed")]
+    StreamClosed { device_id: u64 },
+    
+    #[error("Invalid packet: {0}")]
+    InvalidPacket(String),
+    
+    #[error("Write to device {device_id} failed for {failed} of {total} updates: {detail}")]
+    WriteFailed { device_id: u64, failed: usize, total: usize, detail: String },
+    
+    #[error("Table {table_name} on device {device_id} is full ({capacity} entries)")]
+    TableFull { device_id: u64, table_name: String, capacity: u64 },
+    
+    #[error("Invalid policy: {0}")]
+    InvalidPolicy(String),
 }
 
 /// P4RuntimeデバイスID
@@ -40,17 +73,13 @@ impl MacAddress {
     pub fn as_bytes(&self) -> &[u8; 6] {
         &self.0
     }
-    
-    pub fn to_string(&self) -> String {
-        format!("{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-                self.0[0], self.0[1], self.0[2], 
-                self.0[3], self.0[4], self.0[5])
-    }
 }
 
 impl std::fmt::Display for MacAddress {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+               self.0[0], self.0[1], self.0[2],
+               self.0[3], self.0[4], self.0[5])
     }
 }
 
@@ -74,6 +103,11 @@ impl Ipv4Address {
     pub fn as_ipv4(&self) -> Ipv4Addr {
         Ipv4Addr::from(self.0)
     }
+    
+    /// プレフィックス長でマスクしたネットワーク（長さが32を超えればNone）
+    pub fn network(&self, prefix_len: u8) -> Option<Ipv4Net> {
+        Ipv4Net::new(self.as_ipv4(), prefix_len).ok().map(|net| net.trunc())
+    }
 }
 
 impl std::fmt::Display for Ipv4Address {
@@ -82,14 +116,146 @@ impl std::fmt::Display for Ipv4Address {
     }
 }
 
-/// P4テーブルエントリのキー
+/// IPv6アドレス型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Ipv6Address(u128);
+
+impl Ipv6Address {
+    pub fn new(addr: Ipv6Addr) -> Self {
+        Self(addr.into())
+    }
+    
+    pub fn from_u128(addr: u128) -> Self {
+        Self(addr)
+    }
+    
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+    
+    pub fn as_ipv6(&self) -> Ipv6Addr {
+        Ipv6Addr::from(self.0)
+    }
+    
+    /// プレフィックス長でマスクしたネットワーク（長さが128を超えればNone）
+    pub fn network(&self, prefix_len: u8) -> Option<Ipv6Net> {
+        Ipv6Net::new(self.as_ipv6(), prefix_len).ok().map(|net| net.trunc())
+    }
+    
+    /// MACアドレスからEUI-64で作るリンクローカルアドレス（fe80::/64）
+    pub fn link_local_from_mac(mac: MacAddress) -> Self {
+        let m = mac.as_bytes();
+        let mut bytes = [0u8; 16];
+        bytes[0] = 0xfe;
+        bytes[1] = 0x80;
+        bytes[8..16].copy_from_slice(&[m[0] ^ 0x02, m[1], m[2], 0xff, 0xfe, m[3], m[4], m[5]]);
+        Self(u128::from_be_bytes(bytes))
+    }
+    
+    /// 要請ノードマルチキャストアドレス（ff02::1:ffXX:XXXX、NDPの近隣要請の宛先）
+    pub fn solicited_node(&self) -> Self {
+        Self(0xff02_0000_0000_0000_0000_0001_ff00_0000 | (self.0 & 0x00ff_ffff))
+    }
+    
+    /// マルチキャストアドレスに対応するイーサネットのマルチキャストMAC（33:33:XX:XX:XX:XX）
+    pub fn multicast_mac(&self) -> MacAddress {
+        let low = (self.0 as u32).to_be_bytes();
+        MacAddress::new([0x33, 0x33, low[0], low[1], low[2], low[3]])
+    }
+}
+
+impl std::fmt::Display for Ipv6Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // 表の桁揃えのため幅指定を引き継ぐ
+        std::fmt::Display::fmt(&self.as_ipv6(), f)
+    }
+}
+
+/// IPv4 LPMテーブルとそのフィールド・アクションのP4名
+pub const IPV4_LPM_TABLE: &str = "ipv4_lpm";
+pub const IPV4_DST_FIELD: &str = "hdr.ipv4.dstAddr";
+pub const IPV4_FORWARD_ACTION: &str = "ipv4_forward";
+pub const SET_ECMP_GROUP_ACTION: &str = "set_ecmp_group";
+pub const DROP_ACTION: &str = "drop";
+
+/// IPv6 LPMテーブルとそのフィールド・アクションのP4名
+pub const IPV6_LPM_TABLE: &str = "ipv6_lpm";
+pub const IPV6_DST_FIELD: &str = "hdr.ipv6.dstAddr";
+pub const IPV6_FORWARD_ACTION: &str = "ipv6_forward";
+
+/// ECMPグループテーブルとそのアクションセレクターのP4名
+pub const ECMP_GROUP_TABLE: &str = "ecmp_group";
+pub const ECMP_GROUP_ID_FIELD: &str = "meta.ecmp_group_id";
+pub const ECMP_SELECTOR: &str = "ecmp_selector";
+
+/// ABACの分類テーブルとそのフィールド・アクションのP4名
+pub const ABAC_SUBJECT_TABLE: &str = "abac_subject";
+pub const ABAC_OBJECT_TABLE: &str = "abac_object";
+pub const ABAC_SERVICE_TABLE: &str = "abac_service";
+pub const IPV4_SRC_FIELD: &str = "hdr.ipv4.srcAddr";
+pub const IPV4_PROTOCOL_FIELD: &str = "hdr.ipv4.protocol";
+pub const L4_DST_PORT_FIELD: &str = "hdr.l4_ports.dstPort";
+pub const SET_SUBJECT_ATTRIBUTES_ACTION: &str = "set_subject_attributes";
+pub const SET_OBJECT_ATTRIBUTES_ACTION: &str = "set_object_attributes";
+pub const SET_SERVICE_ATTRIBUTES_ACTION: &str = "set_service_attributes";
+
+/// ABACポリシーテーブルとそのフィールド・アクションのP4名
+pub const ABAC_POLICY_TABLE: &str = "abac_policy";
+pub const ABAC_SUBJECT_FIELD: &str = "meta.abac_subject";
+pub const ABAC_OBJECT_FIELD: &str = "meta.abac_object";
+pub const ABAC_ALLOW_ACTION: &str = "abac_allow";
+pub const ABAC_DROP_ACTION: &str = "abac_drop";
+pub const ABAC_PUNT_ACTION: &str = "abac_punt";
+/// 主体・対象の属性をエンコードしたメタデータのビット幅
+pub const ABAC_ATTRIBUTES_BITWIDTH: u32 = 32;
+/// エンコードの版を表す最上位ビット（ポリシーを入れ替える間、新旧の配置でエンコードしたビット列を区別する）
+pub const ABAC_VERSION_BIT: u32 = 1 << (ABAC_ATTRIBUTES_BITWIDTH - 1);
+/// 属性のエンコードに使えるビット数（版のビットを除く）
+pub const ABAC_ENCODING_BITS: u32 = ABAC_ATTRIBUTES_BITWIDTH - 1;
+
+/// P4テーブルエントリのキー（IPv4 LPMテーブル用）
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TableKey {
     pub ipv4_dst: Ipv4Address,
     pub prefix_len: u8,
 }
 
-/// P4テーブルエントリのアクション
+impl TableKey {
+    /// 汎用のフィールドマッチに変換（/0はワイルドカードなのでマッチを省略）
+    pub fn to_field_matches(&self) -> Vec<FieldMatch> {
+        if self.prefix_len == 0 {
+            return Vec::new();
+        }
+        vec![FieldMatch::lpm(
+            IPV4_DST_FIELD,
+            self.ipv4_dst.as_u32().to_be_bytes().to_vec(),
+            self.prefix_len as u32,
+        )]
+    }
+}
+
+/// P4テーブルエントリのキー（IPv6 LPMテーブル用）
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Ipv6TableKey {
+    pub ipv6_dst: Ipv6Address,
+    pub prefix_len: u8,
+}
+
+impl Ipv6TableKey {
+    /// 汎用のフィールドマッチに変換（/0はワイルドカードなのでマッチを省略）
+    pub fn to_field_matches(&self) -> Vec<FieldMatch> {
+        if self.prefix_len == 0 {
+            return Vec::new();
+        }
+        vec![FieldMatch::lpm(
+            IPV6_DST_FIELD,
+            self.ipv6_dst.as_u128().to_be_bytes().to_vec(),
+            self.prefix_len as u32,
+        )]
+    }
+}
+
+/// P4テーブルエントリのアクション（IPv4 / IPv6 LPMテーブル用）
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TableAction {
     /// IPv4フォワーディングアクション
@@ -97,18 +263,585 @@ pub enum TableAction {
         dst_mac: MacAddress,
         port: PortId,
     },
+    /// IPv6フォワーディングアクション（ホップリミットをデクリメント）
+    Ipv6Forward {
+        dst_mac: MacAddress,
+        port: PortId,
+    },
+    /// ECMPグループに転送するアクション（グループはecmp_groupテーブルで選ぶ）
+    SetEcmpGroup {
+        group_id: u32,
+    },
     /// ドロップアクション
     Drop,
 }
 
-/// P4テーブルエントリ
+impl TableAction {
+    /// 汎用のアクション呼び出しに変換
+    pub fn to_action_call(&self) -> ActionCall {
+        match self {
+            TableAction::Ipv4Forward { dst_mac, port } => ActionCall::new(IPV4_FORWARD_ACTION)
+                .with_param("dstAddr", dst_mac.as_bytes().to_vec())
+                .with_param("port", port.to_be_bytes().to_vec()),
+            TableAction::Ipv6Forward { dst_mac, port } => ActionCall::new(IPV6_FORWARD_ACTION)
+                .with_param("dstAddr", dst_mac.as_bytes().to_vec())
+                .with_param("port", port.to_be_bytes().to_vec()),
+            TableAction::SetEcmpGroup { group_id } => ActionCall::new(SET_ECMP_GROUP_ACTION)
+                .with_param("group_id", group_id.to_be_bytes().to_vec()),
+            TableAction::Drop => ActionCall::new(DROP_ACTION),
+        }
+    }
+    
+    /// 汎用のアクション呼び出しから変換（IPv4 / IPv6 LPMのアクションでなければNone）
+    pub fn from_action_call(action: &ActionCall) -> Option<Self> {
+        let forward_params = || -> Option<(MacAddress, PortId)> {
+            let mac = bytes_to_u64(action.param("dstAddr")?)?.to_be_bytes();
+            let mut dst_mac = [0u8; 6];
+            dst_mac.copy_from_slice(&mac[2..]);
+            Some((MacAddress::new(dst_mac), bytes_to_u64(action.param("port")?)?.try_into().ok()?))
+        };
+        
+        match short_name(&action.name) {
+            IPV4_FORWARD_ACTION => {
+                let (dst_mac, port) = forward_params()?;
+                Some(TableAction::Ipv4Forward { dst_mac, port })
+            }
+            IPV6_FORWARD_ACTION => {
+                let (dst_mac, port) = forward_params()?;
+                Some(TableAction::Ipv6Forward { dst_mac, port })
+            }
+            SET_ECMP_GROUP_ACTION => Some(TableAction::SetEcmpGroup {
+                group_id: bytes_to_u64(action.param("group_id")?)?.try_into().ok()?,
+            }),
+            DROP_ACTION => Some(TableAction::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// ABACポリシーテーブルのアクション
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AbacAction {
+    /// そのまま転送する
+    Allow,
+    /// ドロップする
+    Drop,
+    /// 転送せずにコントローラーに送る
+    Punt,
+}
+
+impl AbacAction {
+    /// 汎用のアクション呼び出しに変換
+    pub fn to_action_call(self) -> ActionCall {
+        ActionCall::new(match self {
+            AbacAction::Allow => ABAC_ALLOW_ACTION,
+            AbacAction::Drop => ABAC_DROP_ACTION,
+            AbacAction::Punt => ABAC_PUNT_ACTION,
+        })
+    }
+    
+    /// 汎用のアクション呼び出しから変換（ABACポリシーのアクションでなければNone）
+    pub fn from_action_call(action: &ActionCall) -> Option<Self> {
+        match short_name(&action.name) {
+            ABAC_ALLOW_ACTION => Some(AbacAction::Allow),
+            ABAC_DROP_ACTION => Some(AbacAction::Drop),
+            ABAC_PUNT_ACTION => Some(AbacAction::Punt),
+            _ => None,
+        }
+    }
+}
+
+/// 属性のビット列に対するternaryマッチ（マスクが0ならワイルドカード）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct AttributeMatch {
+    pub value: u32,
+    pub mask: u32,
+}
+
+impl AttributeMatch {
+    /// どのビット列にも一致するマッチ
+    pub const ANY: Self = Self { value: 0, mask: 0 };
+    
+    /// マスクの外のビットを落として作る
+    pub fn new(value: u32, mask: u32) -> Self {
+        Self { value: value & mask, mask }
+    }
+    
+    pub fn is_wildcard(&self) -> bool {
+        self.mask == 0
+    }
+    
+    /// ビット列がこのマッチに一致するか
+    pub fn matches(&self, attributes: u32) -> bool {
+        attributes & self.mask == self.value
+    }
+    
+    /// ternaryのフィールドマッチに変換（ワイルドカードならマッチを省略するのでNone）
+    pub fn to_field_match(&self, field: &str) -> Option<FieldMatch> {
+        (!self.is_wildcard()).then(|| FieldMatch::ternary(field, self.value.to_be_bytes().to_vec(), self.mask.to_be_bytes().to_vec()))
+    }
+}
+
+/// ABACの分類テーブルのエントリ（パケットのヘッダーから属性のビット列を決める）
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AbacClassifier {
+    /// 送信元のプレフィックス -> 主体の属性（abac_subject）
+    Subject { prefix: Ipv4Net, attributes: u32 },
+    /// 宛先のプレフィックス -> 対象の属性（abac_object）
+    Object { prefix: Ipv4Net, attributes: u32 },
+    /// プロトコル（Noneなら任意）と宛先ポートの範囲 -> 対象のサービスの属性（abac_service、`mask` のビットだけを書き換える）
+    ///
+    /// 同じ版（`version`）の配置で分類した対象だけを書き換える。
+    Service {
+        protocol: Option<u8>,
+        low_port: u16,
+        high_port: u16,
+        attributes: u32,
+        mask: u32,
+        version: bool,
+    },
+}
+
+impl AbacClassifier {
+    /// 分類テーブルのエントリに変換
+    ///
+    /// サービスのエントリは範囲が狭いほど、同じ範囲ならプロトコルを指定したものを優先する。
+    pub fn to_table_entry(&self) -> TableEntry {
+        let prefix_match = |field: &str, prefix: &Ipv4Net| {
+            let prefix = prefix.trunc();
+            (prefix.prefix_len() > 0)
+                .then(|| FieldMatch::lpm(field, u32::from(prefix.network()).to_be_bytes().to_vec(), prefix.prefix_len() as u32))
+        };
+        
+        match self {
+            AbacClassifier::Subject { prefix, attributes } => TableEntry::new(
+                ABAC_SUBJECT_TABLE,
+                prefix_match(IPV4_SRC_FIELD, prefix).into_iter().collect(),
+                ActionCall::new(SET_SUBJECT_ATTRIBUTES_ACTION).with_param("attributes", attributes.to_be_bytes().to_vec()),
+                0,
+            ),
+            AbacClassifier::Object { prefix, attributes } => TableEntry::new(
+                ABAC_OBJECT_TABLE,
+                prefix_match(IPV4_DST_FIELD, prefix).into_iter().collect(),
+                ActionCall::new(SET_OBJECT_ATTRIBUTES_ACTION).with_param("attributes", attributes.to_be_bytes().to_vec()),
+                0,
+            ),
+            AbacClassifier::Service { protocol, low_port, high_port, attributes, mask, version } => {
+                let mut matches = Vec::new();
+                if let Some(protocol) = protocol {
+                    matches.push(FieldMatch::ternary(IPV4_PROTOCOL_FIELD, vec![*protocol], vec![0xff]));
+                }
+                matches.push(FieldMatch::range(L4_DST_PORT_FIELD, low_port.to_be_bytes().to_vec(), high_port.to_be_bytes().to_vec()));
+                let version_bits = if *version { ABAC_VERSION_BIT } else { 0 };
+                matches.push(FieldMatch::ternary(
+                    ABAC_OBJECT_FIELD,
+                    version_bits.to_be_bytes().to_vec(),
+                    ABAC_VERSION_BIT.to_be_bytes().to_vec(),
+                ));
+                
+                let span = high_port.saturating_sub(*low_port) as u32;
+                let priority = (0x1_0000 - span) * 2 + protocol.is_some() as u32;
+                TableEntry::new(
+                    ABAC_SERVICE_TABLE,
+                    matches,
+                    ActionCall::new(SET_SERVICE_ATTRIBUTES_ACTION)
+                        .with_param("attributes", (attributes & mask).to_be_bytes().to_vec())
+                        .with_param("mask", mask.to_be_bytes().to_vec()),
+                    priority,
+                )
+            }
+        }
+    }
+}
+
+/// フィールドマッチの値（ビッグエンディアンのバイト列）
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MatchValue {
+    Exact(Vec<u8>),
+    Lpm { value: Vec<u8>, prefix_len: u32 },
+    Ternary { value: Vec<u8>, mask: Vec<u8> },
+    Range { low: Vec<u8>, high: Vec<u8> },
+    Optional(Vec<u8>),
+}
+
+impl MatchValue {
+    /// 値に対応するマッチタイプ
+    pub fn match_type(&self) -> MatchType {
+        match self {
+            MatchValue::Exact(_) => MatchType::Exact,
+            MatchValue::Lpm { .. } => MatchType::Lpm,
+            MatchValue::Ternary { .. } => MatchType::Ternary,
+            MatchValue::Range { .. } => MatchType::Range,
+            MatchValue::Optional(_) => MatchType::Optional,
+        }
+    }
+}
+
+/// テーブルキーの1フィールド分のマッチ
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FieldMatch {
+    /// P4Infoのマッチフィールド名（例: "hdr.ipv4.dstAddr"）
+    pub field: String,
+    pub value: MatchValue,
+}
+
+impl FieldMatch {
+    pub fn exact(field: &str, value: Vec<u8>) -> Self {
+        Self { field: field.to_string(), value: MatchValue::Exact(value) }
+    }
+    
+    pub fn lpm(field: &str, value: Vec<u8>, prefix_len: u32) -> Self {
+        Self { field: field.to_string(), value: MatchValue::Lpm { value, prefix_len } }
+    }
+    
+    pub fn ternary(field: &str, value: Vec<u8>, mask: Vec<u8>) -> Self {
+        Self { field: field.to_string(), value: MatchValue::Ternary { value, mask } }
+    }
+    
+    pub fn range(field: &str, low: Vec<u8>, high: Vec<u8>) -> Self {
+        Self { field: field.to_string(), value: MatchValue::Range { low, high } }
+    }
+    
+    pub fn optional(field: &str, value: Vec<u8>) -> Self {
+        Self { field: field.to_string(), value: MatchValue::Optional(value) }
+    }
+}
+
+/// アクションパラメータの値
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionParamValue {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+/// アクション名と名前付きパラメータ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionCall {
+    /// P4Infoのアクション名（"MyIngress.drop" でも "drop" でも可）
+    pub name: String,
+    pub params: Vec<ActionParamValue>,
+}
+
+impl ActionCall {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), params: Vec::new() }
+    }
+    
+    pub fn with_param(mut self, name: &str, value: Vec<u8>) -> Self {
+        self.params.push(ActionParamValue { name: name.to_string(), value });
+        self
+    }
+    
+    /// パラメータの値を名前で取得
+    pub fn param(&self, name: &str) -> Option<&[u8]> {
+        self.params.iter().find(|p| p.name == name).map(|p| p.value.as_slice())
+    }
+    
+    /// 同じアクションと同じパラメータか（名前の修飾とパラメータの順序は問わない）
+    pub fn is_equivalent(&self, other: &ActionCall) -> bool {
+        short_name(&self.name) == short_name(&other.name)
+            && self.params.len() == other.params.len()
+            && self.params.iter().all(|p| other.param(&p.name) == Some(p.value.as_slice()))
+    }
+}
+
+/// テーブルエントリのアクション
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryAction {
+    /// アクションを直接指定
+    Direct(ActionCall),
+    /// アクションプロファイルのメンバー（action_profile_member_id）
+    Member(u32),
+    /// アクションセレクターのグループ（action_profile_group_id）
+    Group(u32),
+}
+
+impl EntryAction {
+    /// 直接指定のアクションであれば取り出す
+    pub fn as_direct(&self) -> Option<&ActionCall> {
+        match self {
+            EntryAction::Direct(action) => Some(action),
+            _ => None,
+        }
+    }
+    
+    /// 同じアクションか（直接指定のアクションは `ActionCall::is_equivalent` で比較）
+    pub fn is_equivalent(&self, other: &EntryAction) -> bool {
+        match (self, other) {
+            (EntryAction::Direct(a), EntryAction::Direct(b)) => a.is_equivalent(b),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl From<ActionCall> for EntryAction {
+    fn from(action: ActionCall) -> Self {
+        EntryAction::Direct(action)
+    }
+}
+
+/// P4テーブルエントリ（P4Infoに従う汎用形式）
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TableEntry {
-    pub key: TableKey,
-    pub action: TableAction,
+    /// P4Infoのテーブル名（"MyIngress.ipv4_lpm" でも "ipv4_lpm" でも可）
+    pub table: String,
+    /// マッチ（省略したフィールドはワイルドカード）
+    pub matches: Vec<FieldMatch>,
+    pub action: EntryAction,
+    /// ternary / range / optionalを含むテーブルでのみ使われる優先度
     pub priority: u32,
 }
 
+impl TableEntry {
+    pub fn new(table: &str, matches: Vec<FieldMatch>, action: impl Into<EntryAction>, priority: u32) -> Self {
+        Self {
+            table: table.to_string(),
+            matches,
+            action: action.into(),
+            priority,
+        }
+    }
+    
+    /// ECMPグループテーブルのエントリを作成（グループIDをキーに、同じIDのグループを参照する）
+    pub fn ecmp_group(group_id: u32) -> Self {
+        Self::new(
+            ECMP_GROUP_TABLE,
+            vec![FieldMatch::exact(ECMP_GROUP_ID_FIELD, group_id.to_be_bytes().to_vec())],
+            EntryAction::Group(group_id),
+            0,
+        )
+    }
+    
+    /// IPv4 LPMテーブルのエントリを作成
+    pub fn ipv4_lpm(key: TableKey, action: TableAction, priority: u32) -> Self {
+        Self::new(IPV4_LPM_TABLE, key.to_field_matches(), action.to_action_call(), priority)
+    }
+    
+    /// IPv4 LPMテーブルのエントリであればキーを取り出す
+    pub fn ipv4_lpm_key(&self) -> Option<TableKey> {
+        if short_name(&self.table) != IPV4_LPM_TABLE {
+            return None;
+        }
+        match self.matches.as_slice() {
+            [] => Some(TableKey { ipv4_dst: Ipv4Address::from_u32(0), prefix_len: 0 }),
+            [FieldMatch { value: MatchValue::Lpm { value, prefix_len }, .. }] => Some(TableKey {
+                ipv4_dst: Ipv4Address::from_u32(bytes_to_u64(value)?.try_into().ok()?),
+                prefix_len: (*prefix_len).try_into().ok()?,
+            }),
+            _ => None,
+        }
+    }
+    
+    /// IPv6 LPMテーブルのエントリを作成
+    pub fn ipv6_lpm(key: Ipv6TableKey, action: TableAction, priority: u32) -> Self {
+        Self::new(IPV6_LPM_TABLE, key.to_field_matches(), action.to_action_call(), priority)
+    }
+    
+    /// IPv6 LPMテーブルのエントリであればキーを取り出す
+    pub fn ipv6_lpm_key(&self) -> Option<Ipv6TableKey> {
+        if short_name(&self.table) != IPV6_LPM_TABLE {
+            return None;
+        }
+        match self.matches.as_slice() {
+            [] => Some(Ipv6TableKey { ipv6_dst: Ipv6Address::from_u128(0), prefix_len: 0 }),
+            [FieldMatch { value: MatchValue::Lpm { value, prefix_len }, .. }] => Some(Ipv6TableKey {
+                ipv6_dst: Ipv6Address::from_u128(bytes_to_u128(value)?),
+                prefix_len: (*prefix_len).try_into().ok()?,
+            }),
+            _ => None,
+        }
+    }
+    
+    /// 転送テーブル（IPv4 / IPv6 LPM）のアクションであれば取り出す
+    pub fn forwarding_action(&self) -> Option<TableAction> {
+        TableAction::from_action_call(self.action.as_direct()?)
+    }
+    
+    /// ABACポリシーテーブルのエントリを作成（両方ワイルドカードなら全パケットに一致する）
+    pub fn abac_policy(subject: AttributeMatch, object: AttributeMatch, action: AbacAction, priority: u32) -> Self {
+        let matches = subject
+            .to_field_match(ABAC_SUBJECT_FIELD)
+            .into_iter()
+            .chain(object.to_field_match(ABAC_OBJECT_FIELD))
+            .collect();
+        Self::new(ABAC_POLICY_TABLE, matches, action.to_action_call(), priority)
+    }
+    
+    /// ABACポリシーテーブルのアクションであれば取り出す
+    pub fn abac_action(&self) -> Option<AbacAction> {
+        if short_name(&self.table) != ABAC_POLICY_TABLE {
+            return None;
+        }
+        AbacAction::from_action_call(self.action.as_direct()?)
+    }
+    
+    /// 同じテーブル・同じキーのエントリかどうか（マッチの順序は問わない）
+    ///
+    /// 優先度も比べる。優先度を使わないテーブルのエントリは、P4Infoで正規化すると優先度が0になる。
+    pub fn has_same_key(&self, other: &TableEntry) -> bool {
+        if short_name(&self.table) != short_name(&other.table)
+            || self.matches.len() != other.matches.len()
+            || self.priority != other.priority
+        {
+            return false;
+        }
+        let mut a = self.matches.clone();
+        let mut b = other.matches.clone();
+        a.sort();
+        b.sort();
+        a == b
+    }
+}
+
+/// ビッグエンディアンのバイト列をu64に変換（64ビットを超える値はNone）
+fn bytes_to_u64(bytes: &[u8]) -> Option<u64> {
+    bytes_to_u128(bytes)?.try_into().ok()
+}
+
+/// ビッグエンディアンのバイト列をu128に変換（128ビットを超える値はNone）
+fn bytes_to_u128(bytes: &[u8]) -> Option<u128> {
+    let significant = bytes.iter().skip_while(|b| **b == 0).count();
+    if significant > 16 {
+        return None;
+    }
+    Some(bytes.iter().fold(0u128, |acc, b| (acc << 8) | *b as u128))
+}
+
+/// シャドウとデバイスでアクションが異なるエントリ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModifiedEntry {
+    /// シャドウ（コントローラーが意図する）エントリ
+    pub expected: TableEntry,
+    /// デバイスから読み出したエントリ
+    pub actual: TableEntry,
+}
+
+/// シャドウとデバイスのテーブル状態の差分
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableDrift {
+    pub device_id: DeviceId,
+    /// シャドウにあるがデバイスにないエントリ
+    pub missing: Vec<TableEntry>,
+    /// デバイスにあるがシャドウにないエントリ
+    pub extra: Vec<TableEntry>,
+    /// キーは同じだがアクションが異なるエントリ
+    pub modified: Vec<ModifiedEntry>,
+}
+
+impl TableDrift {
+    /// 差分がないか
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.modified.is_empty()
+    }
+    
+    /// 差分の数
+    pub fn len(&self) -> usize {
+        self.missing.len() + self.extra.len() + self.modified.len()
+    }
+    
+    /// デバイスをシャドウに合わせるための最小の更新を、別々のWriteで送る順（DELETE → MODIFY → INSERT）に返す
+    ///
+    /// 1つのWriteRequest内の更新は順不同で処理されるため、容量を空けるDELETEをINSERTより先に送る。
+    pub fn to_update_phases(&self) -> [Vec<TableUpdate>; 3] {
+        [
+            self.extra.iter().cloned().map(TableUpdate::delete).collect(),
+            self.modified.iter().map(|m| TableUpdate::modify(m.expected.clone())).collect(),
+            self.missing.iter().cloned().map(TableUpdate::insert).collect(),
+        ]
+    }
+}
+
+/// テーブルの使用量
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableUsage {
+    pub table: String,
+    /// シャドウにあるエントリ数
+    pub used: usize,
+    /// P4Infoの `size`（不明なら0）
+    pub capacity: u64,
+}
+
+impl TableUsage {
+    /// 使用率（%、容量が不明ならNone）
+    pub fn percent(&self) -> Option<f64> {
+        (self.capacity > 0).then(|| self.used as f64 * 100.0 / self.capacity as f64)
+    }
+    
+    /// これ以上エントリを追加できないか
+    pub fn is_full(&self) -> bool {
+        self.capacity > 0 && self.used as u64 >= self.capacity
+    }
+}
+
+/// 差分が見つかったときの動作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReconcileMode {
+    /// 差分を報告するだけ
+    Report,
+    /// 差分をデバイスに書き込んで修正する
+    Repair,
+}
+
+/// コントローラーヘッダーの名前とフィールド名
+pub const PACKET_IN_HEADER: &str = "packet_in";
+pub const PACKET_OUT_HEADER: &str = "packet_out";
+pub const INGRESS_PORT_METADATA: &str = "ingress_port";
+pub const EGRESS_PORT_METADATA: &str = "egress_port";
+
+/// コントローラーヘッダーのフィールドの値
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacketMetadata {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+/// スイッチからコントローラーに送られたパケット
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacketIn {
+    pub device_id: DeviceId,
+    /// イーサネットヘッダーから始まるパケット
+    pub payload: Vec<u8>,
+    /// packet_inヘッダーの値（P4Infoで名前に解決済み）
+    pub metadata: Vec<PacketMetadata>,
+}
+
+impl PacketIn {
+    /// メタデータの値を名前で取得
+    pub fn metadata(&self, name: &str) -> Option<&[u8]> {
+        self.metadata.iter().find(|m| m.name == name).map(|m| m.value.as_slice())
+    }
+    
+    /// パケットを受信したポート
+    pub fn ingress_port(&self) -> Option<PortId> {
+        bytes_to_u64(self.metadata(INGRESS_PORT_METADATA)?)?.try_into().ok()
+    }
+}
+
+/// コントローラーからスイッチに送るパケット
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacketOut {
+    /// イーサネットヘッダーから始まるパケット
+    pub payload: Vec<u8>,
+    /// packet_outヘッダーの値
+    pub metadata: Vec<PacketMetadata>,
+}
+
+impl PacketOut {
+    pub fn new(payload: Vec<u8>) -> Self {
+        Self { payload, metadata: Vec::new() }
+    }
+    
+    /// 指定したポートから送信するパケット
+    pub fn to_port(payload: Vec<u8>, port: PortId) -> Self {
+        Self::new(payload).with_metadata(EGRESS_PORT_METADATA, port.to_be_bytes().to_vec())
+    }
+    
+    pub fn with_metadata(mut self, name: &str, value: Vec<u8>) -> Self {
+        self.metadata.push(PacketMetadata { name: name.to_string(), value });
+        self
+    }
+}
+
 /// デバイス情報
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceInfo {
@@ -116,13 +849,462 @@ pub struct DeviceInfo {
     pub name: String,
     pub grpc_endpoint: String,
     pub p4info: Option<P4Info>,
+    /// MasterArbitrationUpdateで使うelection ID
+    #[serde(default = "default_election_id")]
+    pub election_id: u128,
+    /// P4Runtimeのロール名（未指定ならデフォルトロール）
+    #[serde(default)]
+    pub role: Option<String>,
+    /// 接続時にプッシュするパイプライン
+    #[serde(default)]
+    pub pipeline: Option<PipelineSpec>,
+    /// WriteRequestのバッチサイズとアトミック性
+    #[serde(default)]
+    pub write_options: WriteOptions,
 }
 
-/// P4プログラム情報（簡略化版）
+/// デバイスにプッシュするパイプラインのファイル
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineSpec {
+    /// p4cが出力したBMv2 JSON（例: build/ip_forwarding.json）
+    pub bmv2_json: PathBuf,
+    /// p4cが出力したP4Info（例: build/ip_forwarding.p4info.bin）
+    pub p4info: PathBuf,
+    #[serde(default)]
+    pub action: PipelineAction,
+}
+
+/// SetForwardingPipelineConfigのモード
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PipelineAction {
+    /// 検証して適用（テーブルの状態はクリアされる）
+    #[default]
+    VerifyAndCommit,
+    /// 既存のフォワーディング状態を保ったまま適用
+    ReconcileAndCommit,
+}
+
+fn default_election_id() -> u128 {
+    1
+}
+
+/// WriteRequestのアトミック性（P4Runtimeの `WriteRequest.Atomicity`）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WriteAtomicity {
+    /// 失敗した更新があっても残りを適用する
+    #[default]
+    ContinueOnError,
+    /// 1つでも失敗したらバッチ全体を取り消す
+    RollbackOnError,
+    /// バッチ全体をデータプレーンに一度に反映する
+    DataplaneAtomic,
+}
+
+impl std::str::FromStr for WriteAtomicity {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "continue-on-error" => Ok(WriteAtomicity::ContinueOnError),
+            "rollback-on-error" => Ok(WriteAtomicity::RollbackOnError),
+            "dataplane-atomic" => Ok(WriteAtomicity::DataplaneAtomic),
+            _ => Err(format!("Unknown atomicity: {}", s)),
+        }
+    }
+}
+
+/// WriteRequestの送り方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteOptions {
+    /// 1つのWriteRequestに含める更新の最大数
+    pub max_batch_size: usize,
+    pub atomicity: WriteAtomicity,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 128,
+            atomicity: WriteAtomicity::ContinueOnError,
+        }
+    }
+}
+
+/// テーブルエントリの更新種別
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UpdateType {
+    Insert,
+    Modify,
+    Delete,
+}
+
+impl std::fmt::Display for UpdateType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateType::Insert => write!(f, "INSERT"),
+            UpdateType::Modify => write!(f, "MODIFY"),
+            UpdateType::Delete => write!(f, "DELETE"),
+        }
+    }
+}
+
+/// デバイスに送るテーブルエントリの更新
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableUpdate {
+    pub update_type: UpdateType,
+    pub entry: TableEntry,
+}
+
+impl TableUpdate {
+    pub fn insert(entry: TableEntry) -> Self {
+        Self { update_type: UpdateType::Insert, entry }
+    }
+    
+    pub fn modify(entry: TableEntry) -> Self {
+        Self { update_type: UpdateType::Modify, entry }
+    }
+    
+    pub fn delete(entry: TableEntry) -> Self {
+        Self { update_type: UpdateType::Delete, entry }
+    }
+}
+
+/// シャドウに反映済みのデバイスへの更新と、書き込みに失敗したときに戻す更新前のエントリ
+#[derive(Debug, Clone)]
+pub struct StagedUpdate {
+    pub update: TableUpdate,
+    /// 更新前のエントリ（INSERTならNone）
+    pub previous: Option<TableEntry>,
+}
+
+impl StagedUpdate {
+    /// シャドウから削除したエントリのDELETE
+    pub fn delete(removed: TableEntry) -> Self {
+        Self {
+            update: TableUpdate::delete(removed.clone()),
+            previous: Some(removed),
+        }
+    }
+}
+
+/// アクションプロファイルのメンバー（1つのアクション）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionProfileMember {
+    /// P4Infoのアクションプロファイル名
+    pub profile: String,
+    pub member_id: u32,
+    pub action: ActionCall,
+}
+
+/// アクションセレクターのグループ内のメンバー
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupMember {
+    pub member_id: u32,
+    pub weight: u32,
+}
+
+/// アクションセレクターのグループ（ハッシュでメンバーを1つ選ぶ）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionProfileGroup {
+    /// P4Infoのアクションプロファイル名
+    pub profile: String,
+    pub group_id: u32,
+    pub members: Vec<GroupMember>,
+    /// グループに入れられる重みの合計（0ならアクションプロファイルのmax_group_size）
+    pub max_size: u32,
+}
+
+/// Writeで書き込むエンティティ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Entity {
+    TableEntry(TableEntry),
+    ActionProfileMember(ActionProfileMember),
+    ActionProfileGroup(ActionProfileGroup),
+}
+
+impl std::fmt::Display for Entity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Entity::TableEntry(entry) => write!(f, "{}", entry.table),
+            Entity::ActionProfileMember(member) => write!(f, "{} member {}", member.profile, member.member_id),
+            Entity::ActionProfileGroup(group) => write!(f, "{} group {}", group.profile, group.group_id),
+        }
+    }
+}
+
+/// デバイスに送るエンティティの更新
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityUpdate {
+    pub update_type: UpdateType,
+    pub entity: Entity,
+}
+
+impl EntityUpdate {
+    pub fn insert(entity: Entity) -> Self {
+        Self { update_type: UpdateType::Insert, entity }
+    }
+    
+    pub fn modify(entity: Entity) -> Self {
+        Self { update_type: UpdateType::Modify, entity }
+    }
+    
+    pub fn delete(entity: Entity) -> Self {
+        Self { update_type: UpdateType::Delete, entity }
+    }
+}
+
+impl From<TableUpdate> for EntityUpdate {
+    fn from(update: TableUpdate) -> Self {
+        Self { update_type: update.update_type, entity: Entity::TableEntry(update.entry) }
+    }
+}
+
+/// 失敗した更新とその理由（`p4.v1.Error`）
+#[derive(Debug, Clone)]
+pub struct UpdateFailure {
+    /// 書き込みを要求した更新の中での位置
+    pub index: usize,
+    pub update: EntityUpdate,
+    pub code: tonic::Code,
+    pub message: String,
+}
+
+impl UpdateFailure {
+    /// デバイスにすでにないエンティティのDELETE（削除できたものとして扱える）
+    pub fn is_already_deleted(&self) -> bool {
+        self.update.update_type == UpdateType::Delete && self.code == tonic::Code::NotFound
+    }
+}
+
+/// Writeの結果
+#[derive(Debug, Clone)]
+pub struct WriteResult {
+    pub device_id: DeviceId,
+    /// 要求した更新の数
+    pub total: usize,
+    pub failures: Vec<UpdateFailure>,
+}
+
+impl WriteResult {
+    pub fn new(device_id: DeviceId, total: usize) -> Self {
+        Self { device_id, total, failures: Vec::new() }
+    }
+    
+    /// すべての更新が成功したか
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+    
+    /// 成功した更新の数
+    pub fn succeeded(&self) -> usize {
+        self.total - self.failures.len()
+    }
+    
+    /// 失敗した更新があればWriteFailedエラーにする
+    pub fn into_result(self) -> anyhow::Result<Self> {
+        let Some(first) = self.failures.first() else {
+            return Ok(self);
+        };
+        Err(P4RuntimeError::WriteFailed {
+            device_id: self.device_id,
+            failed: self.failures.len(),
+            total: self.total,
+            detail: format!("{} {}: {:?}: {}", first.update.update_type, first.update.entity, first.code, first.message),
+        }
+        .into())
+    }
+}
+
+/// マスターアービトレーションの結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MastershipStatus {
+    /// プライマリ（書き込み可能）
+    Primary,
+    /// バックアップ（読み取りのみ）
+    Backup,
+}
+
+impl std::fmt::Display for MastershipStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MastershipStatus::Primary => write!(f, "primary"),
+            MastershipStatus::Backup => write!(f, "backup"),
+        }
+    }
+}
+
+/// P4プログラム情報（簡略化版）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct P4Info {
     pub tables: HashMap<String, TableInfo>,
     pub actions: HashMap<String, ActionInfo>,
+    #[serde(default)]
+    pub counters: HashMap<String, CounterInfo>,
+    #[serde(default)]
+    pub meters: HashMap<String, MeterInfo>,
+    #[serde(default)]
+    pub digests: HashMap<String, DigestInfo>,
+    /// コントローラーヘッダー（"packet_in" / "packet_out"）
+    #[serde(default)]
+    pub controller_packet_metadata: HashMap<String, ControllerPacketMetadataInfo>,
+    #[serde(default)]
+    pub action_profiles: HashMap<String, ActionProfileInfo>,
+}
+
+impl P4Info {
+    /// テーブルを名前で検索（"MyIngress.ipv4_lpm" でも "ipv4_lpm" でも可）
+    pub fn find_table(&self, name: &str) -> Option<&TableInfo> {
+        find_by_name(&self.tables, name, |t| &t.name)
+    }
+    
+    /// テーブルをIDで検索
+    pub fn find_table_by_id(&self, id: u32) -> Option<&TableInfo> {
+        self.tables.values().find(|t| t.id == id)
+    }
+    
+    /// アクションを名前で検索（"MyIngress.drop" でも "drop" でも可）
+    pub fn find_action(&self, name: &str) -> Option<&ActionInfo> {
+        find_by_name(&self.actions, name, |a| &a.name)
+    }
+    
+    /// アクションをIDで検索
+    pub fn find_action_by_id(&self, id: u32) -> Option<&ActionInfo> {
+        self.actions.values().find(|a| a.id == id)
+    }
+    
+    /// カウンターを名前で検索
+    pub fn find_counter(&self, name: &str) -> Option<&CounterInfo> {
+        find_by_name(&self.counters, name, |c| &c.name)
+    }
+    
+    /// カウンターをIDで検索
+    pub fn find_counter_by_id(&self, id: u32) -> Option<&CounterInfo> {
+        self.counters.values().find(|c| c.id == id)
+    }
+    
+    /// メーターを名前で検索
+    pub fn find_meter(&self, name: &str) -> Option<&MeterInfo> {
+        find_by_name(&self.meters, name, |m| &m.name)
+    }
+    
+    /// メーターをIDで検索
+    pub fn find_meter_by_id(&self, id: u32) -> Option<&MeterInfo> {
+        self.meters.values().find(|m| m.id == id)
+    }
+    
+    /// ダイジェストを名前で検索
+    pub fn find_digest(&self, name: &str) -> Option<&DigestInfo> {
+        find_by_name(&self.digests, name, |d| &d.name)
+    }
+    
+    /// ダイジェストをIDで検索
+    pub fn find_digest_by_id(&self, id: u32) -> Option<&DigestInfo> {
+        self.digests.values().find(|d| d.id == id)
+    }
+    
+    /// テーブル名 -> ID
+    pub fn table_id(&self, name: &str) -> Option<u32> {
+        self.find_table(name).map(|t| t.id)
+    }
+    
+    /// テーブルID -> 名前
+    pub fn table_name(&self, id: u32) -> Option<&str> {
+        self.find_table_by_id(id).map(|t| t.name.as_str())
+    }
+    
+    /// アクション名 -> ID
+    pub fn action_id(&self, name: &str) -> Option<u32> {
+        self.find_action(name).map(|a| a.id)
+    }
+    
+    /// アクションID -> 名前
+    pub fn action_name(&self, id: u32) -> Option<&str> {
+        self.find_action_by_id(id).map(|a| a.name.as_str())
+    }
+    
+    /// アクションパラメータ名 -> ID
+    pub fn action_param_id(&self, action_name: &str, param_name: &str) -> Option<u32> {
+        self.find_action(action_name)?
+            .params
+            .iter()
+            .find(|p| p.name == param_name)
+            .map(|p| p.id)
+    }
+    
+    /// アクションパラメータID -> 名前
+    pub fn action_param_name(&self, action_id: u32, param_id: u32) -> Option<&str> {
+        self.find_action_by_id(action_id)?
+            .params
+            .iter()
+            .find(|p| p.id == param_id)
+            .map(|p| p.name.as_str())
+    }
+    
+    /// カウンター名 -> ID
+    pub fn counter_id(&self, name: &str) -> Option<u32> {
+        self.find_counter(name).map(|c| c.id)
+    }
+    
+    /// カウンターID -> 名前
+    pub fn counter_name(&self, id: u32) -> Option<&str> {
+        self.find_counter_by_id(id).map(|c| c.name.as_str())
+    }
+    
+    /// メーター名 -> ID
+    pub fn meter_id(&self, name: &str) -> Option<u32> {
+        self.find_meter(name).map(|m| m.id)
+    }
+    
+    /// メーターID -> 名前
+    pub fn meter_name(&self, id: u32) -> Option<&str> {
+        self.find_meter_by_id(id).map(|m| m.name.as_str())
+    }
+    
+    /// ダイジェスト名 -> ID
+    pub fn digest_id(&self, name: &str) -> Option<u32> {
+        self.find_digest(name).map(|d| d.id)
+    }
+    
+    /// ダイジェストID -> 名前
+    pub fn digest_name(&self, id: u32) -> Option<&str> {
+        self.find_digest_by_id(id).map(|d| d.name.as_str())
+    }
+    
+    /// アクションプロファイルを名前で検索
+    pub fn find_action_profile(&self, name: &str) -> Option<&ActionProfileInfo> {
+        find_by_name(&self.action_profiles, name, |p| &p.name)
+    }
+    
+    /// アクションプロファイルをIDで検索
+    pub fn find_action_profile_by_id(&self, id: u32) -> Option<&ActionProfileInfo> {
+        self.action_profiles.values().find(|p| p.id == id)
+    }
+    
+    /// コントローラーヘッダーを名前で検索
+    pub fn find_controller_packet_metadata(&self, name: &str) -> Option<&ControllerPacketMetadataInfo> {
+        find_by_name(&self.controller_packet_metadata, name, |m| &m.name)
+    }
+}
+
+/// 完全修飾名、または最後の要素（"MyIngress.ipv4_lpm" -> "ipv4_lpm"）で検索
+///
+/// 最後の要素が同じものが複数ある場合（"MyIngress.drop" と "MyEgress.drop" など）は、
+/// どれを指すか決まらないのでNoneを返す。
+fn find_by_name<'a, T>(
+    items: &'a HashMap<String, T>,
+    name: &str,
+    item_name: impl Fn(&T) -> &String,
+) -> Option<&'a T> {
+    items.get(name).or_else(|| {
+        let mut matches = items.values().filter(|item| short_name(item_name(item)) == name);
+        let found = matches.next()?;
+        matches.next().is_none().then_some(found)
+    })
+}
+
+/// 完全修飾名から最後の要素を取り出す（"MyIngress.ipv4_lpm" -> "ipv4_lpm"）
+pub fn short_name(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or(name)
 }
 
 /// テーブル情報
@@ -132,23 +1314,55 @@ pub struct TableInfo {
     pub id: u32,
     pub key_fields: Vec<KeyField>,
     pub action_refs: Vec<ActionRef>,
+    /// テーブルサイズ（P4プログラムの `size`）
+    #[serde(default)]
+    pub size: u64,
+    /// アクションプロファイル・アクションセレクターを使うテーブルならそのID（直接のアクションなら0）
+    #[serde(default)]
+    pub implementation_id: u32,
+}
+
+impl TableInfo {
+    /// 優先度がキーの一部になるか（ternary / range / optionalのフィールドを含むテーブル）
+    pub fn needs_priority(&self) -> bool {
+        self.key_fields
+            .iter()
+            .any(|k| matches!(k.match_type, MatchType::Ternary | MatchType::Range | MatchType::Optional))
+    }
+}
+
+/// アクションプロファイル情報（with_selectorならアクションセレクター）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionProfileInfo {
+    pub name: String,
+    pub id: u32,
+    pub table_ids: Vec<u32>,
+    pub with_selector: bool,
+    /// メンバー数の上限
+    pub size: u64,
+    /// グループあたりのメンバー数（重みの合計）の上限（0なら制限なし）
+    pub max_group_size: u32,
 }
 
 /// キーフィールド情報
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyField {
+    pub id: u32,
     pub name: String,
     pub bitwidth: u32,
     pub match_type: MatchType,
 }
 
 /// マッチタイプ
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MatchType {
     Exact,
     Lpm,  // Longest Prefix Match
     Ternary,
     Range,
+    Optional,
+    /// アーキテクチャ固有など、コントローラーが扱えないマッチタイプ（このフィールドを持つテーブルには書き込めない）
+    Unsupported,
 }
 
 /// アクション参照
@@ -169,18 +1383,143 @@ pub struct ActionInfo {
 /// アクションパラメータ
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActionParam {
+    pub id: u32,
     pub name: String,
     pub bitwidth: u32,
 }
 
-/// ルーティングテーブルエントリ
+/// カウンター情報（ダイレクトカウンターも含む）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CounterInfo {
+    pub name: String,
+    pub id: u32,
+    pub size: u64,
+    /// ダイレクトカウンターの場合、対象テーブルのID
+    pub direct_table_id: Option<u32>,
+}
+
+/// メーター情報（ダイレクトメーターも含む）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeterInfo {
+    pub name: String,
+    pub id: u32,
+    pub size: u64,
+    /// ダイレクトメーターの場合、対象テーブルのID
+    pub direct_table_id: Option<u32>,
+}
+
+/// ダイジェスト情報
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DigestInfo {
+    pub name: String,
+    pub id: u32,
+}
+
+/// コントローラーヘッダー情報（`@controller_header`）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControllerPacketMetadataInfo {
+    pub name: String,
+    pub id: u32,
+    pub metadata: Vec<PacketMetadataInfo>,
+}
+
+/// コントローラーヘッダーのフィールド
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PacketMetadataInfo {
+    pub id: u32,
+    pub name: String,
+    pub bitwidth: u32,
+}
+
+/// ルーティングテーブルエントリ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RouteEntry {
     pub prefix: Ipv4Address,
     pub prefix_len: u8,
     pub next_hop: Option<Ipv4Address>,
     pub interface: String,
     pub metric: u32,
+    /// next_hop / interface に加えて等コストで負荷分散するネクストホップ（ECMP）
+    #[serde(default)]
+    pub ecmp_next_hops: Vec<NextHop>,
+}
+
+impl RouteEntry {
+    /// 同じプレフィックスへの同じ経路（ネクストホップとインターフェース）か
+    pub fn is_same_path(&self, other: &RouteEntry) -> bool {
+        self.prefix == other.prefix
+            && self.prefix_len == other.prefix_len
+            && self.next_hop == other.next_hop
+            && self.interface == other.interface
+            && self.ecmp_next_hops == other.ecmp_next_hops
+    }
+    
+    /// 複数のネクストホップに負荷分散するルートか
+    pub fn is_ecmp(&self) -> bool {
+        !self.ecmp_next_hops.is_empty()
+    }
+    
+    /// ゲートウェイを経由するネクストホップをすべて取得（直接接続なら空）
+    pub fn next_hops(&self) -> Vec<NextHop> {
+        let primary = self.next_hop.map(|ip| NextHop {
+            ip,
+            interface: self.interface.clone(),
+        });
+        primary.into_iter().chain(self.ecmp_next_hops.iter().cloned()).collect()
+    }
+    
+    /// ネクストホップを経由するルートか
+    pub fn uses_next_hop(&self, ip: Ipv4Address) -> bool {
+        self.next_hop == Some(ip) || self.ecmp_next_hops.iter().any(|n| n.ip == ip)
+    }
+    
+    /// ネクストホップの表示用文字列（直接接続ならインターフェース名）
+    pub fn describe_next_hop(&self) -> String {
+        match self.next_hop {
+            Some(_) => self
+                .next_hops()
+                .iter()
+                .map(|n| n.ip.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => format!("direct ({})", self.interface),
+        }
+    }
+}
+
+/// ECMPルートのネクストホップ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NextHop {
+    pub ip: Ipv4Address,
+    pub interface: String,
+}
+
+/// IPv6ルーティングテーブルエントリ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ipv6RouteEntry {
+    pub prefix: Ipv6Address,
+    pub prefix_len: u8,
+    pub next_hop: Option<Ipv6Address>,
+    pub interface: String,
+    pub metric: u32,
+}
+
+impl Ipv6RouteEntry {
+    /// 同じプレフィックスへの同じ経路（ネクストホップとインターフェース）か
+    pub fn is_same_path(&self, other: &Ipv6RouteEntry) -> bool {
+        self.prefix == other.prefix
+            && self.prefix_len == other.prefix_len
+            && self.next_hop == other.next_hop
+            && self.interface == other.interface
+    }
+    
+    /// ネクストホップの表示用文字列（直接接続ならインターフェース名）
+    pub fn describe_next_hop(&self) -> String {
+        match self.next_hop {
+            Some(next_hop) => next_hop.to_string(),
+            None => format!("direct ({})", self.interface),
+        }
+    }
 }
 
 /// ARPテーブルエントリ
@@ -189,6 +1528,97 @@ pub struct ArpEntry {
     pub ip: Ipv4Address,
     pub mac: MacAddress,
     pub interface: String,
+    /// 設定・CLIで登録したものか、ARP応答から学習したものか
+    #[serde(default)]
+    pub entry_type: ArpEntryType,
+    /// 最後に登録・応答を確認した時刻
+    #[serde(default = "SystemTime::now")]
+    pub last_seen: SystemTime,
+}
+
+impl ArpEntry {
+    /// 設定・CLIで登録する（エージングしない）エントリ
+    pub fn new_static(ip: Ipv4Address, mac: MacAddress, interface: &str) -> Self {
+        Self {
+            ip,
+            mac,
+            interface: interface.to_string(),
+            entry_type: ArpEntryType::Static,
+            last_seen: SystemTime::now(),
+        }
+    }
+    
+    /// ARP応答から学習したエントリ
+    pub fn new_dynamic(ip: Ipv4Address, mac: MacAddress, interface: &str) -> Self {
+        Self {
+            entry_type: ArpEntryType::Dynamic,
+            ..Self::new_static(ip, mac, interface)
+        }
+    }
+    
+    /// 最後に確認してからの経過時間
+    pub fn age(&self) -> Duration {
+        self.last_seen.elapsed().unwrap_or_default()
+    }
+}
+
+/// IPv6の近隣キャッシュ（NDP）エントリ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NeighborEntry {
+    pub ip: Ipv6Address,
+    pub mac: MacAddress,
+    pub interface: String,
+    /// 設定・CLIで登録したものか、近隣広告から学習したものか
+    #[serde(default)]
+    pub entry_type: ArpEntryType,
+    /// 最後に登録・広告を確認した時刻
+    #[serde(default = "SystemTime::now")]
+    pub last_seen: SystemTime,
+}
+
+impl NeighborEntry {
+    /// 設定・CLIで登録するエントリ
+    pub fn new_static(ip: Ipv6Address, mac: MacAddress, interface: &str) -> Self {
+        Self {
+            ip,
+            mac,
+            interface: interface.to_string(),
+            entry_type: ArpEntryType::Static,
+            last_seen: SystemTime::now(),
+        }
+    }
+    
+    /// 近隣広告から学習したエントリ
+    pub fn new_dynamic(ip: Ipv6Address, mac: MacAddress, interface: &str) -> Self {
+        Self {
+            entry_type: ArpEntryType::Dynamic,
+            ..Self::new_static(ip, mac, interface)
+        }
+    }
+    
+    /// 最後に確認してからの経過時間
+    pub fn age(&self) -> Duration {
+        self.last_seen.elapsed().unwrap_or_default()
+    }
+}
+
+/// ARPエントリの種別
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArpEntryType {
+    /// 設定・CLIで登録（エージングしない）
+    #[default]
+    Static,
+    /// ARP応答から学習（一定時間確認できなければ削除）
+    Dynamic,
+}
+
+impl std::fmt::Display for ArpEntryType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArpEntryType::Static => write!(f, "static"),
+            ArpEntryType::Dynamic => write!(f, "dynamic"),
+        }
+    }
 }
 
 /// スイッチポート情報
@@ -202,23 +1632,13 @@ pub struct PortInfo {
 }
 
 /// コントローラー設定
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ControllerConfig {
     pub devices: Vec<DeviceInfo>,
     pub default_routes: Vec<RouteEntry>,
     pub arp_table: Vec<ArpEntry>,
 }
 
-impl Default for ControllerConfig {
-    fn default() -> Self {
-        Self {
-            devices: Vec::new(),
-            default_routes: Vec::new(),
-            arp_table: Vec::new(),
-        }
-    }
-}
-
 /// P4Runtimeメッセージの簡略化版
 #[derive(Debug, Clone)]
 pub struct P4RuntimeMessage {
@@ -227,7 +1647,7 @@ pub struct P4RuntimeMessage {
 }
 
 /// 統計情報
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Statistics {
     pub packets_processed: u64,
     pub bytes_processed: u64,
@@ -235,31 +1655,11 @@ pub struct Statistics {
     pub table_misses: HashMap<String, u64>,
 }
 
-impl Default for Statistics {
-    fn default() -> Self {
-        Self {
-            packets_processed: 0,
-            bytes_processed: 0,
-            table_hits: HashMap::new(),
-            table_misses: HashMap::new(),
-        }
-    }
-}
-
 /// コントローラー状態
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ControllerState {
     pub config: ControllerConfig,
     pub statistics: Statistics,
     pub connected_devices: HashMap<DeviceId, DeviceInfo>,
 }
 
-impl Default for ControllerState {
-    fn default() -> Self {
-        Self {
-            config: ControllerConfig::default(),
-            statistics: Statistics::default(),
-            connected_devices: HashMap::new(),
-        }
-    }
-}
\ No newline at end of file